@@ -0,0 +1,51 @@
+#ifndef IMCO_H
+#define IMCO_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Converts the encoded image in `data` into `format` (e.g. "webp"), with `quality`
+ * 1-100 for lossy formats or 0 for the default.
+ * On success `*out_data` and `*out_len` hold the encoded output, release it with [`imco_free_buffer`]
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes, `format` to a NUL-terminated string
+ * and `out_data` and `out_len` to writable locations
+ */
+int imco_convert_buffer(const uint8_t *data,
+                        size_t len,
+                        const char *format,
+                        int quality,
+                        uint8_t **out_data,
+                        size_t *out_len);
+
+/**
+ * Releases an output of [`imco_convert_buffer`], doing nothing for null
+ *
+ * # Safety
+ * `data` and `len` must be exactly what [`imco_convert_buffer`] handed out, released only once
+ */
+void imco_free_buffer(uint8_t *data, size_t len);
+
+/**
+ * Converts the file at `input` and writes it to `output`.
+ * `format` may be null to use the one `output`'s extension names, `quality` works like in [`imco_convert_buffer`]
+ *
+ * # Safety
+ * `input` and `output` must point to NUL-terminated strings, `format` too unless it is null
+ */
+int imco_convert_file(const char *input, const char *output, const char *format, int quality);
+
+/**
+ * The message of the last failed call on this thread, or null when it succeeded.
+ * It stays valid until the next call on the same thread
+ */
+const char *imco_last_error(void);
+
+#endif  /* IMCO_H */
@@ -0,0 +1,44 @@
+#ifndef IMCO_PLUGIN_H
+#define IMCO_PLUGIN_H
+
+/* What dynamic libraries loaded with --plugin export, their operations joining those of --ops */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define IMCO_PLUGIN_VERSION 1
+
+/**
+ * An image of 8 bit RGBA pixels, rows of width * 4 bytes from top to bottom
+ */
+typedef struct ImcoImage {
+  uint32_t width;
+  uint32_t height;
+  uint8_t *pixels;
+} ImcoImage;
+
+/**
+ * Returns IMCO_PLUGIN_VERSION, plugins of other versions aren't loaded
+ */
+uint32_t imco_plugin_version(void);
+
+/**
+ * The names of the plugin's operations separated by commas, a string that lives as long as the plugin
+ */
+const char *imco_plugin_operations(void);
+
+/**
+ * Applies the operation `name` with `args`, the text after its ':' in --ops (empty without one), to the image.
+ * It changes the pixels in place, or points `image` at pixels of its own (which it also does to change the size),
+ * which imco copies and releases with imco_plugin_free.
+ * Returns 0 on success, otherwise a message may be written into `error`, NUL-terminated within `error_len` bytes.
+ * It is called from several threads at once when converting files in parallel
+ */
+int imco_plugin_apply(const char *name, const char *args, ImcoImage *image, char *error, size_t error_len);
+
+/**
+ * Releases pixels handed out by imco_plugin_apply, only needed by plugins that do
+ */
+void imco_plugin_free(uint8_t *pixels);
+
+#endif /* IMCO_PLUGIN_H */
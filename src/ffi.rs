@@ -0,0 +1,110 @@
+//! A C interface to the conversion core, declared in `include/imco.h` (regenerate it with `cbindgen --output include/imco.h`).
+//!
+//! Every function returns 0 on success and -1 on failure, after which [`imco_last_error`]
+//! tells what went wrong. Strings are NUL-terminated UTF-8
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use crate::{mk_format, ConversionJob, Converter, ImcoError, ImcoResult, Options};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn record(result: ImcoResult<()>) -> c_int {
+    match result {
+        Ok(()) => {
+            LAST_ERROR.with(|last| *last.borrow_mut() = None);
+            0
+        }
+        Err(err) => {
+            let message = CString::new(err.to_string().replace('\0', " ")).expect("NUL bytes were replaced");
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+            -1
+        }
+    }
+}
+
+// Runs a function's body, a panic being recorded as its error instead of unwinding into the caller
+fn guarded(body: impl FnOnce() -> ImcoResult<()>) -> c_int {
+    record(catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|panic| {
+        let message = panic.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown reason".to_string());
+        Err(ImcoError::InternalConversionError(format!("imco panicked: {message}")))
+    }))
+}
+
+/// # Safety
+/// `s` must be null or point to a NUL-terminated string
+unsafe fn string(s: *const c_char, what: &str) -> ImcoResult<Option<String>> {
+    if s.is_null() { return Ok(None) }
+    CStr::from_ptr(s).to_str()
+        .map(|s| Some(s.to_owned()))
+        .map_err(|_| ImcoError::InternalConversionError(format!("the {what} is not valid UTF-8")))
+}
+
+fn converter(format: Option<String>, quality: c_int) -> ImcoResult<Converter> {
+    let quality = match quality {
+        0 => None,
+        1..=100 => Some(quality as u8),
+        _ => return Err(ImcoError::InternalConversionError(format!("quality must be 1-100 or 0 for the default, not {quality}"))),
+    };
+    Ok(Converter::new(Options { o_fmt: format.as_deref().map(mk_format).transpose()?, quality, ..Options::default() }))
+}
+
+/// Converts the encoded image in `data` into `format` (e.g. "webp"), with `quality`
+/// 1-100 for lossy formats or 0 for the default.
+/// On success `*out_data` and `*out_len` hold the encoded output, release it with [`imco_free_buffer`]
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, `format` to a NUL-terminated string
+/// and `out_data` and `out_len` to writable locations
+#[no_mangle]
+pub unsafe extern "C" fn imco_convert_buffer(data: *const u8, len: usize, format: *const c_char, quality: c_int, out_data: *mut *mut u8, out_len: *mut usize) -> c_int {
+    guarded(|| {
+        if data.is_null() || out_data.is_null() || out_len.is_null() {
+            return Err(ImcoError::InternalConversionError("null pointer passed to imco_convert_buffer".to_string()))
+        }
+        let input = std::slice::from_raw_parts(data, len).to_vec();
+        let output = converter(string(format, "format")?, quality)?.convert_bytes(input)?.into_boxed_slice();
+        *out_len = output.len();
+        *out_data = Box::into_raw(output) as *mut u8;
+        Ok(())
+    })
+}
+
+/// Releases an output of [`imco_convert_buffer`], doing nothing for null
+///
+/// # Safety
+/// `data` and `len` must be exactly what [`imco_convert_buffer`] handed out, released only once
+#[no_mangle]
+pub unsafe extern "C" fn imco_free_buffer(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+/// Converts the file at `input` and writes it to `output`.
+/// `format` may be null to use the one `output`'s extension names, `quality` works like in [`imco_convert_buffer`]
+///
+/// # Safety
+/// `input` and `output` must point to NUL-terminated strings, `format` too unless it is null
+#[no_mangle]
+pub unsafe extern "C" fn imco_convert_file(input: *const c_char, output: *const c_char, format: *const c_char, quality: c_int) -> c_int {
+    guarded(|| {
+        let (Some(input), Some(output)) = (string(input, "input")?, string(output, "output")?) else {
+            return Err(ImcoError::InternalConversionError("null path passed to imco_convert_file".to_string()))
+        };
+        converter(string(format, "format")?, quality)?.convert(&ConversionJob::new(input, Some(output.into())))?;
+        Ok(())
+    })
+}
+
+/// The message of the last failed call on this thread, or null when it succeeded.
+/// It stays valid until the next call on the same thread
+#[no_mangle]
+pub extern "C" fn imco_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |message| message.as_ptr()))
+}
@@ -0,0 +1,8476 @@
+//! The conversion core of imco, usable without the command line.
+//!
+//! A [`Converter`] holds the [`Options`] every image is converted with and runs
+//! [`ConversionJob`]s, each naming an input and optionally where to write it:
+//!
+//! ```no_run
+//! use imco::{Converter, ConversionJob, Format, Options};
+//!
+//! let converter = Converter::new(Options { o_fmt: Some(Format::Image(image::ImageFormat::WebP)), ..Options::default() });
+//! let conversion = converter.convert(&ConversionJob::new("photo.png", None)).unwrap();
+//! println!("wrote {}", conversion.output.display());
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::io::{BufReader, BufWriter, Cursor, ErrorKind, Read, Seek, Write};
+use image::{ImageError, ImageFormat, ImageReader};
+use image::error::{UnsupportedError, UnsupportedErrorKind};
+use rayon::prelude::*;
+use indicatif::{ProgressBar, ProgressStyle};
+use image::imageops::FilterType;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::png::{CompressionType, FilterType as PngFilter, PngDecoder, PngEncoder};
+use image::codecs::webp::{WebPDecoder, WebPEncoder};
+use image::codecs::tiff::TiffEncoder;
+use image::{AnimationDecoder, ImageDecoder, ImageEncoder};
+use xxhash_rust::xxh3::Xxh3;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+pub mod ffi;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+
+pub const NAME: &str = env!("CARGO_PKG_NAME");
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
+
+/// Everything that can go wrong, displayed as a message meant for users
+#[derive(Debug)]
+pub enum ImcoError {
+    // IO Errors; Reason, Path
+    FailedFileRead(String, String),
+    FailedFileWrite(String, String),
+    InvalidBatching,
+    InputIsDirectory(String),
+    // Format
+    InvalidFormat(String),
+    NoDestFormat,
+    // file path, [hint]
+    Decoding(String, String),
+    Encoding(String, String),
+    Unsupported(String, String),
+    InternalConversionError(String),
+    ResourceLimitReached(String),
+    // Error, Pattern
+    BatchPattern(String, String),
+    BatchReadEntry(String),
+    // Backup path
+    BackupExists(String),
+    // Link, Target
+    SymlinkTarget(String, String),
+    // Input path, Format found in it
+    ExtensionMismatch(String, String),
+    // Output path
+    OutputExists(String),
+    // Error, Archive path
+    Archive(String, String),
+    // Error, Archive path
+    ArchiveRead(String, String),
+    // URL, Error
+    Download(String, String),
+    ThreadPool(String),
+    // Output path, hint
+    VerifyFailed(String, String),
+    // Listed path
+    ChecksumMismatch(String),
+    // Manifest path, hint
+    ChecksumManifest(String, String),
+    // Resolution
+    InvalidResolution(String),
+    InvalidCrop(String),
+    InvalidAspect(String),
+    InvalidColor(String),
+    InvalidUnsharp(String),
+    InvalidDenoise(String),
+    InvalidTint(String),
+    InvalidVignette(String),
+    InvalidBorder(String),
+    InvalidSize(String),
+    InvalidScale(String),
+    InvalidPages(String),
+    InvalidRawInput(String),
+    // Setting, hint
+    InvalidSetting(String, String),
+    // Edit, hint
+    InvalidExifEdit(String, String),
+    // Input path
+    CropOutside(String),
+    // Input path, rows, columns
+    SliceTooSmall(String, u32, u32),
+    // Input path, first selected page, page count
+    PageOutOfRange(String, usize, usize),
+    // Input path, bits per channel, output format
+    DepthLost(String, u8, String),
+    // Input path, lossy step
+    Lossy(String, String),
+    // Input path, what the output would lose
+    Downgraded(String, String),
+    // Output directory, estimated bytes, free bytes
+    NoSpace(String, u64, u64),
+    // Session file, reason
+    Session(String, String),
+    // Output, first input, second input
+    DuplicateOutput(String, String, String),
+    // Input path, timeout in seconds
+    TimedOut(String, u64),
+    ExtractToStdout,
+    FormatsToStdout,
+    ConfirmFromStdin,
+    Declined,
+    // Option
+    SeveralFormats(String),
+    NoCombinedOutput,
+    EmptyMontage,
+    EmptyConcat,
+    EmptySpriteSheet,
+    NoChannels,
+    // Input path, target bytes
+    TargetSize(String, u64),
+    // Tile path
+    TileName(String),
+    // Row, column
+    MissingTile(u32, u32),
+    Watch(String),
+    Clipboard(String),
+    Gpu(String),
+    // Error, Address
+    Serve(String, String),
+    Tui(String),
+    // Both paths
+    CompareSize(String, String),
+    ChannelSize(String, String),
+    // Input, the first input
+    StackSize(String, String),
+    EmptyStack,
+    // Input path
+    CantOptimize(String),
+    // Config path, hint
+    Config(String, String),
+    // Jobs file path, hint
+    JobsFile(String, String),
+    InvalidJob(String),
+    // LUT path, hint
+    InvalidLut(String, String),
+    // Font path
+    InvalidFont(String),
+    // Preset name
+    UnknownPreset(String),
+    // Operation, hint
+    InvalidOperation(String, String),
+    // Input path, operation, message of the operation
+    OperationFailed(String, String, String),
+    // Plugin path, reason
+    Plugin(String, String),
+}
+
+impl fmt::Display for ImcoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImcoError::FailedFileRead(cause, path) => {write!(f, "Failed reading '{path}' => {cause}")}
+            ImcoError::FailedFileWrite(cause, path) => {write!(f, "Failed writing '{path}' => {cause}")}
+            ImcoError::InvalidFormat(fmt) => {write!(f, "Unknown format {fmt}, use --list-formats for a list")}
+            ImcoError::InputIsDirectory(path) => {write!(f, "'{path}' is a directory, use --recursive or --batch with a pattern like '{}' to convert its contents", std::path::Path::new(path).join("*").display())}
+            ImcoError::InvalidBatching => {write!(f, "Batching inputs without a known format is only allowed when specifying an output format (using --output-format)")}
+            ImcoError::NoDestFormat => {write!(f, "No output format provided (use --output-format)")}
+            ImcoError::Decoding(path, hint) => {write!(f, "Error during decoding of '{path}' => {hint}")}
+            ImcoError::Encoding(path, hint) => {write!(f, "Error during encoding of '{path}' => {hint}")}
+            ImcoError::Unsupported(path, hint) => {write!(f, "{hint} during conversion of '{path}'")}
+            ImcoError::InternalConversionError(path) => {write!(f, "Internal error during conversion of '{path}'")}
+            ImcoError::ResourceLimitReached(path) => {write!(f, "Exceeded resource limitation during conversion of '{path}'")},
+            ImcoError::BatchPattern(err, pat) => { write!(f, "Failed to collect files using glob, '{pat}' => {err}") }
+            ImcoError::BatchReadEntry(err) => { write!(f, "Failed to read directory entry using glob => {err}") },
+            ImcoError::BackupExists(path) => write!(f, "Backup '{path}' already exists"),
+            ImcoError::SymlinkTarget(link, target) => write!(f, "Can't recreate the link '{link}', its target '{target}' wasn't converted"),
+            ImcoError::ExtensionMismatch(path, fmt) => write!(f, "'{path}' holds a {fmt} image, not what its extension says (--strict-extensions)"),
+            ImcoError::OutputExists(path) => write!(f, "Output '{path}' already exists (--no-overwrite)"),
+            ImcoError::VerifyFailed(path, hint) => write!(f, "Verification of '{path}' failed => {hint}"),
+            ImcoError::ChecksumMismatch(path) => write!(f, "'{path}' doesn't match its checksum"),
+            ImcoError::ChecksumManifest(path, hint) => write!(f, "Invalid checksum manifest '{path}' => {hint}"),
+            ImcoError::ThreadPool(err) => write!(f, "Failed to start worker threads => {err}"),
+            ImcoError::Archive(err, path) => write!(f, "Failed writing archive '{path}' => {err}"),
+            ImcoError::ArchiveRead(err, path) => write!(f, "Failed reading archive '{path}' => {err}"),
+            ImcoError::Download(url, err) => write!(f, "Failed downloading '{url}' => {err}"),
+            ImcoError::InvalidCrop(crop) => write!(f, "Invalid crop '{crop}', expected X,Y,WxH"),
+            ImcoError::InvalidRawInput(raw) => write!(f, "Invalid raw input '{raw}', expected WxH:LAYOUT with a layout of rgba8, bgra8 or gray8"),
+            ImcoError::InvalidAspect(aspect) => write!(f, "Invalid aspect ratio '{aspect}', expected W:H like 16:9"),
+            ImcoError::InvalidColor(color) => write!(f, "Invalid color '{color}', expected #RRGGBB or #RGB"),
+            ImcoError::InvalidUnsharp(mask) => write!(f, "Invalid unsharp mask '{mask}', expected AMOUNT,RADIUS[,THRESHOLD]"),
+            ImcoError::InvalidDenoise(denoise) => write!(f, "Invalid denoise '{denoise}', expected STRENGTH[:median|bilateral] with a strength above 0"),
+            ImcoError::InvalidTint(tint) => write!(f, "Invalid tint '{tint}', expected COLOR[:STRENGTH] with a strength from 0 to 1"),
+            ImcoError::InvalidVignette(vignette) => write!(f, "Invalid vignette '{vignette}', expected STRENGTH[,RADIUS] with a strength from 0 to 1 and a radius below 1"),
+            ImcoError::InvalidBorder(border) => write!(f, "Invalid border '{border}', expected WIDTH[:COLOR]"),
+            ImcoError::InvalidSetting(setting, hint) => write!(f, "Invalid encoder setting '{setting}' => {hint}"),
+            ImcoError::InvalidExifEdit(edit, hint) => write!(f, "Invalid exif edit '{edit}' => {hint}"),
+            ImcoError::InvalidSize(size) => write!(f, "Invalid size '{size}', expected a number of bytes like 200KB, 1.5MB or 150000"),
+            ImcoError::InvalidScale(scale) => write!(f, "Invalid scale '{scale}', expected a percentage like 50% or a factor like 0.5"),
+            ImcoError::CropOutside(path) => write!(f, "Crop area lies outside of '{path}'"),
+            ImcoError::InvalidPages(pages) => write!(f, "Invalid page selection '{pages}', expected all, N or FIRST-LAST"),
+            ImcoError::SliceTooSmall(path, rows, columns) => write!(f, "'{path}' is too small to be sliced into {rows}x{columns} cells"),
+            ImcoError::PageOutOfRange(path, first, count) => write!(f, "'{path}' has {count} page(s), there is no page {first}"),
+            ImcoError::Lossy(path, step) => write!(f, "Converting '{path}' would lose information, {step} (--lossless-only)"),
+            ImcoError::Session(path, reason) => write!(f, "Can't resume the session '{path}', {reason}"),
+            ImcoError::NoSpace(dir, needed, free) => write!(f, "The outputs need about {} but '{dir}' only has {} free (see --space-ratio and --no-space-check)", size_name(*needed), size_name(*free)),
+            ImcoError::Downgraded(path, downgrade) => write!(f, "Converting '{path}' would downgrade it, {downgrade} (--strict)"),
+            ImcoError::TimedOut(path, secs) => write!(f, "Converting '{path}' took longer than {secs} s (--timeout)"),
+            ImcoError::DuplicateOutput(output, first, second) => write!(f, "'{first}' and '{second}' would both be written to '{output}', give each input its own output or use --uniquify"),
+            ImcoError::DepthLost(path, bits, fmt) => write!(f, "'{path}' has {} samples but {fmt} only holds 8 bit ones (--preserve-depth)", depth_name(*bits)),
+            ImcoError::ExtractToStdout => write!(f, "Frames can't be extracted to stdout, use an output like out_%03d.png"),
+            ImcoError::FormatsToStdout => write!(f, "Several formats can't be written to stdout, give an output file"),
+            ImcoError::ConfirmFromStdin => write!(f, "--confirm reads the answers from stdin, which already holds an input"),
+            ImcoError::Declined => write!(f, "Nothing was converted, the plan was declined"),
+            ImcoError::SeveralFormats(option) => write!(f, "--{option} only writes one format, give a single output format"),
+            ImcoError::NoCombinedOutput => write!(f, "No output file to combine the inputs into provided"),
+            ImcoError::EmptyMontage => write!(f, "None of the inputs could be put onto the contact sheet"),
+            ImcoError::EmptyConcat => write!(f, "None of the inputs could be joined"),
+            ImcoError::EmptySpriteSheet => write!(f, "None of the inputs could be packed into the sprite sheet"),
+            ImcoError::NoChannels => write!(f, "No channels to merge, give at least one of red, green, blue, luma and alpha"),
+            ImcoError::TargetSize(path, target) => write!(f, "'{path}' doesn't fit into {target} bytes even at quality 1, resize it first"),
+            ImcoError::TileName(path) => write!(f, "Can't tell the row and column of tile '{path}', expected a name like map_ROW_COLUMN.png"),
+            ImcoError::MissingTile(row, column) => write!(f, "Tile at row {row}, column {column} is missing"),
+            ImcoError::Watch(err) => write!(f, "Failed watching for changes => {err}"),
+            ImcoError::Clipboard(err) => write!(f, "Failed using the clipboard => {err}"),
+            ImcoError::Gpu(err) => write!(f, "Can't use the GPU => {err}"),
+            ImcoError::Serve(err, addr) => write!(f, "Failed to serve on '{addr}' => {err}"),
+            ImcoError::Tui(err) => write!(f, "Failed running the terminal interface => {err}"),
+            ImcoError::CompareSize(a, b) => write!(f, "Can't compare '{a}' and '{b}', their dimensions differ"),
+            ImcoError::ChannelSize(a, b) => write!(f, "Can't merge the channels '{a}' and '{b}', their dimensions differ"),
+            ImcoError::StackSize(path, first) => write!(f, "Can't stack '{path}' onto '{first}', their dimensions differ"),
+            ImcoError::EmptyStack => write!(f, "None of the inputs could be stacked"),
+            ImcoError::Config(path, hint) => write!(f, "Invalid config '{path}' => {hint}"),
+            ImcoError::JobsFile(path, hint) => write!(f, "Invalid jobs file '{path}' => {hint}"),
+            ImcoError::InvalidJob(hint) => write!(f, "Invalid job => {hint}"),
+            ImcoError::InvalidLut(path, hint) => write!(f, "Invalid LUT '{path}' => {hint}"),
+            ImcoError::InvalidFont(path) => write!(f, "Invalid font '{path}' => not a TrueType or OpenType font"),
+            ImcoError::InvalidOperation(op, hint) => write!(f, "Invalid operation '{op}' => {hint}"),
+            ImcoError::OperationFailed(path, op, message) => write!(f, "Operation '{op}' failed on '{path}' => {message}"),
+            ImcoError::Plugin(path, reason) => write!(f, "Can't load the plugin '{path}' => {reason}"),
+            ImcoError::UnknownPreset(name) => write!(f, "No preset named '{name}' in ./imco.toml or ~/.config/imco/imco.toml"),
+            ImcoError::CantOptimize(path) => write!(f, "'{path}' is neither a png nor a jpeg, only those can be optimized"),
+            ImcoError::InvalidResolution(res) => write!(f, "Invalid resolution '{res}', expected WxH, Wx or xH with positive numbers"),
+        }
+    }
+}
+
+impl std::error::Error for ImcoError {}
+
+impl ImcoError {
+    /// Whether reading or writing failed in a way that may work when tried again, like a timed out network share
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ImcoError::FailedFileRead(reason, _) | ImcoError::FailedFileWrite(reason, _) => TRANSIENT_ERRORS.iter().any(|(_, transient)| reason == transient),
+            _ => false
+        }
+    }
+}
+
+// I/O failures of network shares and cloud-mounted drives that --retries tries again
+const TRANSIENT_ERRORS: [(ErrorKind, &str); 8] = [
+    (ErrorKind::Interrupted, "Interrupted"),
+    (ErrorKind::TimedOut, "Timed out"),
+    (ErrorKind::WouldBlock, "Temporarily unavailable"),
+    (ErrorKind::ResourceBusy, "Resource busy"),
+    (ErrorKind::StaleNetworkFileHandle, "Stale network file handle"),
+    (ErrorKind::ConnectionReset, "Connection reset"),
+    (ErrorKind::ConnectionAborted, "Connection aborted"),
+    (ErrorKind::NetworkDown, "Network down"),
+];
+
+pub type ImcoResult<T> = Result<T, ImcoError>;
+
+type ImReader = ImageReader<BufReader<File>>;
+
+/// A finished conversion
+pub struct Conversion {
+    /// Path written to, "-" for stdout
+    pub output: PathBuf,
+    /// Detected format of the input, if known
+    pub input_format: Option<Format>,
+    pub output_format: Format,
+    /// Size of the output, if it was written
+    pub output_bytes: Option<u64>,
+    /// The output already existed and was left alone
+    pub skipped: bool,
+    /// Transparent pixels were composited onto white, as the output format has no alpha
+    /// channel and no [`Options::background`] was given
+    pub flattened: bool,
+    /// Outputs written for [`Options::srcset`] with their widths, in ascending order
+    pub srcset: Vec<(PathBuf, u32)>,
+    pub placeholders: Placeholders,
+    /// With [`Symlinks::Recreate`], the output of the link's target the output links to instead of being converted
+    pub symlink: Option<PathBuf>,
+    pub timings: Timings,
+    /// Outputs in the further [`Options::formats`], encoded from the same image
+    pub variants: Vec<Conversion>,
+    /// Pages of a multi-page TIFF after the first, which were left out as no [`Options::pages`] were selected
+    pub ignored_pages: usize,
+    /// Bits per channel the output was written with, 32 for float samples
+    pub depth: Option<u8>,
+    /// What the output lost against the input without an option asking for it, see [`Options::strict`]
+    pub downgrades: Vec<Downgrade>,
+}
+
+impl Conversion {
+    /// An output in output_format and nothing else known about it, the other fields being filled in with struct update syntax
+    pub fn new(output: impl Into<PathBuf>, output_format: Format) -> Self {
+        Conversion {
+            output: output.into(), input_format: None, output_format, output_bytes: None, skipped: false, flattened: false, srcset: Vec::new(),
+            placeholders: Placeholders::default(), symlink: None, timings: Timings::default(), variants: Vec::new(), ignored_pages: 0,
+            depth: None, downgrades: Vec::new(),
+        }
+    }
+}
+
+/// A way an output holds less than its input that no option asked for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Downgrade {
+    /// Transparency was composited onto white, as the output format has no alpha channel
+    AlphaDropped,
+    /// Bits per channel of the input and of the output
+    DepthReduced(u8, u8),
+    /// Only the first of this many frames was written
+    AnimationFlattened(usize),
+    /// The color profile was left out, as the output format can't embed one (sRGB ones don't count)
+    ProfileDiscarded,
+    /// The pixel density (DPI) of the input, which outputs aren't written with
+    ResolutionLost,
+}
+
+impl Downgrade {
+    /// The name of the downgrade in reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            Downgrade::AlphaDropped => "alpha",
+            Downgrade::DepthReduced(..) => "depth",
+            Downgrade::AnimationFlattened(_) => "animation",
+            Downgrade::ProfileDiscarded => "profile",
+            Downgrade::ResolutionLost => "resolution",
+        }
+    }
+}
+
+impl fmt::Display for Downgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Downgrade::AlphaDropped => write!(f, "the format has no alpha channel, transparency was put onto white (see --background)"),
+            Downgrade::DepthReduced(before, after) => write!(f, "its {} samples were reduced to {} (see --depth)", depth_name(*before), depth_name(*after)),
+            Downgrade::AnimationFlattened(frames) => write!(f, "only the first of its {frames} frames was written (see --first-frame-only)"),
+            Downgrade::ProfileDiscarded => write!(f, "the format can't embed its color profile, which was left out (see --convert-to-srgb)"),
+            Downgrade::ResolutionLost => write!(f, "its pixel density (dpi) was left out"),
+        }
+    }
+}
+
+/// Placeholder hashes of the converted image, with [`Options::blurhash`] and [`Options::thumbhash`]
+#[derive(Default)]
+pub struct Placeholders {
+    pub blurhash: Option<String>,
+    /// Base64 encoded
+    pub thumbhash: Option<String>,
+}
+
+impl Placeholders {
+    fn of(image: &image::DynamicImage, opts: &Options) -> Self {
+        // Both only keep the coarse colors, so small copies are hashed
+        let blurhash = opts.blurhash.then(|| {
+            let small = image.thumbnail(64, 64).to_rgba8();
+            let (x, y) = if small.width() >= small.height() { (4, 3) } else { (3, 4) };
+            blurhash::encode(x, y, small.width(), small.height(), small.as_raw()).ok()
+        }).flatten();
+        let thumbhash = opts.thumbhash.then(|| {
+            let small = image.thumbnail(100, 100).to_rgba8();
+            base64(&thumbhash::rgba_to_thumb_hash(small.width() as usize, small.height() as usize, small.as_raw()))
+        });
+        Placeholders { blurhash, thumbhash }
+    }
+
+    // Written to <output>.json with Options::hash_sidecar
+    fn write_sidecar(&self, output: &Path, sources: &[&Path], opts: &Options) -> ImcoResult<()> {
+        if !opts.hash_sidecar || output.as_os_str() == STDIO || (self.blurhash.is_none() && self.thumbhash.is_none()) { return Ok(()) }
+        let json = serde_json::json!({ "blurhash": self.blurhash, "thumbhash": self.thumbhash });
+        write_encoded(json.to_string().as_bytes(), with_suffix(output, ".json"), sources, opts)?;
+        Ok(())
+    }
+}
+
+/// Time a [`Conversion`] spent in each stage, zero for stages it skipped
+#[derive(Clone, Copy, Default)]
+pub struct Timings {
+    pub decode: std::time::Duration,
+    /// Everything between decoding and encoding, see [`Options`]
+    pub transform: std::time::Duration,
+    /// Encoding and writing the output
+    pub encode: std::time::Duration,
+}
+
+// Runs f, adding the time it took to timer
+fn timed<T>(timer: &mut std::time::Duration, f: impl FnOnce() -> T) -> T {
+    let start = std::time::Instant::now();
+    let result = f();
+    *timer += start.elapsed();
+    result
+}
+
+/// What happened to one input of [`Converter::run`]
+pub struct FileOutcome {
+    pub input: PathBuf,
+    pub input_bytes: Option<u64>,
+    pub duration: std::time::Duration,
+    pub result: ImcoResult<Conversion>,
+}
+
+// Runs attempt again after the retry delay while it fails transiently, up to the retries of opts.
+// Stdin and stdout can't be read or written twice
+fn retrying<T>(path: &Path, opts: &Options, mut attempt: impl FnMut() -> ImcoResult<T>) -> ImcoResult<T> {
+    let mut retried = 0;
+    loop {
+        check_abandoned(path)?;
+        match attempt() {
+            Err(e) if e.is_transient() && retried < opts.retries && path != STDIO => {
+                retried += 1;
+                std::thread::sleep(opts.retry_delay);
+            }
+            res => return res
+        }
+    }
+}
+
+fn file_size(path: impl AsRef<Path>) -> Option<u64> {
+    std::fs::metadata(path).ok().filter(|m| m.is_file()).map(|m| m.len())
+}
+
+fn io_error_convert<T>(res: Result<T, std::io::Error>, file_path: impl AsRef<Path>, is_read: bool) -> Result<T, ImcoError> {
+    res.map_err(|x| {
+        let transient = TRANSIENT_ERRORS.iter().find(|(kind, _)| *kind == x.kind());
+        let reason = if let Some((_, reason)) = transient { reason } else { match x.kind() {
+            ErrorKind::NotFound => {"Not found"}
+            ErrorKind::PermissionDenied => {"Permission denied"}
+            ErrorKind::AlreadyExists => {"Already exists"}
+            ErrorKind::NotADirectory => {"Is not a directory"}
+            ErrorKind::IsADirectory => {"Is a directory"}
+            ErrorKind::StorageFull => {"Storage is full"}
+            ErrorKind::FileTooLarge => {"File is too large"}
+            ErrorKind::UnexpectedEof => {"Unexpected end of file, it is truncated"}
+            _ => {"Unknown (unhandled)"}
+        }}.to_string();
+        if is_read {
+            ImcoError::FailedFileRead(reason, file_path.as_ref().display().to_string())
+        } else {
+            ImcoError::FailedFileWrite(reason, file_path.as_ref().display().to_string())
+        }
+    })
+}
+
+fn imread(path: impl AsRef<Path>) -> ImcoResult<ImReader> {
+    let path = path.as_ref();
+    if path.is_dir() { return Err(ImcoError::InputIsDirectory(path.display().to_string())) }
+    io_error_convert::<ImReader>(ImageReader::open(path), path, true)
+}
+
+/// Formats of inputs and outputs, JPEG XL isn't handled by image
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Format {
+    Image(ImageFormat),
+    Jxl,
+    /// Output only, one page per image
+    Pdf,
+    /// Output only, a (block compressed) texture
+    Ktx2,
+    /// Bare pixels in [`Options::raw_layout`], read with [`Options::raw_input`]
+    Raw,
+}
+
+impl Format {
+    /// The extension generated outputs get
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Image(fmt) => fmt.extensions_str()[0],
+            Format::Jxl => "jxl",
+            Format::Pdf => "pdf",
+            Format::Ktx2 => "ktx2",
+            Format::Raw => "raw",
+        }
+    }
+
+    /// Most bits per channel the format holds, 32 for float samples
+    pub fn max_depth(self) -> u8 {
+        match self {
+            Format::Image(ImageFormat::OpenExr | ImageFormat::Hdr | ImageFormat::Tiff) => 32,
+            Format::Image(ImageFormat::Png | ImageFormat::Pnm | ImageFormat::Farbfeld) | Format::Jxl => 16,
+            _ => 8,
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Format::Image(fmt) => fmt.to_mime_type(),
+            Format::Jxl => "image/jxl",
+            Format::Pdf => "application/pdf",
+            Format::Ktx2 => "image/ktx2",
+            Format::Raw => "application/octet-stream",
+        }
+    }
+}
+
+/// Accepted format names (lowercase), the first one is used when listing
+pub const FORMAT_NAMES: &[(Format, &[&str])] = &[
+    (Format::Image(ImageFormat::Avif), &["avif"]),
+    (Format::Image(ImageFormat::Bmp), &["bmp", "bitmap"]),
+    (Format::Image(ImageFormat::Dds), &["dds"]),
+    (Format::Image(ImageFormat::Farbfeld), &["ff", "farbfeld"]),
+    (Format::Image(ImageFormat::Gif), &["gif"]),
+    (Format::Image(ImageFormat::Hdr), &["hdr", "radiance"]),
+    (Format::Image(ImageFormat::Ico), &["ico", "icon"]),
+    (Format::Image(ImageFormat::Jpeg), &["jpg", "jpeg", "jfif"]),
+    (Format::Ktx2, &["ktx2"]),
+    (Format::Jxl, &["jxl", "jpegxl"]),
+    (Format::Image(ImageFormat::OpenExr), &["exr", "openexr"]),
+    (Format::Pdf, &["pdf"]),
+    (Format::Image(ImageFormat::Png), &["png", "apng"]),
+    (Format::Image(ImageFormat::Pnm), &["pnm", "ppm", "pgm", "pbm", "pam"]),
+    (Format::Image(ImageFormat::Qoi), &["qoi"]),
+    (Format::Raw, &["raw"]),
+    (Format::Image(ImageFormat::Tga), &["tga", "targa"]),
+    (Format::Image(ImageFormat::Tiff), &["tif", "tiff"]),
+    (Format::Image(ImageFormat::WebP), &["webp"]),
+];
+
+/// "-" stands for stdin / stdout
+pub const STDIO: &str = "-";
+
+/// Stands for the system clipboard as input or output
+pub const CLIPBOARD: &str = "@clipboard";
+
+// Metadata carried over from the input into the output
+#[derive(Clone, Default)]
+struct Metadata {
+    exif: Option<Vec<u8>>,
+    icc: Option<Vec<u8>>,
+    orientation: Option<image::metadata::Orientation>,
+}
+
+/// Bounds on decoding inputs and on the canvases operations like borders grow them to, protecting against
+/// decompression bombs. None is unlimited
+#[derive(Clone, Copy)]
+pub struct DecodeLimits {
+    /// Width times height
+    pub max_pixels: Option<u64>,
+    /// Bytes a decoder may allocate
+    pub max_memory: Option<u64>,
+}
+
+// The limits of image
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits { max_pixels: None, max_memory: Some(512 * 1024 * 1024) }
+    }
+}
+
+impl DecodeLimits {
+    pub fn unlimited() -> Self {
+        DecodeLimits { max_pixels: None, max_memory: None }
+    }
+
+    fn image_limits(&self) -> image::Limits {
+        let mut limits = image::Limits::no_limits();
+        limits.max_alloc = self.max_memory;
+        limits
+    }
+
+    fn check(&self, width: u32, height: u32, path: &Path) -> ImcoResult<()> {
+        match self.max_pixels {
+            Some(max) if width as u64 * height as u64 > max => Err(ImcoError::ResourceLimitReached(path.display().to_string())),
+            _ => Ok(())
+        }
+    }
+
+    // Checks the size and decoded bytes up front, then hands the limits to the decoder for its own allocations
+    fn apply(&self, decoder: &mut dyn ImageDecoder, path: &Path) -> ImcoResult<()> {
+        let (width, height) = decoder.dimensions();
+        self.check(width, height, path)?;
+        if self.max_memory.is_some_and(|max| decoder.total_bytes() > max) {
+            return Err(ImcoError::ResourceLimitReached(path.display().to_string()))
+        }
+        image_err_convert(decoder.set_limits(self.image_limits()), path)
+    }
+
+    // Operations growing the image check the result the same way before allocating it, sides beyond u32 never fit
+    fn check_canvas(&self, (width, height): (u64, u64), color: image::ColorType, path: &Path) -> ImcoResult<()> {
+        let pixels = width.saturating_mul(height);
+        if width > u32::MAX as u64 || height > u32::MAX as u64 || self.max_pixels.is_some_and(|max| pixels > max)
+            || self.max_memory.is_some_and(|max| pixels.saturating_mul(color.bytes_per_pixel() as u64) > max) {
+            return Err(ImcoError::ResourceLimitReached(path.display().to_string()))
+        }
+        Ok(())
+    }
+}
+
+/// Which inputs are converted at all, by their file size, dimensions and the format of their content.
+/// None and empty accept everything
+#[derive(Default)]
+pub struct InputFilter {
+    pub min_width: Option<u32>,
+    pub max_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub max_height: Option<u32>,
+    pub min_bytes: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub formats: Vec<Format>,
+}
+
+impl InputFilter {
+    pub fn is_empty(&self) -> bool {
+        self.min_width.is_none() && self.max_width.is_none() && self.min_height.is_none() && self.max_height.is_none()
+            && self.min_bytes.is_none() && self.max_bytes.is_none() && self.formats.is_empty()
+    }
+
+    /// Only reads the header, unless the format's decoder can't tell the dimensions on its own. Inputs whose
+    /// header can't be read don't pass the format and dimension bounds
+    pub fn accepts(&self, path: &Path) -> bool {
+        if path == STDIO || path == CLIPBOARD { return true }
+        fn within<T: PartialOrd>(value: T, min: Option<T>, max: Option<T>) -> bool {
+            min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+        }
+        if let Some(bytes) = file_size(path) {
+            if !within(bytes, self.min_bytes, self.max_bytes) { return false }
+        }
+        let needs_header = !self.formats.is_empty() || self.min_width.is_some() || self.max_width.is_some() || self.min_height.is_some() || self.max_height.is_some();
+        if !needs_header { return true }
+        match probe(path) {
+            Ok((format, width, height)) => {
+                within(width, self.min_width, self.max_width) && within(height, self.min_height, self.max_height)
+                    && (self.formats.is_empty() || format.is_some_and(|format| self.formats.contains(&format)))
+            }
+            Err(_) => false
+        }
+    }
+}
+
+/// Which of the matched inputs are converted, to try settings on a few of them before a long run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sample {
+    /// This many inputs picked at random
+    Count(usize),
+    /// This percentage of the inputs picked at random, at least one
+    Percent(f64),
+    /// Every nth input, starting with the first
+    Every(usize),
+}
+
+impl Sample {
+    /// The positions of the inputs sampled from len of them in ascending order, the random picks only
+    /// depending on the seed
+    pub fn pick(&self, len: usize, seed: u64) -> Vec<usize> {
+        let count = match *self {
+            Sample::Count(count) => count.min(len),
+            Sample::Percent(percent) => ((len as f64 * percent / 100.0).ceil() as usize).clamp(len.min(1), len),
+            Sample::Every(step) => return (0..len).step_by(step.max(1)).collect(),
+        };
+        // The first count positions of a Fisher-Yates shuffle driven by splitmix64
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            z ^ (z >> 31)
+        };
+        let mut positions: Vec<usize> = (0..len).collect();
+        for i in 0..count {
+            let j = i + (next() % (len - i) as u64) as usize;
+            positions.swap(i, j);
+        }
+        positions.truncate(count);
+        positions.sort_unstable();
+        positions
+    }
+}
+
+// Format (by content) and dimensions of an input
+fn probe(path: impl AsRef<Path>) -> ImcoResult<(Option<Format>, u32, u32)> {
+    let path = path.as_ref();
+    register_decoding_hooks();
+    if is_svg_path(path) || is_raw_path(path) || starts_like_jxl(path) || starts_like_bc7_dds(path) {
+        let (image, _, format) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+        return Ok((format, image.width(), image.height()))
+    }
+    let reader = io_error_convert(imread(path)?.with_guessed_format(), path, true)?;
+    let format = reader.format().map(Format::Image);
+    let (width, height) = image_err_convert(reader.into_dimensions(), path)?;
+    Ok((format, width, height))
+}
+
+// Converts the pixels from the embedded profile to sRGB, leaving them untagged (meaning sRGB).
+// Profiles qcms can't read or apply are kept as they are
+fn to_srgb(image: image::DynamicImage, meta: &mut Metadata) -> image::DynamicImage {
+    let Some(profile) = meta.icc.as_deref().and_then(|icc| qcms::Profile::new_from_slice(icc, false)) else { return image };
+    if profile.is_sRGB() {
+        meta.icc = None;
+        return image
+    }
+    let mut srgb = qcms::Profile::new_sRGB();
+    srgb.precache_output_transform();
+    let intent = qcms::Intent::default();
+    let gray = !image.color().has_color();
+    let converted = match (gray, image.color().has_alpha()) {
+        (false, false) => qcms::Transform::new(&profile, &srgb, qcms::DataType::RGB8, intent).map(|transform| {
+            let mut rgb = image.to_rgb8();
+            transform.apply(&mut rgb);
+            image::DynamicImage::ImageRgb8(rgb)
+        }),
+        (false, true) => qcms::Transform::new(&profile, &srgb, qcms::DataType::RGBA8, intent).map(|transform| {
+            let mut rgba = image.to_rgba8();
+            transform.apply(&mut rgba);
+            image::DynamicImage::ImageRgba8(rgba)
+        }),
+        (true, _) => qcms::Transform::new_to(&profile, &srgb, qcms::DataType::GrayA8, qcms::DataType::RGBA8, intent).map(|transform| {
+            let gray = image.to_luma_alpha8();
+            let mut rgba = image::RgbaImage::new(gray.width(), gray.height());
+            transform.convert(&gray, &mut rgba);
+            image::DynamicImage::ImageRgba8(rgba)
+        }),
+    };
+    match converted {
+        Some(converted) => {
+            meta.icc = None;
+            converted
+        }
+        None => image
+    }
+}
+
+fn decode_reader<R: std::io::BufRead + Seek>(mut raw_image: ImageReader<R>, path: &Path, limits: &DecodeLimits) -> ImcoResult<(image::DynamicImage, Metadata)> {
+    raw_image.limits(limits.image_limits());
+    let mut decoder = image_err_convert(raw_image.into_decoder(), path)?;
+    limits.apply(&mut decoder, path)?;
+    // Broken metadata shouldn't stop the conversion
+    let meta = Metadata {
+        exif: decoder.exif_metadata().ok().flatten(),
+        icc: decoder.icc_profile().ok().flatten(),
+        orientation: decoder.orientation().ok(),
+    };
+    Ok((image_err_convert(image::DynamicImage::from_decoder(decoder), path)?, meta))
+}
+
+/// Resolution svg inputs are rasterized at
+#[cfg_attr(not(feature = "svg"), allow(dead_code))]
+pub struct Rasterize {
+    pub dpi: f32,
+    /// Overrides the dpi, a missing side keeps the aspect ratio
+    pub size: Option<(Option<u32>, Option<u32>)>,
+}
+
+impl Default for Rasterize {
+    fn default() -> Self {
+        Rasterize { dpi: 96.0, size: None }
+    }
+}
+
+fn is_svg_path(path: impl AsRef<Path>) -> bool {
+    path.as_ref().extension().and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("svg") || e.eq_ignore_ascii_case("svgz"))
+}
+
+// Camera raw extensions rawloader can develop
+const RAW_EXTENSIONS: &[&str] = &["3fr", "arw", "cr2", "crw", "dcr", "dng", "erf", "kdc", "mef", "mos", "mrw", "nef", "nrw", "orf", "pef", "raf", "rw2", "srw"];
+
+fn is_raw_path(path: impl AsRef<Path>) -> bool {
+    cfg!(feature = "raw") && path.as_ref().extension().and_then(|e| e.to_str())
+        .is_some_and(|e| RAW_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+#[cfg(feature = "raw")]
+fn decode_raw(path: &Path) -> ImcoResult<image::DynamicImage> {
+    let fail = |e: String| ImcoError::Decoding(path.display().to_string(), e);
+    let mut pipeline = imagepipe::Pipeline::new_from_file(path).map_err(fail)?;
+    let developed = pipeline.output_16bit(None).map_err(fail)?;
+    image::ImageBuffer::from_raw(developed.width as u32, developed.height as u32, developed.data)
+        .map(image::DynamicImage::ImageRgb16)
+        .ok_or_else(|| fail("developed image doesn't match its size".to_string()))
+}
+
+#[cfg(not(feature = "raw"))]
+fn decode_raw(path: &Path) -> ImcoResult<image::DynamicImage> {
+    Err(ImcoError::Unsupported(path.display().to_string(), "imco was built without raw support".to_string()))
+}
+
+#[cfg(feature = "clipboard")]
+fn read_clipboard() -> ImcoResult<image::DynamicImage> {
+    let data = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_image()).map_err(|e| ImcoError::Clipboard(e.to_string()))?;
+    image::RgbaImage::from_raw(data.width as u32, data.height as u32, data.bytes.into_owned())
+        .map(image::DynamicImage::ImageRgba8)
+        .ok_or_else(|| ImcoError::Clipboard("the image doesn't match its size".to_string()))
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn read_clipboard() -> ImcoResult<image::DynamicImage> {
+    Err(ImcoError::Clipboard("imco was built without clipboard support".to_string()))
+}
+
+#[cfg(feature = "clipboard")]
+fn write_clipboard(image: &image::DynamicImage) -> ImcoResult<()> {
+    let rgba = image.to_rgba8();
+    let data = arboard::ImageData { width: rgba.width() as usize, height: rgba.height() as usize, bytes: rgba.into_raw().into() };
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| ImcoError::Clipboard(e.to_string()))?;
+    // X11 and Wayland clipboards are served by the program that set them, so this waits until something else is copied
+    #[cfg(target_os = "linux")]
+    let set = { use arboard::SetExtLinux; clipboard.set().wait().image(data) };
+    #[cfg(not(target_os = "linux"))]
+    let set = clipboard.set_image(data);
+    set.map_err(|e| ImcoError::Clipboard(e.to_string()))
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn write_clipboard(_image: &image::DynamicImage) -> ImcoResult<()> {
+    Err(ImcoError::Clipboard("imco was built without clipboard support".to_string()))
+}
+
+fn is_heif_path(path: impl AsRef<Path>) -> bool {
+    cfg!(feature = "heif") && path.as_ref().extension().and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("heic") || e.eq_ignore_ascii_case("heif"))
+}
+
+/// How the pixels of [`Format::Raw`] follow each other, in rows from top to bottom without padding
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RawLayout {
+    #[default]
+    Rgba8,
+    Bgra8,
+    Gray8,
+}
+
+impl RawLayout {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "rgba8" => Some(RawLayout::Rgba8),
+            "bgra8" => Some(RawLayout::Bgra8),
+            "gray8" => Some(RawLayout::Gray8),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RawLayout::Rgba8 => "rgba8",
+            RawLayout::Bgra8 => "bgra8",
+            RawLayout::Gray8 => "gray8",
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        if self == RawLayout::Gray8 { 1 } else { 4 }
+    }
+
+    fn pixels(self, image: &image::DynamicImage) -> Vec<u8> {
+        match self {
+            RawLayout::Rgba8 => image.to_rgba8().into_raw(),
+            RawLayout::Bgra8 => {
+                let mut pixels = image.to_rgba8().into_raw();
+                pixels.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+                pixels
+            }
+            RawLayout::Gray8 => image.to_luma8().into_raw(),
+        }
+    }
+
+    fn image(self, width: u32, height: u32, mut pixels: Vec<u8>) -> Option<image::DynamicImage> {
+        match self {
+            RawLayout::Rgba8 => image::RgbaImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageRgba8),
+            RawLayout::Bgra8 => {
+                pixels.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+                image::RgbaImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageRgba8)
+            }
+            RawLayout::Gray8 => image::GrayImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageLuma8),
+        }
+    }
+}
+
+/// The size and layout of bare pixel inputs, see [`Options::raw_input`]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RawInput {
+    pub width: u32,
+    pub height: u32,
+    pub layout: RawLayout,
+}
+
+impl RawInput {
+    // Inputs may start with the header of Options::raw_header, which is skipped
+    fn decode(&self, data: Vec<u8>, path: &Path, limits: &DecodeLimits) -> ImcoResult<image::DynamicImage> {
+        limits.check(self.width, self.height, path)?;
+        let expected = self.width as usize * self.height as usize * self.layout.bytes_per_pixel();
+        let start = match data.iter().position(|b| *b == b'\n') {
+            Some(end) if data.starts_with(b"{") && data.len() - end - 1 == expected => end + 1,
+            _ => 0,
+        };
+        if data.len() - start != expected {
+            return Err(ImcoError::Decoding(path.display().to_string(), format!("{} bytes of pixels don't make a {}x{} {} image of {expected} bytes",
+                data.len() - start, self.width, self.height, self.layout.name())))
+        }
+        let pixels = if start == 0 { data } else { data[start..].to_vec() };
+        Ok(self.layout.image(self.width, self.height, pixels).expect("the length was checked"))
+    }
+}
+
+/// Parses WxH:LAYOUT like 640x480:rgba8
+pub fn parse_raw_input(s: &str) -> ImcoResult<RawInput> {
+    let invalid = || ImcoError::InvalidRawInput(s.to_string());
+    let (size, layout) = s.split_once(':').ok_or_else(invalid)?;
+    let layout = RawLayout::from_name(layout.trim()).ok_or_else(invalid)?;
+    match parse_resolution(size.trim()).map_err(|_| invalid())? {
+        (Some(width), Some(height)) => Ok(RawInput { width, height, layout }),
+        _ => Err(invalid())
+    }
+}
+
+// Bare pixels, with the header of Options::raw_header
+fn encode_raw<W: Write>(image: &image::DynamicImage, writer: &mut W, opts: &Options) -> std::io::Result<()> {
+    // Float samples are linear, the pixels sRGB like other 8 bit formats
+    let image = if matches!(image.color(), image::ColorType::Rgb32F | image::ColorType::Rgba32F) { tonemap(image.clone(), None, 0.0) } else { image.clone() };
+    if opts.raw_header {
+        let stride = image.width() as usize * opts.raw_layout.bytes_per_pixel();
+        let header = serde_json::json!({ "width": image.width(), "height": image.height(), "stride": stride, "layout": opts.raw_layout.name() });
+        writeln!(writer, "{header}")?;
+    }
+    writer.write_all(&opts.raw_layout.pixels(&image))
+}
+
+fn is_svg_data(data: &[u8]) -> bool {
+    let start = &data[data.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(0)..];
+    start.starts_with(b"<svg") || (start.starts_with(b"<?xml") && data.windows(4).any(|w| w == b"<svg"))
+}
+
+#[cfg(feature = "svg")]
+fn rasterize_svg(data: &[u8], path: &Path, raster: &Rasterize, limits: &DecodeLimits) -> ImcoResult<image::DynamicImage> {
+    use resvg::{tiny_skia, usvg};
+    // Loading the system fonts is slow, so it happens once
+    static FONTS: std::sync::OnceLock<std::sync::Arc<usvg::fontdb::Database>> = std::sync::OnceLock::new();
+    let fontdb = FONTS.get_or_init(|| {
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_system_fonts();
+        std::sync::Arc::new(fontdb)
+    }).clone();
+    let options = usvg::Options { fontdb, ..usvg::Options::default() };
+    let tree = usvg::Tree::from_data(data, &options).map_err(|e| ImcoError::Decoding(path.display().to_string(), e.to_string()))?;
+    let (width, height) = (tree.size().width(), tree.size().height());
+    let scale = |side: u32, of: f32| side as f32 / of;
+    let (sx, sy) = match raster.size {
+        Some((Some(w), Some(h))) => (scale(w, width), scale(h, height)),
+        Some((Some(w), None)) => (scale(w, width), scale(w, width)),
+        Some((None, Some(h))) => (scale(h, height), scale(h, height)),
+        _ => (raster.dpi / 96.0, raster.dpi / 96.0)
+    };
+    let (out_width, out_height) = ((width * sx).round().max(1.0) as u32, (height * sy).round().max(1.0) as u32);
+    limits.check(out_width, out_height, path)?;
+    let mut pixmap = tiny_skia::Pixmap::new(out_width, out_height).ok_or_else(|| ImcoError::ResourceLimitReached(path.display().to_string()))?;
+    resvg::render(&tree, tiny_skia::Transform::from_scale(sx, sy), &mut pixmap.as_mut());
+    let buffer = image::RgbaImage::from_raw(out_width, out_height, pixmap.take_demultiplied()).unwrap();
+    Ok(buffer.into())
+}
+
+#[cfg(not(feature = "svg"))]
+fn rasterize_svg(_data: &[u8], path: &Path, _raster: &Rasterize, _limits: &DecodeLimits) -> ImcoResult<image::DynamicImage> {
+    Err(ImcoError::Unsupported(path.display().to_string(), "imco was built without svg support".to_string()))
+}
+
+// Raw and jxl inputs are only checked against max_pixels once decoded
+fn decode_input(path: impl AsRef<Path>, i_fmt: Option<Format>, raster: &Rasterize, limits: &DecodeLimits) -> ImcoResult<(image::DynamicImage, Metadata, Option<Format>)> {
+    let path = path.as_ref();
+    if i_fmt.is_none() && is_svg_path(path) {
+        let data = io_error_convert(std::fs::read(path), path, true)?;
+        return Ok((rasterize_svg(&data, path, raster, limits)?, Metadata::default(), None))
+    }
+    if i_fmt.is_none() && is_raw_path(path) {
+        let image = decode_raw(path)?;
+        limits.check(image.width(), image.height(), path)?;
+        return Ok((image, Metadata::default(), None))
+    }
+    if path.as_os_str() == STDIO {
+        let mut data = vec![];
+        io_error_convert(std::io::stdin().lock().read_to_end(&mut data), path, true)?;
+        return decode_data(data, path, i_fmt, raster, limits)
+    }
+    if path.as_os_str() == CLIPBOARD {
+        let image = read_clipboard()?;
+        limits.check(image.width(), image.height(), path)?;
+        return Ok((image, Metadata::default(), None))
+    }
+    if path.is_dir() { return Err(ImcoError::InputIsDirectory(path.display().to_string())) }
+    if i_fmt == Some(Format::Raw) { return Err(raw_without_geometry(path)) }
+    // The content decides over the extension, which only remains for formats without a signature
+    if i_fmt == Some(Format::Jxl) || (i_fmt.is_none() && starts_like_jxl(path)) {
+        let (image, meta) = decode_jxl(BufReader::new(io_error_convert(File::open(path), path, true)?), path)?;
+        limits.check(image.width(), image.height(), path)?;
+        return Ok((image, meta, Some(Format::Jxl)))
+    }
+    if matches!(i_fmt, None | Some(Format::Image(ImageFormat::Dds))) && starts_like_bc7_dds(path) {
+        let data = io_error_convert(std::fs::read(path), path, true)?;
+        return Ok((decode_bc7_dds(&data, path, limits)?, Metadata::default(), Some(Format::Image(ImageFormat::Dds))))
+    }
+    let mut raw_image = imread(path)?;
+    let org_fmt = if let Some(Format::Image(i_fmt)) = i_fmt {
+        raw_image.set_format(i_fmt);
+        Some(i_fmt)
+    } else {
+        raw_image = io_error_convert(raw_image.with_guessed_format(), path, true)?;
+        raw_image.format()
+    };
+    let (image, meta) = decode_reader(raw_image, path, limits)?;
+    Ok((image, meta, org_fmt.map(Format::Image)))
+}
+
+fn raw_without_geometry(path: &Path) -> ImcoError {
+    ImcoError::Unsupported(path.display().to_string(), "bare pixels need their size and layout, given with --raw-input WxH:LAYOUT".to_string())
+}
+
+// Decodes an input like the options say, once for all of its jobs with a decode_cache
+fn decode_configured(path: &Path, opts: &Options) -> ImcoResult<(image::DynamicImage, Metadata, Option<Format>)> {
+    match &opts.decode_cache {
+        Some(cache) => cache.decode(path, || decode_uncached(path, opts)),
+        None => decode_uncached(path, opts),
+    }
+}
+
+// Decodes an input with the format, rasterization and limits of the options, bare pixels with their raw_input
+fn decode_uncached(path: &Path, opts: &Options) -> ImcoResult<(image::DynamicImage, Metadata, Option<Format>)> {
+    let Some(raw) = opts.raw_input else { return decode_input(path, opts.i_fmt, &opts.rasterize, &opts.limits) };
+    let mut data = vec![];
+    if path.as_os_str() == STDIO {
+        io_error_convert(std::io::stdin().lock().read_to_end(&mut data), path, true)?;
+    } else {
+        if path.is_dir() { return Err(ImcoError::InputIsDirectory(path.display().to_string())) }
+        data = io_error_convert(std::fs::read(path), path, true)?;
+    }
+    Ok((raw.decode(data, path, &opts.limits)?, Metadata::default(), Some(Format::Raw)))
+}
+
+fn starts_like_jxl(path: impl AsRef<Path>) -> bool {
+    let mut head = [0; 12];
+    File::open(path).and_then(|mut file| file.read_exact(&mut head)).is_ok() && is_jxl_data(&head)
+}
+
+// DDS textures in BC7 (DXGI format 98 or 99, declared in the DX10 header), which the image crate doesn't read
+fn is_bc7_dds(head: &[u8]) -> bool {
+    head.starts_with(b"DDS ") && head.get(84..88) == Some(b"DX10")
+        && head.get(128..132).is_some_and(|dxgi| matches!(u32::from_le_bytes(dxgi.try_into().unwrap()), 98 | 99))
+}
+
+fn starts_like_bc7_dds(path: impl AsRef<Path>) -> bool {
+    let mut head = [0; 132];
+    File::open(path).and_then(|mut file| file.read_exact(&mut head)).is_ok() && is_bc7_dds(&head)
+}
+
+// The first mip level of a BC7 DDS texture, its blocks padded to a multiple of 4 like dds_texture writes them
+#[cfg(feature = "textures")]
+fn decode_bc7_dds(data: &[u8], path: &Path, limits: &DecodeLimits) -> ImcoResult<image::DynamicImage> {
+    use block_compression::{decode::decompress_blocks_as_rgba8, BC7Settings, CompressionVariant};
+    let word = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+    let (height, width) = (word(12), word(16));
+    limits.check(width, height, path)?;
+    let (padded_width, padded_height) = (width.div_ceil(4) * 4, height.div_ceil(4) * 4);
+    let variant = CompressionVariant::BC7(BC7Settings::alpha_basic());
+    let blocks = data.get(148..).and_then(|blocks| blocks.get(..variant.blocks_byte_size(padded_width, padded_height)))
+        .ok_or_else(|| ImcoError::Decoding(path.display().to_string(), "the BC7 blocks are truncated".to_string()))?;
+    let mut pixels = vec![0; padded_width as usize * padded_height as usize * 4];
+    decompress_blocks_as_rgba8(variant, padded_width, padded_height, blocks, &mut pixels);
+    let padded = image::RgbaImage::from_raw(padded_width, padded_height, pixels).expect("the length fits the size");
+    Ok(image::DynamicImage::ImageRgba8(image::imageops::crop_imm(&padded, 0, 0, width, height).to_image()))
+}
+
+#[cfg(not(feature = "textures"))]
+fn decode_bc7_dds(_data: &[u8], path: &Path, _limits: &DecodeLimits) -> ImcoResult<image::DynamicImage> {
+    Err(ImcoError::Unsupported(path.display().to_string(), "imco was built without texture compression support".to_string()))
+}
+
+/// The format an input's content contradicts its extension with, None if they agree or either is unknown
+pub fn extension_mismatch(path: impl AsRef<Path>, found: Option<Format>) -> Option<Format> {
+    let named = mk_format_fp(path).ok()?;
+    let found = found?;
+    (named != found).then_some(found)
+}
+
+// Decodes an image held in memory, path only names it in errors
+fn decode_data(data: Vec<u8>, path: &Path, i_fmt: Option<Format>, raster: &Rasterize, limits: &DecodeLimits) -> ImcoResult<(image::DynamicImage, Metadata, Option<Format>)> {
+    if i_fmt.is_none() && is_svg_data(&data) {
+        return Ok((rasterize_svg(&data, path, raster, limits)?, Metadata::default(), None))
+    }
+    if i_fmt == Some(Format::Raw) { return Err(raw_without_geometry(path)) }
+    if i_fmt == Some(Format::Jxl) || (i_fmt.is_none() && is_jxl_data(&data)) {
+        let (image, meta) = decode_jxl(Cursor::new(data), path)?;
+        limits.check(image.width(), image.height(), path)?;
+        return Ok((image, meta, Some(Format::Jxl)))
+    }
+    if matches!(i_fmt, None | Some(Format::Image(ImageFormat::Dds))) && is_bc7_dds(&data) {
+        return Ok((decode_bc7_dds(&data, path, limits)?, Metadata::default(), Some(Format::Image(ImageFormat::Dds))))
+    }
+    let mut raw_image = ImageReader::new(Cursor::new(data));
+    let org_fmt = if let Some(Format::Image(i_fmt)) = i_fmt {
+        raw_image.set_format(i_fmt);
+        Some(i_fmt)
+    } else {
+        raw_image = io_error_convert(raw_image.with_guessed_format(), path, true)?;
+        raw_image.format()
+    };
+    let (image, meta) = decode_reader(raw_image, path, limits)?;
+    Ok((image, meta, org_fmt.map(Format::Image)))
+}
+
+// Bare codestream or ISOBMFF container signature
+fn is_jxl_data(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0x0A]) || data.starts_with(b"\0\0\0\x0CJXL \r\n\x87\n")
+}
+
+#[cfg(feature = "jxl")]
+fn decode_jxl<R: Read>(reader: R, path: &Path) -> ImcoResult<(image::DynamicImage, Metadata)> {
+    let mut decoder = image_err_convert(jxl_oxide::integration::JxlDecoder::new(reader), path)?;
+    let meta = Metadata { icc: decoder.icc_profile().ok().flatten(), ..Metadata::default() };
+    Ok((image_err_convert(image::DynamicImage::from_decoder(decoder), path)?, meta))
+}
+
+#[cfg(not(feature = "jxl"))]
+fn decode_jxl<R: Read>(_reader: R, path: &Path) -> ImcoResult<(image::DynamicImage, Metadata)> {
+    Err(ImcoError::Unsupported(path.display().to_string(), "imco was built without jxl support".to_string()))
+}
+
+// Lossless only, 8 or 16 bits per channel
+#[cfg(feature = "jxl")]
+fn encode_jxl<W: Write>(image: &image::DynamicImage, writer: &mut W) -> image::ImageResult<()> {
+    use zune_core::{bit_depth::BitDepth, colorspace::ColorSpace, options::EncoderOptions};
+    let sixteen_bit = image.color().bytes_per_pixel() / image.color().channel_count() > 1;
+    let (colorspace, alpha) = match (image.color().has_color(), image.color().has_alpha()) {
+        (false, false) => (ColorSpace::Luma, false),
+        (false, true) => (ColorSpace::LumaA, true),
+        (true, false) => (ColorSpace::RGB, false),
+        (true, true) => (ColorSpace::RGBA, true),
+    };
+    let data: Vec<u8> = match (sixteen_bit, colorspace) {
+        (false, ColorSpace::Luma) => image.to_luma8().into_raw(),
+        (false, ColorSpace::LumaA) => image.to_luma_alpha8().into_raw(),
+        (false, ColorSpace::RGB) => image.to_rgb8().into_raw(),
+        (false, _) => image.to_rgba8().into_raw(),
+        (true, _) => {
+            let samples = match (colorspace, alpha) {
+                (ColorSpace::Luma, _) => image.to_luma16().into_raw(),
+                (ColorSpace::LumaA, _) => image.to_luma_alpha16().into_raw(),
+                (ColorSpace::RGB, _) => image.to_rgb16().into_raw(),
+                _ => image.to_rgba16().into_raw(),
+            };
+            samples.iter().flat_map(|s| s.to_ne_bytes()).collect()
+        }
+    };
+    let depth = if sixteen_bit { BitDepth::Sixteen } else { BitDepth::Eight };
+    let options = EncoderOptions::new(image.width() as usize, image.height() as usize, colorspace, depth);
+    let mut encoded = Vec::new();
+    zune_jpegxl::JxlSimpleEncoder::new(&data, options).encode(&mut encoded)
+        .map_err(|e| ImageError::Encoding(image::error::EncodingError::new(image::error::ImageFormatHint::Name("jxl".to_string()), e.to_string())))?;
+    writer.write_all(&encoded).map_err(ImageError::IoError)
+}
+
+#[cfg(not(feature = "jxl"))]
+fn encode_jxl<W: Write>(_image: &image::DynamicImage, _writer: &mut W) -> image::ImageResult<()> {
+    Err(ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        image::error::ImageFormatHint::Name("jxl".to_string()),
+        UnsupportedErrorKind::GenericFeature("imco was built without jxl support".to_string()))))
+}
+
+/// Looks up a format by one of its [`FORMAT_NAMES`], ignoring case
+pub fn mk_format(f: &str) -> ImcoResult<Format> {
+    let name = f.trim_start_matches('.').to_lowercase();
+    FORMAT_NAMES.iter()
+        .find(|(_, names)| names.contains(&name.as_str()))
+        .map(|(fmt, _)| *fmt)
+        .or_else(|| ImageFormat::from_extension(&name).map(Format::Image))
+        .ok_or(ImcoError::InvalidFormat(f.to_owned()))
+}
+
+/// The format a path's extension names
+pub fn mk_format_fp(f: impl AsRef<Path>) -> ImcoResult<Format> {
+    let f = f.as_ref();
+    mk_format(f.extension().and_then(|e| e.to_str()).ok_or_else(|| ImcoError::InvalidFormat(f.display().to_string()))?)
+}
+
+/// What this build of imco does with a format, see [`format_capabilities`]
+pub struct FormatCapabilities {
+    /// None for formats only read, which can't be named as an output format
+    pub format: Option<Format>,
+    /// Accepted by [`mk_format`], the first one is used when listing
+    pub names: &'static [&'static str],
+    /// Extensions inputs are recognized by, the first one is given to outputs
+    pub extensions: Vec<&'static str>,
+    pub mime_type: &'static str,
+    pub read: bool,
+    pub write: bool,
+    /// Whether outputs keep transparency, otherwise it's flattened onto the background
+    pub alpha: bool,
+    /// Animated inputs are read with every frame
+    pub read_animation: bool,
+    /// Animated inputs are written with every frame instead of just the first one
+    pub write_animation: bool,
+    /// Inputs with several pages turn into several images, outputs hold several images in one file
+    pub pages: bool,
+    /// Bits per channel outputs are written with, 32 for float samples
+    pub depths: &'static [u8],
+    /// Outputs lose detail, tuned with [`Options::quality`]
+    pub lossy: bool,
+}
+
+impl FormatCapabilities {
+    fn of(format: Format, names: &'static [&'static str]) -> Self {
+        let (read, write) = match format {
+            // imco writes dds itself
+            Format::Image(ImageFormat::Dds) => (ImageFormat::Dds.reading_enabled(), true),
+            Format::Image(fmt) => (fmt.reading_enabled(), fmt.writing_enabled()),
+            Format::Jxl => (cfg!(feature = "jxl"), cfg!(feature = "jxl")),
+            Format::Pdf | Format::Ktx2 => (false, true),
+            // Given the geometry with Options::raw_input
+            Format::Raw => (true, true),
+        };
+        let extensions = match format {
+            Format::Image(fmt) => fmt.extensions_str().to_vec(),
+            other => vec![other.extension()],
+        };
+        let depths: &[u8] = match format {
+            Format::Image(ImageFormat::Farbfeld) => &[16],
+            Format::Image(ImageFormat::OpenExr | ImageFormat::Hdr) => &[32],
+            _ => match format.max_depth() { 32 => &[8, 16, 32], 16 => &[8, 16], _ => &[8] },
+        };
+        let animated = matches!(format, Format::Image(ImageFormat::Gif | ImageFormat::Png));
+        FormatCapabilities {
+            format: Some(format),
+            names,
+            extensions,
+            mime_type: format.mime_type(),
+            read,
+            write,
+            // HDR is written as RGB floats
+            alpha: supports_alpha(format) && format != Format::Image(ImageFormat::Hdr),
+            read_animation: animated || format == Format::Image(ImageFormat::WebP),
+            write_animation: animated,
+            pages: matches!(format, Format::Image(ImageFormat::Tiff) | Format::Pdf),
+            depths,
+            lossy: matches!(format, Format::Image(ImageFormat::Jpeg | ImageFormat::Avif)),
+        }
+    }
+
+    fn input_only(names: &'static [&'static str], extensions: &[&'static str], mime_type: &'static str, read: bool) -> Self {
+        FormatCapabilities {
+            format: None,
+            names,
+            extensions: extensions.to_vec(),
+            mime_type,
+            read,
+            write: false,
+            alpha: false,
+            read_animation: false,
+            write_animation: false,
+            pages: false,
+            depths: &[],
+            lossy: false,
+        }
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.names[0],
+            "names": self.names,
+            "extensions": self.extensions,
+            "mime_type": self.mime_type,
+            "read": self.read,
+            "write": self.write,
+            "alpha": self.alpha,
+            "animation": { "read": self.read_animation, "write": self.write_animation },
+            "pages": self.pages,
+            "depths": self.depths,
+            "lossy": self.lossy,
+        })
+    }
+}
+
+/// Every format imco knows, with what the features this build was made with let it do
+pub fn format_capabilities() -> Vec<FormatCapabilities> {
+    let mut formats: Vec<FormatCapabilities> = FORMAT_NAMES.iter().map(|(format, names)| FormatCapabilities::of(*format, names)).collect();
+    formats.push(FormatCapabilities::input_only(&["svg"], &["svg", "svgz"], "image/svg+xml", cfg!(feature = "svg")));
+    formats.push(FormatCapabilities::input_only(&["heif", "heic"], &["heic", "heif"], "image/heif", cfg!(feature = "heif")));
+    formats.push(FormatCapabilities::input_only(&["camera-raw"], RAW_EXTENSIONS, "image/x-raw", cfg!(feature = "raw")));
+    formats
+}
+
+fn mk_unsupported_str(u: UnsupportedError) -> String {
+    match u.kind() {
+        UnsupportedErrorKind::Color(c) => {
+            format!("Unsupported color ({:?})", c)
+        }
+        UnsupportedErrorKind::Format(f) => {
+            format!("Unsupported or not allowed image format ({})", f)
+        }
+        UnsupportedErrorKind::GenericFeature(gf) => {
+            gf
+        },
+        _ => "Other".to_string(),
+    }
+}
+
+fn image_err_convert<T>(res: Result<T, ImageError>, img_path: impl AsRef<Path>) -> Result<T, ImcoError> {
+    res.map_err(|e| {
+        let img_path = img_path.as_ref().display().to_string();
+        match e {
+            ImageError::Decoding(de) => { ImcoError::Decoding(img_path, de.to_string()) }
+            ImageError::Encoding(ee) => { ImcoError::Encoding(img_path, ee.to_string()) }
+            ImageError::Parameter(_) => { ImcoError::InternalConversionError(img_path) }
+            ImageError::Limits(_) => { ImcoError::ResourceLimitReached(img_path) }
+            ImageError::Unsupported(u) => {ImcoError::Unsupported(img_path, mk_unsupported_str(u))}
+            ImageError::IoError(e) => { io_error_convert::<String>(Err(e), &img_path, false).unwrap_err() }
+        }
+    })
+}
+
+fn today() -> String {
+    let days = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs() / 86400).unwrap_or(0) as i64;
+    civil_date(days)
+}
+
+/// The current UTC time like 2024-05-01T12:30:00.250Z
+pub fn utc_timestamp() -> String {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    let seconds = now.as_secs() % 86400;
+    format!("{}T{:02}:{:02}:{:02}.{:03}Z", civil_date((now.as_secs() / 86400) as i64), seconds / 3600, seconds / 60 % 60, seconds % 60, now.subsec_millis())
+}
+
+// Days since the epoch to a UTC year-month-day
+fn civil_date(days: i64) -> String {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+// Fills the name template, {width} and {height} are left for fill_dimensions
+fn mk_filename(p: &Path, fmt: Format, template: &str, index: usize) -> PathBuf {
+    let stem = p.file_stem().unwrap_or(p.as_os_str());
+    let org_ext = p.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    let mut name = template.replace("{ext}", fmt.extension())
+        .replace("{format}", &org_ext)
+        .replace("{index}", &index.to_string())
+        .replace("{date}", &today());
+    if template.contains("{exif.") {
+        let (year, month, day) = exif_date(p).unwrap_or_else(|| ("unknown".to_string(), "unknown".to_string(), "unknown".to_string()));
+        name = name.replace("{exif.year}", &year)
+            .replace("{exif.month}", &month)
+            .replace("{exif.day}", &day)
+            .replace("{exif.date}", &if year == "unknown" { year.clone() } else { format!("{year}-{month}-{day}") });
+    }
+    // The stem goes in last, as it doesn't have to be UTF-8
+    fill_placeholder(OsStr::new(&name), "{stem}", stem).into()
+}
+
+/// Replaces the placeholder in name by value, neither of which has to be UTF-8
+pub fn fill_placeholder(name: &OsStr, placeholder: &str, value: &OsStr) -> OsString {
+    let mut rest = name.as_encoded_bytes();
+    let mut filled = Vec::with_capacity(rest.len());
+    while let Some(at) = rest.windows(placeholder.len()).position(|window| window == placeholder.as_bytes()) {
+        filled.extend_from_slice(&rest[..at]);
+        filled.extend_from_slice(value.as_encoded_bytes());
+        rest = &rest[at + placeholder.len()..];
+    }
+    filled.extend_from_slice(rest);
+    // SAFETY: name is only split around the UTF-8 placeholder, the pieces being joined with the bytes of another OsStr
+    unsafe { OsString::from_encoded_bytes_unchecked(filled) }
+}
+
+// The year, month and day a photo was taken, from the DateTimeOriginal (else DateTime) of its exif
+fn exif_date(path: &Path) -> Option<(String, String, String)> {
+    let mut file = std::io::BufReader::new(File::open(path).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut file).ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
+        .or_else(|| exif.get_field(exif::Tag::DateTime, exif::In::PRIMARY))?;
+    let exif::Value::Ascii(ref values) = field.value else { return None };
+    let date = std::str::from_utf8(values.first()?).ok()?;
+    let mut parts = date.get(..10)?.split(':');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    let valid = |part: &str, len: usize| part.len() == len && part.bytes().all(|b| b.is_ascii_digit()) && part.bytes().any(|b| b != b'0');
+    (valid(year, 4) && valid(month, 2) && valid(day, 2)).then(|| (year.to_string(), month.to_string(), day.to_string()))
+}
+
+fn fill_dimensions(output: &Path, width: u32, height: u32) -> PathBuf {
+    let filled = fill_placeholder(output.as_os_str(), "{width}", OsStr::new(&width.to_string()));
+    fill_placeholder(&filled, "{height}", OsStr::new(&height.to_string())).into()
+}
+
+/// Whether the placeholder, like `{stem}`, is part of name
+pub fn has_placeholder(name: &Path, placeholder: &str) -> bool {
+    name.as_os_str().as_encoded_bytes().windows(placeholder.len()).any(|window| window == placeholder.as_bytes())
+}
+
+// The path with suffix put between its file stem and extension, like photo-1.png
+fn behind_stem(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+fn has_dimensions(output: &Path) -> bool {
+    has_placeholder(output, "{width}") || has_placeholder(output, "{height}")
+}
+
+fn mk_sibling(p: &Path, fmt: Format, template: &str, index: usize) -> PathBuf {
+    p.with_file_name(mk_filename(p, fmt, template, index))
+}
+
+// The path with suffix appended to its file name, like out.png.json
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    name.into()
+}
+
+fn join_path(p: &Path, fmt: Format, dir: &Path, template: &str, index: usize) -> PathBuf {
+    dir.join(mk_filename(p, fmt, template, index))
+}
+
+/// Settings every image of a [`Converter`] is converted with, [`Options::default`]
+/// converts without any adjustments
+pub struct Options {
+    pub i_fmt: Option<Format>,
+    pub rasterize: Rasterize,
+    /// Reads inputs as bare pixels of this size and layout, with an i_fmt of [`Format::Raw`]
+    pub raw_input: Option<RawInput>,
+    /// The pixels of [`Format::Raw`] outputs
+    pub raw_layout: RawLayout,
+    /// Puts a line of JSON with the width, height, stride and layout before the pixels of [`Format::Raw`] outputs
+    pub raw_header: bool,
+    pub o_fmt: Option<Format>,
+    /// Further formats every output is also written in, encoded from the same decoded image next to
+    /// it (animations as their first frame), see [`Conversion::variants`]
+    pub formats: Vec<Format>,
+    pub batch: bool,
+    /// Create missing output directories
+    pub create_dirs: bool,
+    /// Names of generated outputs, with the placeholders {stem}, {ext}, {format},
+    /// {width}, {height}, {index} and {date}, and {exif.year}, {exif.month}, {exif.day} and
+    /// {exif.date} from the DateTimeOriginal of the input ("unknown" without one). Names with a '/'
+    /// put outputs into subdirectories
+    pub name_template: String,
+    /// Applied in order before all other transforms, see [`parse_ops`]
+    pub ops: Vec<Operation>,
+    /// Clockwise degrees, applied after auto orientation
+    pub rotate: u16,
+    /// Rotation by any angle, applied after the 90 degree steps
+    pub rotate_degrees: Option<Rotation>,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    /// Applied after the flips, before the trim (which then crops away the keyed margins)
+    pub chroma_key: Option<ChromaKey>,
+    /// Applied before the crop
+    pub trim: Option<Trim>,
+    /// Applied before resizing
+    pub crop: Option<Crop>,
+    /// Applied after the crop
+    pub aspect: Option<Aspect>,
+    pub resize: Option<Resize>,
+    /// Applied after resize
+    pub crop_to: Option<CropTo>,
+    /// Longest side of a fast thumbnail, used instead of resize
+    pub thumbnail: Option<u32>,
+    pub filter: FilterType,
+    pub resize_backend: ResizeBackend,
+    /// Resize (and scale for crop_to and the dimension limits) in linear light instead of on the sRGB values,
+    /// keeping fine bright details on dark ground from darkening
+    pub linear_resize: bool,
+    /// Resizes, applies the LUT, sepia and gray colorspaces on this GPU instead of the CPU, see [`Gpu::open`]
+    pub gpu: Option<std::sync::Arc<Gpu>>,
+    /// Encoder quality for lossy formats (JPEG, AVIF)
+    pub quality: Option<u8>,
+    /// Largest size in bytes of outputs in lossy formats, replaces the quality with
+    /// the highest one that fits
+    pub target_size: Option<u64>,
+    pub encoder: EncoderSettings,
+    /// Factor both sides are resized by, applied after resize
+    pub scale: Option<f64>,
+    /// Bounds for the longest side, applied after resize
+    pub min_dimension: Option<u32>,
+    /// Noise reduction, applied before resizing
+    pub denoise: Option<Denoise>,
+    /// Gaussian blur sigma, applied after resizing
+    pub blur: Option<f32>,
+    /// Applied after the blur
+    pub unsharp: Option<Unsharp>,
+    /// Histogram stretch, applied after sharpening
+    pub auto_levels: Option<AutoLevels>,
+    /// Tonal adjustments, applied in this order after the histogram stretch
+    pub brightness: i32,
+    pub contrast: f32,
+    pub gamma: f32,
+    /// Color grading, applied after the tonal adjustments
+    pub lut: Option<Lut>,
+    /// Color filters, applied in this order after the color grading
+    pub invert: bool,
+    pub sepia: bool,
+    pub tint: Option<Tint>,
+    /// Applied after the tint
+    pub vignette: Option<Vignette>,
+    /// Turns the image black and white (keeping the alpha), white from this luma on
+    pub threshold: Option<u8>,
+    /// Levels left of every color channel, applied after the threshold
+    pub posterize: Option<u8>,
+    /// Its luma replaces the alpha of outputs, applied after the color filters
+    pub alpha_mask: Option<AlphaMask>,
+    /// Applied after the alpha mask, before the watermark
+    pub pad: Option<Pad>,
+    /// Corners (or everything outside the circle) made transparent, applied after the padding
+    pub corners: Option<Corners>,
+    /// Applied after the corners, following their shape
+    pub border: Option<Border>,
+    pub watermark: Option<Watermark>,
+    /// Applied after the watermark
+    pub caption: Option<Caption>,
+    /// Replace outputs by their alpha as a grayscale mask (white for images without one), after the caption
+    pub extract_alpha: bool,
+    pub preset: Option<Preset>,
+    /// Ascending sizes embedded into ico outputs
+    pub ico_sizes: Vec<u32>,
+    /// Write every mip level down to 1x1 into DDS and KTX2 outputs
+    pub mipmaps: bool,
+    /// Block compression of DDS and KTX2 outputs, which hold plain RGBA without
+    pub texture_compression: Option<TextureCompression>,
+    /// Pixel type forced onto the output
+    pub colorspace: Option<image::ColorType>,
+    /// Bits per channel (8 or 16) forced onto the output, keeping its channels
+    pub depth: Option<u8>,
+    /// Fail instead of writing 16 bit and float images into formats that only hold 8 bits per channel.
+    /// Either way they keep as many bits as the output format holds
+    pub preserve_depth: bool,
+    /// Fail instead of writing an output that lost information to its format or the palette and depth options,
+    /// naming the step that would. Deliberate edits like resizing don't count
+    pub lossless_only: bool,
+    /// Fail before converting anything when the outputs likely don't fit onto their volumes, see [`estimate_output_bytes`].
+    /// Only checked on Unix
+    pub check_space: bool,
+    /// Expected output bytes per input byte, instead of the conservative guesses of [`estimate_output_bytes`]
+    pub space_ratio: Option<f64>,
+    /// Fail instead of writing an output with one of the [`Downgrade`]s, which are otherwise reported in
+    /// [`Conversion::downgrades`]
+    pub strict: bool,
+    /// Applied first to HDR (float) images together with the exposure, compressing their
+    /// linear values instead of clipping them when they are written as sRGB
+    pub tonemap: Option<Tonemap>,
+    /// Stops HDR images are brightened (or darkened if negative) by
+    pub exposure: f32,
+    /// Applied last, png outputs are written with the palette
+    pub quantize: Option<Quantize>,
+    /// Shows through transparent pixels of outputs without an alpha channel (JPEG, PDF), white if None
+    pub background: Option<image::Rgb<u8>>,
+    /// Only convert the first frame of animations
+    pub first_frame_only: bool,
+    /// Write every frame or page to its own numbered file
+    pub extract_frames: bool,
+    /// Only convert these pages (or frames), numbering the outputs like [`Options::extract_frames`] when several are selected
+    pub pages: Option<Pages>,
+    /// Split the output into tiles of this size, named after their row and column, see [`stitch`]
+    pub tile: Option<(u32, u32)>,
+    /// Cut the output into this many rows and columns of (within a pixel) equal cells, numbered
+    /// row by row from 1 like [`Options::extract_frames`], e.g. to take a sprite sheet apart
+    pub slice: Option<(u32, u32)>,
+    /// Write every channel as its own grayscale file, named with {channel} or behind the file
+    /// stem (r, g, b or l and a), see [`merge_channels`]
+    pub split_channels: bool,
+    /// Ascending widths outputs are written at instead of their own, see [`Conversion::srcset`]
+    pub srcset: Vec<u32>,
+    /// Compute the placeholder hashes of single image outputs, see [`Conversion::placeholders`]
+    pub blurhash: bool,
+    pub thumbhash: bool,
+    /// Also write them next to the output into <output>.json
+    pub hash_sidecar: bool,
+    /// Write one pdf per input instead of combining all of them into one
+    pub pdf_per_file: bool,
+    /// Frame delay when assembling all inputs into one animation
+    pub animate: Option<image::Delay>,
+    /// Replaces the delay of every frame of animations that are converted, fixing their timing
+    pub frame_delay: Option<image::Delay>,
+    /// How often animations play, 0 for forever
+    pub loops: u16,
+    pub max_dimension: Option<u32>,
+    /// Downscales images with more pixels (in millions), keeping the aspect ratio, after the dimension bounds
+    pub max_megapixels: Option<f64>,
+    pub conflict: Conflict,
+    /// Give inputs that would share an output (like extra ones reusing the last output given) names of
+    /// their own, <name>-1, <name>-2, ..., instead of failing the run before anything is converted
+    pub uniquify: bool,
+    pub symlinks: Symlinks,
+    /// Skip inputs whose output exists and was modified after them
+    pub newer_only: bool,
+    /// Convert PNG and TIFF inputs into PNG or TIFF a strip at a time instead of decoding them whole, when
+    /// every transform works on single pixels. Other inputs and settings are decoded whole as usual
+    pub low_memory: bool,
+    /// Try reading inputs and writing outputs this many more times when that fails transiently (see [`ImcoError::is_transient`]),
+    /// waiting retry_delay in between
+    pub retries: u32,
+    pub retry_delay: std::time::Duration,
+    /// Show a progress bar while converting
+    pub progress: bool,
+    /// Only resolve the outputs, without converting anything
+    pub dry_run: bool,
+    /// Write outputs as data:image/...;base64 URIs, to stdout when there is no output
+    pub data_uri: bool,
+    pub metadata: MetadataPolicy,
+    pub strip_icc: bool,
+    /// Convert the pixels from their embedded ICC profile into sRGB and drop the profile, untagged
+    /// pixels being taken as sRGB
+    pub convert_to_srgb: bool,
+    /// Applied in order to the exif after deciding whether to keep it (on an empty one
+    /// otherwise), see [`parse_exif_set`]
+    pub exif_edits: Vec<ExifEdit>,
+    /// Blank the dates and software names of the exif that is kept, so the same input always
+    /// converts to the same bytes (together with a [sorted](Archive::sorted) archive)
+    pub deterministic: bool,
+    /// Give outputs the modification time and permissions of their input
+    pub preserve_times: bool,
+    pub preserve_mode: bool,
+    /// Rotate / flip according to the EXIF orientation
+    pub auto_orient: bool,
+    /// Suffix to move existing outputs to, error instead of numbering when taken. Outputs replacing their
+    /// input are always backed up, to .bak without a suffix
+    pub backup: Option<String>,
+    pub strict_backup: bool,
+    /// Directory to move the backups into instead of next to the output
+    pub backup_dir: Option<std::path::PathBuf>,
+    /// Write every input back onto itself, in its own format
+    pub in_place: bool,
+    /// Fail on inputs whose content is in another format than their extension says
+    pub strict_extensions: bool,
+    /// Collect per file errors instead of aborting on the first one
+    pub keep_going: bool,
+    /// Number of files converted in parallel, 0 for one per core
+    pub jobs: usize,
+    /// Gives up on files of [`Converter::run`] taking longer to convert, discarding what they would write.
+    /// Can't be combined with an archive
+    pub timeout: Option<std::time::Duration>,
+    /// Once set, [`Converter::run`] starts no further files and returns when the ones being converted are done
+    pub cancel: Arc<AtomicBool>,
+    /// Read outputs back after writing, fully decoding them if verify_full
+    pub verify: bool,
+    pub verify_full: bool,
+    pub dedupe: Option<Dedupe>,
+    /// Decodes inputs given to several jobs of one [`Converter::run`] once
+    pub decode_cache: Option<DecodeCache>,
+    pub checksums: Option<Checksums>,
+    /// Records every finished input, leaving out those finished by the runs of the session before
+    pub session: Option<Session>,
+    pub archive: Option<Archive>,
+    pub limits: DecodeLimits,
+}
+
+// The defaults of the command line, converting without any adjustments
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            i_fmt: None,
+            rasterize: Rasterize::default(),
+            raw_input: None,
+            raw_layout: RawLayout::default(),
+            raw_header: false,
+            o_fmt: None,
+            formats: Vec::new(),
+            batch: false,
+            create_dirs: false,
+            name_template: "{stem}.{ext}".to_string(),
+            ops: vec![],
+            rotate: 0,
+            rotate_degrees: None,
+            flip_horizontal: false,
+            flip_vertical: false,
+            chroma_key: None,
+            trim: None,
+            crop: None,
+            resize: None,
+            crop_to: None,
+            aspect: None,
+            thumbnail: None,
+            filter: FilterType::Lanczos3,
+            resize_backend: ResizeBackend::Fast,
+            linear_resize: false,
+            gpu: None,
+            quality: None,
+            target_size: None,
+            encoder: EncoderSettings::default(),
+            scale: None,
+            min_dimension: None,
+            blur: None,
+            unsharp: None,
+            denoise: None,
+            auto_levels: None,
+            brightness: 0,
+            contrast: 0.0,
+            gamma: 1.0,
+            lut: None,
+            invert: false,
+            sepia: false,
+            tint: None,
+            vignette: None,
+            threshold: None,
+            posterize: None,
+            pad: None,
+            corners: None,
+            border: None,
+            watermark: None,
+            alpha_mask: None,
+            extract_alpha: false,
+            caption: None,
+            preset: None,
+            ico_sizes: vec![],
+            mipmaps: false,
+            texture_compression: None,
+            colorspace: None,
+            depth: None,
+            preserve_depth: false,
+            lossless_only: false,
+            check_space: false,
+            space_ratio: None,
+            strict: false,
+            tonemap: None,
+            exposure: 0.0,
+            quantize: None,
+            background: None,
+            first_frame_only: false,
+            extract_frames: false,
+            pages: None,
+            split_channels: false,
+            srcset: Vec::new(),
+            blurhash: false,
+            thumbhash: false,
+            hash_sidecar: false,
+            tile: None,
+            slice: None,
+            pdf_per_file: false,
+            animate: None,
+            frame_delay: None,
+            loops: 0,
+            max_dimension: None,
+            max_megapixels: None,
+            conflict: Conflict::Overwrite,
+            uniquify: false,
+            symlinks: Symlinks::Follow,
+            newer_only: false,
+            low_memory: false,
+            retries: 0,
+            retry_delay: std::time::Duration::from_millis(500),
+            progress: false,
+            dry_run: false,
+            data_uri: false,
+            metadata: MetadataPolicy::SameFormat,
+            strip_icc: false,
+            convert_to_srgb: false,
+            exif_edits: vec![],
+            deterministic: false,
+            preserve_times: false,
+            preserve_mode: false,
+            auto_orient: true,
+            backup: None,
+            strict_backup: false,
+            backup_dir: None,
+            in_place: false,
+            strict_extensions: false,
+            keep_going: false,
+            jobs: 1,
+            timeout: None,
+            cancel: Arc::default(),
+            verify: false,
+            verify_full: false,
+            dedupe: None,
+            decode_cache: None,
+            checksums: None,
+            session: None,
+            archive: None,
+            limits: DecodeLimits::default(),
+        }
+    }
+}
+
+/// Zip archive receiving all outputs instead of the file system
+pub struct Archive {
+    path: String,
+    temp: PartialArchive,
+    writer: Mutex<ZipWriter<File>>,
+    sorted: bool,
+    /// Entries held back until finishing, see [`Archive::sorted`]
+    pending: Mutex<std::collections::BTreeMap<String, Vec<u8>>>,
+}
+
+impl Archive {
+    /// Writes next to path, only replacing it once finished, so a failed run leaves no partial archive
+    pub fn create(path: &str) -> ImcoResult<Self> {
+        let temp = partial_path(Path::new(path));
+        PARTIAL_OUTPUTS.lock().unwrap().push(temp.clone());
+        let temp = PartialArchive(temp);
+        let file = io_error_convert(File::create(&temp.0), path, false)?;
+        Ok(Archive { path: path.to_string(), temp, writer: Mutex::new(ZipWriter::new(file)), sorted: false, pending: Mutex::default() })
+    }
+
+    /// Keeps the entries in memory and writes them ordered by name when finishing, instead of in the
+    /// order the parallel jobs complete
+    pub fn sorted(self) -> Self {
+        Archive { sorted: true, ..self }
+    }
+
+    fn add(&self, name: &str, data: &[u8]) -> ImcoResult<()> {
+        if self.sorted {
+            self.pending.lock().unwrap().insert(name.to_string(), data.to_vec());
+            return Ok(())
+        }
+        self.write(name, data)
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> ImcoResult<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.start_file(name, SimpleFileOptions::default())
+            .map_err(|e| ImcoError::Archive(e.to_string(), self.path.clone()))?;
+        io_error_convert(writer.write_all(data), &self.path, false)
+    }
+
+    fn finish(self) -> ImcoResult<()> {
+        for (name, data) in std::mem::take(&mut *self.pending.lock().unwrap()) {
+            self.write(&name, &data)?
+        }
+        self.writer.into_inner().unwrap().finish()
+            .map_err(|e| ImcoError::Archive(e.to_string(), self.path.clone()))?;
+        io_error_convert(std::fs::rename(&self.temp.0, &self.path), &self.path, false)
+    }
+}
+
+const EXIF_DATE_TAGS: [u16; 11] = [
+    0x0131, // Software
+    0x0132, // DateTime
+    0x9003, // DateTimeOriginal
+    0x9004, // DateTimeDigitized
+    0x9010, // OffsetTime
+    0x9011, // OffsetTimeOriginal
+    0x9012, // OffsetTimeDigitized
+    0x9290, // SubSecTime
+    0x9291, // SubSecTimeOriginal
+    0x9292, // SubSecTimeDigitized
+    0x000B, // ProcessingSoftware
+];
+
+// Zeroes the text of the date and software tags in the main, thumbnail and Exif IFDs, keeping
+// the layout of the chunk intact
+fn blank_exif_dates(exif: &mut [u8]) {
+    let tiff = if exif.starts_with(b"Exif\0\0") { &mut exif[6..] } else { exif };
+    let big_endian = match tiff.get(..4) {
+        Some(b"MM\0*") => true,
+        Some(b"II*\0") => false,
+        _ => return
+    };
+    let read = |data: &[u8], at: usize, len: usize| -> Option<u32> {
+        let bytes = data.get(at..at.checked_add(len)?)?;
+        Some(if big_endian { bytes.iter().fold(0, |n, b| n << 8 | *b as u32) } else { bytes.iter().rev().fold(0, |n, b| n << 8 | *b as u32) })
+    };
+    let mut ifds = vec![read(tiff, 4, 4).unwrap_or(0)];
+    let mut seen = vec![];
+    while let Some(offset) = ifds.pop() {
+        let offset = offset as usize;
+        if offset == 0 || seen.contains(&offset) { continue }
+        seen.push(offset);
+        let Some(count) = read(tiff, offset, 2) else { continue };
+        for i in 0..count as usize {
+            let entry = offset + 2 + i * 12;
+            let (Some(tag), Some(kind), Some(len), Some(value)) = (read(tiff, entry, 2), read(tiff, entry + 2, 2), read(tiff, entry + 4, 4), read(tiff, entry + 8, 4)) else { break };
+            match tag as u16 {
+                0x8769 => ifds.push(value),
+                // ASCII values of up to 4 bytes sit in the entry itself
+                tag if kind == 2 && EXIF_DATE_TAGS.contains(&tag) => {
+                    let at = if len <= 4 { entry + 8 } else { value as usize };
+                    if let Some(text) = tiff.get_mut(at..at.saturating_add(len as usize)) { text.fill(0) }
+                }
+                _ => {}
+            }
+        }
+        if let Some(next) = read(tiff, offset + 2 + count as usize * 12, 4) { ifds.push(next) }
+    }
+}
+
+/// A change to the exif of outputs
+#[derive(Clone)]
+pub enum ExifEdit {
+    Set(exif::Tag, String),
+    /// Removes the fields with this name, like GPSLatitude
+    Remove(String),
+}
+
+// The text fields that can be set
+const EXIF_TEXT_TAGS: [exif::Tag; 9] = [
+    exif::Tag::Artist, exif::Tag::Copyright, exif::Tag::ImageDescription,
+    exif::Tag::Make, exif::Tag::Model, exif::Tag::Software,
+    exif::Tag::DateTime, exif::Tag::DateTimeOriginal, exif::Tag::DateTimeDigitized,
+];
+
+/// Parses KEY=VALUE, like Copyright=Jane Doe, dates are written as YYYY:MM:DD HH:MM:SS
+pub fn parse_exif_set(edit: &str) -> ImcoResult<ExifEdit> {
+    let fail = |hint: String| ImcoError::InvalidExifEdit(edit.to_string(), hint);
+    let (key, value) = edit.split_once('=').ok_or_else(|| fail("expected KEY=VALUE".to_string()))?;
+    let tag = EXIF_TEXT_TAGS.into_iter().find(|tag| tag.to_string().eq_ignore_ascii_case(key.trim()))
+        .ok_or_else(|| fail(format!("can only set {}", EXIF_TEXT_TAGS.map(|tag| tag.to_string()).join(", "))))?;
+    let is_date = |v: &str| v.len() == 19 && v.bytes().enumerate().all(|(i, b)| match i {
+        4 | 7 | 13 | 16 => b == b':',
+        10 => b == b' ',
+        _ => b.is_ascii_digit()
+    });
+    if tag.to_string().starts_with("DateTime") && !is_date(value) {
+        return Err(fail("dates are written as YYYY:MM:DD HH:MM:SS".to_string()))
+    }
+    if !value.is_ascii() { return Err(fail("exif text must be ASCII".to_string())) }
+    Ok(ExifEdit::Set(tag, value.to_string()))
+}
+
+// Rewrites the exif with the edits, starting from an empty one if there is none (or it's broken).
+// The thumbnail is left out, it would still show the input
+fn edit_exif(exif: Option<Vec<u8>>, edits: &[ExifEdit], path: &Path) -> ImcoResult<Option<Vec<u8>>> {
+    let parsed = exif.map(|data| if data.starts_with(b"Exif\0\0") { data[6..].to_vec() } else { data })
+        .and_then(|data| exif::Reader::new().read_raw(data).ok());
+    let little_endian = parsed.as_ref().is_none_or(|exif| exif.little_endian());
+    let mut fields: Vec<exif::Field> = parsed.iter()
+        .flat_map(|exif| exif.fields())
+        .filter(|field| field.ifd_num == exif::In::PRIMARY)
+        .cloned()
+        .collect();
+    for edit in edits {
+        match edit {
+            ExifEdit::Set(tag, value) => {
+                fields.retain(|field| field.tag != *tag);
+                fields.push(exif::Field { tag: *tag, ifd_num: exif::In::PRIMARY, value: exif::Value::Ascii(vec![value.clone().into_bytes()]) });
+            }
+            ExifEdit::Remove(name) => fields.retain(|field| !field.tag.to_string().eq_ignore_ascii_case(name)),
+        }
+    }
+    let mut writer = exif::experimental::Writer::new();
+    for field in &fields { writer.push_field(field) }
+    if fields.is_empty() { return Ok(None) }
+    let mut data = Cursor::new(vec![]);
+    writer.write(&mut data, little_endian).map_err(|e| ImcoError::Encoding(path.display().to_string(), format!("Failed writing the exif => {e}")))?;
+    Ok(Some(data.into_inner()))
+}
+
+// Turns an output path into a relative entry name ('/' separated)
+fn archive_entry_name(output: &Path) -> String {
+    output.components()
+        .filter_map(|c| match c {
+            std::path::Component::Normal(n) => n.to_str(),
+            _ => None
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Links outputs with the same pixels as an earlier one to it instead of writing them again
+pub struct Dedupe {
+    symlink: bool,
+    seen: Mutex<HashMap<(u64, Format), PathBuf>>,
+    linked: AtomicU64,
+    saved: AtomicU64,
+}
+
+impl Dedupe {
+    /// Symbolic instead of hard links if symlink
+    pub fn new(symlink: bool) -> Self {
+        Dedupe { symlink, seen: Mutex::new(HashMap::new()), linked: AtomicU64::new(0), saved: AtomicU64::new(0) }
+    }
+
+    fn link(&self, original: &Path, output: &Path) -> ImcoResult<()> {
+        if std::path::Path::new(output).exists() {
+            io_error_convert(std::fs::remove_file(output), output, false)?;
+        }
+        let res = if self.symlink {
+            let target = io_error_convert(std::fs::canonicalize(original), original, true)?;
+            symlink_file(&target, std::path::Path::new(output))
+        } else {
+            std::fs::hard_link(original, output)
+        };
+        io_error_convert(res, output, false)?;
+        let size = std::fs::metadata(original).map(|m| m.len()).unwrap_or(0);
+        self.linked.fetch_add(1, Ordering::Relaxed);
+        self.saved.fetch_add(size, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+type Decoded = (image::DynamicImage, Metadata, Option<Format>);
+
+// An input's decode shared by its jobs, locked while the first of them decodes it
+#[derive(Default)]
+struct CacheSlot {
+    uses: usize,
+    decoded: Option<Decoded>,
+}
+
+/// Keeps decoded inputs for the further jobs of a [`Converter::run`] converting them, as long as all kept ones fit into a budget
+pub struct DecodeCache {
+    max_bytes: u64,
+    bytes: AtomicU64,
+    slots: Mutex<HashMap<PathBuf, Arc<Mutex<CacheSlot>>>>,
+}
+
+impl DecodeCache {
+    pub fn new(max_bytes: u64) -> Self {
+        DecodeCache { max_bytes, bytes: AtomicU64::new(0), slots: Mutex::new(HashMap::new()) }
+    }
+
+    // Only inputs of several jobs are kept, until their last job took them
+    fn expect(&self, jobs: &[(usize, ConversionJob)]) {
+        let mut slots = self.slots.lock().unwrap();
+        for (_, job) in jobs {
+            slots.entry(job.input.clone()).or_default().lock().unwrap().uses += 1;
+        }
+        slots.retain(|_, slot| slot.lock().unwrap().uses > 1);
+    }
+
+    // Lets go of what failed or skipped jobs left
+    fn clear(&self) {
+        self.slots.lock().unwrap().clear();
+        self.bytes.store(0, Ordering::Relaxed);
+    }
+
+    fn decode(&self, path: &Path, decode: impl FnOnce() -> ImcoResult<Decoded>) -> ImcoResult<Decoded> {
+        let Some(slot) = self.slots.lock().unwrap().get(path).cloned() else { return decode() };
+        let mut slot = slot.lock().unwrap();
+        slot.uses = slot.uses.saturating_sub(1);
+        // Kept ones are counted into the budget until they are let go
+        let (decoded, counted) = match slot.decoded.take() {
+            Some(decoded) => (decoded, true),
+            None => (decode()?, false),
+        };
+        let size = decoded.0.as_bytes().len() as u64;
+        if slot.uses > 0 && (counted || self.bytes.fetch_add(size, Ordering::Relaxed) + size <= self.max_bytes) {
+            slot.decoded = Some(decoded.clone());
+            return Ok(decoded)
+        }
+        // The last job took it, or it doesn't fit and the remaining jobs decode it themselves
+        if counted || slot.uses > 0 { self.bytes.fetch_sub(size, Ordering::Relaxed); }
+        self.slots.lock().unwrap().remove(path);
+        Ok(decoded)
+    }
+}
+
+/// Hash algorithm of [`Checksums`]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    /// Name on the command line, also the extension of its sidecars
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    // Tells the algorithm of a listed checksum by its length
+    fn of_hex(hex: &str) -> Option<Self> {
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) { return None }
+        match hex.len() {
+            64 => Some(ChecksumAlgorithm::Sha256),
+            128 => Some(ChecksumAlgorithm::Sha512),
+            _ => None
+        }
+    }
+}
+
+/// Records a checksum of every written output, each in a sidecar like `out.png.sha256`
+/// or all of them in one manifest, both in the format `sha256sum -c` checks
+pub struct Checksums {
+    algorithm: ChecksumAlgorithm,
+    manifest: Option<String>,
+    sums: Mutex<Vec<(PathBuf, String)>>,
+}
+
+impl Checksums {
+    /// Into a manifest written when the [`Converter`] finishes if there is one, sidecars otherwise
+    pub fn new(algorithm: ChecksumAlgorithm, manifest: Option<String>) -> Self {
+        Checksums { algorithm, manifest, sums: Mutex::new(Vec::new()) }
+    }
+
+    fn record(&self, output: &Path) -> ImcoResult<()> {
+        let sum = file_checksum(output, self.algorithm)?;
+        if self.manifest.is_some() {
+            self.sums.lock().unwrap().push((output.to_path_buf(), sum));
+            return Ok(())
+        }
+        let name = output.file_name().unwrap_or(output.as_os_str()).to_string_lossy();
+        let sidecar = with_suffix(output, &format!(".{}", self.algorithm.name()));
+        io_error_convert(std::fs::write(&sidecar, format!("{sum}  {name}\n")), &sidecar, false)
+    }
+
+    // Lists the outputs relative to the manifest, sorted so reruns write the same file
+    fn write_manifest(&self) -> ImcoResult<()> {
+        let Some(manifest) = &self.manifest else { return Ok(()) };
+        let dir = std::path::Path::new(manifest).parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(std::path::Path::new("."));
+        io_error_convert(std::fs::create_dir_all(dir), manifest, false)?;
+        let dir = io_error_convert(std::fs::canonicalize(dir), manifest, false)?;
+        // Outputs written more than once keep the checksum of their last write
+        let mut lines = std::collections::BTreeMap::new();
+        for (output, sum) in self.sums.lock().unwrap().iter() {
+            let target = io_error_convert(std::fs::canonicalize(output), output, true)?;
+            lines.insert(relative_path(&dir, &target).to_string_lossy().replace('\\', "/"), sum.clone());
+        }
+        let text: String = lines.iter().map(|(path, sum)| format!("{sum}  {path}\n")).collect();
+        write_atomically(Path::new(manifest), |writer| io_error_convert(writer.write_all(text.as_bytes()), manifest, false))
+    }
+}
+
+/// The progress of a batch, written as it goes so that an interrupted run can be resumed: a line of JSON with
+/// the arguments, working directory and inputs of the run, then one per input that finished
+pub struct Session {
+    path: String,
+    args: Vec<String>,
+    cwd: String,
+    /// Inputs converted by earlier runs, which [`Converter::run`] leaves out. Paths that aren't UTF-8 are
+    /// kept lossily, like in the session file
+    done: HashSet<String>,
+    /// Those of the run the session was started by, when resumed
+    inputs: Option<HashSet<String>>,
+    resumed: bool,
+    file: Mutex<Option<File>>,
+}
+
+impl Session {
+    /// A session of a run with these arguments, only written to path once it [`Session::begin`]s
+    pub fn new(path: String, args: Vec<String>, cwd: String) -> Self {
+        Session { path, args, cwd, done: HashSet::new(), inputs: None, resumed: false, file: Mutex::new(None) }
+    }
+
+    /// Reads the session at path to continue it. A last line cut short, as by a power cut, is left out
+    pub fn resume(path: &str) -> ImcoResult<Self> {
+        let broken = |reason: &str| ImcoError::Session(path.to_string(), reason.to_string());
+        let text = io_error_convert(std::fs::read_to_string(path), path, true)?;
+        let mut lines = text.lines();
+        let header: serde_json::Value = lines.next().and_then(|line| serde_json::from_str(line).ok()).ok_or_else(|| broken("it has no header"))?;
+        let args = header["args"].as_array().and_then(|args| args.iter().map(|arg| arg.as_str().map(str::to_string)).collect::<Option<Vec<_>>>())
+            .ok_or_else(|| broken("the header has no arguments"))?;
+        let cwd = header["cwd"].as_str().ok_or_else(|| broken("the header has no working directory"))?.to_string();
+        let inputs = header["inputs"].as_array().map(|inputs| inputs.iter().filter_map(|input| input.as_str().map(str::to_string)).collect());
+        let done = lines.filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .filter_map(|record| record["done"].as_str().map(str::to_string))
+            .collect();
+        Ok(Session { path: path.to_string(), args, cwd, done, inputs, resumed: true, file: Mutex::new(None) })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Those of the run the session was started by
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn cwd(&self) -> &str {
+        &self.cwd
+    }
+
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// Inputs converted by earlier runs of the session
+    pub fn done(&self) -> usize {
+        self.done.len()
+    }
+
+    /// Whether input was one of the run the session was started by, files matching its patterns since aren't.
+    /// Always true for new sessions
+    pub fn started_with(&self, input: &Path) -> bool {
+        self.inputs.as_ref().is_none_or(|inputs| inputs.contains(&*input.to_string_lossy()))
+    }
+
+    /// The number of inputs of the run the session was started by
+    pub fn inputs(&self) -> Option<usize> {
+        self.inputs.as_ref().map(HashSet::len)
+    }
+
+    /// Writes the header of a new session, resumed ones are appended to
+    pub fn begin(&self, jobs: &[ConversionJob]) -> ImcoResult<()> {
+        if let Some(dir) = std::path::Path::new(&self.path).parent() {
+            io_error_convert(std::fs::create_dir_all(dir), &self.path, false)?;
+        }
+        let mut file = io_error_convert(std::fs::OpenOptions::new().create(true).append(true).open(&self.path), &self.path, false)?;
+        if !self.resumed {
+            let header = serde_json::json!({
+                "args": self.args, "cwd": self.cwd, "started": utc_timestamp(),
+                "inputs": jobs.iter().map(|job| job.input.to_string_lossy()).collect::<Vec<_>>(),
+            });
+            io_error_convert(writeln!(file, "{header}").and_then(|_| file.sync_data()), &self.path, false)?;
+        }
+        *self.file.lock().unwrap() = Some(file);
+        Ok(())
+    }
+
+    fn is_done(&self, input: &Path) -> bool {
+        self.done.contains(&*input.to_string_lossy())
+    }
+
+    // Synced right away, the output it stands for being written already
+    fn record(&self, input: &Path, result: &ImcoResult<Conversion>) {
+        let record = match result {
+            Ok(_) => serde_json::json!({ "done": input.to_string_lossy() }),
+            Err(e) => serde_json::json!({ "failed": input.to_string_lossy(), "error": e.to_string() }),
+        };
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            // A session that can't be written only costs converting the input again when resuming
+            let _ = writeln!(file, "{record}").and_then(|_| file.sync_data());
+        }
+    }
+
+    /// Deletes the session file, once every input was converted
+    pub fn remove(&self) -> ImcoResult<()> {
+        *self.file.lock().unwrap() = None;
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(ImcoError::Session(self.path.clone(), e.to_string())),
+            _ => Ok(())
+        }
+    }
+}
+
+fn file_checksum(path: &Path, algorithm: ChecksumAlgorithm) -> ImcoResult<String> {
+    fn digest<D: sha2::Digest + Write>(path: &Path) -> ImcoResult<String> {
+        let mut file = io_error_convert(File::open(path), path, true)?;
+        let mut hasher = D::new();
+        io_error_convert(std::io::copy(&mut file, &mut hasher), path, true)?;
+        Ok(hasher.finalize().iter().map(|b| format!("{b:02x}")).collect())
+    }
+    match algorithm {
+        ChecksumAlgorithm::Sha256 => digest::<sha2::Sha256>(path),
+        ChecksumAlgorithm::Sha512 => digest::<sha2::Sha512>(path),
+    }
+}
+
+/// One file listed in a checksum manifest, see [`verify_checksums`]
+pub struct ChecksumCheck {
+    pub path: String,
+    /// [`ImcoError::ChecksumMismatch`] when it changed, a read error when it is missing
+    pub result: ImcoResult<()>,
+}
+
+/// Checks every file a manifest or sidecar in the format of `sha256sum` lists
+/// (sha256 or sha512, told apart by length), paths being relative to the manifest
+pub fn verify_checksums(manifest: &Path) -> ImcoResult<Vec<ChecksumCheck>> {
+    let text = io_error_convert(std::fs::read_to_string(manifest), manifest, true)?;
+    let dir = manifest.parent().unwrap_or(std::path::Path::new(""));
+    text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()).map(|(number, line)| {
+        let invalid = || ImcoError::ChecksumManifest(manifest.display().to_string(), format!("line {} isn't '<checksum>  <path>'", number + 1));
+        let (sum, path) = line.split_once(' ').ok_or_else(invalid)?;
+        let path = path.strip_prefix([' ', '*']).filter(|p| !p.is_empty()).ok_or_else(invalid)?;
+        let algorithm = ChecksumAlgorithm::of_hex(sum).ok_or_else(invalid)?;
+        let path = dir.join(path).to_string_lossy().into_owned();
+        let result = file_checksum(Path::new(&path), algorithm).and_then(|found| match found.eq_ignore_ascii_case(sum) {
+            true => Ok(()),
+            false => Err(ImcoError::ChecksumMismatch(path.clone()))
+        });
+        Ok(ChecksumCheck { path, result })
+    }).collect()
+}
+
+fn symlink_file(target: &std::path::Path, link: &std::path::Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, link);
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(target, link);
+    // Like wasm, without a file system to link in
+    #[cfg(not(any(unix, windows)))]
+    Err(std::io::Error::new(ErrorKind::Unsupported, format!("can't link {} to {} on this platform", link.display(), target.display())))
+}
+
+fn pixel_hash(image: &image::DynamicImage) -> u64 {
+    let mut hasher = Xxh3::new();
+    hasher.update(&image.width().to_le_bytes());
+    hasher.update(&image.height().to_le_bytes());
+    hasher.update(format!("{:?}", image.color()).as_bytes());
+    hasher.update(image.as_bytes());
+    hasher.digest()
+}
+
+fn backup_existing(output: &Path, suffix: &str, dir: Option<&std::path::Path>, strict: bool) -> ImcoResult<()> {
+    if !output.exists() { return Ok(()) }
+    let base = match dir {
+        Some(dir) => {
+            io_error_convert(std::fs::create_dir_all(dir), dir, false)?;
+            dir.join(output.file_name().unwrap_or_default())
+        }
+        None => output.to_path_buf()
+    };
+    let mut backup = with_suffix(&base, suffix);
+    let mut counter = 0;
+    while backup.exists() {
+        if strict { return Err(ImcoError::BackupExists(backup.display().to_string())) }
+        counter += 1;
+        backup = with_suffix(&base, &format!("{suffix}.{counter}"));
+    }
+    // The backup directory may be on another file system
+    let moved = std::fs::rename(output, &backup).or_else(|_| std::fs::copy(output, &backup).and_then(|_| std::fs::remove_file(output)));
+    io_error_convert(moved, &backup, false)
+}
+
+// Whether both paths lead to the same existing file
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false
+    }
+}
+
+/// Whether EXIF metadata is copied into the output (if the format supports it)
+#[derive(Clone, Copy)]
+pub enum MetadataPolicy {
+    Keep,
+    Strip,
+    SameFormat,
+}
+
+/// Block compression of DDS and KTX2 outputs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureCompression {
+    /// RGB in 4 bits per pixel
+    Bc1,
+    /// RGBA in 8 bits per pixel
+    Bc3,
+    /// RGBA in 8 bits per pixel at a higher quality, but slower to compress
+    Bc7,
+}
+
+/// What happens when an output file already exists
+#[derive(Clone, Copy)]
+pub enum Conflict {
+    Overwrite,
+    Error,
+    Skip,
+    Rename,
+}
+
+/// How symbolic links among the inputs and below recursed directories are treated
+#[derive(Clone, Copy, PartialEq)]
+pub enum Symlinks {
+    /// Convert what they point to, entering each directory once however many links lead to it
+    Follow,
+    /// Leave them out
+    Skip,
+    /// Links to other inputs become links to their outputs, links leading elsewhere are followed
+    Recreate,
+}
+
+/// Sets of outputs generated from every input
+pub enum Preset {
+    Favicon,
+    /// An Xcode AppIcon.appiconset with every iPhone, iPad and App Store size and its Contents.json
+    IosAppIcon,
+    /// Launcher icons (square and round) in the mipmap directories of every density, and the Play Store icon
+    AndroidMipmap,
+}
+
+#[derive(Clone, Copy)]
+pub enum Fit {
+    Exact,
+    /// Fit inside the box, keeping the aspect ratio
+    Contain,
+    /// Fill the box, keeping the aspect ratio and cropping the overflow
+    Cover,
+}
+
+/// Implementation used for resizing
+#[derive(Clone, Copy, PartialEq)]
+pub enum ResizeBackend {
+    /// The resize of the image crate
+    Image,
+    /// SIMD accelerated resize of fast_image_resize, falls back to [`ResizeBackend::Image`] for unsupported pixel types
+    Fast,
+}
+
+/// Curve compressing the range of HDR (float) images into what regular formats can show
+#[derive(Clone, Copy)]
+pub enum Tonemap {
+    /// x / (1 + x), keeps shadows and midtones
+    Reinhard,
+    /// The filmic curve of the Academy Color Encoding System, with more contrast
+    Aces,
+}
+
+#[derive(Clone, Copy)]
+pub enum Dither {
+    FloydSteinberg,
+    /// A 4x4 Bayer matrix, which compresses better than error diffusion
+    Ordered,
+    None,
+}
+
+/// Reduces the image to a palette of at most colors (2 to 256) colors
+#[derive(Clone, Copy)]
+pub struct Quantize {
+    pub colors: u16,
+    pub dither: Dither,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct PngSettings {
+    pub compression: CompressionType,
+    pub filter: PngFilter,
+    /// Adam7 interlacing, so a coarse preview shows up before the whole file is loaded
+    pub interlace: bool,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct JpegSettings {
+    /// Replaces [`Options::quality`]
+    pub quality: Option<u8>,
+    /// Encodes in several scans of increasing detail, which is usually smaller too
+    pub progressive: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct AvifSettings {
+    /// 1 (slowest, smallest) to 10
+    pub speed: u8,
+    /// Replaces [`Options::quality`]
+    pub quality: Option<u8>,
+}
+
+impl Default for AvifSettings {
+    fn default() -> Self {
+        // The speed of AvifEncoder::new
+        AvifSettings { speed: 4, quality: None }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct GifSettings {
+    /// 1 (slowest, best palette) to 30
+    pub speed: i32,
+}
+
+impl Default for GifSettings {
+    fn default() -> Self {
+        GifSettings { speed: 1 }
+    }
+}
+
+/// Advanced settings of the encoders, set one at a time with [`EncoderSettings::set`].
+/// The PNG settings only apply to still images
+#[derive(Clone, Copy, Default)]
+pub struct EncoderSettings {
+    pub png: PngSettings,
+    pub jpeg: JpegSettings,
+    pub avif: AvifSettings,
+    pub gif: GifSettings,
+}
+
+impl EncoderSettings {
+    /// Settings known to [`EncoderSettings::set`] and their values
+    pub const KEYS: &'static [(&'static str, &'static str)] = &[
+        ("png.compression", "fast, default, best, none or 1-9"),
+        ("png.filter", "none, sub, up, avg, paeth or adaptive"),
+        ("png.interlace", "true or false"),
+        ("jpeg.quality", "1-100"),
+        ("jpeg.progressive", "true or false"),
+        ("avif.quality", "1-100"),
+        ("avif.speed", "1-10"),
+        ("gif.speed", "1-30"),
+        ("webp.lossless", "true (lossy WebP can't be written)"),
+    ];
+
+    /// Applies one FORMAT.KEY=VALUE setting, e.g. png.compression=best
+    pub fn set(&mut self, setting: &str) -> ImcoResult<()> {
+        let invalid = |hint: &str| ImcoError::InvalidSetting(setting.to_string(), hint.to_string());
+        let Some((key, value)) = setting.split_once('=') else { return Err(invalid("expected FORMAT.KEY=VALUE")) };
+        let (key, value) = (key.trim().to_lowercase(), value.trim());
+        let Some((_, expected)) = Self::KEYS.iter().find(|(known, _)| *known == key) else {
+            let known: Vec<_> = Self::KEYS.iter().map(|(known, _)| *known).collect();
+            return Err(invalid(&format!("unknown setting, expected one of {}", known.join(", "))))
+        };
+        let expected = || invalid(&format!("expected {expected}"));
+        let number = |max: u8| value.parse::<u8>().ok().filter(|n| (1..=max).contains(n)).ok_or_else(expected);
+        let flag = || value.parse::<bool>().map_err(|_| expected());
+        match key.as_str() {
+            "png.compression" => self.png.compression = match value {
+                "fast" => CompressionType::Fast,
+                "default" => CompressionType::Default,
+                "best" => CompressionType::Best,
+                "none" => CompressionType::Uncompressed,
+                _ => CompressionType::Level(number(9)?)
+            },
+            "png.filter" => self.png.filter = match value {
+                "none" => PngFilter::NoFilter,
+                "sub" => PngFilter::Sub,
+                "up" => PngFilter::Up,
+                "avg" => PngFilter::Avg,
+                "paeth" => PngFilter::Paeth,
+                "adaptive" => PngFilter::Adaptive,
+                _ => return Err(expected())
+            },
+            "png.interlace" => self.png.interlace = flag()?,
+            "jpeg.quality" => self.jpeg.quality = Some(number(100)?),
+            "jpeg.progressive" => self.jpeg.progressive = flag()?,
+            "avif.quality" => self.avif.quality = Some(number(100)?),
+            "avif.speed" => self.avif.speed = number(10)?,
+            "gif.speed" => self.gif.speed = number(30)? as i32,
+            _ if value == "true" => {}
+            _ => return Err(expected())
+        }
+        Ok(())
+    }
+}
+
+/// A missing side is derived from the aspect ratio of the image
+#[derive(Clone, Copy)]
+pub struct Resize {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+}
+
+/// Parses WxH, Wx or xH
+pub fn parse_resolution(s: &str) -> ImcoResult<(Option<u32>, Option<u32>)> {
+    let invalid = || ImcoError::InvalidResolution(s.to_string());
+    let (width, height) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let side = |v: &str| -> ImcoResult<Option<u32>> {
+        if v.is_empty() { return Ok(None) }
+        v.parse::<u32>().ok().filter(|n| *n > 0).map(Some).ok_or_else(invalid)
+    };
+    match (side(width)?, side(height)?) {
+        (None, None) => Err(invalid()),
+        res => Ok(res)
+    }
+}
+
+/// 3D color lookup table, from a .cube file or a HALD image
+pub struct Lut {
+    /// Entries per side
+    size: usize,
+    /// Red changing fastest, then green, then blue
+    table: Vec<[f32; 3]>,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+}
+
+impl Lut {
+    /// Files ending in .cube are read as Adobe / Resolve cubes, others decoded as HALD images
+    pub fn load(path: &str) -> ImcoResult<Self> {
+        if path.to_lowercase().ends_with(".cube") {
+            let text = io_error_convert(std::fs::read_to_string(path), path, true)?;
+            return Lut::parse_cube(&text).map_err(|hint| ImcoError::InvalidLut(path.to_string(), hint))
+        }
+        let (hald, _, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+        // A HALD of level L is L^3 pixels wide and high, holding L^2 entries per side
+        let level = (1..=16).find(|level: &u32| level.pow(3) == hald.width())
+            .filter(|_| hald.width() == hald.height())
+            .ok_or_else(|| ImcoError::InvalidLut(path.to_string(), "a HALD image is square with a side of a cubed level, like 512x512".to_string()))?;
+        let table = hald.to_rgb32f().pixels().map(|p| p.0).collect();
+        Ok(Lut { size: level.pow(2) as usize, table, domain_min: [0.0; 3], domain_max: [1.0; 3] })
+    }
+
+    fn parse_cube(text: &str) -> Result<Self, String> {
+        let mut size = None;
+        let (mut domain_min, mut domain_max) = ([0.0; 3], [1.0; 3]);
+        let mut table = vec![];
+        let triple = |values: &[&str]| -> Result<[f32; 3], String> {
+            match values.iter().map(|v| v.parse::<f32>()).collect::<Result<Vec<_>, _>>() {
+                Ok(numbers) if numbers.len() == 3 => Ok([numbers[0], numbers[1], numbers[2]]),
+                _ => Err(format!("expected three numbers, got '{}'", values.join(" ")))
+            }
+        };
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[0] {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => size = Some(words.get(1).and_then(|n| n.parse::<usize>().ok()).filter(|n| (2..=256).contains(n)).ok_or("LUT_3D_SIZE must be from 2 to 256")?),
+                "LUT_1D_SIZE" => return Err("1D LUTs aren't supported".to_string()),
+                "DOMAIN_MIN" => domain_min = triple(&words[1..])?,
+                "DOMAIN_MAX" => domain_max = triple(&words[1..])?,
+                _ => table.push(triple(&words)?),
+            }
+        }
+        let size = size.ok_or("LUT_3D_SIZE is missing")?;
+        if table.len() != size.pow(3) { return Err(format!("expected {} entries, found {}", size.pow(3), table.len())) }
+        if (0..3).any(|i| domain_max[i] <= domain_min[i]) { return Err("DOMAIN_MAX must be above DOMAIN_MIN".to_string()) }
+        Ok(Lut { size, table, domain_min, domain_max })
+    }
+
+    // Interpolates trilinearly between the 8 surrounding entries
+    fn lookup(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let steps = (self.size - 1) as f32;
+        let position: [f32; 3] = std::array::from_fn(|i| ((rgb[i] - self.domain_min[i]) / (self.domain_max[i] - self.domain_min[i])).clamp(0.0, 1.0) * steps);
+        let base = position.map(|p| (p.floor() as usize).min(self.size - 2));
+        let fraction: [f32; 3] = std::array::from_fn(|i| position[i] - base[i] as f32);
+        let mut out = [0.0; 3];
+        for corner in 0..8 {
+            let offset = [corner & 1, corner >> 1 & 1, corner >> 2 & 1];
+            let weight: f32 = (0..3).map(|i| if offset[i] == 1 { fraction[i] } else { 1.0 - fraction[i] }).product();
+            let entry = self.table[(base[0] + offset[0]) + (base[1] + offset[1]) * self.size + (base[2] + offset[2]) * self.size * self.size];
+            for i in 0..3 { out[i] += entry[i] * weight }
+        }
+        out
+    }
+}
+
+/// Overlay composited onto every output
+pub struct Watermark {
+    image: image::RgbaImage,
+    // Horizontal, vertical anchor as a fraction of the free space
+    anchor: (f32, f32),
+}
+
+impl Watermark {
+    /// Position is top-left, top, top-right, left, center, right, bottom-left, bottom or bottom-right
+    pub fn load(path: &str, position: &str, opacity: f32) -> ImcoResult<Self> {
+        let (overlay, _, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+        let mut overlay = overlay.to_rgba8();
+        if opacity < 1.0 {
+            overlay.pixels_mut().for_each(|p| p.0[3] = (p.0[3] as f32 * opacity).round() as u8);
+        }
+        Ok(Watermark { image: overlay, anchor: anchor(position) })
+    }
+
+    fn apply(&self, image: image::DynamicImage) -> image::DynamicImage {
+        let color = image.color();
+        let mut base = image.to_rgba8();
+        let free = |total: u32, used: u32, anchor: f32| ((total as f32 - used as f32) * anchor).round() as i64;
+        let x = free(base.width(), self.image.width(), self.anchor.0);
+        let y = free(base.height(), self.image.height(), self.anchor.1);
+        image::imageops::overlay(&mut base, &self.image, x, y);
+        convert_color(base.into(), color)
+    }
+}
+
+/// Grayscale image (or the luma of a color one) set as the alpha of outputs, stretched to their size
+pub struct AlphaMask {
+    luma: image::ImageBuffer<image::Luma<u16>, Vec<u16>>,
+}
+
+impl AlphaMask {
+    pub fn load(path: &str) -> ImcoResult<Self> {
+        let (mask, _, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+        Ok(AlphaMask { luma: mask.to_luma16() })
+    }
+
+    fn apply(&self, image: image::DynamicImage) -> image::DynamicImage {
+        let color = with_alpha(image.color());
+        let (width, height) = (image.width(), image.height());
+        let resized;
+        let luma = if self.luma.dimensions() == (width, height) { &self.luma } else {
+            resized = image::imageops::resize(&self.luma, width, height, FilterType::Triangle);
+            &resized
+        };
+        let mut rgba = image.into_rgba32f();
+        for (p, m) in rgba.pixels_mut().zip(luma.pixels()) { p[3] = m[0] as f32 / 65535.0 }
+        convert_color(image::DynamicImage::ImageRgba32F(rgba), color)
+    }
+}
+
+// The alpha as a grayscale image of the same depth, white where there is none
+fn extract_alpha(image: image::DynamicImage) -> image::DynamicImage {
+    let color = image.color();
+    if color.bytes_per_pixel() / color.channel_count() > 1 {
+        let rgba = image.to_rgba16();
+        image::DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| image::Luma([rgba.get_pixel(x, y)[3]])))
+    } else {
+        let rgba = image.to_rgba8();
+        image::DynamicImage::ImageLuma8(image::ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| image::Luma([rgba.get_pixel(x, y)[3]])))
+    }
+}
+
+// Horizontal, vertical fraction of the free space for a position like top-left, bottom-right for unknown ones
+fn anchor(position: &str) -> (f32, f32) {
+    match position {
+        "top-left" => (0.0, 0.0),
+        "top" => (0.5, 0.0),
+        "top-right" => (1.0, 0.0),
+        "left" => (0.0, 0.5),
+        "center" => (0.5, 0.5),
+        "right" => (1.0, 0.5),
+        "bottom-left" => (0.0, 1.0),
+        "bottom" => (0.5, 1.0),
+        _ => (1.0, 1.0)
+    }
+}
+
+/// Text stamped onto every output with a drop shadow, at least half the font size away from the edges
+pub struct Caption {
+    /// May contain {filename}, {stem}, {width}, {height} and {date}
+    text: String,
+    /// The built-in 5x7 font (ASCII only) is scaled to the size if None
+    font: Option<ab_glyph::FontVec>,
+    size: f32,
+    color: image::Rgba<u8>,
+    anchor: (f32, f32),
+}
+
+impl Caption {
+    /// Position is one of those of [`Watermark::load`], size in pixels
+    pub fn load(text: &str, font: Option<&str>, size: f32, position: &str, color: image::Rgb<u8>) -> ImcoResult<Self> {
+        let font = match font {
+            Some(path) => {
+                let data = io_error_convert(std::fs::read(path), path, true)?;
+                Some(ab_glyph::FontVec::try_from_vec(data).map_err(|_| ImcoError::InvalidFont(path.to_string()))?)
+            }
+            None => None
+        };
+        Ok(Caption { text: text.to_string(), font, size, color: image::Rgba([color[0], color[1], color[2], 255]), anchor: anchor(position) })
+    }
+
+    // Coverage of every pixel by the lines of text
+    fn render(&self, text: &str) -> image::GrayImage {
+        let lines: Vec<&str> = text.lines().collect();
+        let Some(font) = &self.font else {
+            let scale = (self.size / GLYPH_HEIGHT as f32).round().max(1.0) as u32;
+            let longest = lines.iter().map(|line| line.chars().count() as u32).max().unwrap_or(0);
+            let mut canvas = image::RgbaImage::new((longest * GLYPH_WIDTH * scale).max(1), (lines.len() as u32 * GLYPH_HEIGHT * scale).max(1));
+            for (row, line) in lines.iter().enumerate() {
+                draw_text(&mut canvas, line, 0, row as u32 * GLYPH_HEIGHT * scale, scale, image::Rgba([255; 4]));
+            }
+            return image::GrayImage::from_fn(canvas.width(), canvas.height(), |x, y| image::Luma([canvas.get_pixel(x, y)[3]]))
+        };
+        use ab_glyph::{Font, ScaleFont};
+        let scaled = font.as_scaled(self.size);
+        let line_height = scaled.height() + scaled.line_gap();
+        let mut glyphs = vec![];
+        let mut width: f32 = 0.0;
+        for (row, line) in lines.iter().enumerate() {
+            let (mut x, mut previous) = (0.0, None);
+            for c in line.chars() {
+                let id = scaled.glyph_id(c);
+                if let Some(previous) = previous { x += scaled.kern(previous, id) }
+                glyphs.push(id.with_scale_and_position(self.size, ab_glyph::point(x, scaled.ascent() + row as f32 * line_height)));
+                x += scaled.h_advance(id);
+                previous = Some(id);
+            }
+            width = width.max(x);
+        }
+        let height = line_height * lines.len() as f32 - scaled.line_gap();
+        let mut mask = image::GrayImage::new(width.ceil().max(1.0) as u32, height.ceil().max(1.0) as u32);
+        for outlined in glyphs.into_iter().filter_map(|glyph| font.outline_glyph(glyph)) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, coverage| {
+                let (x, y) = (bounds.min.x as i64 + x as i64, bounds.min.y as i64 + y as i64);
+                if let (Ok(x), Ok(y)) = (u32::try_from(x), u32::try_from(y)) {
+                    if let Some(p) = mask.get_pixel_mut_checked(x, y) { p[0] = p[0].max((coverage * 255.0).round() as u8) }
+                }
+            });
+        }
+        mask
+    }
+
+    fn apply(&self, image: image::DynamicImage, path: &Path) -> image::DynamicImage {
+        let text = self.text.replace("{filename}", &path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default())
+            .replace("{stem}", &path.file_stem().map(|n| n.to_string_lossy()).unwrap_or_default())
+            .replace("{width}", &image.width().to_string())
+            .replace("{height}", &image.height().to_string())
+            .replace("{date}", &today());
+        let mask = self.render(&text);
+        // The shadow is offset by a 16th of the font size to the bottom right
+        let offset = (self.size / 16.0).round().max(1.0) as u32;
+        let mut stamp = image::RgbaImage::new(mask.width() + offset, mask.height() + offset);
+        for (x, y, coverage) in mask.enumerate_pixels() {
+            stamp.put_pixel(x + offset, y + offset, image::Rgba([0, 0, 0, coverage[0] / 2]));
+        }
+        for (x, y, coverage) in mask.enumerate_pixels() {
+            let mut ink = self.color;
+            ink[3] = coverage[0];
+            image::Pixel::blend(stamp.get_pixel_mut(x, y), &ink);
+        }
+        let color = image.color();
+        let mut base = image.to_rgba8();
+        let margin = (self.size / 2.0).round() as i64;
+        let place = |total: u32, used: u32, anchor: f32| margin + ((total as f32 - used as f32 - 2.0 * margin as f32) * anchor).round() as i64;
+        let x = place(base.width(), stamp.width(), self.anchor.0);
+        let y = place(base.height(), stamp.height(), self.anchor.1);
+        image::imageops::overlay(&mut base, &stamp, x, y);
+        convert_color(base.into(), color)
+    }
+}
+
+/// A transform of the operation pipeline defined outside of imco, see [`register_operation`]
+pub trait CustomOperation: Send + Sync {
+    /// Transforms the image with the arguments given after the operation's name (empty without any),
+    /// failing with a message for users. Called from several threads at once with [`Options::jobs`]
+    fn apply(&self, image: image::DynamicImage, args: &str) -> Result<image::DynamicImage, String>;
+
+    /// Checks the arguments when the pipeline is parsed, accepting any by default
+    fn check(&self, _args: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Registered operations by name
+static CUSTOM_OPERATIONS: Mutex<Vec<(String, Arc<dyn CustomOperation>)>> = Mutex::new(Vec::new());
+
+/// Makes `name` an operation of [`parse_ops`], replacing an operation registered under that name before.
+/// Built in operations can't be replaced
+pub fn register_operation(name: &str, operation: impl CustomOperation + 'static) {
+    let mut operations = CUSTOM_OPERATIONS.lock().unwrap();
+    operations.retain(|(registered, _)| registered != name);
+    operations.push((name.to_string(), Arc::new(operation)));
+}
+
+/// Names of the operations registered with [`register_operation`]
+pub fn custom_operations() -> Vec<String> {
+    CUSTOM_OPERATIONS.lock().unwrap().iter().map(|(name, _)| name.clone()).collect()
+}
+
+/// A registered operation of a pipeline with its arguments
+#[derive(Clone)]
+pub struct Custom {
+    pub name: String,
+    pub args: String,
+    operation: Arc<dyn CustomOperation>,
+}
+
+/// One step of an operation pipeline
+#[derive(Clone)]
+pub enum Operation {
+    /// Apply the EXIF orientation here instead of right after decoding
+    AutoRotate,
+    /// Clockwise degrees
+    Rotate(u16),
+    RotateDegrees(Rotation),
+    FlipHorizontal,
+    FlipVertical,
+    Crop(Crop),
+    Resize(Resize),
+    Thumbnail(u32),
+    AutoLevels(AutoLevels),
+    Brightness(i32),
+    Contrast(f32),
+    Gamma(f32),
+    Grayscale,
+    Invert,
+    Sepia,
+    Tint(Tint),
+    Vignette(Vignette),
+    Threshold(u8),
+    /// Levels per channel
+    Posterize(u8),
+    Blur(f32),
+    Unsharp(Unsharp),
+    Denoise(Denoise),
+    Pad(Pad),
+    Corners(Corners),
+    /// A square frame, also around cut corners
+    Border(Border),
+    CropTo(CropTo),
+    Aspect(Aspect),
+    Trim(Trim),
+    ChromaKey(ChromaKey),
+    /// Resizes both sides by the factor
+    Scale(f64),
+    Custom(Custom),
+}
+
+/// Where the crop window of [`CropTo`] is placed
+#[derive(Clone, Copy)]
+pub enum Gravity {
+    Center,
+    North,
+    South,
+    East,
+    West,
+    /// The window with the most detail (entropy of the luma histogram)
+    Entropy,
+    /// The window with the most edges, saturated colors and skin tones
+    Attention,
+}
+
+/// Clockwise rotation by any angle, growing the canvas to fit the rotated image
+/// or, with crop, keeping the original size
+#[derive(Clone, Copy)]
+pub struct Rotation {
+    pub degrees: f32,
+    pub crop: bool,
+}
+
+/// Scales the image to cover width x height and crops the overflow
+#[derive(Clone, Copy)]
+pub struct CropTo {
+    pub width: u32,
+    pub height: u32,
+    pub gravity: Gravity,
+}
+
+/// Crops to the largest window with the aspect ratio width:height, placed after the gravity
+#[derive(Clone, Copy)]
+pub struct Aspect {
+    pub width: u32,
+    pub height: u32,
+    pub gravity: Gravity,
+}
+
+/// Parses W:H
+pub fn parse_aspect(s: &str, gravity: Gravity) -> ImcoResult<Aspect> {
+    let invalid = || ImcoError::InvalidAspect(s.to_string());
+    let (width, height) = s.split_once(':').ok_or_else(invalid)?;
+    let term = |n: &str| n.trim().parse::<u32>().ok().filter(|n| *n > 0).ok_or_else(invalid);
+    Ok(Aspect { width: term(width)?, height: term(height)?, gravity })
+}
+
+/// Crops away the border of the color of the top left corner, or the transparent margins if that is transparent.
+/// Colors differing by up to fuzz percent in every channel count as the border, uniform images are left alone
+#[derive(Clone, Copy)]
+pub struct Trim {
+    pub fuzz: f32,
+}
+
+/// Makes the background of this color transparent, like the green or white sweep behind product shots.
+/// Colors differing by up to tolerance percent in every channel are keyed out, those up to twice as far
+/// fade in to soften the edges
+#[derive(Clone, Copy)]
+pub struct ChromaKey {
+    pub color: image::Rgb<u8>,
+    pub tolerance: f32,
+}
+
+/// Gravity by name, see [`Gravity`]
+pub fn parse_gravity(name: &str) -> Option<Gravity> {
+    Some(match name {
+        "center" => Gravity::Center,
+        "north" => Gravity::North,
+        "south" => Gravity::South,
+        "east" => Gravity::East,
+        "west" => Gravity::West,
+        "entropy" => Gravity::Entropy,
+        "attention" => Gravity::Attention,
+        _ => return None
+    })
+}
+
+impl fmt::Display for Gravity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Gravity::Center => "center",
+            Gravity::North => "north",
+            Gravity::South => "south",
+            Gravity::East => "east",
+            Gravity::West => "west",
+            Gravity::Entropy => "entropy",
+            Gravity::Attention => "attention",
+        })
+    }
+}
+
+/// Centers the image on a canvas of this size, shrinking it first if it doesn't fit
+#[derive(Clone, Copy)]
+pub struct Pad {
+    pub width: u32,
+    pub height: u32,
+    pub color: image::Rgb<u8>,
+}
+
+fn pad_image(image: image::DynamicImage, pad: &Pad, filter: FilterType) -> image::DynamicImage {
+    let color = image.color();
+    let fitted = if image.width() > pad.width || image.height() > pad.height {
+        image.resize(pad.width, pad.height, filter)
+    } else { image };
+    let [r, g, b] = pad.color.0.map(|c| c as f32 / 255.0);
+    let mut canvas = image::Rgba32FImage::from_pixel(pad.width, pad.height, image::Rgba([r, g, b, 1.0]));
+    let (x, y) = ((pad.width - fitted.width()) / 2, (pad.height - fitted.height()) / 2);
+    image::imageops::overlay(&mut canvas, &fitted.into_rgba32f(), x as i64, y as i64);
+    convert_color(image::DynamicImage::ImageRgba32F(canvas), color)
+}
+
+/// Frame of width pixels around the image, growing it
+#[derive(Clone, Copy)]
+pub struct Border {
+    pub width: u32,
+    pub color: image::Rgb<u8>,
+}
+
+/// Parses WIDTH[:COLOR], the color being black if left out
+pub fn parse_border(s: &str) -> ImcoResult<Border> {
+    let invalid = || ImcoError::InvalidBorder(s.to_string());
+    let (width, color) = s.split_once(':').unwrap_or((s, "#000000"));
+    Ok(Border { width: width.parse().map_err(|_| invalid())?, color: parse_color(color).map_err(|_| invalid())? })
+}
+
+// With the corners the image was cut with, the border is rounded alike (a ring around circles)
+fn add_border(image: image::DynamicImage, border: &Border, corners: Option<&Corners>) -> image::DynamicImage {
+    let [r, g, b] = border.color.0;
+    // Gray images only stay gray with a gray border
+    let color = if r == g && g == b { image.color() } else { colorful(image.color()) };
+    let (width, height) = (image.width() + 2 * border.width, image.height() + 2 * border.width);
+    let fill = border.color.0.map(|c| c as f32 / 255.0);
+    let mut canvas = image::Rgba32FImage::from_pixel(width, height, image::Rgba([fill[0], fill[1], fill[2], 1.0]));
+    if let Some(corners) = corners {
+        let radius = corner_radius(corners, image.width(), image.height()) + border.width as f32;
+        for (x, y, p) in canvas.enumerate_pixels_mut() { p[3] = rounded_coverage(x, y, width, height, radius) }
+    }
+    image::imageops::overlay(&mut canvas, &image.into_rgba32f(), border.width as i64, border.width as i64);
+    convert_color(image::DynamicImage::ImageRgba32F(canvas), if corners.is_some() { with_alpha(color) } else { color })
+}
+
+/// Corners made transparent, for avatars and cards
+#[derive(Clone, Copy)]
+pub enum Corners {
+    /// Radius in pixels, at most half the shorter side
+    Round(u32),
+    /// Crop the centered square and keep the circle in it
+    Circle,
+}
+
+fn cut_corners(image: image::DynamicImage, corners: &Corners) -> image::DynamicImage {
+    let color = with_alpha(image.color());
+    let image = match corners {
+        Corners::Round(_) => image,
+        Corners::Circle => {
+            let side = image.width().min(image.height());
+            image.crop_imm((image.width() - side) / 2, (image.height() - side) / 2, side, side)
+        }
+    };
+    let (width, height) = (image.width(), image.height());
+    let radius = corner_radius(corners, width, height);
+    let mut rgba = image.into_rgba32f();
+    for (x, y, p) in rgba.enumerate_pixels_mut() { p[3] *= rounded_coverage(x, y, width, height, radius) }
+    convert_color(image::DynamicImage::ImageRgba32F(rgba), color)
+}
+
+// Of an image of this size after cutting the corners
+fn corner_radius(corners: &Corners, width: u32, height: u32) -> f32 {
+    match corners {
+        Corners::Round(radius) => (*radius).min(width.min(height) / 2) as f32,
+        Corners::Circle => width.min(height) as f32 / 2.0,
+    }
+}
+
+// How much of the pixel lies inside the rectangle with corners of radius, fading over one pixel so the edges stay smooth
+fn rounded_coverage(x: u32, y: u32, width: u32, height: u32, radius: f32) -> f32 {
+    let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+    // Distance into the corner square, from the center of its circle
+    let dx = (radius - px).max(px - (width as f32 - radius)).max(0.0);
+    let dy = (radius - py).max(py - (height as f32 - radius)).max(0.0);
+    if dx == 0.0 || dy == 0.0 { return 1.0 }
+    (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0)
+}
+
+// The color type with an alpha channel of the same depth
+fn with_alpha(color: image::ColorType) -> image::ColorType {
+    use image::ColorType;
+    match color {
+        ColorType::L8 => ColorType::La8,
+        ColorType::L16 => ColorType::La16,
+        ColorType::Rgb16 => ColorType::Rgba16,
+        ColorType::Rgb32F => ColorType::Rgba32F,
+        color if color.has_alpha() => color,
+        _ => ColorType::Rgba8,
+    }
+}
+
+/// Adds amount times the difference to a gaussian blur of radius (its sigma), leaving
+/// differences of at most threshold (out of 255) alone so flat areas don't get noisy
+#[derive(Clone, Copy)]
+pub struct Unsharp {
+    pub amount: f32,
+    pub radius: f32,
+    pub threshold: u8,
+}
+
+impl Unsharp {
+    /// What --sharpen applies, enough for downscaled images
+    pub fn light() -> Self {
+        Unsharp { amount: 0.5, radius: 1.0, threshold: 2 }
+    }
+}
+
+/// Noise reduction, keeping edges. For the median filter the strength is the radius of the
+/// window in pixels, for the bilateral filter the sigma of its distance weights in pixels
+/// and a tenth of the sigma (out of 255) of its color difference weights
+#[derive(Clone, Copy)]
+pub struct Denoise {
+    pub strength: f32,
+    pub method: DenoiseMethod,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DenoiseMethod {
+    /// Removes speckles and salt and pepper noise
+    Median,
+    /// Smooths grain while keeping edges and fine detail
+    Bilateral,
+}
+
+/// Parses STRENGTH[:median|bilateral], bilateral by default
+pub fn parse_denoise(s: &str) -> ImcoResult<Denoise> {
+    let invalid = || ImcoError::InvalidDenoise(s.to_string());
+    let (strength, method) = s.split_once(':').unwrap_or((s, "bilateral"));
+    let strength = strength.trim().parse::<f32>().ok().filter(|n| n.is_finite() && *n > 0.0).ok_or_else(invalid)?;
+    let method = match method.trim() {
+        "median" => DenoiseMethod::Median,
+        "bilateral" => DenoiseMethod::Bilateral,
+        _ => return Err(invalid())
+    };
+    Ok(Denoise { strength, method })
+}
+
+fn denoise(image: image::DynamicImage, denoise: &Denoise) -> image::DynamicImage {
+    let color = image.color();
+    let source = image.into_rgba32f();
+    let (width, height) = source.dimensions();
+    let radius = match denoise.method {
+        DenoiseMethod::Median => denoise.strength.round().max(1.0) as i64,
+        DenoiseMethod::Bilateral => (denoise.strength * 2.0).ceil() as i64,
+    };
+    let range = 2.0 * (denoise.strength * 10.0 / 255.0).powi(2);
+    let spatial: Vec<f32> = (-radius..=radius).flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+        .map(|(dx, dy)| (-((dx * dx + dy * dy) as f32) / (2.0 * denoise.strength * denoise.strength)).exp())
+        .collect();
+    // The window around a pixel, clamped to the edges
+    let source = &source;
+    let window = move |x: u32, y: u32| (-radius..=radius).flat_map(move |dy| (-radius..=radius).map(move |dx| {
+        let sx = (x as i64 + dx).clamp(0, width as i64 - 1) as u32;
+        let sy = (y as i64 + dy).clamp(0, height as i64 - 1) as u32;
+        source.get_pixel(sx, sy).0
+    }));
+    let mut output = image::Rgba32FImage::new(width, height);
+    output.par_enumerate_pixels_mut().for_each_init(Vec::new, |values, (x, y, pixel)| match denoise.method {
+        DenoiseMethod::Median => for c in 0..4 {
+            values.clear();
+            values.extend(window(x, y).map(|p| p[c]));
+            let middle = values.len() / 2;
+            pixel.0[c] = *values.select_nth_unstable_by(middle, f32::total_cmp).1;
+        },
+        DenoiseMethod::Bilateral => {
+            let center = source.get_pixel(x, y).0;
+            let mut sum = [0.0; 4];
+            let mut total = 0.0;
+            for (p, weight) in window(x, y).zip(&spatial) {
+                let difference: f32 = (0..3).map(|c| (p[c] - center[c]).powi(2)).sum();
+                let weight = weight * (-difference / range).exp();
+                for c in 0..4 { sum[c] += weight * p[c] }
+                total += weight;
+            }
+            pixel.0 = sum.map(|channel| channel / total);
+        }
+    });
+    convert_color(image::DynamicImage::ImageRgba32F(output), color)
+}
+
+/// Parses a size in bytes with an optional unit, B, KB, MB and GB being powers of 1000,
+/// KiB, MiB and GiB powers of 1024
+pub fn parse_size(s: &str) -> ImcoResult<u64> {
+    let invalid = || ImcoError::InvalidSize(s.to_string());
+    let trimmed = s.trim();
+    let split = trimmed.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000 * 1000,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(invalid())
+    };
+    let number = number.parse::<f64>().ok().filter(|n| n.is_finite()).ok_or_else(invalid)?;
+    Some((number * multiplier as f64).round() as u64).filter(|bytes| *bytes > 0).ok_or_else(invalid)
+}
+
+/// Parses AMOUNT,RADIUS[,THRESHOLD]
+pub fn parse_unsharp(s: &str) -> ImcoResult<Unsharp> {
+    let invalid = || ImcoError::InvalidUnsharp(s.to_string());
+    let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+    let number = |part: &str| part.parse::<f32>().ok().filter(|n| n.is_finite() && *n >= 0.0).ok_or_else(invalid);
+    let (amount, radius, threshold) = match parts[..] {
+        [amount, radius] => (number(amount)?, number(radius)?, 0),
+        [amount, radius, threshold] => (number(amount)?, number(radius)?, threshold.parse().map_err(|_| invalid())?),
+        _ => return Err(invalid())
+    };
+    if radius == 0.0 { return Err(invalid()) }
+    Ok(Unsharp { amount, radius, threshold })
+}
+
+fn unsharp_mask(image: image::DynamicImage, mask: &Unsharp) -> image::DynamicImage {
+    let color = image.color();
+    let float = matches!(color, image::ColorType::Rgb32F | image::ColorType::Rgba32F);
+    let blurred = image.blur(mask.radius).into_rgba32f();
+    let mut sharpened = image.into_rgba32f();
+    let threshold = mask.threshold as f32 / 255.0;
+    for (p, b) in sharpened.pixels_mut().zip(blurred.pixels()) {
+        for c in 0..3 {
+            let difference = p.0[c] - b.0[c];
+            if difference.abs() <= threshold { continue }
+            p.0[c] += mask.amount * difference;
+            if !float { p.0[c] = p.0[c].clamp(0.0, 1.0) }
+        }
+    }
+    convert_color(image::DynamicImage::ImageRgba32F(sharpened), color)
+}
+
+/// Parses operations separated by '|', each a name followed by its ':' separated arguments:
+/// autorotate, rotate:DEGREES[:crop], flip:h|v, crop:X,Y,WxH, crop:center:WxH, resize:WxH[:contain|cover],
+/// scale:PERCENT, thumbnail:SIZE, auto-levels[:CLIP], auto-contrast[:CLIP], brightness:N, contrast:N, gamma:N, grayscale,
+/// invert, sepia, tint:COLOR[:STRENGTH], vignette:STRENGTH[,RADIUS], threshold:LUMA, posterize:LEVELS, blur:SIGMA, denoise:STRENGTH[:METHOD], sharpen,
+/// unsharp:AMOUNT,RADIUS[,THRESHOLD], pad:WxH[:COLOR], border:WIDTH[:COLOR], round-corners:RADIUS,
+/// circle-crop, crop-to:WxH[:GRAVITY], aspect:W:H[:GRAVITY], trim[:FUZZ%] and chroma-key:COLOR[:TOLERANCE%], followed
+/// by those of [`register_operation`]
+pub fn parse_ops(s: &str) -> ImcoResult<Vec<Operation>> {
+    s.split('|').map(str::trim).map(|op| {
+        let invalid = |hint: &str| ImcoError::InvalidOperation(op.to_string(), hint.to_string());
+        let (name, args) = op.split_once(':').unwrap_or((op, ""));
+        let number = |args: &str| args.parse::<f32>().map_err(|_| invalid("expected a number"));
+        Ok(match (name, args) {
+            ("autorotate", "") => Operation::AutoRotate,
+            ("rotate", "90" | "180" | "270") => Operation::Rotate(args.parse().unwrap()),
+            ("rotate", args) => {
+                let (degrees, crop) = match args.strip_suffix(":crop") {
+                    Some(degrees) => (degrees, true),
+                    None => (args, false)
+                };
+                let degrees = number(degrees).ok().filter(|d| d.is_finite()).ok_or_else(|| invalid("expected DEGREES or DEGREES:crop"))?;
+                Operation::RotateDegrees(Rotation { degrees, crop })
+            }
+            ("flip", "h") => Operation::FlipHorizontal,
+            ("flip", "v") => Operation::FlipVertical,
+            ("flip", _) => return Err(invalid("expected h or v")),
+            ("crop", args) => match args.strip_prefix("center:") {
+                Some(size) => match parse_resolution(size) {
+                    Ok((Some(width), Some(height))) => Operation::Crop(Crop::Center(width, height)),
+                    _ => return Err(invalid("expected center:WxH"))
+                },
+                None => Operation::Crop(parse_crop(args).map_err(|_| invalid("expected X,Y,WxH or center:WxH"))?)
+            },
+            ("resize", args) => {
+                let (size, fit) = args.split_once(':').unwrap_or((args, "exact"));
+                let (width, height) = parse_resolution(size).map_err(|_| invalid("expected WxH, Wx or xH"))?;
+                let fit = match fit {
+                    "exact" => Fit::Exact,
+                    "contain" => Fit::Contain,
+                    "cover" => Fit::Cover,
+                    _ => return Err(invalid("fit must be exact, contain or cover"))
+                };
+                Operation::Resize(Resize { width, height, fit })
+            }
+            ("scale", args) => Operation::Scale(parse_scale(args).map_err(|_| invalid("expected a percentage like 50% or a factor like 0.5"))?),
+            ("thumbnail", args) => Operation::Thumbnail(args.parse().ok().filter(|size| *size > 0).ok_or_else(|| invalid("expected a size in pixels"))?),
+            ("auto-levels" | "auto-contrast", args) => {
+                let clip = if args.is_empty() { 0.5 } else { number(args)? };
+                if !(0.0..50.0).contains(&clip) { return Err(invalid("expected a clip percentage below 50")) }
+                Operation::AutoLevels(AutoLevels { per_channel: name == "auto-levels", clip })
+            }
+            ("brightness", args) => Operation::Brightness(args.parse().map_err(|_| invalid("expected a whole number"))?),
+            ("contrast", args) => Operation::Contrast(number(args)?),
+            ("gamma", args) => Operation::Gamma(number(args)?),
+            ("grayscale", "") => Operation::Grayscale,
+            ("invert", "") => Operation::Invert,
+            ("sepia", "") => Operation::Sepia,
+            ("threshold", args) => Operation::Threshold(args.parse().map_err(|_| invalid("expected a luma from 0 to 255"))?),
+            ("posterize", args) => Operation::Posterize(args.parse().ok().filter(|levels| *levels >= 2).ok_or_else(|| invalid("expected 2 to 255 levels"))?),
+            ("tint", args) => Operation::Tint(parse_tint(args).map_err(|_| invalid("expected COLOR[:STRENGTH]"))?),
+            ("vignette", args) => Operation::Vignette(parse_vignette(args).map_err(|_| invalid("expected STRENGTH[,RADIUS]"))?),
+            ("blur", args) => Operation::Blur(number(args)?),
+            ("denoise", args) => Operation::Denoise(parse_denoise(args).map_err(|_| invalid("expected STRENGTH[:median|bilateral]"))?),
+            ("sharpen", "") => Operation::Unsharp(Unsharp::light()),
+            ("crop-to", args) => {
+                let (size, gravity) = args.split_once(':').unwrap_or((args, "center"));
+                let (Ok((Some(width), Some(height))), Some(gravity)) = (parse_resolution(size), parse_gravity(gravity)) else {
+                    return Err(invalid("expected WxH or WxH:GRAVITY"))
+                };
+                Operation::CropTo(CropTo { width, height, gravity })
+            }
+            ("aspect", args) => {
+                let (ratio, gravity) = match args.match_indices(':').nth(1) {
+                    Some((index, _)) => (&args[..index], &args[index + 1..]),
+                    None => (args, "center")
+                };
+                let (Some(gravity), Ok(aspect)) = (parse_gravity(gravity), parse_aspect(ratio, Gravity::Center)) else {
+                    return Err(invalid("expected W:H or W:H:GRAVITY"))
+                };
+                Operation::Aspect(Aspect { gravity, ..aspect })
+            }
+            ("trim", args) => {
+                let fuzz = if args.is_empty() { 0.0 } else { number(args.strip_suffix('%').unwrap_or(args))? };
+                if !(0.0..=100.0).contains(&fuzz) { return Err(invalid("expected a fuzz percentage up to 100")) }
+                Operation::Trim(Trim { fuzz })
+            }
+            ("chroma-key", args) => {
+                let (color, tolerance) = args.split_once(':').unwrap_or((args, "10"));
+                let (Ok(color), Ok(tolerance)) = (parse_color(color), tolerance.strip_suffix('%').unwrap_or(tolerance).parse::<f32>()) else {
+                    return Err(invalid("expected COLOR or COLOR:TOLERANCE%"))
+                };
+                if !(0.0..=100.0).contains(&tolerance) { return Err(invalid("expected a tolerance percentage up to 100")) }
+                Operation::ChromaKey(ChromaKey { color, tolerance })
+            }
+            ("pad", args) => {
+                let (size, color) = args.split_once(':').unwrap_or((args, "#000000"));
+                let (Ok((Some(width), Some(height))), Ok(color)) = (parse_resolution(size), parse_color(color)) else {
+                    return Err(invalid("expected WxH or WxH:COLOR"))
+                };
+                Operation::Pad(Pad { width, height, color })
+            }
+            ("border", args) => Operation::Border(parse_border(args).map_err(|_| invalid("expected WIDTH[:COLOR]"))?),
+            ("round-corners", args) => Operation::Corners(Corners::Round(args.parse().map_err(|_| invalid("expected a radius in pixels"))?)),
+            ("circle-crop", "") => Operation::Corners(Corners::Circle),
+            ("unsharp", args) => Operation::Unsharp(parse_unsharp(args).map_err(|_| invalid("expected AMOUNT,RADIUS[,THRESHOLD]"))?),
+            ("autorotate" | "grayscale" | "invert" | "sepia" | "sharpen" | "circle-crop", _) => return Err(invalid("takes no arguments")),
+            (name, args) => {
+                let operations = CUSTOM_OPERATIONS.lock().unwrap();
+                let Some((_, operation)) = operations.iter().find(|(registered, _)| registered == name) else { return Err(invalid("unknown operation")) };
+                operation.check(args).map_err(|hint| invalid(&hint))?;
+                Operation::Custom(Custom { name: name.to_string(), args: args.to_string(), operation: operation.clone() })
+            }
+        })
+    }).collect()
+}
+
+// Written back in the syntax of parse_ops
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |side: Option<u32>| side.map(|s| s.to_string()).unwrap_or_default();
+        match self {
+            Operation::AutoRotate => write!(f, "autorotate"),
+            Operation::Rotate(degrees) => write!(f, "rotate:{degrees}"),
+            Operation::RotateDegrees(rotation) => write!(f, "rotate:{}{}", rotation.degrees, if rotation.crop { ":crop" } else { "" }),
+            Operation::FlipHorizontal => write!(f, "flip:h"),
+            Operation::FlipVertical => write!(f, "flip:v"),
+            Operation::Crop(Crop::Area(x, y, width, height)) => write!(f, "crop:{x},{y},{width}x{height}"),
+            Operation::Crop(Crop::Center(width, height)) => write!(f, "crop:center:{width}x{height}"),
+            Operation::Resize(resize) => {
+                write!(f, "resize:{}x{}", side(resize.width), side(resize.height))?;
+                match resize.fit {
+                    Fit::Exact => Ok(()),
+                    Fit::Contain => write!(f, ":contain"),
+                    Fit::Cover => write!(f, ":cover"),
+                }
+            }
+            Operation::Thumbnail(size) => write!(f, "thumbnail:{size}"),
+            Operation::AutoLevels(levels) => write!(f, "{}:{}", if levels.per_channel { "auto-levels" } else { "auto-contrast" }, levels.clip),
+            Operation::Brightness(value) => write!(f, "brightness:{value}"),
+            Operation::Contrast(value) => write!(f, "contrast:{value}"),
+            Operation::Gamma(value) => write!(f, "gamma:{value}"),
+            Operation::Grayscale => write!(f, "grayscale"),
+            Operation::Invert => write!(f, "invert"),
+            Operation::Sepia => write!(f, "sepia"),
+            Operation::Threshold(luma) => write!(f, "threshold:{luma}"),
+            Operation::Posterize(levels) => write!(f, "posterize:{levels}"),
+            Operation::Tint(tint) => write!(f, "tint:#{:02x}{:02x}{:02x}:{}", tint.color.0[0], tint.color.0[1], tint.color.0[2], tint.strength),
+            Operation::Vignette(vignette) => write!(f, "vignette:{},{}", vignette.strength, vignette.radius),
+            Operation::Blur(sigma) => write!(f, "blur:{sigma}"),
+            Operation::Denoise(denoise) => write!(f, "denoise:{}:{}", denoise.strength, if denoise.method == DenoiseMethod::Median { "median" } else { "bilateral" }),
+            Operation::Unsharp(mask) => write!(f, "unsharp:{},{},{}", mask.amount, mask.radius, mask.threshold),
+            Operation::CropTo(crop) => write!(f, "crop-to:{}x{}:{}", crop.width, crop.height, crop.gravity),
+            Operation::Aspect(aspect) => write!(f, "aspect:{}:{}:{}", aspect.width, aspect.height, aspect.gravity),
+            Operation::Trim(trim) => write!(f, "trim:{}%", trim.fuzz),
+            Operation::ChromaKey(key) => write!(f, "chroma-key:#{:02x}{:02x}{:02x}:{}%", key.color.0[0], key.color.0[1], key.color.0[2], key.tolerance),
+            Operation::Scale(factor) => write!(f, "scale:{}%", factor * 100.0),
+            Operation::Pad(pad) => write!(f, "pad:{}x{}:#{:02x}{:02x}{:02x}", pad.width, pad.height, pad.color.0[0], pad.color.0[1], pad.color.0[2]),
+            Operation::Border(border) => write!(f, "border:{}:#{:02x}{:02x}{:02x}", border.width, border.color.0[0], border.color.0[1], border.color.0[2]),
+            Operation::Corners(Corners::Round(radius)) => write!(f, "round-corners:{radius}"),
+            Operation::Corners(Corners::Circle) => write!(f, "circle-crop"),
+            Operation::Custom(custom) if custom.args.is_empty() => write!(f, "{}", custom.name),
+            Operation::Custom(custom) => write!(f, "{}:{}", custom.name, custom.args),
+        }
+    }
+}
+
+fn apply_operation(image: image::DynamicImage, op: &Operation, orientation: Option<image::metadata::Orientation>, opts: &Options, path: &Path) -> ImcoResult<image::DynamicImage> {
+    check_grown(&image, op, opts, path)?;
+    Ok(match op {
+        Operation::AutoRotate => {
+            let mut image = image;
+            if let Some(orientation) = orientation { image.apply_orientation(orientation) }
+            image
+        }
+        Operation::Rotate(90) => image.rotate90(),
+        Operation::Rotate(180) => image.rotate180(),
+        Operation::Rotate(_) => image.rotate270(),
+        Operation::RotateDegrees(rotation) => rotate_degrees(image, rotation, opts.background),
+        Operation::FlipHorizontal => image.fliph(),
+        Operation::FlipVertical => image.flipv(),
+        Operation::Crop(crop) => crop_image(image, crop, path)?,
+        Operation::Resize(resize) => linear_light(image, opts.linear_resize, |image| resize_image(image, resize, opts.filter, opts.resize_backend, opts.gpu.as_deref())),
+        Operation::Thumbnail(size) => image.thumbnail(*size, *size),
+        Operation::AutoLevels(levels) => auto_levels(image, levels),
+        Operation::Brightness(value) => image.brighten(*value),
+        Operation::Contrast(value) => image.adjust_contrast(*value),
+        Operation::Gamma(gamma) => adjust_gamma(image, *gamma),
+        Operation::Grayscale => image.grayscale(),
+        Operation::Invert => invert(image),
+        Operation::Sepia => sepia(image, opts.gpu.as_deref()),
+        Operation::Tint(tint) => tint_image(image, tint),
+        Operation::Vignette(vignette) => vignette_image(image, vignette),
+        Operation::Threshold(luma) => threshold(image, *luma),
+        Operation::Posterize(levels) => posterize(image, *levels),
+        Operation::Blur(sigma) => image.blur(*sigma),
+        Operation::Denoise(settings) => denoise(image, settings),
+        Operation::Unsharp(mask) => unsharp_mask(image, mask),
+        Operation::Pad(pad) => pad_image(image, pad, opts.filter),
+        Operation::Corners(corners) => cut_corners(image, corners),
+        Operation::Border(border) => add_border(image, border, None),
+        Operation::CropTo(crop) => linear_light(image, opts.linear_resize, |image| crop_to(image, crop, opts.filter)),
+        Operation::Aspect(aspect) => crop_to_aspect(image, aspect, opts.filter),
+        Operation::Trim(trim) => trim_image(image, trim),
+        Operation::ChromaKey(key) => chroma_key(image, key),
+        Operation::Scale(factor) => scale_image(image, *factor, opts),
+        Operation::Custom(custom) => custom.operation.apply(image, &custom.args)
+            .map_err(|message| ImcoError::OperationFailed(path.display().to_string(), custom.name.clone(), message))?,
+    })
+}
+
+// The largest canvas an operation allocates for the image, None for those that don't grow it
+fn grown_size(image: &image::DynamicImage, op: &Operation) -> Option<(u64, u64)> {
+    let (width, height) = (image.width() as u64, image.height() as u64);
+    match op {
+        Operation::Resize(resize) => resize_dimensions(image, resize).map(|(width, height, _)| (width as u64, height as u64)),
+        Operation::Scale(factor) => Some((scaled_side(image.width(), *factor), scaled_side(image.height(), *factor))),
+        Operation::Pad(pad) => Some((pad.width as u64, pad.height as u64)),
+        Operation::Border(border) => Some((width + 2 * border.width as u64, height + 2 * border.width as u64)),
+        // Resized to cover the crop first
+        Operation::CropTo(crop) => {
+            let scale = f64::max(crop.width as f64 / width as f64, crop.height as f64 / height as f64);
+            Some((((width as f64 * scale).round() as u64).max(crop.width as u64), ((height as f64 * scale).round() as u64).max(crop.height as u64)))
+        }
+        _ => None
+    }
+}
+
+// Fails when the canvas of the operation would be over the limits, instead of running out of memory allocating it
+fn check_grown(image: &image::DynamicImage, op: &Operation, opts: &Options, path: &Path) -> ImcoResult<()> {
+    match grown_size(image, op) {
+        Some(size) => opts.limits.check_canvas(size, image.color(), path),
+        None => Ok(())
+    }
+}
+
+// An autorotate operation moves the EXIF orientation into the pipeline
+fn defers_orientation(opts: &Options) -> bool {
+    opts.ops.iter().any(|op| matches!(op, Operation::AutoRotate))
+}
+
+#[derive(Clone, Copy)]
+pub enum Crop {
+    /// X, y, width, height
+    Area(u32, u32, u32, u32),
+    /// Width, height around the center
+    Center(u32, u32),
+}
+
+/// Parses X,Y,WxH
+pub fn parse_crop(s: &str) -> ImcoResult<Crop> {
+    let invalid = || ImcoError::InvalidCrop(s.to_string());
+    let mut parts = s.splitn(3, ',');
+    let (Some(x), Some(y), Some(size)) = (parts.next(), parts.next(), parts.next()) else { return Err(invalid()) };
+    let (x, y) = (x.trim().parse::<u32>().map_err(|_| invalid())?, y.trim().parse::<u32>().map_err(|_| invalid())?);
+    match parse_resolution(size.trim()).map_err(|_| invalid())? {
+        (Some(width), Some(height)) => Ok(Crop::Area(x, y, width, height)),
+        _ => Err(invalid())
+    }
+}
+
+/// A range of pages, counted from 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pages {
+    pub first: usize,
+    /// Up to the last page if None
+    pub last: Option<usize>,
+}
+
+/// Parses all, N, FIRST-LAST or FIRST-
+pub fn parse_pages(s: &str) -> ImcoResult<Pages> {
+    let invalid = || ImcoError::InvalidPages(s.to_string());
+    let page = |n: &str| n.trim().parse::<usize>().ok().filter(|&n| n > 0).ok_or_else(invalid);
+    let pages = match s.trim().split_once('-') {
+        _ if s.trim().eq_ignore_ascii_case("all") => Pages { first: 1, last: None },
+        None => Pages { first: page(s)?, last: Some(page(s)?) },
+        Some((first, "")) => Pages { first: page(first)?, last: None },
+        Some((first, last)) => Pages { first: page(first)?, last: Some(page(last)?) },
+    };
+    if pages.last.is_some_and(|last| last < pages.first) { return Err(invalid()) }
+    Ok(pages)
+}
+
+/// Parses #RRGGBB or #RGB, the # being optional
+pub fn parse_color(s: &str) -> ImcoResult<image::Rgb<u8>> {
+    let invalid = || ImcoError::InvalidColor(s.to_string());
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !hex.is_ascii() { return Err(invalid()) }
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).map_err(|_| invalid());
+    match hex.len() {
+        6 => Ok(image::Rgb([channel(&hex[0..2])?, channel(&hex[2..4])?, channel(&hex[4..6])?])),
+        3 => Ok(image::Rgb([channel(&hex[0..1])? * 17, channel(&hex[1..2])? * 17, channel(&hex[2..3])? * 17])),
+        _ => Err(invalid())
+    }
+}
+
+fn crop_image(image: image::DynamicImage, crop: &Crop, path: &Path) -> ImcoResult<image::DynamicImage> {
+    let (x, y, width, height) = match *crop {
+        Crop::Area(x, y, width, height) => (x, y, width, height),
+        Crop::Center(width, height) => (
+            image.width().saturating_sub(width) / 2,
+            image.height().saturating_sub(height) / 2,
+            width, height
+        ),
+    };
+    if x >= image.width() || y >= image.height() { return Err(ImcoError::CropOutside(path.display().to_string())) }
+    // crop_imm clamps the area to the image bounds
+    Ok(image.crop_imm(x, y, width, height))
+}
+
+fn convert_color(image: image::DynamicImage, color: image::ColorType) -> image::DynamicImage {
+    use image::ColorType;
+    if image.color() == color { return image }
+    match color {
+        ColorType::L8 => image.to_luma8().into(),
+        ColorType::La8 => image.to_luma_alpha8().into(),
+        ColorType::Rgb8 => image.to_rgb8().into(),
+        ColorType::L16 => image.to_luma16().into(),
+        ColorType::La16 => image.to_luma_alpha16().into(),
+        ColorType::Rgb16 => image.to_rgb16().into(),
+        ColorType::Rgba16 => image.to_rgba16().into(),
+        ColorType::Rgb32F => image.to_rgb32f().into(),
+        ColorType::Rgba32F => image.to_rgba32f().into(),
+        _ => image.to_rgba8().into(),
+    }
+}
+
+// Raises color channels to 1 / gamma, so values above 1 brighten the image
+fn adjust_gamma(image: image::DynamicImage, gamma: f32) -> image::DynamicImage {
+    let color = image.color();
+    let adjusted: image::DynamicImage = if color.bytes_per_pixel() / color.channel_count() == 1 {
+        let lut: Vec<u8> = (0..=255u8).map(|v| ((v as f32 / 255.0).powf(1.0 / gamma) * 255.0).round() as u8).collect();
+        let mut buffer = image.to_rgba8();
+        buffer.pixels_mut().for_each(|p| p.0[..3].iter_mut().for_each(|c| *c = lut[*c as usize]));
+        buffer.into()
+    } else {
+        let mut buffer = image.to_rgba32f();
+        buffer.pixels_mut().for_each(|p| p.0[..3].iter_mut().for_each(|c| *c = c.max(0.0).powf(1.0 / gamma)));
+        buffer.into()
+    };
+    convert_color(adjusted, color)
+}
+
+/// Stretches the histogram so the darkest and brightest clip percent of the pixels become black and white
+#[derive(Clone, Copy)]
+pub struct AutoLevels {
+    /// Stretch every color channel on its own (auto-levels, also correcting color casts)
+    /// instead of all of them by the luma (auto-contrast, keeping the hues)
+    pub per_channel: bool,
+    pub clip: f32,
+}
+
+/// Colorizes the image, blending it by strength (0 to 1) with its luma in this color
+#[derive(Clone, Copy)]
+pub struct Tint {
+    pub color: image::Rgb<u8>,
+    pub strength: f32,
+}
+
+/// Parses COLOR[:STRENGTH], the strength being 0.5 if left out
+pub fn parse_tint(s: &str) -> ImcoResult<Tint> {
+    let invalid = || ImcoError::InvalidTint(s.to_string());
+    let (color, strength) = s.rsplit_once(':').unwrap_or((s, "0.5"));
+    let color = parse_color(color).map_err(|_| invalid())?;
+    let strength = strength.parse::<f32>().ok().filter(|n| (0.0..=1.0).contains(n)).ok_or_else(invalid)?;
+    Ok(Tint { color, strength })
+}
+
+/// Darkens towards the edges, the corners by strength (0 to 1). The falloff starts at radius
+/// times the distance from the center to the corners and follows the aspect ratio
+#[derive(Clone, Copy)]
+pub struct Vignette {
+    pub strength: f32,
+    pub radius: f32,
+}
+
+/// Parses STRENGTH[,RADIUS], the radius being 0.5 if left out
+pub fn parse_vignette(s: &str) -> ImcoResult<Vignette> {
+    let invalid = || ImcoError::InvalidVignette(s.to_string());
+    let (strength, radius) = s.split_once(',').unwrap_or((s, "0.5"));
+    let strength = strength.trim().parse::<f32>().ok().filter(|n| (0.0..=1.0).contains(n)).ok_or_else(invalid)?;
+    let radius = radius.trim().parse::<f32>().ok().filter(|n| (0.0..1.0).contains(n)).ok_or_else(invalid)?;
+    Ok(Vignette { strength, radius })
+}
+
+fn vignette_image(image: image::DynamicImage, vignette: &Vignette) -> image::DynamicImage {
+    let color = image.color();
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    // Smoothstep from the radius to the corners, 1 - strength there
+    let factor = |x: u32, y: u32| {
+        let dx = (x as f32 + 0.5) / width * 2.0 - 1.0;
+        let dy = (y as f32 + 0.5) / height * 2.0 - 1.0;
+        let t = (((dx * dx + dy * dy) / 2.0).sqrt() - vignette.radius) / (1.0 - vignette.radius);
+        let t = t.clamp(0.0, 1.0);
+        1.0 - vignette.strength * t * t * (3.0 - 2.0 * t)
+    };
+    let darkened: image::DynamicImage = if color.bytes_per_pixel() / color.channel_count() == 1 {
+        let mut buffer = image.to_rgba8();
+        for (x, y, p) in buffer.enumerate_pixels_mut() {
+            let factor = factor(x, y);
+            for c in &mut p.0[..3] { *c = (*c as f32 * factor).round() as u8 }
+        }
+        buffer.into()
+    } else {
+        let mut buffer = image.to_rgba32f();
+        for (x, y, p) in buffer.enumerate_pixels_mut() {
+            let factor = factor(x, y);
+            for c in &mut p.0[..3] { *c *= factor }
+        }
+        buffer.into()
+    };
+    convert_color(darkened, color)
+}
+
+// The color type with color channels of the same depth, keeping the alpha
+fn colorful(color: image::ColorType) -> image::ColorType {
+    match color {
+        image::ColorType::L8 => image::ColorType::Rgb8,
+        image::ColorType::La8 => image::ColorType::Rgba8,
+        image::ColorType::L16 => image::ColorType::Rgb16,
+        image::ColorType::La16 => image::ColorType::Rgba16,
+        color => color
+    }
+}
+
+// Maps the color channels (from 0 to 1) of every pixel, 8 bit images through rgba8 and others through floats
+fn map_colors(image: image::DynamicImage, color: image::ColorType, f: impl Fn([f32; 3]) -> [f32; 3]) -> image::DynamicImage {
+    let source = image.color();
+    let mapped: image::DynamicImage = if source.bytes_per_pixel() / source.channel_count() == 1 {
+        let mut buffer = image.to_rgba8();
+        for p in buffer.pixels_mut() {
+            let rgb = f([p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0]);
+            for (c, v) in p.0[..3].iter_mut().zip(rgb) { *c = (v * 255.0).round().clamp(0.0, 255.0) as u8 }
+        }
+        buffer.into()
+    } else {
+        let mut buffer = image.to_rgba32f();
+        for p in buffer.pixels_mut() {
+            let rgb = f([p[0], p[1], p[2]]);
+            p.0[..3].copy_from_slice(&rgb);
+        }
+        buffer.into()
+    };
+    convert_color(mapped, color)
+}
+
+fn invert(mut image: image::DynamicImage) -> image::DynamicImage {
+    // Floats have no fixed maximum to invert against
+    if matches!(image.color(), image::ColorType::Rgb32F | image::ColorType::Rgba32F) {
+        let color = image.color();
+        return map_colors(image, color, |rgb| rgb.map(|c| 1.0 - c.clamp(0.0, 1.0)))
+    }
+    image.invert();
+    image
+}
+
+const SEPIA: [[f32; 4]; 3] = [[0.393, 0.769, 0.189, 0.0], [0.349, 0.686, 0.168, 0.0], [0.272, 0.534, 0.131, 0.0]];
+
+fn sepia(image: image::DynamicImage, gpu: Option<&Gpu>) -> image::DynamicImage {
+    let color = colorful(image.color());
+    if let Some(toned) = gpu.and_then(|gpu| gpu.matrix(&image, SEPIA, color)) { return toned }
+    map_colors(image, color, |[r, g, b]| [
+        0.393 * r + 0.769 * g + 0.189 * b,
+        0.349 * r + 0.686 * g + 0.168 * b,
+        0.272 * r + 0.534 * g + 0.131 * b,
+    ])
+}
+
+// Gray on the GPU from the same luma weights as image
+fn convert_colorspace(image: image::DynamicImage, color: image::ColorType, gpu: Option<&Gpu>) -> image::DynamicImage {
+    const LUMA: [f32; 4] = [0.2126, 0.7152, 0.0722, 0.0];
+    if !color.has_color() && image.color().has_color() {
+        if let Some(gray) = gpu.and_then(|gpu| gpu.matrix(&image, [LUMA; 3], color)) { return gray }
+    }
+    convert_color(image, color)
+}
+
+fn tint_image(image: image::DynamicImage, tint: &Tint) -> image::DynamicImage {
+    let color = colorful(image.color());
+    let target = tint.color.0.map(|c| c as f32 / 255.0);
+    map_colors(image, color, |rgb| {
+        let luma = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+        [0, 1, 2].map(|i| rgb[i] + (luma * target[i] - rgb[i]) * tint.strength)
+    })
+}
+
+fn auto_levels(image: image::DynamicImage, levels: &AutoLevels) -> image::DynamicImage {
+    // Bounds from the 8 bit histograms of the visible pixels, per channel or of the luma
+    let mut bins = [[0u64; 256]; 3];
+    for p in image.to_rgba8().pixels().filter(|p| p[3] > 0) {
+        if levels.per_channel {
+            (0..3).for_each(|channel| bins[channel][p[channel] as usize] += 1);
+        } else {
+            let luma = (0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32).round() as usize;
+            bins[0][luma.min(255)] += 1;
+        }
+    }
+    let bounds = |bins: &[u64; 256]| {
+        let clipped = (bins.iter().sum::<u64>() as f64 * levels.clip as f64 / 100.0) as u64;
+        let past_clipped = |counted: &mut u64, count: &u64| {
+            *counted += count;
+            *counted > clipped
+        };
+        let (mut below, mut above) = (0, 0);
+        let low = bins.iter().position(|count| past_clipped(&mut below, count)).unwrap_or(0) as f32 / 255.0;
+        let high = (255 - bins.iter().rev().position(|count| past_clipped(&mut above, count)).unwrap_or(0)) as f32 / 255.0;
+        // Flat channels are left alone
+        if high > low { (low, high) } else { (0.0, 1.0) }
+    };
+    let ranges = if levels.per_channel { bins.each_ref().map(bounds) } else { [bounds(&bins[0]); 3] };
+    let color = image.color();
+    map_colors(image, color, |rgb| [0, 1, 2].map(|i| (rgb[i] - ranges[i].0) / (ranges[i].1 - ranges[i].0)))
+}
+
+fn threshold(image: image::DynamicImage, luma: u8) -> image::DynamicImage {
+    let alpha = image.color().has_alpha();
+    let mut gray = image.to_luma_alpha8();
+    gray.pixels_mut().for_each(|p| p[0] = if p[0] >= luma { 255 } else { 0 });
+    if alpha { gray.into() } else { image::DynamicImage::ImageLumaA8(gray).to_luma8().into() }
+}
+
+fn posterize(image: image::DynamicImage, levels: u8) -> image::DynamicImage {
+    let color = image.color();
+    let steps = (levels - 1) as f32;
+    map_colors(image, color, |rgb| rgb.map(|c| (c.clamp(0.0, 1.0) * steps).round() / steps))
+}
+
+/// Resampling filter by name, Lanczos3 for unknown names
+pub fn mk_filter(name: &str) -> FilterType {
+    match name {
+        "nearest" => FilterType::Nearest,
+        "triangle" => FilterType::Triangle,
+        "catmull-rom" => FilterType::CatmullRom,
+        "gaussian" => FilterType::Gaussian,
+        _ => FilterType::Lanczos3
+    }
+}
+
+// Resizes with fast_image_resize, None if it doesn't support the pixel type. Cover crops the source
+// to the aspect ratio of the box first
+fn fast_resize(image: &image::DynamicImage, width: u32, height: u32, filter: FilterType, cover: bool) -> Option<image::DynamicImage> {
+    use fast_image_resize::{FilterType as Fast, ResizeAlg, ResizeOptions, Resizer};
+    let algorithm = match filter {
+        FilterType::Nearest => ResizeAlg::Nearest,
+        FilterType::Triangle => ResizeAlg::Convolution(Fast::Bilinear),
+        FilterType::CatmullRom => ResizeAlg::Convolution(Fast::CatmullRom),
+        FilterType::Gaussian => ResizeAlg::Convolution(Fast::Gaussian),
+        FilterType::Lanczos3 => ResizeAlg::Convolution(Fast::Lanczos3),
+    };
+    let mut options = ResizeOptions::new().resize_alg(algorithm);
+    if cover { options = options.fit_into_destination(None) }
+    let mut resized = image::DynamicImage::new(width, height, image.color());
+    Resizer::new().resize(image, &mut resized, &options).ok().map(|_| resized)
+}
+
+// Computing one output pixel per invocation on RGBA floats. The resize runs as a horizontal pass, which
+// premultiplies the alpha, and a vertical one undoing it
+#[cfg(feature = "gpu")]
+const GPU_SHADER: &str = r"
+struct Params {
+    source: vec2<u32>,
+    size: vec2<u32>,
+    taps: u32,
+    vertical: u32,
+    lut_size: u32,
+    padding: u32,
+    domain_min: vec4<f32>,
+    domain_max: vec4<f32>,
+    rows: array<vec4<f32>, 3>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> source: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read_write> destination: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read> starts: array<u32>;
+@group(0) @binding(4) var<storage, read> weights: array<f32>;
+@group(0) @binding(5) var<storage, read> table: array<vec4<f32>>;
+
+@compute @workgroup_size(16, 16)
+fn resize(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.size.x || id.y >= params.size.y { return; }
+    let vertical = params.vertical == 1u;
+    let out = select(id.x, id.y, vertical);
+    let last = select(params.source.x, params.source.y, vertical) - 1u;
+    var sum = vec4<f32>(0.0);
+    for (var tap = 0u; tap < params.taps; tap++) {
+        let i = min(starts[out] + tap, last);
+        var pixel: vec4<f32>;
+        if vertical {
+            pixel = source[i * params.source.x + id.x];
+        } else {
+            let straight = source[id.y * params.source.x + i];
+            pixel = vec4<f32>(straight.rgb * straight.a, straight.a);
+        }
+        sum += pixel * weights[out * params.taps + tap];
+    }
+    if vertical {
+        sum = select(vec4<f32>(0.0), vec4<f32>(sum.rgb / sum.a, sum.a), sum.a > 0.0);
+    }
+    destination[id.y * params.size.x + id.x] = sum;
+}
+
+@compute @workgroup_size(16, 16)
+fn lut(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.size.x || id.y >= params.size.y { return; }
+    let index = id.y * params.size.x + id.x;
+    let pixel = destination[index];
+    let steps = f32(params.lut_size - 1u);
+    let range = params.domain_max.rgb - params.domain_min.rgb;
+    let position = clamp((pixel.rgb - params.domain_min.rgb) / range, vec3<f32>(0.0), vec3<f32>(1.0)) * steps;
+    let base = min(vec3<u32>(floor(position)), vec3<u32>(params.lut_size - 2u));
+    let fraction = position - vec3<f32>(base);
+    var mixed = vec3<f32>(0.0);
+    for (var corner = 0u; corner < 8u; corner++) {
+        let offset = vec3<u32>(corner & 1u, (corner >> 1u) & 1u, (corner >> 2u) & 1u);
+        let weight = select(1.0 - fraction, fraction, offset == vec3<u32>(1u));
+        let entry = base + offset;
+        mixed += table[entry.x + (entry.y + entry.z * params.lut_size) * params.lut_size].rgb * weight.x * weight.y * weight.z;
+    }
+    destination[index] = vec4<f32>(mixed, pixel.a);
+}
+
+@compute @workgroup_size(16, 16)
+fn matrix(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.size.x || id.y >= params.size.y { return; }
+    let index = id.y * params.size.x + id.x;
+    let pixel = destination[index];
+    let rgb = vec4<f32>(pixel.rgb, 1.0);
+    destination[index] = vec4<f32>(dot(params.rows[0], rgb), dot(params.rows[1], rgb), dot(params.rows[2], rgb), pixel.a);
+}
+";
+
+/// Graphics card some transforms run on, see [`Options::gpu`]
+#[cfg(feature = "gpu")]
+pub struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    resize: wgpu::ComputePipeline,
+    lut: wgpu::ComputePipeline,
+    matrix: wgpu::ComputePipeline,
+}
+
+#[cfg(feature = "gpu")]
+impl Gpu {
+    /// The default adapter of the system, an error when there is none
+    pub fn open() -> ImcoResult<Self> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+            .map_err(|e| ImcoError::Gpu(e.to_string()))?;
+        let descriptor = wgpu::DeviceDescriptor { label: Some("imco"), required_limits: adapter.limits(), ..Default::default() };
+        let (device, queue) = pollster::block_on(adapter.request_device(&descriptor)).map_err(|e| ImcoError::Gpu(e.to_string()))?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor { label: Some("imco"), source: wgpu::ShaderSource::Wgsl(GPU_SHADER.into()) });
+        let pipeline = |entry_point| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point), layout: None, module: &module, entry_point: Some(entry_point), compilation_options: Default::default(), cache: None
+        });
+        Ok(Gpu { resize: pipeline("resize"), lut: pipeline("lut"), matrix: pipeline("matrix"), device, queue })
+    }
+
+    // Resized in the color of the image, None when it doesn't fit into the GPU's buffers
+    fn resize(&self, image: &image::DynamicImage, width: u32, height: u32, filter: FilterType, cover: bool) -> Option<image::DynamicImage> {
+        // Cover crops the source to the aspect ratio of the box first, like fast_resize
+        let cropped;
+        let source = if cover {
+            let scale = f64::min(image.width() as f64 / width as f64, image.height() as f64 / height as f64);
+            let crop_width = ((width as f64 * scale).round() as u32).clamp(1, image.width());
+            let crop_height = ((height as f64 * scale).round() as u32).clamp(1, image.height());
+            cropped = image.crop_imm((image.width() - crop_width) / 2, (image.height() - crop_height) / 2, crop_width, crop_height);
+            &cropped
+        } else { image };
+        let (source_width, source_height) = (source.width(), source.height());
+        let input = self.upload(source)?;
+        let between = self.buffer(width as u64 * source_height as u64)?;
+        let output = self.buffer(width as u64 * height as u64)?;
+        let (x_taps, x_starts, x_weights) = resize_taps(source_width, width, filter);
+        let (y_taps, y_starts, y_weights) = resize_taps(source_height, height, filter);
+        self.run(&output, width, height, image.color(), |gpu, encoder| {
+            let horizontal = [(1, &input), (2, &between), (3, &gpu.slice(&x_starts)), (4, &gpu.slice(&x_weights))];
+            gpu.pass(encoder, &gpu.resize, gpu_params((source_width, source_height), (width, source_height), x_taps, 0, None, None), &horizontal);
+            let vertical = [(1, &between), (2, &output), (3, &gpu.slice(&y_starts)), (4, &gpu.slice(&y_weights))];
+            gpu.pass(encoder, &gpu.resize, gpu_params((width, source_height), (width, height), y_taps, 1, None, None), &vertical);
+        })
+    }
+
+    fn lut(&self, image: &image::DynamicImage, lut: &Lut, color: image::ColorType) -> Option<image::DynamicImage> {
+        let pixels = self.upload(image)?;
+        let table: Vec<[f32; 4]> = lut.table.iter().map(|&[r, g, b]| [r, g, b, 0.0]).collect();
+        let (width, height) = (image.width(), image.height());
+        let params = gpu_params((width, height), (width, height), 0, 0, Some(lut), None);
+        self.run(&pixels, width, height, color, |gpu, encoder| {
+            gpu.pass(encoder, &gpu.lut, params, &[(2, &pixels), (5, &gpu.slice(&table))]);
+        })
+    }
+
+    // Rows multiplied with the RGB and 1 of every pixel
+    fn matrix(&self, image: &image::DynamicImage, rows: [[f32; 4]; 3], color: image::ColorType) -> Option<image::DynamicImage> {
+        let pixels = self.upload(image)?;
+        let (width, height) = (image.width(), image.height());
+        let params = gpu_params((width, height), (width, height), 0, 0, None, Some(rows));
+        self.run(&pixels, width, height, color, |gpu, encoder| {
+            gpu.pass(encoder, &gpu.matrix, params, &[(2, &pixels)]);
+        })
+    }
+
+    // Storage for this many RGBA float pixels, None if the GPU has no buffers that large
+    fn buffer(&self, pixels: u64) -> Option<wgpu::Buffer> {
+        let size = pixels * 16;
+        let limits = self.device.limits();
+        if size > limits.max_storage_buffer_binding_size.min(limits.max_buffer_size) { return None }
+        Some(self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None, size, usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST, mapped_at_creation: false
+        }))
+    }
+
+    fn upload(&self, image: &image::DynamicImage) -> Option<wgpu::Buffer> {
+        let buffer = self.buffer(image.width() as u64 * image.height() as u64)?;
+        let bytes: Vec<u8> = image.to_rgba32f().into_raw().into_iter().flat_map(f32::to_ne_bytes).collect();
+        self.queue.write_buffer(&buffer, 0, &bytes);
+        Some(buffer)
+    }
+
+    fn slice<T: GpuValue>(&self, values: &[T]) -> wgpu::Buffer {
+        use wgpu::util::DeviceExt;
+        let contents: Vec<u8> = values.iter().flat_map(|value| value.bytes()).collect();
+        self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents: &contents, usage: wgpu::BufferUsages::STORAGE })
+    }
+
+    fn pass(&self, encoder: &mut wgpu::CommandEncoder, pipeline: &wgpu::ComputePipeline, params: Vec<u8>, buffers: &[(u32, &wgpu::Buffer)]) {
+        use wgpu::util::DeviceExt;
+        let uniform = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: None, contents: &params, usage: wgpu::BufferUsages::UNIFORM });
+        let mut entries = vec![wgpu::BindGroupEntry { binding: 0, resource: uniform.as_entire_binding() }];
+        entries.extend(buffers.iter().map(|(binding, buffer)| wgpu::BindGroupEntry { binding: *binding, resource: buffer.as_entire_binding() }));
+        let group = self.device.create_bind_group(&wgpu::BindGroupDescriptor { label: None, layout: &pipeline.get_bind_group_layout(0), entries: &entries });
+        // The output size is right behind the source size
+        let size = |at: usize| u32::from_ne_bytes([params[at], params[at + 1], params[at + 2], params[at + 3]]);
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &group, &[]);
+        pass.dispatch_workgroups(size(8).div_ceil(16), size(12).div_ceil(16), 1);
+    }
+
+    // Records the passes, then reads the output back in the color. None on any error of the GPU
+    fn run(&self, output: &wgpu::Buffer, width: u32, height: u32, color: image::ColorType, passes: impl FnOnce(&Self, &mut wgpu::CommandEncoder)) -> Option<image::DynamicImage> {
+        let validation = self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let memory = self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None, size: output.size(), usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST, mapped_at_creation: false
+        });
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        passes(self, &mut encoder);
+        encoder.copy_buffer_to_buffer(output, 0, &staging, 0, output.size());
+        self.queue.submit([encoder.finish()]);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        staging.slice(..).map_async(wgpu::MapMode::Read, move |result| drop(sender.send(result)));
+        let polled = self.device.poll(wgpu::PollType::wait_indefinitely());
+        let failed = pollster::block_on(memory.pop()).is_some() | pollster::block_on(validation.pop()).is_some();
+        if failed || polled.is_err() || !matches!(receiver.recv(), Ok(Ok(()))) { return None }
+        let samples = staging.slice(..).get_mapped_range().ok()?.chunks_exact(4).map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]])).collect();
+        image::Rgba32FImage::from_raw(width, height, samples).map(|pixels| convert_color(pixels.into(), color))
+    }
+}
+
+#[cfg(feature = "gpu")]
+trait GpuValue {
+    fn bytes(&self) -> Vec<u8>;
+}
+
+#[cfg(feature = "gpu")]
+impl GpuValue for u32 {
+    fn bytes(&self) -> Vec<u8> { self.to_ne_bytes().to_vec() }
+}
+
+#[cfg(feature = "gpu")]
+impl GpuValue for f32 {
+    fn bytes(&self) -> Vec<u8> { self.to_ne_bytes().to_vec() }
+}
+
+#[cfg(feature = "gpu")]
+impl GpuValue for [f32; 4] {
+    fn bytes(&self) -> Vec<u8> { self.iter().flat_map(|v| v.to_ne_bytes()).collect() }
+}
+
+// The Params of the shader, laid out like WGSL does
+#[cfg(feature = "gpu")]
+fn gpu_params(source: (u32, u32), size: (u32, u32), taps: u32, vertical: u32, lut: Option<&Lut>, rows: Option<[[f32; 4]; 3]>) -> Vec<u8> {
+    let lut_size = lut.map_or(2, |lut| lut.size as u32);
+    let (domain_min, domain_max) = lut.map_or(([0.0; 3], [1.0; 3]), |lut| (lut.domain_min, lut.domain_max));
+    let mut params: Vec<u8> = [source.0, source.1, size.0, size.1, taps, vertical, lut_size, 0].into_iter().flat_map(u32::to_ne_bytes).collect();
+    let floats = [domain_min, domain_max].into_iter().flat_map(|[a, b, c]| [a, b, c, 0.0]).chain(rows.unwrap_or_default().into_iter().flatten());
+    params.extend(floats.flat_map(f32::to_ne_bytes));
+    params
+}
+
+// Per output pixel the first source pixel and the weights of the taps from it, each axis like the
+// filters of image
+#[cfg(feature = "gpu")]
+fn resize_taps(from: u32, to: u32, filter: FilterType) -> (u32, Vec<u32>, Vec<f32>) {
+    fn sinc(x: f32) -> f32 { if x == 0.0 { 1.0 } else { (x * std::f32::consts::PI).sin() / (x * std::f32::consts::PI) } }
+    let (kernel, support): (fn(f32) -> f32, f32) = match filter {
+        FilterType::Nearest => (|_| 1.0, 0.0),
+        FilterType::Triangle => (|x| (1.0 - x.abs()).max(0.0), 1.0),
+        FilterType::CatmullRom => (|x| match x.abs() {
+            x if x < 1.0 => 1.5 * x.powi(3) - 2.5 * x.powi(2) + 1.0,
+            x if x < 2.0 => -0.5 * x.powi(3) + 2.5 * x.powi(2) - 4.0 * x + 2.0,
+            _ => 0.0
+        }, 2.0),
+        FilterType::Gaussian => (|x| (-2.0 * x * x).exp() / (std::f32::consts::PI / 2.0).sqrt(), 3.0),
+        FilterType::Lanczos3 => (|x| if x.abs() < 3.0 { sinc(x) * sinc(x / 3.0) } else { 0.0 }, 3.0),
+    };
+    let ratio = from as f32 / to as f32;
+    let scale = ratio.max(1.0);
+    let radius = support * scale;
+    let taps = if filter == FilterType::Nearest { 1 } else { (radius * 2.0).ceil() as u32 + 2 };
+    let (mut starts, mut weights) = (Vec::with_capacity(to as usize), Vec::with_capacity((to * taps) as usize));
+    for out in 0..to {
+        let center = (out as f32 + 0.5) * ratio;
+        let start = if filter == FilterType::Nearest { center.floor() } else { (center - radius).floor().max(0.0) };
+        let start = (start as u32).min(from - 1);
+        let tap_weights: Vec<f32> = (start..start + taps).map(|i| if i < from && filter != FilterType::Nearest {
+            kernel((i as f32 + 0.5 - center) / scale)
+        } else { (i < from) as u32 as f32 }).collect();
+        let sum: f32 = tap_weights.iter().sum();
+        starts.push(start);
+        weights.extend(tap_weights.iter().map(|weight| if sum == 0.0 { 0.0 } else { weight / sum }));
+    }
+    (taps, starts, weights)
+}
+
+/// Stands in for a GPU in builds without the gpu feature, which can't open one
+#[cfg(not(feature = "gpu"))]
+pub struct Gpu;
+
+#[cfg(not(feature = "gpu"))]
+impl Gpu {
+    pub fn open() -> ImcoResult<Self> {
+        Err(ImcoError::Gpu("imco was built without GPU support".to_string()))
+    }
+
+    fn resize(&self, _image: &image::DynamicImage, _width: u32, _height: u32, _filter: FilterType, _cover: bool) -> Option<image::DynamicImage> { None }
+
+    fn lut(&self, _image: &image::DynamicImage, _lut: &Lut, _color: image::ColorType) -> Option<image::DynamicImage> { None }
+
+    fn matrix(&self, _image: &image::DynamicImage, _rows: [[f32; 4]; 3], _color: image::ColorType) -> Option<image::DynamicImage> { None }
+}
+
+// The size an image is resized to and whether it is cropped to it, None when it is left alone
+fn resize_dimensions(image: &image::DynamicImage, resize: &Resize) -> Option<(u32, u32, bool)> {
+    let scale = |side: u32, to: u32, from: u32| ((side as u64 * to as u64) as f64 / from as f64).round().max(1.0) as u32;
+    Some(match (resize.width, resize.height) {
+        (Some(width), Some(height)) => match resize.fit {
+            Fit::Exact => (width, height, false),
+            Fit::Contain if (width as u64 * image.height() as u64) < (height as u64 * image.width() as u64) =>
+                (width, scale(image.height(), width, image.width()), false),
+            Fit::Contain => (scale(image.width(), height, image.height()), height, false),
+            Fit::Cover => (width, height, true),
+        },
+        (Some(width), None) => (width, scale(image.height(), width, image.width()), false),
+        (None, Some(height)) => (scale(image.width(), height, image.height()), height, false),
+        (None, None) => return None
+    })
+}
+
+fn resize_image(image: image::DynamicImage, resize: &Resize, filter: FilterType, backend: ResizeBackend, gpu: Option<&Gpu>) -> image::DynamicImage {
+    let Some((width, height, cover)) = resize_dimensions(&image, resize) else { return image };
+    if let Some(resized) = gpu.and_then(|gpu| gpu.resize(&image, width, height, filter, cover)) { return resized }
+    if backend == ResizeBackend::Fast {
+        if let Some(resized) = fast_resize(&image, width, height, filter, cover) { return resized }
+    }
+    if cover { image.resize_to_fill(width, height, filter) } else { image.resize_exact(width, height, filter) }
+}
+
+// Samples every output pixel bilinearly from the source, uncovered corners get the background
+// or, without one, become transparent
+fn rotate_degrees(image: image::DynamicImage, rotation: &Rotation, background: Option<image::Rgb<u8>>) -> image::DynamicImage {
+    let color = if background.is_some() { image.color() } else { with_alpha(image.color()) };
+    let (width, height) = (image.width(), image.height());
+    let (sin, cos) = rotation.degrees.to_radians().sin_cos();
+    let (rotated_width, rotated_height) = if rotation.crop { (width, height) } else {
+        let fit = |a: u32, b: u32| ((a as f32 * cos.abs() + b as f32 * sin.abs()).round() as u32).max(1);
+        (fit(width, height), fit(height, width))
+    };
+    let fill = background.map(|c| [c[0], c[1], c[2]].map(|v| v as f32 / 255.0)).map_or([0.0; 4], |[r, g, b]| [r, g, b, 1.0]);
+    let source = image.to_rgba32f();
+    // Premultiplied, so transparent neighbours don't darken the edges
+    let sample = |x: i64, y: i64| match (u32::try_from(x), u32::try_from(y)) {
+        (Ok(x), Ok(y)) if x < width && y < height => {
+            let p = source.get_pixel(x, y).0;
+            [p[0] * p[3], p[1] * p[3], p[2] * p[3], p[3]]
+        }
+        _ => [fill[0] * fill[3], fill[1] * fill[3], fill[2] * fill[3], fill[3]],
+    };
+    let mut rotated = image::Rgba32FImage::new(rotated_width, rotated_height);
+    for (x, y, p) in rotated.enumerate_pixels_mut() {
+        let dx = x as f32 + 0.5 - rotated_width as f32 / 2.0;
+        let dy = y as f32 + 0.5 - rotated_height as f32 / 2.0;
+        let sx = dx * cos + dy * sin + width as f32 / 2.0 - 0.5;
+        let sy = dy * cos - dx * sin + height as f32 / 2.0 - 0.5;
+        let (x0, y0) = (sx.floor(), sy.floor());
+        let (fx, fy) = (sx - x0, sy - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let corners = [(sample(x0, y0), (1.0 - fx) * (1.0 - fy)), (sample(x0 + 1, y0), fx * (1.0 - fy)),
+            (sample(x0, y0 + 1), (1.0 - fx) * fy), (sample(x0 + 1, y0 + 1), fx * fy)];
+        let mixed = corners.iter().fold([0f32; 4], |sum, (c, weight)| [0, 1, 2, 3].map(|i| sum[i] + c[i] * weight));
+        let alpha = mixed[3];
+        p.0 = if alpha > 0.0 { [mixed[0] / alpha, mixed[1] / alpha, mixed[2] / alpha, alpha] } else { [0.0; 4] };
+    }
+    convert_color(rotated.into(), color)
+}
+
+fn trim_image(image: image::DynamicImage, trim: &Trim) -> image::DynamicImage {
+    use image::GenericImageView;
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 { return image }
+    let corner = image.get_pixel(0, 0);
+    let tolerance = (trim.fuzz / 100.0 * 255.0).round() as i32;
+    let border = |x, y| {
+        let pixel = image.get_pixel(x, y);
+        if corner[3] == 0 { return pixel[3] as i32 <= tolerance }
+        pixel.0.iter().zip(corner.0).all(|(a, b)| (*a as i32 - b as i32).abs() <= tolerance)
+    };
+    // Rows and columns are scanned inwards, stopping at the first one with content
+    let blank_row = |y| (0..width).all(|x| border(x, y));
+    let Some(top) = (0..height).find(|y| !blank_row(*y)) else { return image };
+    let bottom = (top..height).rfind(|y| !blank_row(*y)).unwrap() + 1;
+    let blank_column = |x| (top..bottom).all(|y| border(x, y));
+    let left = (0..width).find(|x| !blank_column(*x)).unwrap();
+    let right = (left..width).rfind(|x| !blank_column(*x)).unwrap() + 1;
+    image.crop_imm(left, top, right - left, bottom - top)
+}
+
+fn chroma_key(image: image::DynamicImage, key: &ChromaKey) -> image::DynamicImage {
+    let color = with_alpha(image.color());
+    let tolerance = key.tolerance / 100.0;
+    let target = key.color.0.map(|c| c as f32 / 255.0);
+    let mut rgba = image.into_rgba32f();
+    for p in rgba.pixels_mut() {
+        let distance = (0..3).map(|c| (p[c] - target[c]).abs()).fold(0.0, f32::max);
+        // Fully keyed within the tolerance, fading in over as much again
+        let coverage = if distance <= tolerance { 0.0 } else if tolerance == 0.0 { 1.0 } else { ((distance - tolerance) / tolerance).min(1.0) };
+        p[3] *= coverage;
+    }
+    convert_color(image::DynamicImage::ImageRgba32F(rgba), color)
+}
+
+fn crop_to_aspect(image: image::DynamicImage, aspect: &Aspect, filter: FilterType) -> image::DynamicImage {
+    let (width, height) = (image.width() as u64, image.height() as u64);
+    let (aspect_width, aspect_height) = (aspect.width as u64, aspect.height as u64);
+    let (width, height) = if width * aspect_height > height * aspect_width {
+        ((height * aspect_width + aspect_height / 2) / aspect_height, height)
+    } else {
+        (width, (width * aspect_height + aspect_width / 2) / aspect_width)
+    };
+    let crop = CropTo { width: (width as u32).clamp(1, image.width()), height: (height as u32).clamp(1, image.height()), gravity: aspect.gravity };
+    crop_to(image, &crop, filter)
+}
+
+fn crop_to(image: image::DynamicImage, crop: &CropTo, filter: FilterType) -> image::DynamicImage {
+    let scale = f64::max(crop.width as f64 / image.width() as f64, crop.height as f64 / image.height() as f64);
+    let width = ((image.width() as f64 * scale).round() as u32).max(crop.width);
+    let height = ((image.height() as f64 * scale).round() as u32).max(crop.height);
+    let image = if (width, height) == (image.width(), image.height()) { image } else { image.resize_exact(width, height, filter) };
+    let (spare_x, spare_y) = (width - crop.width, height - crop.height);
+    let (x, y) = match crop.gravity {
+        Gravity::Center => (spare_x / 2, spare_y / 2),
+        Gravity::North => (spare_x / 2, 0),
+        Gravity::South => (spare_x / 2, spare_y),
+        Gravity::West => (0, spare_y / 2),
+        Gravity::East => (spare_x, spare_y / 2),
+        Gravity::Entropy | Gravity::Attention => best_window(&image, crop),
+    };
+    image.crop_imm(x, y, crop.width, crop.height)
+}
+
+// Tries windows along the axis that has to be cropped, preferring the one closest to the center on ties
+fn best_window(image: &image::DynamicImage, crop: &CropTo) -> (u32, u32) {
+    const STEPS: u32 = 48;
+    let (spare_x, spare_y) = (image.width() - crop.width, image.height() - crop.height);
+    let luma = image.to_luma8();
+    let rgb = image.to_rgb8();
+    let saliency: Vec<f64> = match crop.gravity {
+        Gravity::Attention => rgb.enumerate_pixels().map(|(x, y, p)| {
+            let [r, g, b] = p.0.map(|c| c as i32);
+            let here = luma.get_pixel(x, y).0[0] as i32;
+            let right = luma.get_pixel((x + 1).min(luma.width() - 1), y).0[0] as i32;
+            let below = luma.get_pixel(x, (y + 1).min(luma.height() - 1)).0[0] as i32;
+            let edge = (right - here).abs() + (below - here).abs();
+            let saturation = r.max(g).max(b) - r.min(g).min(b);
+            let skin = r > 95 && g > 40 && b > 20 && r > g && r > b && r - g.min(b) > 15;
+            (edge + saturation / 2 + if skin { 128 } else { 0 }) as f64
+        }).collect(),
+        _ => vec![],
+    };
+    let score = |x: u32, y: u32| -> f64 {
+        match crop.gravity {
+            Gravity::Attention => (y..y + crop.height)
+                .map(|row| saliency[(row * image.width() + x) as usize..(row * image.width() + x + crop.width) as usize].iter().sum::<f64>())
+                .sum(),
+            _ => {
+                let mut histogram = [0u64; 256];
+                for row in y..y + crop.height {
+                    for column in x..x + crop.width { histogram[luma.get_pixel(column, row).0[0] as usize] += 1 }
+                }
+                let total = (crop.width * crop.height) as f64;
+                histogram.iter().filter(|n| **n > 0).map(|n| { let p = *n as f64 / total; -p * p.log2() }).sum()
+            }
+        }
+    };
+    let spare = spare_x.max(spare_y);
+    let step = spare.div_ceil(STEPS).max(1);
+    let window = |offset: u32| if spare_x >= spare_y { (offset, spare_y / 2) } else { (spare_x / 2, offset) };
+    let (mut best, mut best_score) = (spare / 2, score(window(spare / 2).0, window(spare / 2).1));
+    for offset in (0..=spare).step_by(step as usize) {
+        let (x, y) = window(offset);
+        let candidate = score(x, y);
+        if candidate > best_score || (candidate == best_score && offset.abs_diff(spare / 2) < best.abs_diff(spare / 2)) {
+            (best, best_score) = (offset, candidate);
+        }
+    }
+    window(best)
+}
+
+// Aspect preserving size when the longest side is out of bounds, None if it already fits
+fn clamp_dimensions(width: u32, height: u32, min: Option<u32>, max: Option<u32>) -> Option<(u32, u32)> {
+    let longest = width.max(height);
+    let target = match (min, max) {
+        (_, Some(max)) if longest > max => max,
+        (Some(min), _) if longest < min => min,
+        _ => return None
+    };
+    let scale = |side: u32| ((side as u64 * target as u64) as f64 / longest as f64).round().max(1.0) as u32;
+    Some((scale(width), scale(height)))
+}
+
+// Of an image scaled by factor, at least 1
+fn scaled_side(side: u32, factor: f64) -> u64 {
+    ((side as f64 * factor).round() as u64).max(1)
+}
+
+fn scale_image(image: image::DynamicImage, factor: f64, opts: &Options) -> image::DynamicImage {
+    let scaled = |side: u32| scaled_side(side, factor) as u32;
+    let resize = Resize { width: Some(scaled(image.width())), height: Some(scaled(image.height())), fit: Fit::Exact };
+    linear_light(image, opts.linear_resize, |image| resize_image(image, &resize, opts.filter, opts.resize_backend, opts.gpu.as_deref()))
+}
+
+// Aspect preserving size with at most megapixels million pixels, None if it already fits. Rounded down so the cap holds
+fn cap_megapixels(width: u32, height: u32, megapixels: f64) -> Option<(u32, u32)> {
+    let pixels = width as f64 * height as f64;
+    let cap = megapixels * 1_000_000.0;
+    if pixels <= cap { return None }
+    let factor = (cap / pixels).sqrt();
+    Some((((width as f64 * factor) as u32).max(1), ((height as f64 * factor) as u32).max(1)))
+}
+
+/// Parses a percentage like 50% or a factor like 0.5 into the factor
+pub fn parse_scale(s: &str) -> ImcoResult<f64> {
+    let factor = match s.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+        None => s.trim().parse::<f64>(),
+    };
+    factor.ok().filter(|f| f.is_finite() && *f > 0.0).ok_or_else(|| ImcoError::InvalidScale(s.to_string()))
+}
+
+fn resolve_output(path: &Path, output: Option<PathBuf>, index: usize, opts: &Options) -> ImcoResult<(PathBuf, Format)> {
+    if opts.in_place {
+        if path.as_os_str() == STDIO { return Err(ImcoError::NoDestFormat) }
+        return Ok((path.to_path_buf(), mk_format_fp(path)?))
+    }
+    match (output, opts.o_fmt) {
+        (Some(output), _) if !opts.batch => explicit_output(&output, opts),
+        (output, Some(fmt)) => Ok((output_name(path, output, index, fmt, opts), fmt)),
+        // Outputs into a directory keep the format of their input
+        (Some(output), None) => match mk_format_fp(path) {
+            Ok(fmt) => Ok((output_name(path, Some(output), index, fmt, opts), fmt)),
+            Err(_) => Err(ImcoError::InvalidBatching)
+        },
+        (None, None) => Err(ImcoError::NoDestFormat),
+    }
+}
+
+/// Splits an output like out.bin:png into its path and the format named behind it, if any
+pub fn split_format(output: &Path) -> (&Path, Option<Format>) {
+    let bytes = output.as_os_str().as_encoded_bytes();
+    let Some(colon) = bytes.iter().rposition(|b| *b == b':') else { return (output, None) };
+    match std::str::from_utf8(&bytes[colon + 1..]).map(mk_format) {
+        Ok(Ok(fmt)) if colon > 0 => {
+            // SAFETY: split right before the colon, which is UTF-8
+            let path = unsafe { OsStr::from_encoded_bytes_unchecked(&bytes[..colon]) };
+            (Path::new(path), Some(fmt))
+        }
+        _ => (output, None)
+    }
+}
+
+// A given output with the format it is written in, taken from a :format suffix, else its extension and
+// else the output format
+fn explicit_output(output: &Path, opts: &Options) -> ImcoResult<(PathBuf, Format)> {
+    let (path, named) = split_format(output);
+    let fmt = match (named, mk_format_fp(path), opts.o_fmt) {
+        (Some(fmt), _, _) | (None, Ok(fmt), _) | (None, Err(_), Some(fmt)) => fmt,
+        (None, Err(_), None) if path.as_os_str() == STDIO => return Err(ImcoError::NoDestFormat),
+        (None, Err(e), None) => return Err(e),
+    };
+    Ok((path.to_path_buf(), fmt))
+}
+
+fn output_name(path: &Path, output: Option<PathBuf>, index: usize, fmt: Format, opts: &Options) -> PathBuf {
+    // Without an output, results are written next to their input
+    match output {
+        Some(dir) if opts.batch => join_path(path, fmt, &dir, &opts.name_template, index),
+        Some(output) => output,
+        None if path.as_os_str() == STDIO || opts.data_uri => PathBuf::from(STDIO),
+        None => mk_sibling(path, fmt, &opts.name_template, index)
+    }
+}
+
+// Where the output in one of the further formats goes, a given output file only getting its extension replaced
+fn variant_name(path: &Path, output: Option<&Path>, index: usize, fmt: Format, opts: &Options) -> PathBuf {
+    match output {
+        Some(output) if !opts.batch && output.as_os_str() != STDIO => split_format(output).0.with_extension(fmt.extension()),
+        output => output_name(path, output.map(Path::to_path_buf), index, fmt, opts)
+    }
+}
+
+// Appends -1, -2, ... to the file stem until the path is free
+fn free_path(output: &Path) -> PathBuf {
+    numbered_path(output, Path::exists)
+}
+
+// Appends the first number to the file stem giving a name that isn't taken
+fn numbered_path(output: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    (1..).map(|n| behind_stem(output, &format!("-{n}")))
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+// Whether the output was modified after every one of its sources
+fn up_to_date<S: AsRef<Path>>(output: &Path, sources: &[S]) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let Some(output_time) = modified(output) else { return false };
+    // Equal times count as up to date, outputs written with preserve_times have their source's
+    sources.iter().all(|source| modified(source.as_ref()).is_some_and(|time| time <= output_time))
+}
+
+// Where to write when the output exists already, None if it should be skipped
+fn check_conflict<S: AsRef<Path>>(output: PathBuf, sources: &[S], opts: &Options) -> ImcoResult<Option<PathBuf>> {
+    if output.as_os_str() == STDIO || opts.archive.is_some() || !output.exists() {
+        return Ok(Some(output))
+    }
+    if opts.newer_only && up_to_date(&output, sources) { return Ok(None) }
+    match opts.conflict {
+        Conflict::Overwrite => Ok(Some(output)),
+        Conflict::Error => Err(ImcoError::OutputExists(output.display().to_string())),
+        Conflict::Skip => Ok(None),
+        Conflict::Rename => Ok(Some(free_path(&output))),
+    }
+}
+
+// A renamed output replaces the one the input would be written to, see Options::uniquify
+fn individual_process(path: PathBuf, output: Option<PathBuf>, index: usize, renamed: Option<&Path>, opts: &Options) -> ImcoResult<Conversion> {
+    if output.as_ref().is_some_and(|output| output.as_os_str() == CLIPBOARD) {
+        return clipboard_process(path, opts)
+    }
+    if output.is_none() && opts.o_fmt.is_none() && !opts.in_place { return Err(ImcoError::NoDestFormat) }
+    
+    let variants: Vec<(PathBuf, Format)> = opts.formats.iter().map(|fmt| match renamed {
+        Some(renamed) => (renamed.with_extension(fmt.extension()), *fmt),
+        None => (variant_name(&path, output.as_deref(), index, *fmt, opts), *fmt),
+    }).collect();
+    let (output, fmt) = resolve_output(&path, output, index, opts)?;
+    let output = renamed.map(Path::to_path_buf).unwrap_or(output);
+    if opts.extract_frames || opts.pages.is_some() {
+        return extract_process(path, output, fmt, opts)
+    }
+    if opts.tile.is_some() || opts.slice.is_some() {
+        return tile_process(path, output, fmt, opts)
+    }
+    if opts.split_channels {
+        return channels_process(path, output, fmt, opts)
+    }
+    if !opts.srcset.is_empty() {
+        return srcset_process(path, output, fmt, opts)
+    }
+    let skipped = |output: PathBuf| Ok(Conversion { output_bytes: file_size(&output), skipped: true, ..Conversion::new(output, fmt) });
+    // Names with dimensions are only known after transforming, so are checked then
+    let deferred = has_dimensions(&output);
+    let output = if deferred { output } else {
+        let Some(output) = check_conflict(output.clone(), &[&path], opts)? else { return skipped(output) };
+        output
+    };
+    if opts.dry_run {
+        let input_format = opts.i_fmt.or_else(|| mk_format_fp(&path).ok());
+        let variants = variants.into_iter().map(|(output, output_format)| Conversion { input_format, ..Conversion::new(output, output_format) }).collect();
+        return Ok(Conversion { input_format, variants, ..Conversion::new(output, fmt) })
+    }
+
+    let mut timings = Timings::default();
+    // Interlaced pngs need the whole image
+    let interlaced = opts.encoder.png.interlace && matches!(fmt, Format::Image(ImageFormat::Png));
+    if opts.low_memory && !deferred && variants.is_empty() && opts.i_fmt.is_none() && output.as_os_str() != STDIO && opts.archive.is_none() && !opts.data_uri && !interlaced && strip_safe(opts) {
+        if let Format::Image(streamed @ (ImageFormat::Png | ImageFormat::Tiff)) = fmt {
+            if let Some(conversion) = retrying(&path, opts, || streamed_process(&path, &output, streamed, opts))? { return Ok(conversion) }
+        }
+    }
+    // Animations written in several formats are only written as their first frame
+    if !opts.first_frame_only && variants.is_empty() && matches!(fmt, Format::Image(ImageFormat::Gif | ImageFormat::Png)) {
+        if let Some((frames, org_fmt)) = timed(&mut timings.decode, || retrying(&path, opts, || decode_frames(&path, opts)))? {
+            let output = if deferred {
+                // All frames end up the size of the first one
+                let first = transform(image::DynamicImage::ImageRgba8(frames[0].buffer().clone()), None, opts, &path)?;
+                let output = fill_dimensions(&output, first.width(), first.height());
+                let Some(output) = check_conflict(output.clone(), &[&path], opts)? else { return skipped(output) };
+                output
+            } else { output };
+            return animated_process(frames, org_fmt, path, output, fmt, timings, opts)
+        }
+    }
+
+    let (image, meta, org_fmt, pending) = decode_with_metadata(&path, fmt, &mut timings, opts)?;
+    let ignored_pages = match org_fmt {
+        Some(Format::Image(ImageFormat::Tiff)) if path.as_os_str() != STDIO => tiff_page_count(&path) - 1,
+        _ => 0,
+    };
+    let source = image.color();
+    let image = timed(&mut timings.transform, || transform(image, pending, opts, &path))?;
+    let output = if deferred {
+        let output = fill_dimensions(&output, image.width(), image.height());
+        let Some(output) = check_conflict(output.clone(), &[&path], opts)? else { return skipped(output) };
+        output
+    } else { output };
+    check_lossless(source, &image, fmt, &path, opts)?;
+    let depth = output_depth(&image, fmt, &path, opts)?;
+    // Every format is encoded from the same transformed image
+    let variants = variants.into_iter()
+        .map(|(output, fmt)| variant_process(&image, &meta, org_fmt, &path, fill_dimensions(&output, image.width(), image.height()), fmt, opts))
+        .collect::<ImcoResult<Vec<_>>>()?;
+    let (image, flattened) = timed(&mut timings.transform, || flatten_for(image, fmt, opts));
+    let downgrades = check_downgrades(&path, org_fmt, source, depth, flattened, &meta, fmt, opts)?;
+    let placeholders = Placeholders::of(&image, opts);
+    let (output, output_bytes) = write_output(&image, &meta, &path, output, fmt, &mut timings, opts)?;
+    placeholders.write_sidecar(&output, &[&path], opts)?;
+    Ok(Conversion { input_format: org_fmt, output_bytes, flattened, placeholders, timings, variants, ignored_pages, depth: Some(depth), downgrades, ..Conversion::new(output, fmt) })
+}
+
+// Encodes the transformed image into one of the further formats
+fn variant_process(image: &image::DynamicImage, meta: &Metadata, input_format: Option<Format>, path: &Path, output: PathBuf, fmt: Format, opts: &Options) -> ImcoResult<Conversion> {
+    if output.as_os_str() == STDIO { return Err(ImcoError::FormatsToStdout) }
+    let Some(output) = check_conflict(output.clone(), &[path], opts)? else {
+        return Ok(Conversion { output_bytes: file_size(&output), input_format, skipped: true, ..Conversion::new(output, fmt) })
+    };
+    check_lossless(image.color(), image, fmt, path, opts)?;
+    let depth = output_depth(image, fmt, path, opts)?;
+    let mut timings = Timings::default();
+    let (flat, flattened) = timed(&mut timings.transform, || flatten_for(image.clone(), fmt, opts));
+    let downgrades = check_downgrades(path, input_format, image.color(), depth, flattened, meta, fmt, opts)?;
+    let (output, output_bytes) = write_output(&flat, meta, path, output, fmt, &mut timings, opts)?;
+    Ok(Conversion { input_format, output_bytes, flattened, timings, depth: Some(depth), downgrades, ..Conversion::new(output, fmt) })
+}
+
+// Encodes and writes the finished image, giving where it went and its size
+fn write_output(image: &image::DynamicImage, meta: &Metadata, path: &Path, output: PathBuf, fmt: Format, timings: &mut Timings, opts: &Options) -> ImcoResult<(PathBuf, Option<u64>)> {
+    let to_file = output.as_os_str() != STDIO && opts.archive.is_none() && !opts.data_uri;
+    if let Some(target) = opts.target_size {
+        let data = timed(&mut timings.encode, || encode_to_size(image, fmt, meta, target, path, &opts.encoder))?;
+        let data = if opts.data_uri { data_uri(&data, fmt, &output) } else { data };
+        let output = timed(&mut timings.encode, || write_encoded(&data, output, &[path], opts))?;
+        if to_file && (opts.verify || opts.verify_full) {
+            verify_output(&output, fmt, (image.width(), image.height()), opts.verify_full)?;
+        }
+        return Ok((output, Some(data.len() as u64)))
+    }
+
+    if !to_file {
+        let mut data = Cursor::new(Vec::new());
+        let output = timed(&mut timings.encode, || {
+            image_err_convert(encode(image, &mut data, fmt, meta, opts), path)?;
+            if opts.data_uri { *data.get_mut() = data_uri(data.get_ref(), fmt, &output) }
+            write_encoded(data.get_ref(), output, &[path], opts)
+        })?;
+        return Ok((output, Some(data.get_ref().len() as u64)))
+    }
+    prepare_file(&output, &[path], opts)?;
+    timed(&mut timings.encode, || save_image(image, &output, fmt, meta, path, opts))?;
+    preserve_attributes(&output, &[path], opts)?;
+    if opts.verify || opts.verify_full {
+        // Icon readers pick the largest embedded size
+        let dimensions = match opts.ico_sizes.last() {
+            Some(size) if fmt == Format::Image(ImageFormat::Ico) => (*size, *size),
+            _ => (image.width(), image.height())
+        };
+        verify_output(&output, fmt, dimensions, opts.verify_full)?;
+    }
+    if let Some(checksums) = &opts.checksums { checksums.record(&output)? }
+    let output_bytes = file_size(&output);
+    Ok((output, output_bytes))
+}
+
+// Rows of PNG inputs decoded, transformed and encoded at a time with low_memory, TIFF inputs go by their own strips
+const STRIP_ROWS: u32 = 256;
+
+// Whether every transform works on single pixels (or rows), so that strips of the image come out like the whole one
+fn strip_safe(opts: &Options) -> bool {
+    opts.ops.is_empty() && opts.rotate == 0 && opts.rotate_degrees.is_none() && !opts.flip_vertical
+        && opts.trim.is_none() && opts.crop.is_none() && opts.aspect.is_none() && opts.resize.is_none() && opts.crop_to.is_none() && opts.thumbnail.is_none()
+        && opts.scale.is_none() && opts.min_dimension.is_none() && opts.max_dimension.is_none() && opts.max_megapixels.is_none() && opts.blur.is_none() && opts.unsharp.is_none() && opts.denoise.is_none() && opts.vignette.is_none()
+        && opts.auto_levels.is_none() && opts.alpha_mask.is_none() && opts.pad.is_none() && opts.corners.is_none()
+        && opts.border.is_none() && opts.watermark.is_none() && opts.caption.is_none() && opts.quantize.is_none()
+        // These look at the whole image as well
+        && !opts.blurhash && !opts.thumbhash && opts.dedupe.is_none() && !opts.convert_to_srgb && opts.exif_edits.is_empty()
+}
+
+enum StripDecoder {
+    Png(Box<png::Reader<BufReader<File>>>),
+    // With the index of the next strip
+    Tiff(Box<tiff::decoder::Decoder<BufReader<File>>>, u32),
+}
+
+// Hands out the image of a PNG or TIFF input strip by strip
+struct StripSource {
+    decoder: StripDecoder,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    // Rows handed out so far
+    row: u32,
+    icc: Option<Vec<u8>>,
+}
+
+impl StripSource {
+    // None for inputs that are only decoded whole: other formats, interlaced or animated PNGs, tiled or planar
+    // TIFFs and those whose EXIF is kept or orients them
+    fn open(path: &Path, opts: &Options) -> ImcoResult<Option<Self>> {
+        let decoding = |e: String| ImcoError::Decoding(path.display().to_string(), e);
+        let mut reader = BufReader::new(io_error_convert(File::open(path), path, true)?);
+        let Ok(format) = image::guess_format(io_error_convert(std::io::BufRead::fill_buf(&mut reader), path, true)?) else { return Ok(None) };
+        let keeps_exif = !matches!(opts.metadata, MetadataPolicy::Strip);
+        let (decoder, width, height, icc) = match format {
+            ImageFormat::Png => {
+                let mut decoder = png::Decoder::new(reader);
+                decoder.set_transformations(png::Transformations::EXPAND);
+                let decoder = decoder.read_info().map_err(|e| decoding(e.to_string()))?;
+                let info = decoder.info();
+                if info.interlaced || info.animation_control.is_some() || (info.exif_metadata.is_some() && (keeps_exif || opts.auto_orient)) {
+                    return Ok(None)
+                }
+                let (width, height, icc) = (info.width, info.height, info.icc_profile.as_ref().map(|icc| icc.to_vec()));
+                (StripDecoder::Png(Box::new(decoder)), width, height, icc)
+            }
+            ImageFormat::Tiff => {
+                use tiff::tags::Tag;
+                let fail = |e: tiff::TiffError| decoding(e.to_string());
+                let mut tiff_limits = tiff::decoder::Limits::default();
+                tiff_limits.decoding_buffer_size = opts.limits.max_memory.map_or(usize::MAX, |max| max.try_into().unwrap_or(usize::MAX));
+                let mut decoder = tiff::decoder::Decoder::new(reader).map_err(fail)?.with_limits(tiff_limits);
+                let planar = decoder.find_tag_unsigned::<u16>(Tag::PlanarConfiguration).map_err(fail)?.is_some_and(|planar| planar != 1);
+                let oriented = decoder.find_tag_unsigned::<u16>(Tag::Orientation).map_err(fail)?.is_some_and(|orientation| orientation > 1);
+                let exif = decoder.find_tag(Tag::ExifDirectory).map_err(fail)?.is_some();
+                if decoder.get_chunk_type() != tiff::decoder::ChunkType::Strip || planar || (oriented && opts.auto_orient) || (exif && keeps_exif) {
+                    return Ok(None)
+                }
+                let icc = decoder.find_tag(Tag::IccProfile).map_err(fail)?.map(|icc| icc.into_u8_vec()).transpose().map_err(fail)?;
+                let (width, height) = decoder.dimensions().map_err(fail)?;
+                (StripDecoder::Tiff(Box::new(decoder), 0), width, height, icc)
+            }
+            _ => return Ok(None)
+        };
+        opts.limits.check(width, height, path)?;
+        Ok(Some(StripSource { decoder, format, width, height, row: 0, icc: icc.filter(|_| !opts.strip_icc) }))
+    }
+
+    fn next(&mut self, path: &Path) -> ImcoResult<Option<image::DynamicImage>> {
+        if self.row >= self.height { return Ok(None) }
+        let decoding = |e: String| ImcoError::Decoding(path.display().to_string(), e);
+        let strip = match &mut self.decoder {
+            StripDecoder::Png(reader) => {
+                let rows = STRIP_ROWS.min(self.height - self.row);
+                let (color, depth) = reader.output_color_type();
+                let mut data = Vec::new();
+                for _ in 0..rows {
+                    let row = reader.next_row().map_err(|e| decoding(e.to_string()))?.ok_or_else(|| decoding("image data ends early".to_string()))?;
+                    data.extend_from_slice(row.data());
+                }
+                png_strip(color, depth, self.width, rows, data).ok_or_else(|| ImcoError::Unsupported(path.display().to_string(), format!("Color type {color:?}")))?
+            }
+            StripDecoder::Tiff(decoder, index) => {
+                let fail = |e: tiff::TiffError| decoding(e.to_string());
+                let (width, rows) = decoder.chunk_data_dimensions(*index);
+                let color = decoder.colortype().map_err(fail)?;
+                let data = decoder.read_chunk(*index).map_err(fail)?;
+                *index += 1;
+                tiff_image(color, data, width, rows, path)?
+            }
+        };
+        if strip.height() == 0 { return Err(decoding("empty strip".to_string())) }
+        self.row += strip.height();
+        Ok(Some(strip))
+    }
+}
+
+// Rows of samples as decoded by png, 16 bit ones big endian
+fn png_strip(color: png::ColorType, depth: png::BitDepth, width: u32, rows: u32, data: Vec<u8>) -> Option<image::DynamicImage> {
+    use image::{DynamicImage, ImageBuffer};
+    use png::ColorType;
+    if depth == png::BitDepth::Sixteen {
+        let samples: Vec<u16> = data.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+        return match color {
+            ColorType::Grayscale => ImageBuffer::from_raw(width, rows, samples).map(DynamicImage::ImageLuma16),
+            ColorType::GrayscaleAlpha => ImageBuffer::from_raw(width, rows, samples).map(DynamicImage::ImageLumaA16),
+            ColorType::Rgb => ImageBuffer::from_raw(width, rows, samples).map(DynamicImage::ImageRgb16),
+            ColorType::Rgba => ImageBuffer::from_raw(width, rows, samples).map(DynamicImage::ImageRgba16),
+            ColorType::Indexed => None,
+        }
+    }
+    match color {
+        ColorType::Grayscale => ImageBuffer::from_raw(width, rows, data).map(DynamicImage::ImageLuma8),
+        ColorType::GrayscaleAlpha => ImageBuffer::from_raw(width, rows, data).map(DynamicImage::ImageLumaA8),
+        ColorType::Rgb => ImageBuffer::from_raw(width, rows, data).map(DynamicImage::ImageRgb8),
+        ColorType::Rgba => ImageBuffer::from_raw(width, rows, data).map(DynamicImage::ImageRgba8),
+        ColorType::Indexed => None,
+    }
+}
+
+// Converts a PNG or TIFF input into PNG or TIFF a strip at a time, None if it has to be decoded whole
+fn streamed_process(path: &Path, output: &Path, fmt: ImageFormat, opts: &Options) -> ImcoResult<Option<Conversion>> {
+    let Some(mut source) = StripSource::open(path, opts)? else { return Ok(None) };
+    let (width, height, icc, input_format) = (source.width, source.height, source.icc.take(), Format::Image(source.format));
+    let mut timings = Timings::default();
+    let start = std::time::Instant::now();
+    // The color type of the decoded strips, for check_lossless
+    let decoded = std::cell::Cell::new(None);
+    let mut next = || -> ImcoResult<Option<image::DynamicImage>> {
+        let Some(strip) = timed(&mut timings.decode, || source.next(path))? else { return Ok(None) };
+        decoded.set(Some(strip.color()));
+        timed(&mut timings.transform, || transform(strip, None, opts, path)).map(Some)
+    };
+    prepare_file(output, &[path], opts)?;
+    let mut depth = None;
+    write_atomically(output, |writer| {
+        let first = next()?.ok_or_else(|| ImcoError::Decoding(path.display().to_string(), "no image data".to_string()))?;
+        check_lossless(decoded.get().unwrap_or(first.color()), &first, Format::Image(fmt), path, opts)?;
+        depth = Some(sample_depth(first.color()).min(Format::Image(fmt).max_depth()));
+        match fmt {
+            ImageFormat::Png => write_png_strips(writer, width, height, icc, first, next, output, opts),
+            _ => write_tiff_strips(writer, width, height, icc, first, next, output),
+        }
+    })?;
+    timings.encode = start.elapsed().saturating_sub(timings.decode + timings.transform);
+    preserve_attributes(output, &[path], opts)?;
+    if opts.verify || opts.verify_full {
+        verify_output(output, Format::Image(fmt), (width, height), opts.verify_full)?;
+    }
+    if let Some(checksums) = &opts.checksums { checksums.record(output)? }
+    Ok(Some(Conversion { input_format: Some(input_format), output_bytes: file_size(output), timings, depth, ..Conversion::new(output, Format::Image(fmt)) }))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_png_strips(writer: &mut BufWriter<File>, width: u32, height: u32, icc: Option<Vec<u8>>, first: image::DynamicImage, mut next: impl FnMut() -> ImcoResult<Option<image::DynamicImage>>, output: &Path, opts: &Options) -> ImcoResult<()> {
+    use image::ColorType;
+    let fail = |e: png::EncodingError| ImcoError::Encoding(output.display().to_string(), e.to_string());
+    // Float samples are clipped into 16 bit sRGB like when encoding whole images
+    let finish = |strip: image::DynamicImage| if matches!(strip.color(), ColorType::Rgb32F | ColorType::Rgba32F) { tonemap(strip, None, 0.0) } else { strip };
+    let first = finish(first);
+    let (png_color, depth, color) = png_layout(first.color());
+    let mut info = png::Info::with_size(width, height);
+    info.color_type = png_color;
+    info.bit_depth = depth;
+    info.icc_profile = icc.map(std::borrow::Cow::Owned);
+    let mut encoder = png::Encoder::with_info(writer, info).map_err(fail)?;
+    set_png_settings(&mut encoder, &opts.encoder.png);
+    let mut png_writer = encoder.write_header().map_err(fail)?;
+    let mut stream = png_writer.stream_writer().map_err(fail)?;
+    let mut strip = Some(first);
+    while let Some(current) = strip {
+        let bytes = png_samples(convert_color(current, color), depth);
+        io_error_convert(stream.write_all(&bytes), output, false)?;
+        strip = next()?.map(finish);
+    }
+    stream.finish().map_err(fail)
+}
+
+// The png color type and depth an image is written with, and what it has to be converted into for that
+fn png_layout(color: image::ColorType) -> (png::ColorType, png::BitDepth, image::ColorType) {
+    use image::ColorType;
+    match color {
+        ColorType::L8 => (png::ColorType::Grayscale, png::BitDepth::Eight, color),
+        ColorType::La8 => (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight, color),
+        ColorType::Rgb8 => (png::ColorType::Rgb, png::BitDepth::Eight, color),
+        ColorType::L16 => (png::ColorType::Grayscale, png::BitDepth::Sixteen, color),
+        ColorType::La16 => (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen, color),
+        ColorType::Rgb16 => (png::ColorType::Rgb, png::BitDepth::Sixteen, color),
+        ColorType::Rgba16 => (png::ColorType::Rgba, png::BitDepth::Sixteen, color),
+        _ => (png::ColorType::Rgba, png::BitDepth::Eight, ColorType::Rgba8),
+    }
+}
+
+// Samples like png wants them, 16 bit ones big endian
+fn png_samples(image: image::DynamicImage, depth: png::BitDepth) -> Vec<u8> {
+    if depth == png::BitDepth::Sixteen {
+        image.as_bytes().chunks_exact(2).flat_map(|pair| u16::from_ne_bytes([pair[0], pair[1]]).to_be_bytes()).collect()
+    } else { image.into_bytes() }
+}
+
+fn set_png_settings<W: Write>(encoder: &mut png::Encoder<'_, W>, settings: &PngSettings) {
+    encoder.set_compression(match settings.compression {
+        CompressionType::Best => png::Compression::High,
+        CompressionType::Uncompressed => png::Compression::NoCompression,
+        CompressionType::Default => png::Compression::Balanced,
+        _ => png::Compression::Fast,
+    });
+    encoder.set_filter(match settings.filter {
+        PngFilter::NoFilter => png::Filter::NoFilter,
+        PngFilter::Sub => png::Filter::Sub,
+        PngFilter::Up => png::Filter::Up,
+        PngFilter::Avg => png::Filter::Avg,
+        PngFilter::Paeth => png::Filter::Paeth,
+        _ => png::Filter::Adaptive,
+    });
+}
+
+// The png crate can't interlace, so the Adam7 passes are filtered and deflated here into one IDAT.
+// data holds the rows of the whole image, packed like write_image_data wants them
+fn write_interlaced_png<W: Write>(writer: W, mut info: png::Info<'_>, data: &[u8], settings: &PngSettings) -> Result<(), png::EncodingError> {
+    const PASSES: [(usize, usize, usize, usize); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+    let (width, height) = (info.width as usize, info.height as usize);
+    let bits = info.bits_per_pixel();
+    let row_bytes = (width * bits).div_ceil(8);
+    // Filters predict from the byte of the previous pixel, or the previous byte below 8 bits
+    let bpp = bits.div_ceil(8);
+    let level = match settings.compression {
+        CompressionType::Best => flate2::Compression::best(),
+        CompressionType::Uncompressed => flate2::Compression::none(),
+        CompressionType::Fast => flate2::Compression::fast(),
+        CompressionType::Level(level) => flate2::Compression::new(level as u32),
+        _ => flate2::Compression::default(),
+    };
+    let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), level);
+    for (x0, y0, dx, dy) in PASSES {
+        if x0 >= width || y0 >= height { continue }
+        let columns = (width - x0).div_ceil(dx);
+        let pass_bytes = (columns * bits).div_ceil(8);
+        let mut previous = vec![0u8; pass_bytes];
+        for y in (y0..height).step_by(dy) {
+            let source = &data[y * row_bytes..(y + 1) * row_bytes];
+            let mut row = vec![0u8; pass_bytes];
+            for (i, x) in (x0..width).step_by(dx).enumerate() {
+                if bits >= 8 {
+                    row[i * bpp..(i + 1) * bpp].copy_from_slice(&source[x * bpp..(x + 1) * bpp]);
+                } else {
+                    let sample = (source[x * bits / 8] >> (8 - bits - x * bits % 8)) & ((1 << bits) - 1) as u8;
+                    row[i * bits / 8] |= sample << (8 - bits - i * bits % 8);
+                }
+            }
+            zlib.write_all(&png_filter_row(&row, &previous, bpp, settings.filter))?;
+            previous = row;
+        }
+    }
+    info.interlaced = true;
+    let mut writer = png::Encoder::with_info(writer, info)?.write_header()?;
+    writer.write_chunk(png::chunk::IDAT, &zlib.finish()?)?;
+    writer.finish()
+}
+
+// The filter type byte followed by the filtered row, adaptive picks the one with the smallest sum of absolute differences
+fn png_filter_row(row: &[u8], previous: &[u8], bpp: usize, filter: PngFilter) -> Vec<u8> {
+    let filtered = |kind: u8| -> Vec<u8> {
+        let mut out = Vec::with_capacity(row.len() + 1);
+        out.push(kind);
+        for i in 0..row.len() {
+            let (left, up) = (if i >= bpp { row[i - bpp] } else { 0 }, previous[i]);
+            let upper_left = if i >= bpp { previous[i - bpp] } else { 0 };
+            let prediction = match kind {
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => {
+                    let estimate = left as i16 + up as i16 - upper_left as i16;
+                    let (a, b, c) = ((estimate - left as i16).abs(), (estimate - up as i16).abs(), (estimate - upper_left as i16).abs());
+                    if a <= b && a <= c { left } else if b <= c { up } else { upper_left }
+                }
+                _ => 0,
+            };
+            out.push(row[i].wrapping_sub(prediction));
+        }
+        out
+    };
+    match filter {
+        PngFilter::NoFilter => filtered(0),
+        PngFilter::Sub => filtered(1),
+        PngFilter::Up => filtered(2),
+        PngFilter::Avg => filtered(3),
+        PngFilter::Paeth => filtered(4),
+        _ => (0..5).map(filtered).min_by_key(|out| out[1..].iter().map(|&b| (b as i8).unsigned_abs() as u32).sum::<u32>()).unwrap(),
+    }
+}
+
+fn write_tiff_strips(writer: &mut BufWriter<File>, width: u32, height: u32, icc: Option<Vec<u8>>, first: image::DynamicImage, next: impl FnMut() -> ImcoResult<Option<image::DynamicImage>>, output: &Path) -> ImcoResult<()> {
+    use image::ColorType;
+    use tiff::encoder::colortype;
+    let icc = icc.as_deref();
+    // TIFF has no gray with alpha, which goes into RGBA like when encoding whole images
+    match first.color() {
+        ColorType::L8 => tiff_strips::<colortype::Gray8>(writer, width, height, icc, first, next, |strip| strip.to_luma8().into_raw(), output),
+        ColorType::L16 => tiff_strips::<colortype::Gray16>(writer, width, height, icc, first, next, |strip| strip.to_luma16().into_raw(), output),
+        ColorType::Rgb8 => tiff_strips::<colortype::RGB8>(writer, width, height, icc, first, next, |strip| strip.to_rgb8().into_raw(), output),
+        ColorType::Rgb16 => tiff_strips::<colortype::RGB16>(writer, width, height, icc, first, next, |strip| strip.to_rgb16().into_raw(), output),
+        ColorType::La16 | ColorType::Rgba16 => tiff_strips::<colortype::RGBA16>(writer, width, height, icc, first, next, |strip| strip.to_rgba16().into_raw(), output),
+        ColorType::Rgb32F => tiff_strips::<colortype::RGB32Float>(writer, width, height, icc, first, next, |strip| strip.to_rgb32f().into_raw(), output),
+        ColorType::Rgba32F => tiff_strips::<colortype::RGBA32Float>(writer, width, height, icc, first, next, |strip| strip.to_rgba32f().into_raw(), output),
+        _ => tiff_strips::<colortype::RGBA8>(writer, width, height, icc, first, next, |strip| strip.to_rgba8().into_raw(), output),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn tiff_strips<C: tiff::encoder::colortype::ColorType>(writer: &mut BufWriter<File>, width: u32, height: u32, icc: Option<&[u8]>, first: image::DynamicImage, mut next: impl FnMut() -> ImcoResult<Option<image::DynamicImage>>, samples: impl Fn(image::DynamicImage) -> Vec<C::Inner>, output: &Path) -> ImcoResult<()>
+where [C::Inner]: tiff::encoder::TiffValue {
+    let fail = |e: tiff::TiffError| ImcoError::Encoding(output.display().to_string(), e.to_string());
+    let mut encoder = tiff::encoder::TiffEncoder::new(writer).map_err(fail)?;
+    let mut image = encoder.new_image::<C>(width, height).map_err(fail)?;
+    if let Some(icc) = icc {
+        image.encoder().write_tag(tiff::tags::Tag::IccProfile, icc).map_err(fail)?;
+    }
+    image.rows_per_strip(first.height()).map_err(fail)?;
+    let mut strip = Some(first);
+    while let Some(current) = strip {
+        image.write_strip(&samples(current)).map_err(fail)?;
+        strip = next()?;
+    }
+    image.finish().map_err(fail)
+}
+
+// Decodes the input with the metadata the output keeps, and the orientation left for an autorotate operation
+fn decode_with_metadata(path: &Path, fmt: Format, timings: &mut Timings, opts: &Options) -> ImcoResult<(image::DynamicImage, Metadata, Option<Format>, Option<image::metadata::Orientation>)> {
+    let (mut image, mut meta, org_fmt) = timed(&mut timings.decode, || retrying(path, opts, || decode_configured(path, opts)))?;
+    if opts.strict_extensions && opts.i_fmt.is_none() {
+        if let Some(found) = extension_mismatch(path, org_fmt) { return Err(ImcoError::ExtensionMismatch(path.display().to_string(), found.extension().to_string())) }
+    }
+    let mut pending = None;
+    if opts.auto_orient || defers_orientation(opts) {
+        match meta.orientation.take() {
+            Some(orientation) if defers_orientation(opts) => pending = Some(orientation),
+            Some(orientation) => image.apply_orientation(orientation),
+            None => {}
+        }
+        // The pixels are upright now, so viewers must not rotate them again
+        if let Some(exif) = &mut meta.exif {
+            let _ = image::metadata::Orientation::remove_from_exif_chunk(exif);
+        }
+    }
+    let keep_metadata = match opts.metadata {
+        MetadataPolicy::Keep => true,
+        MetadataPolicy::Strip => false,
+        MetadataPolicy::SameFormat => org_fmt == Some(fmt),
+    };
+    if !keep_metadata {
+        meta.exif = None;
+    }
+    if opts.convert_to_srgb {
+        image = to_srgb(image, &mut meta);
+    }
+    if opts.strip_icc {
+        meta.icc = None;
+    }
+    if opts.deterministic {
+        if let Some(exif) = &mut meta.exif { blank_exif_dates(exif) }
+    }
+    if !opts.exif_edits.is_empty() {
+        meta.exif = edit_exif(meta.exif.take(), &opts.exif_edits, path)?;
+    }
+    Ok((image, meta, org_fmt, pending))
+}
+
+// Outputs named after the width, at a {width} placeholder or behind the file stem like photo-480.webp
+fn srcset_name(output: &Path, width: u32, height: u32) -> PathBuf {
+    if has_dimensions(output) { return fill_dimensions(output, width, height) }
+    behind_stem(output, &format!("-{width}"))
+}
+
+// Writes the transformed image at every width of the srcset. Outputs are never enlarged, widths above
+// the image's own are left out and it is written at its own width instead
+fn srcset_process(path: PathBuf, output: PathBuf, fmt: Format, opts: &Options) -> ImcoResult<Conversion> {
+    if output.as_os_str() == STDIO { return Err(ImcoError::ExtractToStdout) }
+    if opts.dry_run {
+        let input_format = opts.i_fmt.or_else(|| mk_format_fp(&path).ok());
+        let srcset = opts.srcset.iter().map(|width| (srcset_name(&output, *width, 0), *width)).collect();
+        return Ok(Conversion { input_format, srcset, ..Conversion::new(output, fmt) })
+    }
+    let mut timings = Timings::default();
+    let (image, meta, org_fmt, pending) = decode_with_metadata(&path, fmt, &mut timings, opts)?;
+    let source = image.color();
+    let image = timed(&mut timings.transform, || transform(image, pending, opts, &path))?;
+    check_lossless(source, &image, fmt, &path, opts)?;
+    let (image, flattened) = timed(&mut timings.transform, || flatten_for(image, fmt, opts));
+    let depth = output_depth(&image, fmt, &path, opts)?;
+    let mut widths: Vec<u32> = opts.srcset.iter().map(|width| (*width).min(image.width())).collect();
+    widths.dedup();
+    let mut output_bytes = 0;
+    let mut srcset = vec![];
+    for width in widths {
+        let height = ((image.height() as u64 * width as u64) as f64 / image.width() as f64).round().max(1.0) as u32;
+        let name = srcset_name(&output, width, height);
+        // Existing outputs still belong to the set
+        let Some(variant_output) = check_conflict(name.clone(), &[&path], opts)? else {
+            srcset.push((name, width));
+            continue
+        };
+        let variant = timed(&mut timings.transform, || if width == image.width() { image.clone() } else { image.resize_exact(width, height, opts.filter) });
+        let mut data = Cursor::new(Vec::new());
+        let written = timed(&mut timings.encode, || {
+            image_err_convert(encode(&variant, &mut data, fmt, &meta, opts), &path)?;
+            write_encoded(data.get_ref(), variant_output, &[&path], opts)
+        })?;
+        output_bytes += data.get_ref().len() as u64;
+        srcset.push((written, width));
+    }
+    let placeholders = Placeholders::of(&image, opts);
+    placeholders.write_sidecar(&output, &[&path], opts)?;
+    Ok(Conversion { input_format: org_fmt, output_bytes: Some(output_bytes), flattened, srcset, placeholders, timings, depth: Some(depth), ..Conversion::new(output, fmt) })
+}
+
+// Puts the transformed image onto the clipboard, which takes raw pixels (offered to other programs as png)
+fn clipboard_process(path: PathBuf, opts: &Options) -> ImcoResult<Conversion> {
+    let output_format = Format::Image(ImageFormat::Png);
+    let output = CLIPBOARD.to_string();
+    if opts.dry_run {
+        let input_format = opts.i_fmt.or_else(|| mk_format_fp(&path).ok());
+        return Ok(Conversion { input_format, ..Conversion::new(output, output_format) })
+    }
+    let mut timings = Timings::default();
+    let (image, input_format, orientation) = timed(&mut timings.decode, || decode_upright(&path, opts))?;
+    let image = timed(&mut timings.transform, || transform(image, orientation, opts, &path))?;
+    timed(&mut timings.encode, || write_clipboard(&image))?;
+    Ok(Conversion { input_format, timings, ..Conversion::new(output, output_format) })
+}
+
+fn animated_process(frames: Vec<image::Frame>, org_fmt: Option<Format>, path: PathBuf, output: PathBuf, fmt: Format, mut timings: Timings, opts: &Options) -> ImcoResult<Conversion> {
+    let frames = timed(&mut timings.transform, || frames.into_iter().map(|frame| {
+        let delay = opts.frame_delay.unwrap_or(frame.delay());
+        let image = transform(image::DynamicImage::ImageRgba8(frame.into_buffer()), None, opts, &path)?;
+        check_lossless(image::ColorType::Rgba8, &image, fmt, &path, opts)?;
+        Ok(image::Frame::from_parts(image.to_rgba8(), 0, 0, delay))
+    }).collect::<ImcoResult<Vec<_>>>())?;
+    let mut data = Vec::new();
+    let to_file = output.as_os_str() != STDIO && opts.archive.is_none() && !opts.data_uri;
+    let output = timed(&mut timings.encode, || {
+        encode_frames(&frames, &mut data, fmt, opts.loops, &opts.encoder, &path)?;
+        if opts.data_uri { data = data_uri(&data, fmt, &output) }
+        write_encoded(&data, output, &[&path], opts)
+    })?;
+    if to_file && (opts.verify || opts.verify_full) {
+        let first = frames[0].buffer();
+        verify_output(&output, fmt, (first.width(), first.height()), opts.verify_full)?;
+    }
+    Ok(Conversion { input_format: org_fmt, output_bytes: Some(data.len() as u64), timings, ..Conversion::new(output, fmt) })
+}
+
+// Rotation, flips, crop, resizing and tonal changes, in that order
+// Orientation is the EXIF orientation left for an autorotate operation
+fn transform(mut image: image::DynamicImage, orientation: Option<image::metadata::Orientation>, opts: &Options, path: &Path) -> ImcoResult<image::DynamicImage> {
+    let float = matches!(image.color(), image::ColorType::Rgb32F | image::ColorType::Rgba32F);
+    if float && (opts.tonemap.is_some() || opts.exposure != 0.0 || opts.depth.is_some()) {
+        image = tonemap(image, opts.tonemap, opts.exposure);
+    }
+    for op in &opts.ops {
+        image = apply_operation(image, op, orientation, opts, path)?;
+    }
+    let mut image = match opts.rotate {
+        90 => image.rotate90(),
+        180 => image.rotate180(),
+        270 => image.rotate270(),
+        _ => image
+    };
+    if let Some(rotation) = &opts.rotate_degrees {
+        image = rotate_degrees(image, rotation, opts.background);
+    }
+    if opts.flip_horizontal {
+        image = image.fliph();
+    }
+    if opts.flip_vertical {
+        image = image.flipv();
+    }
+    if let Some(key) = &opts.chroma_key {
+        image = chroma_key(image, key);
+    }
+    if let Some(trim) = &opts.trim {
+        image = trim_image(image, trim);
+    }
+    if let Some(crop) = &opts.crop {
+        image = crop_image(image, crop, path)?;
+    }
+    if let Some(aspect) = &opts.aspect {
+        image = crop_to_aspect(image, aspect, opts.filter);
+    }
+    if let Some(settings) = &opts.denoise {
+        image = denoise(image, settings);
+    }
+    if let Some(resize) = &opts.resize {
+        check_grown(&image, &Operation::Resize(*resize), opts, path)?;
+        image = linear_light(image, opts.linear_resize, |image| resize_image(image, resize, opts.filter, opts.resize_backend, opts.gpu.as_deref()));
+    }
+    if let Some(factor) = opts.scale {
+        check_grown(&image, &Operation::Scale(factor), opts, path)?;
+        image = scale_image(image, factor, opts);
+    }
+    if let Some(crop) = &opts.crop_to {
+        check_grown(&image, &Operation::CropTo(*crop), opts, path)?;
+        image = linear_light(image, opts.linear_resize, |image| crop_to(image, crop, opts.filter));
+    }
+    if let Some(size) = opts.thumbnail {
+        image = image.thumbnail(size, size);
+    }
+    if let Some((width, height)) = clamp_dimensions(image.width(), image.height(), opts.min_dimension, opts.max_dimension) {
+        opts.limits.check_canvas((width as u64, height as u64), image.color(), path)?;
+        image = linear_light(image, opts.linear_resize, |image| image.resize_exact(width, height, opts.filter));
+    }
+    if let Some((width, height)) = opts.max_megapixels.and_then(|megapixels| cap_megapixels(image.width(), image.height(), megapixels)) {
+        image = linear_light(image, opts.linear_resize, |image| image.resize_exact(width, height, opts.filter));
+    }
+    if let Some(sigma) = opts.blur {
+        image = image.blur(sigma);
+    }
+    if let Some(mask) = &opts.unsharp {
+        image = unsharp_mask(image, mask);
+    }
+    if let Some(levels) = &opts.auto_levels {
+        image = auto_levels(image, levels);
+    }
+    if opts.brightness != 0 {
+        image = image.brighten(opts.brightness);
+    }
+    if opts.contrast != 0.0 {
+        image = image.adjust_contrast(opts.contrast);
+    }
+    if opts.gamma != 1.0 {
+        image = adjust_gamma(image, opts.gamma);
+    }
+    if let Some(lut) = &opts.lut {
+        let color = colorful(image.color());
+        image = match opts.gpu.as_deref().and_then(|gpu| gpu.lut(&image, lut, color)) {
+            Some(looked_up) => looked_up,
+            None => map_colors(image, color, |rgb| lut.lookup(rgb))
+        };
+    }
+    if opts.invert {
+        image = invert(image);
+    }
+    if opts.sepia {
+        image = sepia(image, opts.gpu.as_deref());
+    }
+    if let Some(tint) = &opts.tint {
+        image = tint_image(image, tint);
+    }
+    if let Some(vignette) = &opts.vignette {
+        image = vignette_image(image, vignette);
+    }
+    if let Some(luma) = opts.threshold {
+        image = threshold(image, luma);
+    }
+    if let Some(levels) = opts.posterize {
+        image = posterize(image, levels);
+    }
+    if let Some(mask) = &opts.alpha_mask {
+        image = mask.apply(image);
+    }
+    if let Some(pad) = &opts.pad {
+        check_grown(&image, &Operation::Pad(*pad), opts, path)?;
+        image = pad_image(image, pad, opts.filter);
+    }
+    if let Some(corners) = &opts.corners {
+        image = cut_corners(image, corners);
+    }
+    if let Some(border) = &opts.border {
+        check_grown(&image, &Operation::Border(*border), opts, path)?;
+        image = add_border(image, border, opts.corners.as_ref());
+    }
+    if let Some(watermark) = &opts.watermark {
+        image = watermark.apply(image);
+    }
+    if let Some(caption) = &opts.caption {
+        image = caption.apply(image, path);
+    }
+    if opts.extract_alpha {
+        image = extract_alpha(image);
+    }
+    if let Some(color) = opts.colorspace {
+        image = convert_colorspace(image, color, opts.gpu.as_deref());
+    }
+    if let Some(depth) = opts.depth {
+        image = convert_depth(image, depth);
+    }
+    if let Some(quantize) = opts.quantize {
+        image = quantize_image(&image, quantize);
+    }
+    Ok(image)
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.040_45 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.003_130_8 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+// Decodes the sRGB values into linear float ones, keeping the alpha
+fn linearize(image: &image::DynamicImage) -> image::DynamicImage {
+    let alpha = image.color().has_alpha();
+    let mut buffer = image.to_rgba32f();
+    buffer.par_pixels_mut().for_each(|p| p.0[..3].iter_mut().for_each(|c| *c = srgb_to_linear(*c)));
+    if alpha { buffer.into() } else { image::DynamicImage::ImageRgba32F(buffer).to_rgb32f().into() }
+}
+
+// Runs scale on linear light, decoding the sRGB values before and encoding them again after it, so that
+// dark and bright pixels mix like light does. Float images already hold linear values
+fn linear_light(image: image::DynamicImage, linear: bool, scale: impl FnOnce(image::DynamicImage) -> image::DynamicImage) -> image::DynamicImage {
+    let color = image.color();
+    if !linear || matches!(color, image::ColorType::Rgb32F | image::ColorType::Rgba32F) { return scale(image) }
+    let mut buffer = image.into_rgba32f();
+    buffer.par_pixels_mut().for_each(|p| p.0[..3].iter_mut().for_each(|c| *c = srgb_to_linear(*c)));
+    let mut buffer = scale(buffer.into()).into_rgba32f();
+    // Filters overshooting below black or above white are clipped
+    buffer.par_pixels_mut().for_each(|p| p.0[..3].iter_mut().for_each(|c| *c = linear_to_srgb(c.clamp(0.0, 1.0))));
+    convert_color(buffer.into(), color)
+}
+
+// Scales linear HDR values by the exposure, compresses them with the curve (clipping without one)
+// and encodes them as 16 bit sRGB
+fn tonemap(image: image::DynamicImage, curve: Option<Tonemap>, exposure: f32) -> image::DynamicImage {
+    let alpha = image.color().has_alpha();
+    let scale = 2f32.powf(exposure);
+    let mut buffer = image.into_rgba32f();
+    for p in buffer.pixels_mut() {
+        for c in &mut p.0[..3] {
+            let x = c.max(0.0) * scale;
+            let mapped = match curve {
+                Some(Tonemap::Reinhard) => x / (1.0 + x),
+                Some(Tonemap::Aces) => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+                None => x,
+            }.clamp(0.0, 1.0);
+            *c = linear_to_srgb(mapped);
+        }
+    }
+    let mapped = image::DynamicImage::ImageRgba32F(buffer);
+    if alpha { mapped.to_rgba16().into() } else { mapped.to_rgb16().into() }
+}
+
+fn convert_depth(image: image::DynamicImage, depth: u8) -> image::DynamicImage {
+    use image::ColorType;
+    let color = image.color();
+    let target = match (color.has_color(), color.has_alpha(), depth) {
+        (false, false, 8) => ColorType::L8,
+        (false, true, 8) => ColorType::La8,
+        (true, false, 8) => ColorType::Rgb8,
+        (true, true, 8) => ColorType::Rgba8,
+        (false, false, _) => ColorType::L16,
+        (false, true, _) => ColorType::La16,
+        (true, false, _) => ColorType::Rgb16,
+        (true, true, _) => ColorType::Rgba16,
+    };
+    convert_color(image, target)
+}
+
+// Bits per channel of the samples, 32 for float ones
+fn sample_depth(color: image::ColorType) -> u8 {
+    color.bytes_per_pixel() / color.channel_count() * 8
+}
+
+/// How bits per channel of [`Format::max_depth`] and [`Conversion::depth`] read, e.g. 16 bit or 32 bit float
+pub fn depth_name(bits: u8) -> String {
+    if bits == 32 { "32 bit float".to_string() } else { format!("{bits} bit") }
+}
+
+// The bits per channel image is written into fmt with, float formats taking every image as float
+fn output_depth(image: &image::DynamicImage, fmt: Format, path: &Path, opts: &Options) -> ImcoResult<u8> {
+    let bits = sample_depth(image.color());
+    let max = fmt.max_depth();
+    if opts.preserve_depth && bits > 8 && max == 8 {
+        return Err(ImcoError::DepthLost(path.display().to_string(), bits, fmt.extension().to_string()))
+    }
+    Ok(if matches!(fmt, Format::Image(ImageFormat::OpenExr | ImageFormat::Hdr)) { 32 } else { bits.min(max) })
+}
+
+// With lossless_only, fails naming the first step that loses information when image is written as fmt.
+// source is the color type the input was decoded with
+fn check_lossless(source: image::ColorType, image: &image::DynamicImage, fmt: Format, path: &Path, opts: &Options) -> ImcoResult<()> {
+    if !opts.lossless_only { return Ok(()) }
+    let (before, bits) = (sample_depth(source), sample_depth(image.color()));
+    let name = fmt.extension();
+    let step = if opts.quantize.is_some() {
+        "--colors reduces it to a palette".to_string()
+    } else if before > bits {
+        format!("its {} samples are reduced to {}", depth_name(before), depth_name(bits))
+    } else if matches!(fmt, Format::Image(ImageFormat::Jpeg | ImageFormat::Avif)) {
+        format!("{name} compresses lossily")
+    } else if fmt == Format::Image(ImageFormat::Hdr) {
+        "hdr rounds samples to 8 bits with a shared exponent".to_string()
+    } else if matches!(fmt, Format::Image(ImageFormat::Dds) | Format::Ktx2) && opts.texture_compression.is_some() {
+        "texture compression is lossy".to_string()
+    } else if bits > fmt.max_depth() {
+        format!("{name} holds {} samples, not {}", depth_name(fmt.max_depth()), depth_name(bits))
+    } else if !supports_alpha(fmt) && image.color().has_alpha() && image.to_rgba8().pixels().any(|p| p.0[3] < 255) {
+        format!("{name} has no alpha channel for its transparency")
+    } else if fmt == Format::Image(ImageFormat::Gif) && !fits_gif(&image.to_rgba8()) {
+        "gif holds at most 256 colors and no partial transparency".to_string()
+    } else {
+        return Ok(())
+    };
+    Err(ImcoError::Lossy(path.display().to_string(), step))
+}
+
+// What writing the image as fmt loses that no option asked for, failing on the first with strict.
+// source is the color type the input was decoded with, depth the bits per channel of the output
+#[allow(clippy::too_many_arguments)]
+fn check_downgrades(path: &Path, org_fmt: Option<Format>, source: image::ColorType, depth: u8, flattened: bool, meta: &Metadata, fmt: Format, opts: &Options) -> ImcoResult<Vec<Downgrade>> {
+    let mut downgrades = vec![];
+    if flattened { downgrades.push(Downgrade::AlphaDropped) }
+    let before = sample_depth(source);
+    if before > depth && opts.depth.is_none() && opts.quantize.is_none() && opts.tonemap.is_none() {
+        downgrades.push(Downgrade::DepthReduced(before, depth))
+    }
+    let file = path.as_os_str() != STDIO && path.as_os_str() != CLIPBOARD;
+    if file && !opts.first_frame_only && matches!(org_fmt, Some(Format::Image(ImageFormat::Gif | ImageFormat::Png | ImageFormat::WebP))) {
+        let frames = count_frames(path, org_fmt).unwrap_or(1);
+        if frames > 1 { downgrades.push(Downgrade::AnimationFlattened(frames)) }
+    }
+    let srgb = |icc: &[u8]| qcms::Profile::new_from_slice(icc, false).is_some_and(|profile| profile.is_sRGB());
+    if meta.icc.as_deref().is_some_and(|icc| !srgb(icc)) && !embeds_icc(fmt) {
+        downgrades.push(Downgrade::ProfileDiscarded)
+    }
+    if file && !matches!(opts.metadata, MetadataPolicy::Strip) && has_density(path, org_fmt) {
+        downgrades.push(Downgrade::ResolutionLost)
+    }
+    match downgrades.first() {
+        Some(downgrade) if opts.strict => Err(ImcoError::Downgraded(path.display().to_string(), downgrade.to_string())),
+        _ => Ok(downgrades)
+    }
+}
+
+// Whether encode writes the color profile into fmt
+fn embeds_icc(fmt: Format) -> bool {
+    matches!(fmt, Format::Image(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Tiff))
+}
+
+// Whether the input records its physical pixel density, which outputs are never written with
+fn has_density(path: &Path, fmt: Option<Format>) -> bool {
+    let Ok(file) = File::open(path) else { return false };
+    let mut reader = BufReader::new(file);
+    match fmt {
+        Some(Format::Image(ImageFormat::Png)) => png::Decoder::new(reader).read_info().ok()
+            .and_then(|png| png.info().pixel_dims)
+            .is_some_and(|dims| dims.unit == png::Unit::Meter),
+        // The JFIF segment right after the start of image, its units being dots per inch or centimeter
+        Some(Format::Image(ImageFormat::Jpeg)) => {
+            let mut head = [0; 14];
+            reader.read_exact(&mut head).is_ok() && head[2..4] == [0xFF, 0xE0] && &head[6..11] == b"JFIF\0" && matches!(head[13], 1 | 2)
+        }
+        Some(Format::Image(ImageFormat::Tiff)) => tiff::decoder::Decoder::new(reader).ok()
+            .and_then(|mut decoder| decoder.find_tag(tiff::tags::Tag::XResolution).ok().flatten())
+            .is_some(),
+        _ => false
+    }
+}
+
+// Whether a gif keeps every pixel, fully transparent ones all sharing an entry
+fn fits_gif(rgba: &image::RgbaImage) -> bool {
+    let mut colors = HashSet::new();
+    rgba.pixels().all(|p| match p.0[3] {
+        0 => { colors.insert([0; 4]); colors.len() <= 256 }
+        255 => { colors.insert(p.0); colors.len() <= 256 }
+        _ => false
+    })
+}
+
+// Formats whose encoders drop (or can't take) an alpha channel
+fn supports_alpha(fmt: Format) -> bool {
+    !matches!(fmt, Format::Pdf | Format::Image(ImageFormat::Jpeg))
+}
+
+// Composites the image onto the background if fmt has no alpha channel, telling whether
+// transparent pixels were put onto the default white background
+fn flatten_for(image: image::DynamicImage, fmt: Format, opts: &Options) -> (image::DynamicImage, bool) {
+    if supports_alpha(fmt) || !image.color().has_alpha() { return (image, false) }
+    let background = opts.background.unwrap_or(image::Rgb([255, 255, 255]));
+    let gray = !image.color().has_color() && background.0[0] == background.0[1] && background.0[1] == background.0[2];
+    let mut transparent = false;
+    let mut rgb = image::RgbImage::new(image.width(), image.height());
+    for (p, flat) in image.to_rgba8().pixels().zip(rgb.pixels_mut()) {
+        let alpha = p.0[3] as u32;
+        transparent |= alpha < 255;
+        for c in 0..3 {
+            flat.0[c] = ((p.0[c] as u32 * alpha + background.0[c] as u32 * (255 - alpha) + 127) / 255) as u8;
+        }
+    }
+    let flat = image::DynamicImage::ImageRgb8(rgb);
+    (if gray { image::DynamicImage::ImageLuma8(flat.to_luma8()) } else { flat }, transparent && opts.background.is_none())
+}
+
+const BAYER: [[f32; 4]; 4] = [[0.0, 8.0, 2.0, 10.0], [12.0, 4.0, 14.0, 6.0], [3.0, 11.0, 1.0, 9.0], [15.0, 7.0, 13.0, 5.0]];
+
+// Maps every pixel onto a NeuQuant palette learned from the image
+fn quantize_image(image: &image::DynamicImage, quantize: Quantize) -> image::DynamicImage {
+    use image::imageops::ColorMap;
+    let mut rgba = image.to_rgba8();
+    let palette = color_quant::NeuQuant::new(10, quantize.colors as usize, rgba.as_raw());
+    match quantize.dither {
+        Dither::FloydSteinberg => image::imageops::dither(&mut rgba, &palette),
+        Dither::Ordered => {
+            // Roughly the distance between neighbouring palette colors
+            let spread = 255.0 / (quantize.colors as f32).cbrt();
+            for (x, y, p) in rgba.enumerate_pixels_mut() {
+                let offset = (BAYER[y as usize % 4][x as usize % 4] + 0.5) / 16.0 - 0.5;
+                p.0[..3].iter_mut().for_each(|c| *c = (*c as f32 + offset * spread).round().clamp(0.0, 255.0) as u8);
+                palette.map_color(p);
+            }
+        }
+        Dither::None => rgba.pixels_mut().for_each(|p| palette.map_color(p)),
+    }
+    image::DynamicImage::ImageRgba8(rgba)
+}
+
+// Create missing directories and back up what is about to be overwritten
+fn prepare_file<S: AsRef<Path>>(output: &Path, sources: &[S], opts: &Options) -> ImcoResult<()> {
+    if opts.create_dirs {
+        if let Some(parent) = output.parent() {
+            io_error_convert(std::fs::create_dir_all(parent), output, false)?;
+        }
+    }
+    // Replacing an input loses the original, so it is kept even without asking
+    let replaces_source = sources.iter().any(|source| same_file(source.as_ref(), output));
+    let suffix = match (&opts.backup, &opts.backup_dir) {
+        (Some(suffix), _) => Some(suffix.as_str()),
+        (None, Some(_)) => Some(""),
+        (None, None) => replaces_source.then_some(".bak"),
+    };
+    if let Some(suffix) = suffix {
+        backup_existing(output, suffix, opts.backup_dir.as_deref(), opts.strict_backup)?;
+    }
+    Ok(())
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| bits | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            encoded.push(if i <= chunk.len() { ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char } else { '=' });
+        }
+    }
+    encoded
+}
+
+// The encoded image as a data URI, ending in a newline on a terminal
+fn data_uri(data: &[u8], fmt: Format, output: &Path) -> Vec<u8> {
+    let newline = if output.as_os_str() == STDIO { "\n" } else { "" };
+    format!("data:{};base64,{}{newline}", fmt.mime_type(), base64(data)).into_bytes()
+}
+
+// Writes already encoded data to stdout, the archive or a file, returning where it ended up
+fn write_encoded<S: AsRef<Path>>(data: &[u8], output: PathBuf, sources: &[S], opts: &Options) -> ImcoResult<PathBuf> {
+    if output.as_os_str() == STDIO {
+        let mut stdout = std::io::stdout().lock();
+        io_error_convert(stdout.write_all(data).and_then(|_| stdout.flush()), &output, false)?;
+        return Ok(output)
+    }
+    if let Some(archive) = &opts.archive {
+        let output = archive_entry_name(&output);
+        archive.add(&output, data)?;
+        return Ok(output.into())
+    }
+    prepare_file(&output, sources, opts)?;
+    retrying(&output, opts, || write_atomically(&output, |writer| io_error_convert(writer.write_all(data), &output, false)))?;
+    preserve_attributes(&output, sources, opts)?;
+    if let Some(checksums) = &opts.checksums { checksums.record(&output)? }
+    Ok(output)
+}
+
+// Gives a written output the modification time of its newest source and the permissions of the first one
+fn preserve_attributes<S: AsRef<Path>>(output: &Path, sources: &[S], opts: &Options) -> ImcoResult<()> {
+    // Stdin has nothing to preserve
+    let Some(first) = sources.first().filter(|_| sources.iter().all(|s| s.as_ref().as_os_str() != STDIO && s.as_ref().as_os_str() != CLIPBOARD)) else { return Ok(()) };
+    if opts.preserve_times {
+        let mut times = None;
+        for source in sources {
+            let source = source.as_ref();
+            let metadata = io_error_convert(std::fs::metadata(source), source, true)?;
+            let modified = io_error_convert(metadata.modified(), source, true)?;
+            if times.as_ref().is_none_or(|(_, newest)| modified > *newest) {
+                times = Some((metadata.accessed().unwrap_or(modified), modified));
+            }
+        }
+        let (accessed, modified) = times.unwrap();
+        let file = io_error_convert(File::options().write(true).open(output), output, false)?;
+        io_error_convert(file.set_times(std::fs::FileTimes::new().set_accessed(accessed).set_modified(modified)), output, false)?;
+    }
+    if opts.preserve_mode {
+        let permissions = io_error_convert(std::fs::metadata(first.as_ref()), first.as_ref(), true)?.permissions();
+        io_error_convert(std::fs::set_permissions(output, permissions), output, false)?;
+    }
+    Ok(())
+}
+
+fn input_format(path: &Path, opts: &Options) -> ImcoResult<Option<Format>> {
+    match opts.i_fmt {
+        Some(i_fmt) => Ok(Some(i_fmt)),
+        None => Ok(io_error_convert(imread(path)?.with_guessed_format(), path, true)?.format().map(Format::Image))
+    }
+}
+
+// Frames of an animated input, None for still images
+fn decode_frames(path: &Path, opts: &Options) -> ImcoResult<Option<(Vec<image::Frame>, Option<Format>)>> {
+    if path.as_os_str() == STDIO || path.as_os_str() == CLIPBOARD { return Ok(None) }
+    let in_fmt = input_format(path, opts)?;
+    let reader = BufReader::new(io_error_convert(File::open(path), path, true)?);
+    let frames = match in_fmt {
+        Some(Format::Image(ImageFormat::Gif)) => {
+            let mut decoder = image_err_convert(GifDecoder::new(reader), path)?;
+            opts.limits.apply(&mut decoder, path)?;
+            decoder.into_frames()
+        }
+        Some(Format::Image(ImageFormat::Png)) => {
+            let mut decoder = image_err_convert(PngDecoder::new(reader), path)?;
+            if !image_err_convert(decoder.is_apng(), path)? { return Ok(None) }
+            opts.limits.apply(&mut decoder, path)?;
+            image_err_convert(decoder.apng(), path)?.into_frames()
+        }
+        Some(Format::Image(ImageFormat::WebP)) => {
+            let mut decoder = image_err_convert(WebPDecoder::new(reader), path)?;
+            if !decoder.has_animation() { return Ok(None) }
+            opts.limits.apply(&mut decoder, path)?;
+            decoder.into_frames()
+        }
+        _ => return Ok(None)
+    };
+    let frames = image_err_convert(frames.collect_frames(), path)?;
+    Ok(if frames.len() > 1 { Some((frames, in_fmt)) } else { None })
+}
+
+// Every page of a (multi-page) TIFF
+fn decode_tiff_pages(path: &Path, limits: &DecodeLimits) -> ImcoResult<Vec<image::DynamicImage>> {
+    let fail = |e: tiff::TiffError| ImcoError::Decoding(path.display().to_string(), e.to_string());
+    let reader = BufReader::new(io_error_convert(File::open(path), path, true)?);
+    let mut tiff_limits = tiff::decoder::Limits::default();
+    tiff_limits.decoding_buffer_size = limits.max_memory.map_or(usize::MAX, |max| max.try_into().unwrap_or(usize::MAX));
+    let mut decoder = tiff::decoder::Decoder::new(reader).map_err(fail)?.with_limits(tiff_limits);
+    let mut pages = vec![];
+    loop {
+        let (width, height) = decoder.dimensions().map_err(fail)?;
+        limits.check(width, height, path)?;
+        let color = decoder.colortype().map_err(fail)?;
+        pages.push(tiff_image(color, decoder.read_image().map_err(fail)?, width, height, path)?);
+        if !decoder.more_images() { break }
+        decoder.next_image().map_err(fail)?;
+    }
+    Ok(pages)
+}
+
+// The number of pages of a TIFF, 1 when it can't be told
+fn tiff_page_count(path: &Path) -> usize {
+    let Ok(file) = File::open(path) else { return 1 };
+    let Ok(mut decoder) = tiff::decoder::Decoder::new(BufReader::new(file)) else { return 1 };
+    let mut pages = 1;
+    while decoder.more_images() && decoder.next_image().is_ok() { pages += 1 }
+    pages
+}
+
+// The decoded samples of a TIFF page or strip as an image
+fn tiff_image(color: tiff::ColorType, data: tiff::decoder::DecodingResult, width: u32, height: u32, path: &Path) -> ImcoResult<image::DynamicImage> {
+    use image::{DynamicImage, ImageBuffer};
+    use tiff::decoder::DecodingResult;
+    use tiff::ColorType;
+    let image = match (color, data) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+        (ColorType::GrayA(8), DecodingResult::U8(data)) => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8),
+        (ColorType::RGB(8), DecodingResult::U8(data)) => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+        (ColorType::Gray(16), DecodingResult::U16(data)) => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16),
+        (ColorType::GrayA(16), DecodingResult::U16(data)) => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA16),
+        (ColorType::RGB(16), DecodingResult::U16(data)) => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16),
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16),
+        (ColorType::RGB(32), DecodingResult::F32(data)) => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb32F),
+        (ColorType::RGBA(32), DecodingResult::F32(data)) => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba32F),
+        (color, _) => return Err(ImcoError::Unsupported(path.display().to_string(), format!("Page color type {color:?}")))
+    };
+    image.ok_or_else(|| ImcoError::Decoding(path.display().to_string(), "page data doesn't match its size".to_string()))
+}
+
+// Inserts the frame number at a printf style placeholder like %03d, or behind the file stem
+fn frame_name(template: &Path, index: usize) -> PathBuf {
+    let name = template.as_os_str().as_encoded_bytes();
+    if let Some(start) = name.iter().position(|b| *b == b'%') {
+        let digits = name[start + 1..].iter().take_while(|b| b.is_ascii_digit()).count();
+        if name.get(start + 1 + digits) == Some(&b'd') {
+            // Only ASCII, so the placeholder is UTF-8
+            let placeholder = std::str::from_utf8(&name[start..start + digits + 2]).unwrap();
+            let width = placeholder[1..digits + 1].parse().unwrap_or(0);
+            return fill_placeholder(template.as_os_str(), placeholder, OsStr::new(&format!("{index:0width$}"))).into()
+        }
+    }
+    behind_stem(template, &format!("_{index:03}"))
+}
+
+// Decodes an input with its EXIF orientation applied (unless disabled), dropping other metadata.
+// With an autorotate operation the orientation is returned for transform instead
+fn decode_upright(path: &Path, opts: &Options) -> ImcoResult<(image::DynamicImage, Option<Format>, Option<image::metadata::Orientation>)> {
+    let (mut image, meta, org_fmt) = retrying(path, opts, || decode_configured(path, opts))?;
+    if defers_orientation(opts) { return Ok((image, org_fmt, meta.orientation)) }
+    if let Some(orientation) = meta.orientation.filter(|_| opts.auto_orient) {
+        image.apply_orientation(orientation);
+    }
+    Ok((image, org_fmt, None))
+}
+
+// Assembles the inputs, in order, into one animation where every frame takes the size of the first
+fn animate(inputs: &[PathBuf], output: Option<&PathBuf>, delay: image::Delay, opts: &Options) -> ImcoResult<Conversion> {
+    let Some(output) = output else { return Err(ImcoError::NoCombinedOutput) };
+    let (output, fmt) = explicit_output(output, opts)?;
+    if !matches!(fmt, Format::Image(ImageFormat::Gif | ImageFormat::Png)) {
+        return Err(ImcoError::Unsupported(output.display().to_string(), format!("Writing animated {} files is not supported (use gif or png)", fmt.extension())))
+    }
+    let Some(output) = check_conflict(output.clone(), inputs, opts)? else {
+        return Ok(Conversion { output_bytes: file_size(&output), skipped: true, ..Conversion::new(output, fmt) })
+    };
+    if opts.dry_run {
+        return Ok(Conversion::new(output, fmt))
+    }
+    let mut frames: Vec<image::Frame> = vec![];
+    for path in inputs {
+        let (image, _, orientation) = decode_upright(path, opts)?;
+        let mut image = transform(image, orientation, opts, path)?;
+        if let Some(first) = frames.first() {
+            let (width, height) = first.buffer().dimensions();
+            if (image.width(), image.height()) != (width, height) {
+                image = image.resize_exact(width, height, opts.filter);
+            }
+        }
+        frames.push(image::Frame::from_parts(image.to_rgba8(), 0, 0, delay));
+    }
+    let mut data = Vec::new();
+    encode_frames(&frames, &mut data, fmt, opts.loops, &opts.encoder, &output)?;
+    let to_file = output.as_os_str() != STDIO && opts.archive.is_none();
+    let output = write_encoded(&data, output, inputs, opts)?;
+    if to_file && (opts.verify || opts.verify_full) {
+        let first = frames[0].buffer();
+        verify_output(&output, fmt, (first.width(), first.height()), opts.verify_full)?;
+    }
+    Ok(Conversion { output_bytes: Some(data.len() as u64), ..Conversion::new(output, fmt) })
+}
+
+// Fills the {row} and {col} placeholders, or puts both behind the file stem
+fn tile_name(template: &Path, row: u32, column: u32) -> PathBuf {
+    if has_placeholder(template, "{row}") || has_placeholder(template, "{col}") {
+        let filled = fill_placeholder(template.as_os_str(), "{row}", OsStr::new(&row.to_string()));
+        return fill_placeholder(&filled, "{col}", OsStr::new(&column.to_string())).into()
+    }
+    behind_stem(template, &format!("_{row:03}_{column:03}"))
+}
+
+// Splits the image into a grid of tiles, those at the right and bottom edge being smaller
+// Writes the --tile tiles or --slice cells of the transformed input
+fn tile_process(path: PathBuf, output: PathBuf, fmt: Format, opts: &Options) -> ImcoResult<Conversion> {
+    if output.as_os_str() == STDIO { return Err(ImcoError::ExtractToStdout) }
+    if opts.dry_run {
+        let input_format = opts.i_fmt.or_else(|| mk_format_fp(&path).ok());
+        return Ok(Conversion { input_format, ..Conversion::new(output, fmt) })
+    }
+    let mut timings = Timings::default();
+    let (image, org_fmt, orientation) = timed(&mut timings.decode, || decode_upright(&path, opts))?;
+    let (image, flattened) = timed(&mut timings.transform, || transform(image, orientation, opts, &path).map(|image| flatten_for(image, fmt, opts)))?;
+    let mut cells = Vec::new();
+    if let Some((tile_width, tile_height)) = opts.tile {
+        for row in 0..image.height().div_ceil(tile_height) {
+            for column in 0..image.width().div_ceil(tile_width) {
+                cells.push((tile_name(&output, row, column), column * tile_width, row * tile_height, tile_width, tile_height));
+            }
+        }
+    } else if let Some((rows, columns)) = opts.slice {
+        if rows > image.height() || columns > image.width() { return Err(ImcoError::SliceTooSmall(path.display().to_string(), rows, columns)) }
+        // Cell edges are spread evenly, so cells of sizes that don't divide the image differ by a pixel
+        let edge = |index: u32, count: u32, size: u32| (index as u64 * size as u64 / count as u64) as u32;
+        for row in 0..rows {
+            let (top, bottom) = (edge(row, rows, image.height()), edge(row + 1, rows, image.height()));
+            for column in 0..columns {
+                let (left, right) = (edge(column, columns, image.width()), edge(column + 1, columns, image.width()));
+                let index = (row * columns + column) as usize + 1;
+                cells.push((frame_name(&output, index), left, top, right - left, bottom - top));
+            }
+        }
+    }
+    let mut output_bytes = 0;
+    for (cell_output, x, y, width, height) in cells {
+        let Some(cell_output) = check_conflict(cell_output, &[&path], opts)? else { continue };
+        let cell = timed(&mut timings.transform, || image.crop_imm(x, y, width, height));
+        let mut data = Cursor::new(Vec::new());
+        timed(&mut timings.encode, || {
+            image_err_convert(encode(&cell, &mut data, fmt, &Metadata::default(), opts), &path)?;
+            write_encoded(data.get_ref(), cell_output, &[&path], opts)
+        })?;
+        output_bytes += data.get_ref().len() as u64;
+    }
+    Ok(Conversion { input_format: org_fmt, output_bytes: Some(output_bytes), flattened, timings, ..Conversion::new(output, fmt) })
+}
+
+// Row and column at the end of a tile's file stem, like map_002_013.png
+fn tile_position(path: &str) -> ImcoResult<(u32, u32)> {
+    let stem = std::path::Path::new(path).file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let mut parts = stem.rsplit(['_', '-']);
+    match (parts.next().map(str::parse::<u32>), parts.next().map(str::parse::<u32>)) {
+        (Some(Ok(column)), Some(Ok(row))) => Ok((row, column)),
+        _ => Err(ImcoError::TileName(path.to_string()))
+    }
+}
+
+fn assemble<I: image::GenericImageView>(tiles: &[(u32, u32, I)], width: u32, height: u32) -> image::ImageBuffer<I::Pixel, Vec<<I::Pixel as image::Pixel>::Subpixel>> {
+    let mut canvas = image::ImageBuffer::new(width, height);
+    for (x, y, tile) in tiles {
+        image::imageops::replace(&mut canvas, tile, *x as i64, *y as i64);
+    }
+    canvas
+}
+
+/// Reassembles tiles named like those of [`Options::tile`] (ending in _ROW_COLUMN) into one image written
+/// to output, returning its width, height and size in bytes. Every tile of the grid must be present
+pub fn stitch(paths: &[PathBuf], output: &Path) -> ImcoResult<(u32, u32, u64)> {
+    register_decoding_hooks();
+    let fmt = mk_format_fp(output)?;
+    let positions = paths.iter().map(|path| tile_position(&path.to_string_lossy())).collect::<ImcoResult<Vec<_>>>()?;
+    let tiles = paths.par_iter()
+        .map(|path| decode_input(path, None, &Rasterize::default(), &DecodeLimits::default()).map(|(image, _, _)| image))
+        .collect::<ImcoResult<Vec<_>>>()?;
+    let mut grid: HashMap<(u32, u32), &image::DynamicImage> = HashMap::new();
+    for (position, tile) in positions.iter().zip(&tiles) { grid.insert(*position, tile); }
+    let rows = positions.iter().map(|(row, _)| row + 1).max().ok_or(ImcoError::MissingTile(0, 0))?;
+    let columns = positions.iter().map(|(_, column)| column + 1).max().unwrap();
+    let tile_at = |row: u32, column: u32| grid.get(&(row, column)).copied().ok_or(ImcoError::MissingTile(row, column));
+    // Column widths come from the first row, row heights from the first column
+    let mut xs = vec![0];
+    for column in 0..columns { xs.push(xs[column as usize] + tile_at(0, column)?.width()) }
+    let mut ys = vec![0];
+    for row in 0..rows { ys.push(ys[row as usize] + tile_at(row, 0)?.height()) }
+    let (width, height) = (xs[columns as usize], ys[rows as usize]);
+    let mut placed = vec![];
+    for row in 0..rows {
+        for column in 0..columns {
+            placed.push((xs[column as usize], ys[row as usize], tile_at(row, column)?));
+        }
+    }
+    let color = placed[0].2.color();
+    let sixteen_bit = color.bytes_per_pixel() / color.channel_count() > 1;
+    let stitched: image::DynamicImage = if sixteen_bit {
+        assemble(&placed.iter().map(|(x, y, tile)| (*x, *y, tile.to_rgba16())).collect::<Vec<_>>(), width, height).into()
+    } else {
+        assemble(&placed.iter().map(|(x, y, tile)| (*x, *y, tile.to_rgba8())).collect::<Vec<_>>(), width, height).into()
+    };
+    let stitched = convert_color(stitched, color);
+    let opts = Options::default();
+    let mut data = Cursor::new(Vec::new());
+    image_err_convert(encode(&stitched, &mut data, fmt, &Metadata::default(), &opts), output)?;
+    write_encoded(data.get_ref(), output.into(), paths, &opts)?;
+    Ok((width, height, data.get_ref().len() as u64))
+}
+
+fn channel_name(template: &Path, channel: &str) -> PathBuf {
+    if has_placeholder(template, "{channel}") { return fill_placeholder(template.as_os_str(), "{channel}", OsStr::new(channel)).into() }
+    behind_stem(template, &format!("_{channel}"))
+}
+
+// Writes every channel of the image as its own grayscale output, keeping 16 bit depths
+fn channels_process(path: PathBuf, output: PathBuf, fmt: Format, opts: &Options) -> ImcoResult<Conversion> {
+    if output.as_os_str() == STDIO { return Err(ImcoError::ExtractToStdout) }
+    if opts.dry_run {
+        let input_format = opts.i_fmt.or_else(|| mk_format_fp(&path).ok());
+        return Ok(Conversion { input_format, ..Conversion::new(output, fmt) })
+    }
+    let mut timings = Timings::default();
+    let (image, org_fmt, orientation) = timed(&mut timings.decode, || decode_upright(&path, opts))?;
+    let image = timed(&mut timings.transform, || transform(image, orientation, opts, &path))?;
+    let color = image.color();
+    // Indices into rgba, gray images having their luma in every color channel
+    let channels: &[(&str, usize)] = match (color.has_color(), color.has_alpha()) {
+        (true, true) => &[("r", 0), ("g", 1), ("b", 2), ("a", 3)],
+        (true, false) => &[("r", 0), ("g", 1), ("b", 2)],
+        (false, true) => &[("l", 0), ("a", 3)],
+        (false, false) => &[("l", 0)],
+    };
+    let sixteen_bit = color.bytes_per_pixel() / color.channel_count() > 1;
+    let (rgba8, rgba16) = if sixteen_bit { (None, Some(image.to_rgba16())) } else { (Some(image.to_rgba8()), None) };
+    let mut output_bytes = 0;
+    for (name, index) in channels {
+        let Some(channel_output) = check_conflict(channel_name(&output, name), &[&path], opts)? else { continue };
+        let channel: image::DynamicImage = timed(&mut timings.transform, || match (&rgba8, &rgba16) {
+            (Some(rgba), _) => image::GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| image::Luma([rgba.get_pixel(x, y)[*index]])).into(),
+            (_, Some(rgba)) => image::ImageBuffer::<image::Luma<u16>, _>::from_fn(rgba.width(), rgba.height(), |x, y| image::Luma([rgba.get_pixel(x, y)[*index]])).into(),
+            _ => unreachable!()
+        });
+        let mut data = Cursor::new(Vec::new());
+        timed(&mut timings.encode, || {
+            image_err_convert(encode(&channel, &mut data, fmt, &Metadata::default(), opts), &path)?;
+            write_encoded(data.get_ref(), channel_output, &[&path], opts)
+        })?;
+        output_bytes += data.get_ref().len() as u64;
+    }
+    Ok(Conversion { input_format: org_fmt, output_bytes: Some(output_bytes), timings, ..Conversion::new(output, fmt) })
+}
+
+/// Grayscale images combined by [`merge_channels`], color images giving their luma
+#[derive(Default)]
+pub struct ChannelSources {
+    pub red: Option<PathBuf>,
+    pub green: Option<PathBuf>,
+    pub blue: Option<PathBuf>,
+    /// Makes the output grayscale, instead of red, green and blue
+    pub luma: Option<PathBuf>,
+    pub alpha: Option<PathBuf>,
+}
+
+/// Combines the sources into the channels of one image written to output, returning its width, height and
+/// size in bytes. Missing color channels are black and the output is opaque without an alpha source
+pub fn merge_channels(sources: &ChannelSources, output: &Path) -> ImcoResult<(u32, u32, u64)> {
+    register_decoding_hooks();
+    let fmt = mk_format_fp(output)?;
+    let slots = [(&sources.red, 0), (&sources.green, 1), (&sources.blue, 2), (&sources.luma, 0), (&sources.alpha, 3)];
+    let slots: Vec<(&PathBuf, usize)> = slots.into_iter().filter_map(|(path, index)| path.as_ref().map(|path| (path, index))).collect();
+    let decoded = slots.par_iter()
+        .map(|(path, _)| decode_input(path, None, &Rasterize::default(), &DecodeLimits::default()).map(|(image, _, _)| image))
+        .collect::<ImcoResult<Vec<_>>>()?;
+    let Some(first) = decoded.first() else { return Err(ImcoError::NoChannels) };
+    let (width, height) = (first.width(), first.height());
+    for ((path, _), image) in slots.iter().zip(&decoded) {
+        if (image.width(), image.height()) != (width, height) { return Err(ImcoError::ChannelSize(slots[0].0.display().to_string(), path.display().to_string())) }
+    }
+    let sixteen_bit = decoded.iter().any(|image| image.color().bytes_per_pixel() / image.color().channel_count() > 1);
+    let mut merged = image::ImageBuffer::<image::Rgba<u16>, _>::from_pixel(width, height, image::Rgba([0, 0, 0, u16::MAX]));
+    for ((_, index), image) in slots.iter().zip(&decoded) {
+        let luma = image.to_luma16();
+        for (pixel, value) in merged.pixels_mut().zip(luma.pixels()) {
+            // Luma fills all color channels
+            if sources.luma.is_some() && *index == 0 { pixel.0[..3].fill(value[0]) } else { pixel[*index] = value[0] }
+        }
+    }
+    let color = match (sources.luma.is_some(), sources.alpha.is_some(), sixteen_bit) {
+        (true, false, false) => image::ColorType::L8,
+        (true, true, false) => image::ColorType::La8,
+        (false, false, false) => image::ColorType::Rgb8,
+        (false, true, false) => image::ColorType::Rgba8,
+        (true, false, true) => image::ColorType::L16,
+        (true, true, true) => image::ColorType::La16,
+        (false, false, true) => image::ColorType::Rgb16,
+        (false, true, true) => image::ColorType::Rgba16,
+    };
+    let merged = convert_color(merged.into(), color);
+    let opts = Options::default();
+    let mut data = Cursor::new(Vec::new());
+    image_err_convert(encode(&merged, &mut data, fmt, &Metadata::default(), &opts), output)?;
+    let paths: Vec<&PathBuf> = slots.iter().map(|(path, _)| *path).collect();
+    write_encoded(data.get_ref(), output.into(), &paths, &opts)?;
+    Ok((width, height, data.get_ref().len() as u64))
+}
+
+/// How [`stack`] combines the pixels of its inputs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StackMethod {
+    /// The average, decoding one input at a time
+    Mean,
+    /// The middle value, leaving out outliers like passing planes or hot pixels, keeping every input in memory
+    Median,
+}
+
+/// The image written by [`stack`]
+pub struct Stacked {
+    pub width: u32,
+    pub height: u32,
+    /// Number of inputs combined
+    pub images: usize,
+    pub output_bytes: u64,
+}
+
+/// Combines images of the same size (after applying their EXIF orientation) pixel by pixel into one written to output,
+/// without aligning them, also returning the inputs left out with their error.
+/// Outputs get 16 bits per channel if the format holds them, more than the inputs may have
+pub fn stack(paths: &[PathBuf], output: &Path, method: StackMethod) -> ImcoResult<(Stacked, Vec<(PathBuf, ImcoError)>)> {
+    register_decoding_hooks();
+    let fmt = mk_format_fp(output)?;
+    let decode = |path: &PathBuf| -> ImcoResult<image::DynamicImage> {
+        let (mut image, meta, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+        if let Some(orientation) = meta.orientation {
+            image.apply_orientation(orientation);
+        }
+        Ok(image)
+    };
+    // The first input that decodes sets the size
+    let mut failed = vec![];
+    let mut rest = paths.iter();
+    let (first_path, first) = loop {
+        let Some(path) = rest.next() else { return Err(ImcoError::EmptyStack) };
+        match decode(path) {
+            Ok(image) => break (path, image),
+            Err(e) => failed.push((path.clone(), e)),
+        }
+    };
+    let (width, height) = (first.width(), first.height());
+    let rest: Vec<&PathBuf> = rest.collect();
+    let failed = Mutex::new(failed);
+    let (has_color, has_alpha) = (AtomicBool::new(first.color().has_color()), AtomicBool::new(first.color().has_alpha()));
+    // Decoded in parallel, the ones of another size left out
+    let checked = |path: &PathBuf| -> Option<image::DynamicImage> {
+        let image = decode(path).and_then(|image| if (image.width(), image.height()) == (width, height) { Ok(image) } else {
+            Err(ImcoError::StackSize(path.display().to_string(), first_path.display().to_string()))
+        });
+        match image {
+            Ok(image) => {
+                has_color.fetch_or(image.color().has_color(), Ordering::Relaxed);
+                has_alpha.fetch_or(image.color().has_alpha(), Ordering::Relaxed);
+                Some(image)
+            }
+            Err(e) => {
+                failed.lock().unwrap().push((path.clone(), e));
+                None
+            }
+        }
+    };
+    let (stacked, images): (Vec<u16>, usize) = match method {
+        StackMethod::Mean => {
+            let sum = Mutex::new((first.to_rgba32f().into_raw(), 1));
+            drop(first);
+            rest.par_iter().for_each(|path| {
+                let Some(image) = checked(path) else { return };
+                let image = image.to_rgba32f();
+                let mut sum = sum.lock().unwrap();
+                sum.0.iter_mut().zip(image.as_raw()).for_each(|(total, value)| *total += value);
+                sum.1 += 1;
+            });
+            let (sum, count) = sum.into_inner().unwrap();
+            (sum.into_iter().map(|total| (total / count as f32 * u16::MAX as f32).round().clamp(0.0, u16::MAX as f32) as u16).collect(), count)
+        }
+        StackMethod::Median => {
+            let mut decoded = vec![first.into_rgba16().into_raw()];
+            decoded.extend(rest.par_iter().filter_map(|path| checked(path)).map(|image| image.into_rgba16().into_raw()).collect::<Vec<_>>());
+            let images = decoded.len();
+            let mut stacked = vec![0; width as usize * height as usize * 4];
+            stacked.par_chunks_mut(width as usize * 4).enumerate().for_each(|(y, row)| {
+                let start = y * width as usize * 4;
+                let mut values = vec![0; images];
+                for (x, sample) in row.iter_mut().enumerate() {
+                    values.iter_mut().zip(&decoded).for_each(|(value, image)| *value = image[start + x]);
+                    let middle = values.len() / 2;
+                    let (lower, upper, _) = values.select_nth_unstable(middle);
+                    // The mean of both middle values for an even count
+                    *sample = if images % 2 == 0 {
+                        ((*lower.iter().max().unwrap() as u32 + *upper as u32) / 2) as u16
+                    } else { *upper };
+                }
+            });
+            (stacked, images)
+        }
+    };
+    let stacked = image::ImageBuffer::<image::Rgba<u16>, _>::from_raw(width, height, stacked).expect("one sample per channel and pixel");
+    let color = match (has_color.into_inner(), has_alpha.into_inner(), fmt.max_depth() >= 16) {
+        (false, false, false) => image::ColorType::L8,
+        (false, true, false) => image::ColorType::La8,
+        (true, false, false) => image::ColorType::Rgb8,
+        (true, true, false) => image::ColorType::Rgba8,
+        (false, false, true) => image::ColorType::L16,
+        (false, true, true) => image::ColorType::La16,
+        (true, false, true) => image::ColorType::Rgb16,
+        (true, true, true) => image::ColorType::Rgba16,
+    };
+    let opts = Options::default();
+    let (stacked, _) = flatten_for(convert_color(stacked.into(), color), fmt, &opts);
+    let mut data = Cursor::new(Vec::new());
+    image_err_convert(encode(&stacked, &mut data, fmt, &Metadata::default(), &opts), output)?;
+    write_encoded(data.get_ref(), output.into(), paths, &opts)?;
+    let mut failed = failed.into_inner().unwrap();
+    failed.sort_by_key(|(path, _)| paths.iter().position(|p| p == path));
+    Ok((Stacked { width, height, images, output_bytes: data.get_ref().len() as u64 }, failed))
+}
+
+// Writes every (selected) frame or page of the input to its own numbered output,
+// a single selected one to the output itself
+fn extract_process(path: PathBuf, output: PathBuf, fmt: Format, opts: &Options) -> ImcoResult<Conversion> {
+    let single = !opts.extract_frames && opts.pages.is_some_and(|pages| pages.last == Some(pages.first));
+    if output.as_os_str() == STDIO && !single { return Err(ImcoError::ExtractToStdout) }
+    if opts.dry_run {
+        let input_format = opts.i_fmt.or_else(|| mk_format_fp(&path).ok());
+        return Ok(Conversion { input_format, ..Conversion::new(output, fmt) })
+    }
+    let mut timings = Timings::default();
+    let (pages, org_fmt, orientation) = timed(&mut timings.decode, || Ok(match retrying(&path, opts, || decode_frames(&path, opts))? {
+        Some((frames, org_fmt)) => (frames.into_iter().map(|f| image::DynamicImage::ImageRgba8(f.into_buffer())).collect(), org_fmt, None),
+        None if path.as_os_str() != STDIO && input_format(&path, opts)? == Some(Format::Image(ImageFormat::Tiff)) => (retrying(&path, opts, || decode_tiff_pages(&path, &opts.limits))?, Some(Format::Image(ImageFormat::Tiff)), None),
+        None => {
+            let (image, org_fmt, orientation) = decode_upright(&path, opts)?;
+            (vec![image], org_fmt, orientation)
+        }
+    }))?;
+    let count = pages.len();
+    let (first, last) = opts.pages.map_or((1, count), |pages| (pages.first, pages.last.unwrap_or(count).min(count)));
+    if first > count { return Err(ImcoError::PageOutOfRange(path.display().to_string(), first, count)) }
+    let numbered = !single || has_placeholder(&output, "%");
+    let mut output_bytes = 0;
+    let mut flattened = false;
+    for (index, page) in pages.into_iter().enumerate().take(last).skip(first - 1) {
+        let (page, page_flattened) = timed(&mut timings.transform, || transform(page, orientation, opts, &path).map(|page| flatten_for(page, fmt, opts)))?;
+        flattened |= page_flattened;
+        let page_output = if numbered { frame_name(&output, index + 1) } else { output.clone() };
+        let page_output = fill_dimensions(&page_output, page.width(), page.height());
+        let Some(page_output) = check_conflict(page_output, &[&path], opts)? else { continue };
+        let mut data = Cursor::new(Vec::new());
+        timed(&mut timings.encode, || {
+            image_err_convert(encode(&page, &mut data, fmt, &Metadata::default(), opts), &path)?;
+            write_encoded(data.get_ref(), page_output, &[&path], opts)
+        })?;
+        output_bytes += data.get_ref().len() as u64;
+    }
+    Ok(Conversion { input_format: org_fmt, output_bytes: Some(output_bytes), flattened, timings, ..Conversion::new(output, fmt) })
+}
+
+fn pdf_object(pdf: &mut Vec<u8>, offsets: &mut Vec<usize>, dictionary: &str, stream: Option<&[u8]>) {
+    offsets.push(pdf.len());
+    pdf.extend(format!("{} 0 obj\n{dictionary}\n", offsets.len()).as_bytes());
+    if let Some(stream) = stream {
+        pdf.extend(b"stream\n");
+        pdf.extend(stream);
+        pdf.extend(b"\nendstream\n");
+    }
+    pdf.extend(b"endobj\n");
+}
+
+// A PDF with one page per image, one pixel being one point. Transparency is flattened onto white
+fn write_pdf(pages: &[image::DynamicImage]) -> std::io::Result<Vec<u8>> {
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = vec![];
+    // Objects 1 and 2 are the catalog and page tree, every page uses three more (page, contents and image)
+    let kids: Vec<String> = (0..pages.len()).map(|i| format!("{} 0 R", 3 + i * 3)).collect();
+    pdf_object(&mut pdf, &mut offsets, "<< /Type /Catalog /Pages 2 0 R >>", None);
+    pdf_object(&mut pdf, &mut offsets, &format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()), None);
+    for page in pages {
+        let (width, height) = (page.width(), page.height());
+        let (color_space, samples) = if !page.color().has_color() && !page.color().has_alpha() {
+            ("DeviceGray", page.to_luma8().into_raw())
+        } else {
+            let mut rgba = page.to_rgba8();
+            rgba.pixels_mut().for_each(|p| {
+                let alpha = p.0[3] as u32;
+                p.0[..3].iter_mut().for_each(|c| *c = ((*c as u32 * alpha + 255 * (255 - alpha)) / 255) as u8);
+            });
+            ("DeviceRGB", image::DynamicImage::ImageRgba8(rgba).to_rgb8().into_raw())
+        };
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&samples)?;
+        let samples = encoder.finish()?;
+        let contents = format!("q {width} 0 0 {height} 0 0 cm /Im0 Do Q");
+        let id = offsets.len() + 1;
+        pdf_object(&mut pdf, &mut offsets, &format!("<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {width} {height}] /Resources << /XObject << /Im0 {} 0 R >> >> /Contents {} 0 R >>", id + 2, id + 1), None);
+        pdf_object(&mut pdf, &mut offsets, &format!("<< /Length {} >>", contents.len()), Some(contents.as_bytes()));
+        pdf_object(&mut pdf, &mut offsets, &format!("<< /Type /XObject /Subtype /Image /Width {width} /Height {height} /ColorSpace /{color_space} /BitsPerComponent 8 /Filter /FlateDecode /Length {} >>", samples.len()), Some(&samples));
+    }
+    let xref = pdf.len();
+    pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).as_bytes());
+    for offset in &offsets {
+        pdf.extend(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n", offsets.len() + 1).as_bytes());
+    Ok(pdf)
+}
+
+// Puts the inputs, in order, onto the pages of one PDF
+fn combine_pdf(inputs: &[PathBuf], output: Option<&PathBuf>, opts: &Options) -> ImcoResult<Conversion> {
+    let Some(output) = output.map(|output| split_format(output).0) else { return Err(ImcoError::NoCombinedOutput) };
+    let Some(output) = check_conflict(output.to_path_buf(), inputs, opts)? else {
+        return Ok(Conversion { output_bytes: file_size(output), skipped: true, ..Conversion::new(output, Format::Pdf) })
+    };
+    if opts.dry_run {
+        return Ok(Conversion::new(output, Format::Pdf))
+    }
+    let (pages, flattened): (Vec<_>, Vec<_>) = inputs.iter()
+        .map(|path| {
+            let (image, _, orientation) = decode_upright(path, opts)?;
+            Ok(flatten_for(transform(image, orientation, opts, path)?, Format::Pdf, opts))
+        })
+        .collect::<ImcoResult<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    let flattened = flattened.contains(&true);
+    let data = io_error_convert(write_pdf(&pages), &output, false)?;
+    let output = write_encoded(&data, output, inputs, opts)?;
+    if opts.verify || opts.verify_full {
+        verify_output(&output, Format::Pdf, (0, 0), false)?;
+    }
+    Ok(Conversion { output_bytes: Some(data.len() as u64), flattened, ..Conversion::new(output, Format::Pdf) })
+}
+
+// Loops is how often the animation plays, 0 for forever
+fn encode_frames<W: Write>(frames: &[image::Frame], writer: &mut W, fmt: Format, loops: u16, settings: &EncoderSettings, path: &Path) -> ImcoResult<()> {
+    if fmt == Format::Image(ImageFormat::Gif) {
+        let mut encoder = GifEncoder::new_with_speed(writer, settings.gif.speed);
+        // GIFs count the repetitions after the first play
+        let repeat = if loops == 0 { Repeat::Infinite } else { Repeat::Finite(loops - 1) };
+        let res = encoder.set_repeat(repeat).and_then(|_| encoder.encode_frames(frames.iter().cloned()));
+        return image_err_convert(res, path)
+    }
+    // The png encoder of image can't write animations (APNG) yet
+    let fail = |e: png::EncodingError| ImcoError::Encoding(path.display().to_string(), e.to_string());
+    let (width, height) = frames[0].buffer().dimensions();
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, loops as u32).map_err(fail)?;
+    let mut writer = encoder.write_header().map_err(fail)?;
+    for frame in frames {
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let millis = (numer as f64 / denom as f64).round().min(u16::MAX as f64) as u16;
+        writer.set_frame_delay(millis, 1000).map_err(fail)?;
+        writer.write_image_data(frame.buffer().as_raw()).map_err(fail)?;
+    }
+    writer.finish().map_err(fail)
+}
+
+fn write_with_metadata(image: &image::DynamicImage, mut encoder: impl ImageEncoder, meta: &Metadata) -> image::ImageResult<()> {
+    if let Some(exif) = &meta.exif {
+        encoder.set_exif_metadata(exif.clone()).map_err(ImageError::Unsupported)?;
+    }
+    if let Some(icc) = &meta.icc {
+        // Not every encoder of a format with ICC support can embed one yet (AVIF)
+        let _ = encoder.set_icc_profile(icc.clone());
+    }
+    image.write_with_encoder(encoder)
+}
+
+fn encode<W: Write + Seek>(image: &image::DynamicImage, writer: &mut W, fmt: Format, meta: &Metadata, opts: &Options) -> image::ImageResult<()> {
+    let fmt = match fmt {
+        Format::Image(fmt) => fmt,
+        Format::Jxl => return encode_jxl(image, writer),
+        Format::Pdf => return writer.write_all(&write_pdf(std::slice::from_ref(image))?).map_err(ImageError::IoError),
+        Format::Ktx2 => return encode_texture(image, writer, Container::Ktx2, opts),
+        Format::Raw => return encode_raw(image, writer, opts).map_err(ImageError::IoError),
+    };
+    // Formats without float samples get the linear values clipped and encoded as sRGB
+    let float = matches!(image.color(), image::ColorType::Rgb32F | image::ColorType::Rgba32F);
+    if float && !matches!(fmt, ImageFormat::OpenExr | ImageFormat::Hdr | ImageFormat::Tiff) {
+        return encode(&tonemap(image.clone(), None, 0.0), writer, Format::Image(fmt), meta, opts)
+    }
+    // and float formats get the other samples as linear light, Radiance HDR without alpha
+    if matches!(fmt, ImageFormat::OpenExr | ImageFormat::Hdr) && (!float || (fmt == ImageFormat::Hdr && image.color().has_alpha())) {
+        let linear = if float { image.clone() } else { linearize(image) };
+        let linear = if fmt == ImageFormat::Hdr { image::DynamicImage::ImageRgb32F(linear.into_rgb32f()) } else { linear };
+        return encode(&linear, writer, Format::Image(fmt), meta, opts)
+    }
+    // Formats with only 8 bits per channel get the most significant ones
+    if sample_depth(image.color()) > 8 && Format::Image(fmt).max_depth() == 8 {
+        return encode(&convert_depth(image.clone(), 8), writer, Format::Image(fmt), meta, opts)
+    }
+    match fmt {
+        ImageFormat::Jpeg => match opts.encoder.jpeg.quality.or(opts.quality) {
+            Some(quality) => encode_lossy(image, writer, fmt, quality, &opts.encoder, meta),
+            // The quality of JpegEncoder::new
+            None if opts.encoder.jpeg.progressive => encode_lossy(image, writer, fmt, 75, &opts.encoder, meta),
+            None => write_with_metadata(image, JpegEncoder::new(writer), meta)
+        },
+        ImageFormat::Avif => encode_lossy(image, writer, fmt, opts.encoder.avif.quality.or(opts.quality).unwrap_or(80), &opts.encoder, meta),
+        ImageFormat::Png if opts.quantize.is_some() => encode_indexed_png(image, writer, meta, &opts.encoder.png),
+        ImageFormat::Png if opts.encoder.png.interlace => encode_interlaced_png(image, writer, meta, &opts.encoder.png),
+        ImageFormat::Png => write_with_metadata(image, PngEncoder::new_with_quality(writer, opts.encoder.png.compression, opts.encoder.png.filter), meta),
+        ImageFormat::Gif => GifEncoder::new_with_speed(writer, opts.encoder.gif.speed).encode_frame(image::Frame::new(image.to_rgba8())),
+        ImageFormat::WebP => write_with_metadata(image, WebPEncoder::new_lossless(writer), meta),
+        // Farbfeld is always 16 bit RGBA
+        ImageFormat::Farbfeld if image.color() != image::ColorType::Rgba16 => image::DynamicImage::ImageRgba16(image.to_rgba16()).write_to(writer, fmt),
+        ImageFormat::Tiff => write_with_metadata(image, TiffEncoder::new(writer), meta),
+        ImageFormat::Ico if !opts.ico_sizes.is_empty() => encode_ico(image, writer, &opts.ico_sizes, opts.filter),
+        ImageFormat::Dds => encode_texture(image, writer, Container::Dds, opts),
+        _ => image.write_to(writer, fmt)
+    }
+}
+
+// Only called for JPEG and AVIF
+fn encode_lossy<W: Write>(image: &image::DynamicImage, writer: &mut W, fmt: ImageFormat, quality: u8, settings: &EncoderSettings, meta: &Metadata) -> image::ImageResult<()> {
+    match fmt {
+        ImageFormat::Jpeg if settings.jpeg.progressive => encode_progressive_jpeg(image, writer, quality, meta),
+        ImageFormat::Jpeg => write_with_metadata(image, JpegEncoder::new_with_quality(writer, quality), meta),
+        _ => write_with_metadata(image, AvifEncoder::new_with_speed_quality(writer, settings.avif.speed, quality), meta),
+    }
+}
+
+// JpegEncoder has no progressive mode
+fn encode_progressive_jpeg<W: Write>(image: &image::DynamicImage, writer: &mut W, quality: u8, meta: &Metadata) -> image::ImageResult<()> {
+    use jpeg_encoder::ColorType;
+    let fail = |e: jpeg_encoder::EncodingError| ImageError::Encoding(image::error::EncodingError::new(ImageFormat::Jpeg.into(), e));
+    let (Ok(width), Ok(height)) = (u16::try_from(image.width()), u16::try_from(image.height())) else {
+        return Err(ImageError::Encoding(image::error::EncodingError::new(ImageFormat::Jpeg.into(), "JPEG images can be at most 65535 pixels wide and high")))
+    };
+    let mut encoder = jpeg_encoder::Encoder::new(writer, quality);
+    encoder.set_progressive(true);
+    encoder.set_optimized_huffman_tables(true);
+    // Not subsampling the chroma below quality 90 like JpegEncoder
+    encoder.set_sampling_factor(jpeg_encoder::SamplingFactor::F_1_1);
+    if let Some(exif) = &meta.exif { encoder.add_exif_metadata(exif).map_err(fail)? }
+    if let Some(icc) = &meta.icc { encoder.add_icc_profile(icc).map_err(fail)? }
+    // Like JpegEncoder, alpha is dropped
+    if image.color().has_color() {
+        encoder.encode(image.to_rgb8().as_raw(), width, height, ColorType::Rgb).map_err(fail)
+    } else {
+        encoder.encode(image.to_luma8().as_raw(), width, height, ColorType::Luma).map_err(fail)
+    }
+}
+
+// The encoding at the highest quality that is at most target bytes, found by binary search
+fn encode_to_size(image: &image::DynamicImage, fmt: Format, meta: &Metadata, target: u64, path: &Path, settings: &EncoderSettings) -> ImcoResult<Vec<u8>> {
+    let Format::Image(lossy @ (ImageFormat::Jpeg | ImageFormat::Avif)) = fmt else {
+        return Err(ImcoError::Unsupported(path.display().to_string(), format!("A target size needs a lossy output format (jpg or avif), not {}", fmt.extension())))
+    };
+    // Like encode, formats without float samples get sRGB
+    let float = matches!(image.color(), image::ColorType::Rgb32F | image::ColorType::Rgba32F);
+    let image = if float { &tonemap(image.clone(), None, 0.0) } else { image };
+    let (mut low, mut high) = (1u8, 100u8);
+    let mut best = None;
+    while low <= high {
+        let quality = low + (high - low) / 2;
+        let mut data = Vec::new();
+        image_err_convert(encode_lossy(image, &mut data, lossy, quality, settings, meta), path)?;
+        if data.len() as u64 <= target {
+            best = Some(data);
+            low = quality + 1;
+        } else {
+            high = quality - 1;
+        }
+    }
+    best.ok_or_else(|| ImcoError::TargetSize(path.display().to_string(), target))
+}
+
+fn encode_ico<W: Write>(image: &image::DynamicImage, writer: &mut W, sizes: &[u32], filter: FilterType) -> image::ImageResult<()> {
+    let icons = sizes.iter().map(|size| square_icon(image, *size, filter)).collect::<Vec<_>>();
+    let frames = icons.iter()
+        .map(|icon| IcoFrame::as_png(icon.as_raw(), icon.width(), icon.height(), image::ExtendedColorType::Rgba8))
+        .collect::<image::ImageResult<Vec<_>>>()?;
+    IcoEncoder::new(writer).encode_images(&frames)
+}
+
+#[derive(Clone, Copy)]
+enum Container {
+    Dds,
+    Ktx2,
+}
+
+// A texture of the image, with Options::mipmaps followed by every level down to 1x1
+fn encode_texture<W: Write>(image: &image::DynamicImage, writer: &mut W, container: Container, opts: &Options) -> image::ImageResult<()> {
+    let mut levels = vec![image.to_rgba8()];
+    while let Some(last) = levels.last().filter(|last| opts.mipmaps && (last.width() > 1 || last.height() > 1)) {
+        let next = image::imageops::resize(last, (last.width() / 2).max(1), (last.height() / 2).max(1), opts.filter);
+        levels.push(next);
+    }
+    let alpha = image.color().has_alpha();
+    let levels = levels.iter().map(|level| texture_level(level, opts.texture_compression, alpha)).collect::<image::ImageResult<Vec<_>>>()?;
+    let texture = match container {
+        Container::Dds => dds_texture(image.width(), image.height(), &levels, opts.texture_compression),
+        Container::Ktx2 => ktx2_texture(image.width(), image.height(), &levels, opts.texture_compression),
+    };
+    writer.write_all(&texture).map_err(ImageError::IoError)
+}
+
+// The pixels or blocks a mip level is stored as
+#[cfg(feature = "textures")]
+fn texture_level(level: &image::RgbaImage, compression: Option<TextureCompression>, alpha: bool) -> image::ImageResult<Vec<u8>> {
+    use block_compression::{encode::compress_rgba8, BC7Settings, CompressionVariant};
+    let variant = match compression {
+        None => return Ok(level.as_raw().clone()),
+        Some(TextureCompression::Bc1) => CompressionVariant::BC1,
+        Some(TextureCompression::Bc3) => CompressionVariant::BC3,
+        Some(TextureCompression::Bc7) => CompressionVariant::BC7(if alpha { BC7Settings::alpha_basic() } else { BC7Settings::opaque_basic() }),
+    };
+    // Blocks cover 4x4 pixels, so the last row and column are repeated up to a multiple of 4
+    let (width, height) = (level.width().div_ceil(4) * 4, level.height().div_ceil(4) * 4);
+    let padded = image::RgbaImage::from_fn(width, height, |x, y| *level.get_pixel(x.min(level.width() - 1), y.min(level.height() - 1)));
+    let mut blocks = vec![0; variant.blocks_byte_size(width, height)];
+    compress_rgba8(variant, padded.as_raw(), &mut blocks, width, height, width * 4);
+    Ok(blocks)
+}
+
+#[cfg(not(feature = "textures"))]
+fn texture_level(level: &image::RgbaImage, compression: Option<TextureCompression>, _alpha: bool) -> image::ImageResult<Vec<u8>> {
+    match compression {
+        None => Ok(level.as_raw().clone()),
+        Some(compression) => Err(ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+            image::error::ImageFormatHint::Name(format!("{compression:?}").to_lowercase()),
+            UnsupportedErrorKind::GenericFeature("imco was built without texture compression support".to_string())))),
+    }
+}
+
+// A DDS file, BC7 needing the DX10 header extension
+fn dds_texture(width: u32, height: u32, levels: &[Vec<u8>], compression: Option<TextureCompression>) -> Vec<u8> {
+    // CAPS | HEIGHT | WIDTH | PIXELFORMAT, and LINEARSIZE or PITCH
+    let mut flags = 0x1 | 0x2 | 0x4 | 0x1000 | if compression.is_some() { 0x80000 } else { 0x8 };
+    // TEXTURE, and COMPLEX | MIPMAP with mip levels (and MIPMAPCOUNT in the flags)
+    let mut caps = 0x1000;
+    if levels.len() > 1 {
+        flags |= 0x20000;
+        caps |= 0x8 | 0x400000;
+    }
+    let pitch = if compression.is_some() { levels[0].len() as u32 } else { width * 4 };
+    let mut header = vec![124, flags, height, width, pitch, 0, levels.len() as u32];
+    header.extend([0; 11]);
+    header.extend(match compression {
+        // RGB | ALPHAPIXELS with the channel masks
+        None => [32, 0x41, 0, 32, 0xff, 0xff00, 0xff0000, 0xff000000],
+        Some(compression) => {
+            let four_cc = match compression {
+                TextureCompression::Bc1 => b"DXT1",
+                TextureCompression::Bc3 => b"DXT5",
+                TextureCompression::Bc7 => b"DX10",
+            };
+            [32, 0x4, u32::from_le_bytes(*four_cc), 0, 0, 0, 0, 0]
+        }
+    });
+    header.extend([caps, 0, 0, 0, 0]);
+    if compression == Some(TextureCompression::Bc7) {
+        // DXGI_FORMAT_BC7_UNORM, a 2D texture, no flags, one array element
+        header.extend([98, 3, 0, 1, 0]);
+    }
+    let mut dds = b"DDS ".to_vec();
+    dds.extend(header.iter().flat_map(|word| word.to_le_bytes()));
+    for level in levels {
+        dds.extend(level);
+    }
+    dds
+}
+
+// A KTX2 file with a basic data format descriptor and no key/value data
+fn ktx2_texture(width: u32, height: u32, levels: &[Vec<u8>], compression: Option<TextureCompression>) -> Vec<u8> {
+    // VkFormat, color model, bytes per pixel or block and the samples as (channel, bit offset, bit length)
+    let (vk_format, model, block_bytes, samples): (u32, u8, u8, &[(u8, u16, u8)]) = match compression {
+        None => (37, 1, 4, &[(0, 0, 8), (1, 8, 8), (2, 16, 8), (15, 24, 8)]),
+        Some(TextureCompression::Bc1) => (131, 128, 8, &[(0, 0, 64)]),
+        Some(TextureCompression::Bc3) => (137, 130, 16, &[(15, 0, 64), (0, 64, 64)]),
+        Some(TextureCompression::Bc7) => (145, 134, 16, &[(0, 0, 128)]),
+    };
+    let block = if compression.is_some() { 3 } else { 0 };
+    let block_size = 24 + 16 * samples.len() as u16;
+    let mut dfd = (4 + block_size as u32).to_le_bytes().to_vec();
+    // Khronos' basic descriptor, version 2
+    dfd.extend([0, 0, 0, 0, 2, 0]);
+    dfd.extend(block_size.to_le_bytes());
+    // BT.709 primaries, linear transfer and straight alpha, then the block dimensions minus one and the bytes of the only plane
+    dfd.extend([model, 1, 1, 0, block, block, 0, 0, block_bytes, 0, 0, 0, 0, 0, 0, 0]);
+    for &(channel, offset, length) in samples {
+        dfd.extend(offset.to_le_bytes());
+        dfd.extend([length - 1, channel, 0, 0, 0, 0]);
+        dfd.extend(0u32.to_le_bytes());
+        dfd.extend(if compression.is_some() { u32::MAX } else { 255 }.to_le_bytes());
+    }
+    let dfd_offset = 80 + 24 * levels.len();
+    // Levels are stored smallest first, each aligned to its block size
+    let mut offsets = vec![0; levels.len()];
+    let mut end = dfd_offset + dfd.len();
+    for (index, level) in levels.iter().enumerate().rev() {
+        offsets[index] = end.next_multiple_of(block_bytes as usize);
+        end = offsets[index] + level.len();
+    }
+    let mut ktx = vec![0xab, b'K', b'T', b'X', b' ', b'2', b'0', 0xbb, b'\r', b'\n', 0x1a, b'\n'];
+    for word in [vk_format, 1, width, height, 0, 0, 1, levels.len() as u32, 0, dfd_offset as u32, dfd.len() as u32, 0, 0] {
+        ktx.extend(word.to_le_bytes());
+    }
+    ktx.extend([0; 16]);
+    for (level, offset) in levels.iter().zip(&offsets) {
+        for word in [*offset as u64, level.len() as u64, level.len() as u64] {
+            ktx.extend(word.to_le_bytes());
+        }
+    }
+    ktx.extend(dfd);
+    for (index, level) in levels.iter().enumerate().rev() {
+        ktx.resize(offsets[index], 0);
+        ktx.extend(level);
+    }
+    ktx
+}
+
+// File name, format and size of the assets generated by the favicon preset
+const FAVICON_ASSETS: &[(&str, ImageFormat, u32)] = &[
+    ("favicon.ico", ImageFormat::Ico, 48),
+    ("apple-touch-icon.png", ImageFormat::Png, 180),
+    ("android-chrome-192x192.png", ImageFormat::Png, 192),
+    ("android-chrome-512x512.png", ImageFormat::Png, 512),
+];
+const FAVICON_ICO_SIZES: &[u32] = &[16, 32, 48];
+
+// Idiom, size in points and scale of every icon an app icon set lists
+const IOS_APP_ICONS: &[(&str, f32, u32)] = &[
+    ("iphone", 20.0, 2), ("iphone", 20.0, 3), ("iphone", 29.0, 2), ("iphone", 29.0, 3),
+    ("iphone", 40.0, 2), ("iphone", 40.0, 3), ("iphone", 60.0, 2), ("iphone", 60.0, 3),
+    ("ipad", 20.0, 1), ("ipad", 20.0, 2), ("ipad", 29.0, 1), ("ipad", 29.0, 2),
+    ("ipad", 40.0, 1), ("ipad", 40.0, 2), ("ipad", 76.0, 1), ("ipad", 76.0, 2), ("ipad", 83.5, 2),
+    ("ios-marketing", 1024.0, 1),
+];
+const IOS_ICON_SET: &str = "AppIcon.appiconset";
+
+// Launcher icon size of every density
+const ANDROID_DENSITIES: &[(&str, u32)] = &[("mdpi", 48), ("hdpi", 72), ("xhdpi", 96), ("xxhdpi", 144), ("xxxhdpi", 192)];
+
+// How a preset's icon is cut out of the square the source is fitted into
+#[derive(Clone, Copy, PartialEq)]
+enum IconStyle {
+    Transparent,
+    /// Flattened onto the background, as the App Store rejects icons with an alpha channel
+    Opaque,
+    Circle,
+}
+
+// Path in the output directory, format, size and style of every asset of the preset
+fn preset_assets(preset: &Preset) -> Vec<(String, ImageFormat, u32, IconStyle)> {
+    match preset {
+        Preset::Favicon => FAVICON_ASSETS.iter().map(|(name, fmt, size)| (name.to_string(), *fmt, *size, IconStyle::Transparent)).collect(),
+        Preset::IosAppIcon => {
+            let mut assets: Vec<(String, ImageFormat, u32, IconStyle)> = vec![];
+            for (_, points, scale) in IOS_APP_ICONS {
+                let name = format!("{IOS_ICON_SET}/{}", ios_icon_name(*points, *scale));
+                // iPhone and iPad share the files of the same size and scale
+                if assets.iter().all(|asset| asset.0 != name) {
+                    assets.push((name, ImageFormat::Png, (points * *scale as f32) as u32, IconStyle::Opaque));
+                }
+            }
+            assets
+        }
+        Preset::AndroidMipmap => ANDROID_DENSITIES.iter()
+            .flat_map(|(density, size)| [
+                (format!("mipmap-{density}/ic_launcher.png"), ImageFormat::Png, *size, IconStyle::Transparent),
+                (format!("mipmap-{density}/ic_launcher_round.png"), ImageFormat::Png, *size, IconStyle::Circle),
+            ])
+            .chain([("ic_launcher-playstore.png".to_string(), ImageFormat::Png, 512, IconStyle::Transparent)])
+            .collect(),
+    }
+}
+
+fn ios_icon_name(points: f32, scale: u32) -> String {
+    format!("Icon-App-{points}x{points}@{scale}x.png")
+}
+
+// The Contents.json Xcode reads the icons of the set from
+fn ios_contents() -> String {
+    let images: Vec<serde_json::Value> = IOS_APP_ICONS.iter().map(|(idiom, points, scale)| serde_json::json!({
+        "size": format!("{points}x{points}"),
+        "idiom": idiom,
+        "filename": ios_icon_name(*points, *scale),
+        "scale": format!("{scale}x"),
+    })).collect();
+    serde_json::to_string_pretty(&serde_json::json!({ "images": images, "info": { "version": 1, "author": "imco" } })).unwrap()
+}
+
+// Writes every asset of the preset generated from one source into a directory
+fn preset_process(preset: &Preset, path: &Path, dir: &Path, opts: &Options) -> ImcoResult<Vec<Conversion>> {
+    let (image, input_format) = if opts.dry_run {
+        (None, opts.i_fmt.or_else(|| mk_format_fp(path).ok()))
+    } else {
+        io_error_convert(std::fs::create_dir_all(dir), dir, false)?;
+        let (image, org_fmt, orientation) = decode_upright(path, opts)?;
+        (Some(transform(image, orientation, opts, path)?), org_fmt)
+    };
+    let mut conversions = vec![];
+    for (name, fmt, size, style) in preset_assets(preset) {
+        let output = dir.join(&name);
+        let Some(output) = check_conflict(output.clone(), &[path], opts)? else {
+            conversions.push(Conversion { output_bytes: file_size(&output), skipped: true, ..Conversion::new(output, Format::Image(fmt)) });
+            continue
+        };
+        let Some(image) = &image else {
+            conversions.push(Conversion { input_format, ..Conversion::new(output, Format::Image(fmt)) });
+            continue
+        };
+        if let Some(parent) = output.parent() {
+            io_error_convert(std::fs::create_dir_all(parent), &output, false)?;
+        }
+        let mut data = Cursor::new(Vec::new());
+        let res = match (fmt, style) {
+            (ImageFormat::Ico, _) => encode_ico(image, &mut data, FAVICON_ICO_SIZES, opts.filter),
+            (_, IconStyle::Transparent) => square_icon(image, size, opts.filter).write_to(&mut data, fmt),
+            (_, IconStyle::Opaque) => flatten_for(square_icon(image, size, opts.filter).into(), Format::Image(ImageFormat::Jpeg), opts).0.write_to(&mut data, fmt),
+            (_, IconStyle::Circle) => cut_corners(square_icon(image, size, opts.filter).into(), &Corners::Circle).write_to(&mut data, fmt),
+        };
+        image_err_convert(res, path)?;
+        let output = write_encoded(data.get_ref(), output, &[path], opts)?;
+        conversions.push(Conversion { input_format, output_bytes: Some(data.get_ref().len() as u64), ..Conversion::new(output, Format::Image(fmt)) });
+    }
+    if let (Preset::IosAppIcon, Some(_)) = (preset, &image) {
+        let contents = dir.join(IOS_ICON_SET).join("Contents.json");
+        if let Some(contents) = check_conflict(contents, &[path], opts)? {
+            write_encoded(ios_contents().as_bytes(), contents, &[path], opts)?;
+        }
+    }
+    Ok(conversions)
+}
+
+// Fits the image into a transparent square
+fn square_icon(image: &image::DynamicImage, size: u32, filter: FilterType) -> image::RgbaImage {
+    let fitted = image.resize(size, size, filter).to_rgba8();
+    let mut icon = image::RgbaImage::new(size, size);
+    image::imageops::overlay(&mut icon, &fitted, ((size - fitted.width()) / 2) as i64, ((size - fitted.height()) / 2) as i64);
+    icon
+}
+
+// The hidden files of write_atomically and archives being written right now
+static PARTIAL_OUTPUTS: Mutex<Vec<std::path::PathBuf>> = Mutex::new(Vec::new());
+
+/// Removes the temporary files outputs are written to before being renamed into place,
+/// for exiting in the middle of converting
+pub fn discard_partial_outputs() {
+    for temp in PARTIAL_OUTPUTS.lock().unwrap().drain(..) {
+        let _ = std::fs::remove_file(temp);
+    }
+}
+
+// Writes to a hidden file next to the output first and renames it into place once complete,
+// so an interrupted or failed write never leaves a truncated output behind
+fn write_atomically(output: &Path, write: impl FnOnce(&mut BufWriter<File>) -> ImcoResult<()>) -> ImcoResult<()> {
+    let target = std::path::Path::new(output);
+    let temp = partial_path(target);
+    PARTIAL_OUTPUTS.lock().unwrap().push(temp.clone());
+    let written = (|| -> ImcoResult<()> {
+        let mut writer = BufWriter::new(io_error_convert(File::create(&temp), output, false)?);
+        write(&mut writer)?;
+        io_error_convert(writer.flush(), output, false)?;
+        // Overwritten files keep their permissions
+        if let Ok(existing) = std::fs::metadata(target) {
+            io_error_convert(std::fs::set_permissions(&temp, existing.permissions()), output, false)?;
+        }
+        check_abandoned(output)?;
+        io_error_convert(std::fs::rename(&temp, target), output, false)
+    })();
+    if written.is_err() { let _ = std::fs::remove_file(&temp); }
+    PARTIAL_OUTPUTS.lock().unwrap().retain(|partial| *partial != temp);
+    written
+}
+
+fn partial_path(output: &Path) -> PathBuf {
+    output.with_file_name(format!(".{}.imco-tmp", output.file_name().and_then(|n| n.to_str()).unwrap_or_default()))
+}
+
+// The hidden file an archive is written to, removed again unless the archive was renamed into place
+struct PartialArchive(PathBuf);
+
+impl Drop for PartialArchive {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+        PARTIAL_OUTPUTS.lock().unwrap().retain(|partial| *partial != self.0);
+    }
+}
+
+fn write_image(image: &image::DynamicImage, output: &Path, fmt: Format, meta: &Metadata, path: &Path, opts: &Options) -> ImcoResult<()> {
+    retrying(output, opts, || write_atomically(output, |writer| image_err_convert(encode(image, writer, fmt, meta, opts), path)))
+}
+
+fn save_image(image: &image::DynamicImage, output: &Path, fmt: Format, meta: &Metadata, path: &Path, opts: &Options) -> ImcoResult<()> {
+    let Some(dedupe) = &opts.dedupe else {
+        return write_image(image, output, fmt, meta, path, opts)
+    };
+    let key = (pixel_hash(image), fmt);
+    let original = dedupe.seen.lock().unwrap().get(&key).cloned();
+    if let Some(original) = original.filter(|o| o != output) {
+        return dedupe.link(&original, output)
+    }
+    write_image(image, output, fmt, meta, path, opts)?;
+    dedupe.seen.lock().unwrap().insert(key, output.to_path_buf());
+    Ok(())
+}
+
+// Reads a written output back, checking its format and dimensions (and pixel data if full)
+fn verify_output(output: &Path, fmt: Format, dimensions: (u32, u32), full: bool) -> ImcoResult<()> {
+    let fail = |hint: String| ImcoError::VerifyFailed(output.display().to_string(), hint);
+    let signature: Option<&[u8]> = match fmt {
+        Format::Pdf => Some(b"%PDF-"),
+        Format::Ktx2 => Some(b"\xabKTX 20\xbb"),
+        Format::Image(ImageFormat::Dds) => Some(b"DDS "),
+        _ => None,
+    };
+    if fmt == Format::Raw {
+        // Bare pixels only have their length to check, one or four bytes per pixel after a header line
+        let data = io_error_convert(std::fs::read(output), output, true)?;
+        let start = if data.starts_with(b"{") { data.iter().position(|b| *b == b'\n').map_or(0, |end| end + 1) } else { 0 };
+        let pixels = dimensions.0 as usize * dimensions.1 as usize;
+        return if [pixels, pixels * 4].contains(&(data.len() - start)) { Ok(()) } else { Err(fail(format!("{} bytes don't hold {}x{} pixels", data.len() - start, dimensions.0, dimensions.1))) }
+    }
+    if let Some(signature) = signature {
+        // Pages and textures aren't decoded, only the signature is checked
+        let data = io_error_convert(std::fs::read(output), output, true)?;
+        return if data.starts_with(signature) { Ok(()) } else { Err(fail("format not recognized".to_string())) }
+    }
+    let found = if fmt == Format::Jxl {
+        // The header alone doesn't tell the dimensions, so JPEG XL is always decoded
+        let data = io_error_convert(std::fs::read(output), output, true)?;
+        if !is_jxl_data(&data) { return Err(fail("format not recognized".to_string())) }
+        let (image, _) = decode_jxl(Cursor::new(data), output).map_err(|e| fail(e.to_string()))?;
+        (image.width(), image.height())
+    } else {
+        let reader = io_error_convert(imread(output)?.with_guessed_format(), output, true)?;
+        match reader.format() {
+            Some(found) if Format::Image(found) != fmt => return Err(fail(format!("expected {} but found {}", fmt.extension(), found.extensions_str()[0]))),
+            None => return Err(fail("format not recognized".to_string())),
+            _ => {}
+        }
+        if full {
+            let image = reader.decode().map_err(|e| fail(e.to_string()))?;
+            (image.width(), image.height())
+        } else {
+            reader.into_dimensions().map_err(|e| fail(e.to_string()))?
+        }
+    };
+    if found != dimensions {
+        return Err(fail(format!("expected {}x{} but found {}x{}", dimensions.0, dimensions.1, found.0, found.1)))
+    }
+    Ok(())
+}
+
+// Lets ImageReader decode heic / heif files like any other format
+fn register_decoding_hooks() {
+    #[cfg(feature = "heif")]
+    {
+        static HOOKS: std::sync::Once = std::sync::Once::new();
+        HOOKS.call_once(|| { libheif_rs::integration::image::register_all_decoding_hooks(); });
+    }
+}
+
+/// What [`identify`] found out about an image
+pub struct ImageInfo {
+    /// None for inputs without a [`Format`], like svg or camera raw
+    pub format: Option<Format>,
+    pub width: u32,
+    pub height: u32,
+    pub color: image::ColorType,
+    /// Frames of an animation or pages of a TIFF, 1 for still images
+    pub frames: usize,
+    pub file_size: Option<u64>,
+    pub exif: bool,
+    pub icc: bool,
+}
+
+impl ImageInfo {
+    /// Bits per channel
+    pub fn bit_depth(&self) -> u16 {
+        self.color.bits_per_pixel() / self.color.channel_count() as u16
+    }
+}
+
+// The JPEG decoder pads truncated scans quietly, so the last scan must be followed by an end of image marker
+fn jpeg_complete(data: &[u8]) -> bool {
+    let Some(scan) = data.windows(2).rposition(|w| w == [0xFF, 0xDA]) else { return false };
+    data[scan..].windows(2).any(|w| w == [0xFF, 0xD9])
+}
+
+/// Fully decodes the image at path without writing anything, with every frame of animations
+/// and every page of TIFFs, to find corrupt and truncated files
+pub fn verify_image(path: &Path) -> ImcoResult<()> {
+    register_decoding_hooks();
+    let opts = Options::default();
+    let decoded = input_format(path, &opts).and_then(|fmt| {
+        if decode_frames(path, &opts)?.is_some() { return Ok(fmt) }
+        if fmt == Some(Format::Image(ImageFormat::Tiff)) {
+            decode_tiff_pages(path, &opts.limits)?;
+        } else {
+            decode_input(path, None, &opts.rasterize, &opts.limits)?;
+        }
+        Ok(fmt)
+    });
+    let fmt = match decoded {
+        Ok(fmt) => fmt,
+        // Running out of data is a read error, not one of writing the (decoded) output
+        Err(ImcoError::FailedFileWrite(cause, path)) => return Err(ImcoError::Decoding(path, cause)),
+        Err(e) => return Err(e)
+    };
+    if fmt == Some(Format::Image(ImageFormat::Jpeg)) && !jpeg_complete(&io_error_convert(std::fs::read(path), path, true)?) {
+        return Err(ImcoError::Decoding(path.display().to_string(), "The end of image marker is missing, the file is truncated".to_string()))
+    }
+    Ok(())
+}
+
+/// How [`terminal_preview`] draws images
+#[derive(Clone, Copy, PartialEq)]
+pub enum Graphics {
+    Kitty,
+    /// The inline images of iTerm2, also understood by WezTerm
+    Iterm,
+    Sixel,
+    /// Half block characters in 24-bit color, which every modern terminal shows
+    Blocks,
+}
+
+// Pixels of one character cell, guessed as terminals don't tell without a query
+const CELL_WIDTH: u32 = 8;
+const CELL_HEIGHT: u32 = 16;
+
+// Appends a sixel run, repeating the character for short ones
+fn sixel_run(out: &mut String, sixel: u8, count: usize) {
+    if count > 3 {
+        out.push_str(&format!("!{count}{}", sixel as char));
+    } else {
+        (0..count).for_each(|_| out.push(sixel as char));
+    }
+}
+
+fn encode_sixel(image: &image::RgbaImage) -> String {
+    let palette = color_quant::NeuQuant::new(10, 256, image.as_raw());
+    let colors = palette.color_map_rgb();
+    let indices: Vec<usize> = image.pixels().map(|p| palette.index_of(&p.0)).collect();
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    // Transparent pixels (P2 = 1) are left unpainted
+    let mut out = format!("\x1bP0;1q\"1;1;{width};{height}");
+    let mut defined = vec![false; colors.len() / 3];
+    indices.iter().for_each(|i| defined[*i] = true);
+    for (i, rgb) in colors.chunks(3).enumerate().filter(|(i, _)| defined[*i]) {
+        out.push_str(&format!("#{i};2;{};{};{}", rgb[0] as u32 * 100 / 255, rgb[1] as u32 * 100 / 255, rgb[2] as u32 * 100 / 255));
+    }
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+        let mut used: Vec<usize> = rows.clone().flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|(x, y)| image.get_pixel(*x as u32, *y as u32).0[3] >= 128)
+            .map(|(x, y)| indices[y * width + x])
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+        for color in used {
+            out.push_str(&format!("#{color}"));
+            let (mut previous, mut count) = (0, 0);
+            for x in 0..width {
+                let bits = rows.clone().enumerate()
+                    .filter(|(_, y)| indices[y * width + x] == color && image.get_pixel(x as u32, *y as u32).0[3] >= 128)
+                    .fold(0, |bits, (dy, _)| bits | 1 << dy);
+                let sixel = 63 + bits;
+                if sixel != previous && count > 0 {
+                    sixel_run(&mut out, previous, count);
+                    count = 0;
+                }
+                previous = sixel;
+                count += 1;
+            }
+            sixel_run(&mut out, previous, count);
+            // Back to the start of the band for the next color
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\\n");
+    out
+}
+
+// Two pixels per character, the upper one as the foreground of ▀ and the lower one as its background
+fn encode_blocks(image: &image::RgbaImage) -> String {
+    // Transparency is put onto black
+    let rgb = |p: &image::Rgba<u8>| p.0[..3].iter().map(|c| (*c as u32 * p.0[3] as u32 / 255).to_string()).collect::<Vec<_>>().join(";");
+    let mut out = String::new();
+    for y in (0..image.height()).step_by(2) {
+        for x in 0..image.width() {
+            out.push_str(&format!("\x1b[38;2;{}m", rgb(image.get_pixel(x, y))));
+            match (y + 1 < image.height()).then(|| image.get_pixel(x, y + 1)) {
+                Some(lower) => out.push_str(&format!("\x1b[48;2;{}m▀", rgb(lower))),
+                None => out.push_str("\x1b[49m▀"),
+            }
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+/// The image at path drawn for a terminal, scaled down to fit into columns x rows character cells
+pub fn terminal_preview(path: &Path, graphics: Graphics, columns: u32, rows: u32) -> ImcoResult<String> {
+    register_decoding_hooks();
+    let (mut image, meta, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+    if let Some(orientation) = meta.orientation {
+        image.apply_orientation(orientation);
+    }
+    // Blocks show two pixels per cell, the graphics protocols the pixels of the cells
+    let (max_width, max_height) = match graphics {
+        Graphics::Blocks => (columns, rows * 2),
+        _ => (columns * CELL_WIDTH, rows * CELL_HEIGHT),
+    };
+    if image.width() > max_width || image.height() > max_height {
+        image = image.resize(max_width.max(1), max_height.max(1), FilterType::Triangle);
+    }
+    let rgba = image.to_rgba8();
+    let png = || {
+        let mut data = Cursor::new(Vec::new());
+        image_err_convert(image::DynamicImage::ImageRgba8(rgba.clone()).write_to(&mut data, ImageFormat::Png), path)?;
+        Ok::<_, ImcoError>(data.into_inner())
+    };
+    Ok(match graphics {
+        Graphics::Kitty => {
+            // The payload is sent in chunks of at most 4096 bytes, m=1 telling more are coming
+            let payload = base64(&png()?);
+            let chunks: Vec<_> = payload.as_bytes().chunks(4096).collect();
+            let mut out = String::new();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let more = (i + 1 < chunks.len()) as u8;
+                let keys = if i == 0 { format!("a=T,f=100,m={more}") } else { format!("m={more}") };
+                out.push_str(&format!("\x1b_G{keys};{}\x1b\\", std::str::from_utf8(chunk).unwrap()));
+            }
+            out.push('\n');
+            out
+        }
+        Graphics::Iterm => {
+            let png = png()?;
+            format!("\x1b]1337;File=inline=1;size={};preserveAspectRatio=1:{}\x07\n", png.len(), base64(&png))
+        }
+        Graphics::Sixel => encode_sixel(&rgba),
+        Graphics::Blocks => encode_blocks(&rgba),
+    })
+}
+
+/// Reads the properties of an image, only decoding the pixels when the format needs it for that
+pub fn identify(path: &Path) -> ImcoResult<ImageInfo> {
+    register_decoding_hooks();
+    let file_size = file_size(path);
+    // Their decoders don't offer the properties on their own
+    if is_svg_path(path) || is_raw_path(path) || mk_format_fp(path).ok() == Some(Format::Jxl) || starts_like_bc7_dds(path) {
+        let (image, meta, format) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+        return Ok(ImageInfo {
+            format, width: image.width(), height: image.height(), color: image.color(), frames: 1,
+            file_size, exif: meta.exif.is_some(), icc: meta.icc.is_some()
+        })
+    }
+    let reader = io_error_convert(imread(path)?.with_guessed_format(), path, true)?;
+    let format = reader.format().map(Format::Image);
+    let mut decoder = image_err_convert(reader.into_decoder(), path)?;
+    let (width, height) = decoder.dimensions();
+    Ok(ImageInfo {
+        format, width, height,
+        color: decoder.color_type(),
+        frames: count_frames(path, format)?,
+        file_size,
+        exif: decoder.exif_metadata().ok().flatten().is_some(),
+        icc: decoder.icc_profile().ok().flatten().is_some(),
+    })
+}
+
+// Frames of an animation or pages of a TIFF
+fn count_frames(path: &Path, format: Option<Format>) -> ImcoResult<usize> {
+    let reader = || io_error_convert(File::open(path), path, true).map(BufReader::new);
+    let decoding = |e: String| ImcoError::Decoding(path.display().to_string(), e);
+    Ok(match format {
+        Some(Format::Image(ImageFormat::Gif)) => {
+            let decoder = image_err_convert(GifDecoder::new(reader()?), path)?;
+            image_err_convert(decoder.into_frames().collect_frames(), path)?.len()
+        }
+        // The animation control chunk knows the count up front
+        Some(Format::Image(ImageFormat::Png)) => {
+            let png = png::Decoder::new(reader()?).read_info().map_err(|e| decoding(e.to_string()))?;
+            png.info().animation_control.map_or(1, |control| control.num_frames as usize)
+        }
+        Some(Format::Image(ImageFormat::WebP)) => {
+            let decoder = image_err_convert(WebPDecoder::new(reader()?), path)?;
+            if !decoder.has_animation() { return Ok(1) }
+            image_err_convert(decoder.into_frames().collect_frames(), path)?.len()
+        }
+        Some(Format::Image(ImageFormat::Tiff)) => {
+            let mut decoder = tiff::decoder::Decoder::new(reader()?).map_err(|e| decoding(e.to_string()))?;
+            let mut pages = 1;
+            while decoder.more_images() {
+                decoder.next_image().map_err(|e| decoding(e.to_string()))?;
+                pages += 1;
+            }
+            pages
+        }
+        _ => 1
+    })
+}
+
+/// Perceptual hashes of 64 bits, their Hamming distance grows with the visual difference of two images
+#[derive(Clone, Copy)]
+pub enum HashKind {
+    /// Brighter or darker than the mean (aHash)
+    Average,
+    /// Brighter or darker than the right neighbour (dHash)
+    Difference,
+    /// Low frequencies of the cosine transform above or below their median (pHash)
+    Perceptual,
+}
+
+pub fn perceptual_hash(image: &image::DynamicImage, kind: HashKind) -> u64 {
+    let bits: Vec<bool> = match kind {
+        HashKind::Average => {
+            let small = image.thumbnail_exact(8, 8).to_luma8();
+            let mean = small.pixels().map(|p| p.0[0] as u32).sum::<u32>() / 64;
+            small.pixels().map(|p| p.0[0] as u32 > mean).collect()
+        }
+        HashKind::Difference => {
+            let small = image.thumbnail_exact(9, 8).to_luma8();
+            (0..8).flat_map(|y| (0..8).map(move |x| (x, y)))
+                .map(|(x, y)| small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0])
+                .collect()
+        }
+        HashKind::Perceptual => {
+            const SIZE: usize = 32;
+            let small = image.thumbnail_exact(SIZE as u32, SIZE as u32).to_luma8();
+            let pixels: Vec<f64> = small.pixels().map(|p| p.0[0] as f64).collect();
+            let cosines: Vec<f64> = (0..8 * SIZE).map(|i| {
+                let (u, x) = (i / SIZE, i % SIZE);
+                ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * SIZE) as f64).cos()
+            }).collect();
+            // Only the 8x8 lowest frequencies are needed
+            let mut low = Vec::with_capacity(64);
+            for v in 0..8 {
+                for u in 0..8 {
+                    let mut sum = 0.0;
+                    for y in 0..SIZE {
+                        for x in 0..SIZE {
+                            sum += pixels[y * SIZE + x] * cosines[u * SIZE + x] * cosines[v * SIZE + y];
+                        }
+                    }
+                    low.push(sum);
+                }
+            }
+            // The DC term only says how bright the image is
+            let mut sorted = low[1..].to_vec();
+            sorted.sort_by(f64::total_cmp);
+            let median = sorted[sorted.len() / 2];
+            low.iter().map(|c| *c > median).collect()
+        }
+    };
+    bits.iter().fold(0, |hash, bit| hash << 1 | *bit as u64)
+}
+
+/// Groups of visually identical images, the images of a group being at most max_distance bits apart
+/// (directly or through other members). The largest image of a group comes first, images without
+/// duplicates are left out. Inputs that can't be decoded are returned with their error
+pub fn find_duplicates(paths: &[PathBuf], kind: HashKind, max_distance: u32) -> (Vec<Vec<PathBuf>>, Vec<(PathBuf, ImcoError)>) {
+    register_decoding_hooks();
+    let hashed: Vec<_> = paths.par_iter().map(|path| {
+        let (mut image, meta, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+        if let Some(orientation) = meta.orientation {
+            image.apply_orientation(orientation);
+        }
+        Ok((perceptual_hash(&image, kind), image.width() as u64 * image.height() as u64))
+    }).collect();
+    let mut failed = vec![];
+    let mut images = vec![];
+    for (path, result) in paths.iter().zip(hashed) {
+        match result {
+            Ok((hash, pixels)) => images.push((path.clone(), hash, pixels)),
+            Err(e) => failed.push((path.clone(), e))
+        }
+    }
+    // Union find over all pairs close enough
+    let mut parent: Vec<usize> = (0..images.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for a in 0..images.len() {
+        for b in a + 1..images.len() {
+            if (images[a].1 ^ images[b].1).count_ones() <= max_distance {
+                let (root_a, root_b) = (root(&mut parent, a), root(&mut parent, b));
+                parent[root_b] = root_a;
+            }
+        }
+    }
+    let mut groups: Vec<Vec<usize>> = vec![vec![]; images.len()];
+    for i in 0..images.len() {
+        let r = root(&mut parent, i);
+        groups[r].push(i);
+    }
+    let groups = groups.into_iter().filter(|group| group.len() > 1).map(|mut group| {
+        group.sort_by_key(|i| std::cmp::Reverse(images[*i].2));
+        group.into_iter().map(|i| images[i].0.clone()).collect()
+    }).collect();
+    (groups, failed)
+}
+
+/// What happens to the duplicates of the first image of a group
+#[derive(Clone, Copy)]
+pub enum DuplicateAction {
+    Report,
+    Delete,
+    /// Replace them by hard links to the first image
+    Hardlink,
+}
+
+pub fn apply_duplicate_action(group: &[PathBuf], action: DuplicateAction) -> ImcoResult<()> {
+    let Some((keep, duplicates)) = group.split_first() else { return Ok(()) };
+    for duplicate in duplicates {
+        match action {
+            DuplicateAction::Report => {}
+            DuplicateAction::Delete => io_error_convert(std::fs::remove_file(duplicate), duplicate, false)?,
+            DuplicateAction::Hardlink => {
+                io_error_convert(std::fs::remove_file(duplicate), duplicate, false)?;
+                io_error_convert(std::fs::hard_link(keep, duplicate), duplicate, false)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How close two images are, see [`compare`]
+pub struct Comparison {
+    /// Peak signal to noise ratio of the color channels in dB, infinite for identical images
+    pub psnr: f64,
+    /// Structural similarity of the luma, 1 for identical images
+    pub ssim: f64,
+    /// Largest difference of any channel (alpha included) of any pixel
+    pub max_difference: u8,
+}
+
+/// Compares two images of the same size, after applying their EXIF orientation
+pub fn compare(a: &Path, b: &Path) -> ImcoResult<Comparison> {
+    register_decoding_hooks();
+    let upright = |path: &Path| -> ImcoResult<image::DynamicImage> {
+        let (mut image, meta, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+        if let Some(orientation) = meta.orientation { image.apply_orientation(orientation) }
+        Ok(image)
+    };
+    let (image_a, image_b) = (upright(a)?, upright(b)?);
+    if (image_a.width(), image_a.height()) != (image_b.width(), image_b.height()) { return Err(ImcoError::CompareSize(a.display().to_string(), b.display().to_string())) }
+    Ok(compare_images(&image_a, &image_b))
+}
+
+/// Like [`compare`], but for decoded images of the same size
+pub fn compare_images(a: &image::DynamicImage, b: &image::DynamicImage) -> Comparison {
+    let (a_rgba, b_rgba) = (a.to_rgba8(), b.to_rgba8());
+    let mut squared = 0.0;
+    let mut max_difference = 0;
+    for (pa, pb) in a_rgba.pixels().zip(b_rgba.pixels()) {
+        for channel in 0..4 {
+            let difference = pa.0[channel].abs_diff(pb.0[channel]);
+            max_difference = max_difference.max(difference);
+            if channel < 3 { squared += (difference as f64).powi(2) }
+        }
+    }
+    let mse = squared / (3 * a_rgba.pixels().len()).max(1) as f64;
+    let psnr = if mse == 0.0 { f64::INFINITY } else { 10.0 * (255.0f64.powi(2) / mse).log10() };
+    Comparison { psnr, ssim: ssim(&a.to_luma8(), &b.to_luma8()), max_difference }
+}
+
+// Mean SSIM over 8x8 windows, 4 pixels apart. Images smaller than a window are one window
+fn ssim(a: &image::GrayImage, b: &image::GrayImage) -> f64 {
+    const WINDOW: u32 = 8;
+    const STEP: usize = 4;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (width, height) = a.dimensions();
+    let (window_w, window_h) = (WINDOW.min(width), WINDOW.min(height));
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y in (0..=height - window_h).step_by(STEP) {
+        for x in (0..=width - window_w).step_by(STEP) {
+            let n = (window_w * window_h) as f64;
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for wy in y..y + window_h {
+                for wx in x..x + window_w {
+                    let (pa, pb) = (a.get_pixel(wx, wy).0[0] as f64, b.get_pixel(wx, wy).0[0] as f64);
+                    sum_a += pa;
+                    sum_b += pb;
+                    sum_aa += pa * pa;
+                    sum_bb += pb * pb;
+                    sum_ab += pa * pb;
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    if windows == 0 { 1.0 } else { total / windows as f64 }
+}
+
+/// What [`diff_image`] draws
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DiffMode {
+    /// Both images next to each other, labeled with their file names
+    SideBySide,
+    /// An animation switching between them
+    Blink,
+    /// How much every pixel differs, from black for equal ones over red and yellow to white for the largest difference
+    Heatmap,
+}
+
+/// Writes a visual comparison of two images of the same size (after applying their EXIF orientation),
+/// returning how close they are and the bytes written. Blinking needs a gif or png output
+pub fn diff_image(a: &Path, b: &Path, output: &Path, mode: DiffMode, delay: image::Delay) -> ImcoResult<(Comparison, u64)> {
+    register_decoding_hooks();
+    let fmt = mk_format_fp(output)?;
+    if mode == DiffMode::Blink && !matches!(fmt, Format::Image(ImageFormat::Gif | ImageFormat::Png)) {
+        return Err(ImcoError::Unsupported(output.display().to_string(), format!("Writing animated {} files is not supported (use gif or png)", fmt.extension())))
+    }
+    let opts = Options::default();
+    let (image_a, _, _) = decode_upright(a, &opts)?;
+    let (image_b, _, _) = decode_upright(b, &opts)?;
+    let (width, height) = (image_a.width(), image_a.height());
+    if (image_b.width(), image_b.height()) != (width, height) { return Err(ImcoError::CompareSize(a.display().to_string(), b.display().to_string())) }
+    let comparison = compare_images(&image_a, &image_b);
+    let name = |path: &Path| path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned();
+    // Labels grow with the images, one glyph pixel per 160 pixels of width
+    let scale = (width / 160).max(1);
+    let black = image::Rgba([0, 0, 0, 255]);
+    let white = image::Rgba([255, 255, 255, 255]);
+    // Transparent pixels show the white background
+    let opaque = |image: &image::DynamicImage| {
+        let mut canvas = image::RgbaImage::from_pixel(width, height, white);
+        image::imageops::overlay(&mut canvas, &image.to_rgba8(), 0, 0);
+        canvas
+    };
+    let mut data = Cursor::new(Vec::new());
+    match mode {
+        DiffMode::SideBySide => {
+            let gap = 4 * scale;
+            let label_height = (GLYPH_HEIGHT + 4) * scale;
+            let mut strip = image::RgbaImage::from_pixel(2 * width + 3 * gap, height + label_height + 2 * gap, white);
+            for (i, (path, image)) in [(a, &image_a), (b, &image_b)].into_iter().enumerate() {
+                let x = gap + i as u32 * (width + gap);
+                image::imageops::overlay(&mut strip, &opaque(image), x as i64, gap as i64);
+                draw_text(&mut strip, &name(path), x, gap + height + 2 * scale, scale, black);
+            }
+            let strip = image::DynamicImage::ImageRgb8(image::DynamicImage::ImageRgba8(strip).to_rgb8());
+            image_err_convert(encode(&strip, &mut data, fmt, &Metadata::default(), &opts), output)?;
+        }
+        DiffMode::Blink => {
+            let frames: Vec<image::Frame> = [(a, &image_a), (b, &image_b)].into_iter().map(|(path, image)| {
+                let mut frame = opaque(image);
+                // On a black box in the top left corner
+                let label = name(path);
+                let box_width = (label.chars().count() as u32 * GLYPH_WIDTH + 2) * scale;
+                for (x, y) in (0..box_width.min(width)).flat_map(|x| (0..((GLYPH_HEIGHT + 2) * scale).min(height)).map(move |y| (x, y))) {
+                    frame.put_pixel(x, y, black);
+                }
+                draw_text(&mut frame, &label, 2 * scale, 2 * scale, scale, white);
+                image::Frame::from_parts(frame, 0, 0, delay)
+            }).collect();
+            encode_frames(&frames, &mut data, fmt, 0, &opts.encoder, output)?;
+        }
+        DiffMode::Heatmap => {
+            let (rgba_a, rgba_b) = (image_a.to_rgba8(), image_b.to_rgba8());
+            let largest = comparison.max_difference.max(1) as f32;
+            let heat = image::RgbImage::from_fn(width, height, |x, y| {
+                let (pa, pb) = (rgba_a.get_pixel(x, y).0, rgba_b.get_pixel(x, y).0);
+                let difference = (0..4).map(|c| pa[c].abs_diff(pb[c])).max().unwrap() as f32 / largest;
+                // Black, red, yellow, white in even steps
+                let ramp = |start: f32| ((difference * 3.0 - start).clamp(0.0, 1.0) * 255.0).round() as u8;
+                image::Rgb([ramp(0.0), ramp(1.0), ramp(2.0)])
+            });
+            image_err_convert(encode(&image::DynamicImage::ImageRgb8(heat), &mut data, fmt, &Metadata::default(), &opts), output)?;
+        }
+    }
+    write_encoded(data.get_ref(), output.into(), &[a, b], &opts)?;
+    Ok((comparison, data.get_ref().len() as u64))
+}
+
+/// Histograms and exposure figures of an image, see [`stats`]
+pub struct Stats {
+    pub width: u32,
+    pub height: u32,
+    /// 256 bins for r, g, b or l of gray images, and a with alpha
+    pub histograms: Vec<(&'static str, [u64; 256])>,
+    /// Of the luma, from 0 to 255
+    pub mean_luma: f64,
+    pub median_luma: u8,
+    /// Percentage of pixels with a color channel at 0
+    pub shadows_clipped: f64,
+    /// Percentage of pixels with a color channel at 255
+    pub highlights_clipped: f64,
+}
+
+/// Counts the 8 bit channels of the image, after applying its EXIF orientation
+pub fn stats(path: &Path) -> ImcoResult<Stats> {
+    register_decoding_hooks();
+    let (mut image, meta, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+    if let Some(orientation) = meta.orientation { image.apply_orientation(orientation) }
+    let color = image.color();
+    let names: &[&'static str] = match (color.has_color(), color.has_alpha()) {
+        (true, true) => &["r", "g", "b", "a"],
+        (true, false) => &["r", "g", "b"],
+        (false, true) => &["l", "a"],
+        (false, false) => &["l"],
+    };
+    let rgba = image.to_rgba8();
+    let mut bins = [[0u64; 256]; 4];
+    let mut luma_bins = [0u64; 256];
+    let (mut shadows, mut highlights) = (0u64, 0u64);
+    for p in rgba.pixels() {
+        for channel in 0..4 { bins[channel][p[channel] as usize] += 1 }
+        let luma = (0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32).round() as usize;
+        luma_bins[luma.min(255)] += 1;
+        if p.0[..3].contains(&0) { shadows += 1 }
+        if p.0[..3].contains(&255) { highlights += 1 }
+    }
+    let pixels = rgba.pixels().len().max(1) as u64;
+    let mean_luma = luma_bins.iter().enumerate().map(|(value, count)| value as f64 * *count as f64).sum::<f64>() / pixels as f64;
+    let mut counted = 0;
+    let median_luma = luma_bins.iter().position(|count| {
+        counted += count;
+        counted * 2 >= pixels
+    }).unwrap_or(0) as u8;
+    // Gray images have their luma in every color channel
+    let histograms = names.iter().map(|name| (*name, bins[match *name {
+        "r" | "l" => 0,
+        "g" => 1,
+        "b" => 2,
+        _ => 3
+    }])).collect();
+    let percent = |count: u64| count as f64 * 100.0 / pixels as f64;
+    Ok(Stats { width: image.width(), height: image.height(), histograms, mean_luma, median_luma, shadows_clipped: percent(shadows), highlights_clipped: percent(highlights) })
+}
+
+/// Draws the color histograms of the stats (alpha left out) over each other, each bin 2 pixels wide,
+/// and writes them to output
+pub fn write_histogram(stats: &Stats, output: &Path) -> ImcoResult<()> {
+    const HEIGHT: u32 = 200;
+    let fmt = mk_format_fp(output)?;
+    let colors = stats.histograms.iter().filter(|(name, _)| *name != "a");
+    let highest = colors.clone().flat_map(|(_, bins)| bins.iter()).copied().max().unwrap_or(0).max(1);
+    let mut canvas = image::RgbImage::new(512, HEIGHT);
+    for (name, bins) in colors {
+        let add = match *name {
+            "r" => [255, 0, 0],
+            "g" => [0, 255, 0],
+            "b" => [0, 0, 255],
+            _ => [255, 255, 255],
+        };
+        for (value, count) in bins.iter().enumerate() {
+            let bar = (*count as f64 / highest as f64 * HEIGHT as f64).round() as u32;
+            for y in HEIGHT - bar..HEIGHT {
+                for x in [value as u32 * 2, value as u32 * 2 + 1] {
+                    let p = canvas.get_pixel_mut(x, y);
+                    for channel in 0..3 { p[channel] = p[channel].saturating_add(add[channel]) }
+                }
+            }
+        }
+    }
+    let opts = Options { create_dirs: true, ..Options::default() };
+    let mut data = Cursor::new(Vec::new());
+    image_err_convert(encode(&canvas.into(), &mut data, fmt, &Metadata::default(), &opts), output)?;
+    write_encoded(data.get_ref(), output.into(), &[] as &[&str], &opts)?;
+    Ok(())
+}
+
+/// One of the dominant colors of an image, see [`palette`]
+pub struct Swatch {
+    pub color: image::Rgb<u8>,
+    /// Of the opaque pixels closest to this color, from 0 to 1
+    pub share: f64,
+}
+
+/// Finds up to colors dominant colors by splitting a thumbnail of the image at the median of its widest channel
+/// until there are enough boxes, then refining their means with k-means.
+/// Most common first, fully transparent images yield none
+pub fn palette(path: &Path, colors: usize) -> ImcoResult<Vec<Swatch>> {
+    register_decoding_hooks();
+    let (image, _, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+    // The palette hardly changes with the resolution
+    let pixels: Vec<[f32; 3]> = image.thumbnail(128, 128).to_rgba8().pixels()
+        .filter(|p| p[3] >= 128)
+        .map(|p| [p[0] as f32, p[1] as f32, p[2] as f32])
+        .collect();
+    if pixels.is_empty() { return Ok(Vec::new()) }
+    let spread = |group: &[[f32; 3]]| (0..3).map(|channel| {
+        let (low, high) = group.iter().fold((255f32, 0f32), |(low, high), p| (low.min(p[channel]), high.max(p[channel])));
+        (high - low, channel)
+    }).fold((0.0, 0), |widest, c| if c.0 > widest.0 { c } else { widest });
+    let mut boxes = vec![pixels.clone()];
+    while boxes.len() < colors {
+        let Some((index, (_, channel))) = boxes.iter().map(|group| spread(group)).enumerate()
+            .filter(|(_, (range, _))| *range > 0.0)
+            .max_by(|a, b| a.1.0.total_cmp(&b.1.0)) else { break };
+        let mut group = boxes.swap_remove(index);
+        group.sort_unstable_by(|a, b| a[channel].total_cmp(&b[channel]));
+        let upper = group.split_off(group.len() / 2);
+        boxes.push(group);
+        boxes.push(upper);
+    }
+    let mean = |group: &[&[f32; 3]]| {
+        let sum = group.iter().fold([0f32; 3], |sum, p| [sum[0] + p[0], sum[1] + p[1], sum[2] + p[2]]);
+        sum.map(|c| c / group.len() as f32)
+    };
+    let mut centers: Vec<[f32; 3]> = boxes.iter().map(|group| mean(&group.iter().collect::<Vec<_>>())).collect();
+    let nearest = |centers: &[[f32; 3]], p: &[f32; 3]| (0..centers.len())
+        .min_by(|a, b| distance(&centers[*a], p).total_cmp(&distance(&centers[*b], p))).unwrap();
+    let mut assigned = vec![0; pixels.len()];
+    for _ in 0..8 {
+        pixels.iter().zip(assigned.iter_mut()).for_each(|(p, center)| *center = nearest(&centers, p));
+        for (i, center) in centers.iter_mut().enumerate() {
+            let members: Vec<&[f32; 3]> = pixels.iter().zip(&assigned).filter(|(_, c)| **c == i).map(|(p, _)| p).collect();
+            if !members.is_empty() { *center = mean(&members) }
+        }
+    }
+    pixels.iter().zip(assigned.iter_mut()).for_each(|(p, center)| *center = nearest(&centers, p));
+    let mut counts = vec![0usize; centers.len()];
+    assigned.iter().for_each(|center| counts[*center] += 1);
+    let mut swatches: Vec<Swatch> = centers.iter().zip(counts).filter(|(_, count)| *count > 0)
+        .map(|(center, count)| Swatch {
+            color: image::Rgb(center.map(|c| c.round() as u8)),
+            share: count as f64 / pixels.len() as f64,
+        })
+        .collect();
+    swatches.sort_by(|a, b| b.share.total_cmp(&a.share));
+    Ok(swatches)
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]).powi(2)).sum()
+}
+
+/// Writes the swatches as a row of size pixels large squares to output
+pub fn write_swatches(swatches: &[Swatch], size: u32, output: &Path) -> ImcoResult<()> {
+    let fmt = mk_format_fp(output)?;
+    let mut canvas = image::RgbImage::new(size * swatches.len().max(1) as u32, size);
+    for (x, _, p) in canvas.enumerate_pixels_mut() {
+        if let Some(swatch) = swatches.get((x / size) as usize) { *p = swatch.color }
+    }
+    let opts = Options { create_dirs: true, ..Options::default() };
+    let mut data = Cursor::new(Vec::new());
+    image_err_convert(encode(&canvas.into(), &mut data, fmt, &Metadata::default(), &opts), output)?;
+    write_encoded(data.get_ref(), output.into(), &[] as &[&str], &opts)?;
+    Ok(())
+}
+
+/// Sizes before and after [`optimize`], the same when the original was kept
+pub struct Optimization {
+    pub original_bytes: u64,
+    pub optimized_bytes: u64,
+}
+
+impl Optimization {
+    pub fn saved(&self) -> u64 {
+        self.original_bytes - self.optimized_bytes
+    }
+}
+
+/// Re-encodes a png or jpeg losslessly and replaces it when that is smaller (unless dry_run).
+/// Pngs get the smallest color type and filter, jpegs lose metadata that doesn't change their look.
+/// Animated pngs are left alone
+pub fn optimize(path: &Path, dry_run: bool) -> ImcoResult<Optimization> {
+    let data = io_error_convert(std::fs::read(path), path, true)?;
+    let original_bytes = data.len() as u64;
+    let optimized = match image::guess_format(&data) {
+        Ok(ImageFormat::Png) => {
+            let apng = PngDecoder::new(Cursor::new(&data)).and_then(|decoder| decoder.is_apng());
+            if image_err_convert(apng, path)? { return Ok(Optimization { original_bytes, optimized_bytes: original_bytes }) }
+            let (mut image, meta) = decode_reader(ImageReader::with_format(Cursor::new(&data), ImageFormat::Png), path, &DecodeLimits::default())?;
+            if let Some(orientation) = meta.orientation { image.apply_orientation(orientation) }
+            optimize_png(&image, meta.icc, path)?
+        }
+        Ok(ImageFormat::Jpeg) => strip_jpeg(&data, path)?,
+        _ => return Err(ImcoError::CantOptimize(path.display().to_string()))
+    };
+    if optimized.len() as u64 >= original_bytes { return Ok(Optimization { original_bytes, optimized_bytes: original_bytes }) }
+    if !dry_run { write_atomically(path, |writer| io_error_convert(writer.write_all(&optimized), path, false))? }
+    Ok(Optimization { original_bytes, optimized_bytes: optimized.len() as u64 })
+}
+
+// The smallest png of the candidates for every filter, palette and reduced color types are tried for 8 bit images
+// Depth, packed indices, palette and palette alpha (if not opaque)
+type PngPalette = (png::BitDepth, Vec<u8>, Vec<u8>, Option<Vec<u8>>);
+
+// Only images with at most 256 colors have one
+fn png_palette(rgba: &image::RgbaImage) -> Option<PngPalette> {
+    use png::BitDepth;
+    let mut palette: HashMap<[u8; 4], u8> = HashMap::new();
+    let fits = rgba.pixels().all(|p| {
+        let next = palette.len();
+        if palette.contains_key(&p.0) { return true }
+        if next == 256 { return false }
+        palette.insert(p.0, next as u8);
+        true
+    });
+    if !fits { return None }
+    let width = rgba.width() as usize;
+    let bits = match palette.len() { 0..=2 => 1, 3..=4 => 2, 5..=16 => 4, _ => 8 };
+    let row_bytes = (width * bits).div_ceil(8);
+    let mut packed = vec![0u8; row_bytes * rgba.height() as usize];
+    for (i, p) in rgba.pixels().enumerate() {
+        let (x, y) = (i % width, i / width);
+        let bit = x * bits;
+        packed[y * row_bytes + bit / 8] |= palette[&p.0] << (8 - bits - bit % 8);
+    }
+    let mut entries: Vec<_> = palette.iter().collect();
+    entries.sort_by_key(|(_, index)| **index);
+    let colors = entries.iter().flat_map(|(c, _)| [c[0], c[1], c[2]]).collect();
+    let opaque = entries.iter().all(|(c, _)| c[3] == 255);
+    let alpha = (!opaque).then(|| entries.iter().map(|(c, _)| c[3]).collect());
+    let depth = match bits { 1 => BitDepth::One, 2 => BitDepth::Two, 4 => BitDepth::Four, _ => BitDepth::Eight };
+    Some((depth, packed, colors, alpha))
+}
+
+// Writes quantized images with their palette instead of as RGBA, which PngEncoder always does
+fn encode_indexed_png<W: Write>(image: &image::DynamicImage, writer: &mut W, meta: &Metadata, settings: &PngSettings) -> image::ImageResult<()> {
+    let rgba = image.to_rgba8();
+    let Some((depth, packed, colors, alpha)) = png_palette(&rgba) else {
+        if settings.interlace { return encode_interlaced_png(image, writer, meta, settings) }
+        return write_with_metadata(image, PngEncoder::new(writer), meta)
+    };
+    let fail = |e: png::EncodingError| ImageError::Encoding(image::error::EncodingError::new(ImageFormat::Png.into(), e));
+    let mut info = png::Info::with_size(rgba.width(), rgba.height());
+    info.color_type = png::ColorType::Indexed;
+    info.bit_depth = depth;
+    info.icc_profile = meta.icc.as_deref().map(std::borrow::Cow::Borrowed);
+    info.exif_metadata = meta.exif.as_deref().map(std::borrow::Cow::Borrowed);
+    info.palette = Some(std::borrow::Cow::Owned(colors));
+    info.trns = alpha.map(std::borrow::Cow::Owned);
+    if settings.interlace { return write_interlaced_png(writer, info, &packed, settings).map_err(fail) }
+    let mut writer = png::Encoder::with_info(writer, info).map_err(fail)?.write_header().map_err(fail)?;
+    writer.write_image_data(&packed).map_err(fail)?;
+    writer.finish().map_err(fail)
+}
+
+fn encode_interlaced_png<W: Write>(image: &image::DynamicImage, writer: &mut W, meta: &Metadata, settings: &PngSettings) -> image::ImageResult<()> {
+    let fail = |e: png::EncodingError| ImageError::Encoding(image::error::EncodingError::new(ImageFormat::Png.into(), e));
+    let (color, depth, converted) = png_layout(image.color());
+    let mut info = png::Info::with_size(image.width(), image.height());
+    info.color_type = color;
+    info.bit_depth = depth;
+    info.icc_profile = meta.icc.as_deref().map(std::borrow::Cow::Borrowed);
+    info.exif_metadata = meta.exif.as_deref().map(std::borrow::Cow::Borrowed);
+    let data = png_samples(convert_color(image.clone(), converted), depth);
+    write_interlaced_png(writer, info, &data, settings).map_err(fail)
+}
+
+fn optimize_png(image: &image::DynamicImage, icc: Option<Vec<u8>>, path: &Path) -> ImcoResult<Vec<u8>> {
+    use png::{BitDepth, ColorType};
+    let fail = |e: png::EncodingError| ImcoError::Encoding(path.display().to_string(), e.to_string());
+    const FILTERS: [png::Filter; 7] = [png::Filter::NoFilter, png::Filter::Sub, png::Filter::Up, png::Filter::Avg, png::Filter::Paeth, png::Filter::Adaptive, png::Filter::MinEntropy];
+    let (width, height) = (image.width(), image.height());
+    // Color type, depth, samples, palette, palette alpha
+    type Candidate = (ColorType, BitDepth, Vec<u8>, Option<Vec<u8>>, Option<Vec<u8>>);
+    let mut candidates: Vec<Candidate> = vec![];
+    if matches!(image.color(), image::ColorType::L8 | image::ColorType::La8 | image::ColorType::Rgb8 | image::ColorType::Rgba8) {
+        let rgba = image.to_rgba8();
+        let opaque = rgba.pixels().all(|p| p.0[3] == 255);
+        let gray = rgba.pixels().all(|p| p.0[0] == p.0[1] && p.0[1] == p.0[2]);
+        let samples: Vec<u8> = match (gray, opaque) {
+            (true, true) => rgba.pixels().map(|p| p.0[0]).collect(),
+            (true, false) => rgba.pixels().flat_map(|p| [p.0[0], p.0[3]]).collect(),
+            (false, true) => rgba.pixels().flat_map(|p| [p.0[0], p.0[1], p.0[2]]).collect(),
+            (false, false) => rgba.as_raw().clone(),
+        };
+        let color = match (gray, opaque) {
+            (true, true) => ColorType::Grayscale,
+            (true, false) => ColorType::GrayscaleAlpha,
+            (false, true) => ColorType::Rgb,
+            (false, false) => ColorType::Rgba,
+        };
+        candidates.push((color, BitDepth::Eight, samples, None, None));
+
+        if let Some((depth, packed, colors, alpha)) = png_palette(&rgba) {
+            candidates.push((ColorType::Indexed, depth, packed, Some(colors), alpha));
+        }
+    } else {
+        // 16 bit and float images keep their samples, png wants them big endian
+        let image = match image.color() {
+            image::ColorType::L16 | image::ColorType::La16 | image::ColorType::Rgb16 | image::ColorType::Rgba16 => image.clone(),
+            _ => image::DynamicImage::ImageRgba16(image.to_rgba16()),
+        };
+        let color = match image.color() {
+            image::ColorType::L16 => ColorType::Grayscale,
+            image::ColorType::La16 => ColorType::GrayscaleAlpha,
+            image::ColorType::Rgb16 => ColorType::Rgb,
+            _ => ColorType::Rgba,
+        };
+        let samples = image.as_bytes().chunks_exact(2).flat_map(|s| [s[1], s[0]]).collect();
+        candidates.push((color, BitDepth::Sixteen, samples, None, None));
+    }
+
+    let mut best: Option<Vec<u8>> = None;
+    for (color, depth, samples, palette, alpha) in &candidates {
+        for filter in FILTERS {
+            let mut info = png::Info::with_size(width, height);
+            info.color_type = *color;
+            info.bit_depth = *depth;
+            info.icc_profile = icc.as_deref().map(std::borrow::Cow::Borrowed);
+            info.palette = palette.as_deref().map(std::borrow::Cow::Borrowed);
+            info.trns = alpha.as_deref().map(std::borrow::Cow::Borrowed);
+            let mut data = vec![];
+            let mut encoder = png::Encoder::with_info(&mut data, info).map_err(fail)?;
+            encoder.set_deflate_compression(png::DeflateCompression::Level(9));
+            encoder.set_filter(filter);
+            let mut writer = encoder.write_header().map_err(fail)?;
+            writer.write_image_data(samples).map_err(fail)?;
+            writer.finish().map_err(fail)?;
+            if best.as_ref().is_none_or(|b| data.len() < b.len()) { best = Some(data) }
+        }
+    }
+    Ok(best.unwrap())
+}
+
+// Drops segments that don't change how the image looks: EXIF without a rotation, XMP, comments
+// and other application data. The compressed scan is copied as is
+fn strip_jpeg(data: &[u8], path: &Path) -> ImcoResult<Vec<u8>> {
+    let broken = || ImcoError::Decoding(path.display().to_string(), "broken jpeg segment".to_string());
+    let mut output = data[..2].to_vec();
+    let mut pos = 2;
+    loop {
+        if data.get(pos) != Some(&0xFF) { return Err(broken()) }
+        let marker = *data.get(pos + 1).ok_or_else(broken)?;
+        // Fill bytes and markers without a length
+        if marker == 0xFF { pos += 1; continue }
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            output.extend(&data[pos..pos + 2]);
+            pos += 2;
+            continue
+        }
+        // Start of scan, everything from here on is image data
+        if marker == 0xDA {
+            output.extend(&data[pos..]);
+            return Ok(output)
+        }
+        let length = u16::from_be_bytes([*data.get(pos + 2).ok_or_else(broken)?, *data.get(pos + 3).ok_or_else(broken)?]) as usize;
+        let segment = data.get(pos..pos + 2 + length).ok_or_else(broken)?;
+        let payload = &segment[4..];
+        let keep = match marker {
+            0xE1 => payload.starts_with(b"Exif\0\0") && image::metadata::Orientation::from_exif_chunk(&payload[6..])
+                .is_some_and(|o| o != image::metadata::Orientation::NoTransforms),
+            0xE2 => payload.starts_with(b"ICC_PROFILE\0"),
+            // JFIF and the Adobe color transform are needed for decoding
+            0xE0 | 0xEE => true,
+            0xE3..=0xEF | 0xFE => false,
+            _ => true
+        };
+        if keep { output.extend(segment) }
+        pos += 2 + length;
+    }
+}
+
+thread_local! {
+    // Set on the threads of with_timeout, with the timeout
+    static ABANDONED: std::cell::RefCell<Option<(Arc<AtomicBool>, std::time::Duration)>> = const { std::cell::RefCell::new(None) };
+}
+
+// Fails once the conversion on this thread was given up on, see with_timeout
+fn check_abandoned(path: &Path) -> ImcoResult<()> {
+    ABANDONED.with(|abandoned| match &*abandoned.borrow() {
+        Some((flag, timeout)) if flag.load(Ordering::Relaxed) => Err(ImcoError::TimedOut(path.display().to_string(), timeout.as_secs())),
+        _ => Ok(())
+    })
+}
+
+// Converts on a thread of its own, giving up on it after the timeout. The thread can't be stopped, but
+// notices being abandoned when it retries or finishes writing an output, discarding that
+fn with_timeout(timeout: Option<std::time::Duration>, input: &Path, convert: impl FnOnce() -> ImcoResult<Conversion> + Send + 'static) -> ImcoResult<Conversion> {
+    let Some(timeout) = timeout else { return convert() };
+    let abandoned = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let flag = abandoned.clone();
+    std::thread::spawn(move || {
+        ABANDONED.with(|abandoned| *abandoned.borrow_mut() = Some((flag, timeout)));
+        let _ = sender.send(convert());
+    });
+    receiver.recv_timeout(timeout).unwrap_or_else(|e| match e {
+        std::sync::mpsc::RecvTimeoutError::Timeout => {
+            abandoned.store(true, Ordering::Relaxed);
+            Err(ImcoError::TimedOut(input.display().to_string(), timeout.as_secs()))
+        }
+        std::sync::mpsc::RecvTimeoutError::Disconnected => Err(ImcoError::InternalConversionError(format!("converting '{}' panicked", input.display()))),
+    })
+}
+
+// Whether all inputs go into one animation or document
+fn combined(jobs: &[ConversionJob], opts: &Options) -> bool {
+    let combine_pdf_pages = !opts.pdf_per_file && jobs.len() > 1 && match opts.o_fmt {
+        Some(fmt) => fmt == Format::Pdf,
+        None => jobs.iter().all(|job| job.output == jobs[0].output)
+            && jobs[0].output.as_ref().and_then(|o| explicit_output(o, opts).ok()).map(|(_, fmt)| fmt) == Some(Format::Pdf)
+    };
+    opts.animate.is_some() || combine_pdf_pages
+}
+
+// Every job's output and format
+type Plan = Vec<ImcoResult<(PathBuf, Format)>>;
+
+// Where every job goes, together with the new outputs of the jobs that were renamed by their index.
+// Later inputs of an output that's already taken get a numbered one with uniquify and fail the plan otherwise,
+// existing files being left to the conflict policy so reruns write the same names.
+// Stdout, the clipboard and names that are only known after transforming aren't checked
+fn plan_outputs(jobs: &[ConversionJob], opts: &Options) -> ImcoResult<(Plan, HashMap<usize, PathBuf>)> {
+    let mut plan: Vec<_> = jobs.iter().enumerate().map(|(index, job)| resolve_output(&job.input, job.output.clone(), index + 1, opts)).collect();
+    let mut renamed = HashMap::new();
+    if combined(jobs, opts) || opts.preset.is_some() { return Ok((plan, renamed)) }
+    // out.png and ./out.png are the same file
+    let key = |output: &Path| -> PathBuf {
+        output.components().filter(|c| *c != std::path::Component::CurDir).collect()
+    };
+    let mut taken: HashMap<PathBuf, usize> = HashMap::new();
+    for (index, planned) in plan.iter_mut().enumerate() {
+        let Ok((output, _)) = planned else { continue };
+        if output.as_os_str() == STDIO || jobs[index].output.as_ref().is_some_and(|output| output.as_os_str() == CLIPBOARD) || has_dimensions(output) { continue }
+        let Some(first) = taken.get(&key(output)) else {
+            taken.insert(key(output), index);
+            continue
+        };
+        if !opts.uniquify {
+            return Err(ImcoError::DuplicateOutput(output.display().to_string(), jobs[*first].input.display().to_string(), jobs[index].input.display().to_string()))
+        }
+        let unique = numbered_path(output, |candidate| taken.contains_key(&key(candidate)));
+        taken.insert(key(&unique), index);
+        *output = unique.clone();
+        renamed.insert(index, unique);
+    }
+    Ok((plan, renamed))
+}
+
+// Output bytes per input byte guessed for compressed formats, generous as a lossless output of a JPEG is several times its size
+const SPACE_RATIO: f64 = 3.0;
+
+/// About how many bytes converting the input into fmt writes: the pixels of formats without compression
+/// (read from the input's header), otherwise the input size times [`Options::space_ratio`] or a generous guess
+pub fn estimate_output_bytes(input: &Path, fmt: Format, opts: &Options) -> u64 {
+    let input_bytes = file_size(input).unwrap_or(0);
+    if let Some(ratio) = opts.space_ratio { return (input_bytes as f64 * ratio) as u64 }
+    let bytes_per_pixel = match fmt {
+        Format::Image(ImageFormat::OpenExr) => 16,
+        Format::Image(ImageFormat::Farbfeld) => 8,
+        Format::Image(ImageFormat::Bmp | ImageFormat::Tga | ImageFormat::Pnm | ImageFormat::Tiff | ImageFormat::Hdr | ImageFormat::Ico | ImageFormat::Dds)
+            | Format::Ktx2 | Format::Raw => 4,
+        _ => return (input_bytes as f64 * SPACE_RATIO) as u64
+    };
+    match probe(input) {
+        Ok((_, width, height)) => width as u64 * height as u64 * bytes_per_pixel,
+        Err(_) => (input_bytes as f64 * SPACE_RATIO) as u64
+    }
+}
+
+// Fails when the estimated outputs (in every format) exceed the free space of a volume they go to
+fn check_space(jobs: &[ConversionJob], plan: &Plan, opts: &Options) -> ImcoResult<()> {
+    // Per volume the directory named in errors, its free bytes and the bytes needed
+    let mut volumes: HashMap<u64, (String, u64, u64)> = HashMap::new();
+    for (job, planned) in jobs.iter().zip(plan) {
+        let Ok((output, fmt)) = planned else { continue };
+        if output == STDIO || output == CLIPBOARD { continue }
+        let Some((volume, dir, free)) = free_space(std::path::Path::new(output)) else { continue };
+        let needed = std::iter::once(*fmt).chain(opts.formats.iter().copied()).map(|fmt| estimate_output_bytes(&job.input, fmt, opts)).sum::<u64>();
+        volumes.entry(volume).or_insert((dir, free, 0)).2 += needed;
+    }
+    match volumes.into_values().find(|(_, free, needed)| needed > free) {
+        Some((dir, free, needed)) => Err(ImcoError::NoSpace(dir, needed, free)),
+        None => Ok(())
+    }
+}
+
+// The volume of the closest existing directory of path, that directory and the bytes free on it for unprivileged users
+#[cfg(unix)]
+fn free_space(path: &std::path::Path) -> Option<(u64, String, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+    let dir = path.ancestors().skip(1)
+        .map(|dir| if dir.as_os_str().is_empty() { std::path::Path::new(".") } else { dir })
+        .find(|dir| dir.is_dir())?;
+    let volume = std::fs::metadata(dir).ok()?.dev();
+    let c_dir = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: c_dir is NUL-terminated, statvfs fills stats when it returns 0
+    if unsafe { libc::statvfs(c_dir.as_ptr(), stats.as_mut_ptr()) } != 0 { return None }
+    // SAFETY: see above
+    let stats = unsafe { stats.assume_init() };
+    // The field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    let free = stats.f_bavail as u64 * stats.f_frsize as u64;
+    Some((volume, dir.to_string_lossy().into_owned(), free))
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &std::path::Path) -> Option<(u64, String, u64)> {
+    None
+}
+
+// Bytes like 1.5 GiB
+fn size_name(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["bytes", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{bytes} bytes") } else { format!("{size:.1} {}", UNITS[unit]) }
+}
+
+// Jobs with their position among those given
+type IndexedJobs = Vec<(usize, ConversionJob)>;
+
+// Splits the symlinks to other inputs off the jobs with Symlinks::Recreate
+fn recreated_links(jobs: Vec<ConversionJob>, opts: &Options) -> (IndexedJobs, IndexedJobs) {
+    let jobs: IndexedJobs = jobs.into_iter().enumerate().collect();
+    // Archives can't hold links
+    if opts.symlinks != Symlinks::Recreate || opts.archive.is_some() { return (vec![], jobs) }
+    let is_link = |job: &ConversionJob| job.input.is_symlink();
+    let targets: HashSet<PathBuf> = jobs.iter()
+        .filter(|(_, job)| !is_link(job))
+        .filter_map(|(_, job)| std::fs::canonicalize(&job.input).ok())
+        .collect();
+    jobs.into_iter().partition(|(_, job)| is_link(job) && std::fs::canonicalize(&job.input).is_ok_and(|target| targets.contains(&target)))
+}
+
+// Links the job's output to the output of its input's target, relative to the link like the input
+fn recreate_link(job: &ConversionJob, index: usize, converted: &HashMap<PathBuf, PathBuf>, opts: &Options) -> ImcoResult<Conversion> {
+    let target = io_error_convert(std::fs::canonicalize(&job.input), &job.input, true)?;
+    let (output, fmt) = resolve_output(&job.input, job.output.clone(), index, opts)?;
+    let Some(target_output) = converted.get(&target) else {
+        return Err(ImcoError::SymlinkTarget(job.input.display().to_string(), target.display().to_string()))
+    };
+    let conversion = |output: PathBuf, skipped: bool| Conversion { skipped, symlink: Some(target_output.clone()), ..Conversion::new(output, fmt) };
+    let Some(output) = check_conflict(output.clone(), &[&job.input], opts)? else { return Ok(conversion(output, true)) };
+    if opts.dry_run { return Ok(conversion(output, false)) }
+    let link = output.as_path();
+    let parent = link.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(std::path::Path::new("."));
+    io_error_convert(std::fs::create_dir_all(parent), &output, false)?;
+    let from = io_error_convert(std::fs::canonicalize(parent), &output, false)?;
+    let to = io_error_convert(std::fs::canonicalize(target_output), target_output, true)?;
+    let relative = relative_path(&from, &to);
+    if link.is_symlink() || link.exists() {
+        io_error_convert(std::fs::remove_file(link), &output, false)?;
+    }
+    io_error_convert(symlink_file(&relative, link), &output, false)?;
+    Ok(conversion(output, false))
+}
+
+// The path from the directory to the file, both absolute
+fn relative_path(from: &std::path::Path, to: &std::path::Path) -> std::path::PathBuf {
+    let common = from.components().zip(to.components()).take_while(|(a, b)| a == b).count();
+    let mut path: std::path::PathBuf = from.components().skip(common).map(|_| std::path::Component::ParentDir).collect();
+    path.extend(to.components().skip(common));
+    path
+}
+
+/// One input and where to write it, see [`Converter::convert`]
+pub struct ConversionJob {
+    pub input: PathBuf,
+    /// Without one the output goes next to the input, named after [`Options::name_template`].
+    /// With batching it is the directory outputs are written into
+    pub output: Option<PathBuf>,
+}
+
+impl ConversionJob {
+    pub fn new(input: impl Into<PathBuf>, output: Option<PathBuf>) -> Self {
+        ConversionJob { input: input.into(), output }
+    }
+}
+
+/// Converts images with the same [`Options`]
+pub struct Converter {
+    // Shared with the threads of conversions that have a timeout
+    opts: Arc<Options>,
+}
+
+impl Converter {
+    pub fn new(opts: Options) -> Self {
+        register_decoding_hooks();
+        Converter { opts: Arc::new(opts) }
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.opts
+    }
+
+    /// Where [`Converter::run`] writes every job and in which format, without converting anything.
+    /// Names with {width} or {height} keep them, as those are only known after transforming.
+    /// Fails like `run` when several inputs would be written to the same output, unless [`Options::uniquify`] renames them
+    pub fn plan(&self, jobs: &[ConversionJob]) -> ImcoResult<Vec<ImcoResult<(PathBuf, Format)>>> {
+        plan_outputs(jobs, &self.opts).map(|(plan, _)| plan)
+    }
+
+    /// Converts a single input, the first of a batch as far as the name template is concerned
+    pub fn convert(&self, job: &ConversionJob) -> ImcoResult<Conversion> {
+        individual_process(job.input.clone(), job.output.clone(), 1, None, &self.opts)
+    }
+
+    /// Converts an encoded image held in memory into the output format
+    pub fn convert_bytes(&self, data: Vec<u8>) -> ImcoResult<Vec<u8>> {
+        let memory = Path::new("memory");
+        let fmt = self.opts.o_fmt.ok_or(ImcoError::NoDestFormat)?;
+        let (mut image, mut meta, org_fmt) = match self.opts.raw_input {
+            Some(raw) => (raw.decode(data, memory, &self.opts.limits)?, Metadata::default(), Some(Format::Raw)),
+            None => decode_data(data, memory, self.opts.i_fmt, &self.opts.rasterize, &self.opts.limits)?,
+        };
+        let mut pending = None;
+        match meta.orientation.take() {
+            Some(orientation) if defers_orientation(&self.opts) => pending = Some(orientation),
+            Some(orientation) if self.opts.auto_orient => image.apply_orientation(orientation),
+            _ => {}
+        }
+        let keep_metadata = match self.opts.metadata {
+            MetadataPolicy::Keep => true,
+            MetadataPolicy::Strip => false,
+            MetadataPolicy::SameFormat => org_fmt == Some(fmt),
+        };
+        if !keep_metadata { meta.exif = None }
+        if self.opts.convert_to_srgb { image = to_srgb(image, &mut meta) }
+        if self.opts.strip_icc { meta.icc = None }
+        if let Some(exif) = meta.exif.as_mut().filter(|_| self.opts.deterministic) { blank_exif_dates(exif) }
+        if !self.opts.exif_edits.is_empty() { meta.exif = edit_exif(meta.exif.take(), &self.opts.exif_edits, memory)? }
+        let image = transform(image, pending, &self.opts, memory)?;
+        let mut output = Cursor::new(vec![]);
+        image_err_convert(encode(&image, &mut output, fmt, &meta, &self.opts), memory)?;
+        Ok(output.into_inner())
+    }
+
+    /// Converts all jobs, in parallel according to [`Options::jobs`]. Animations and combined pdfs
+    /// turn them into a single outcome, a preset into one per generated asset.
+    /// Without [`Options::keep_going`] the outcomes end with the first failure
+    pub fn run(&self, jobs: Vec<ConversionJob>) -> ImcoResult<Vec<FileOutcome>> {
+        let opts = &self.opts;
+        if combined(&jobs, opts) {
+            let inputs: Vec<PathBuf> = jobs.iter().map(|job| job.input.clone()).collect();
+            let output = jobs.first().and_then(|job| job.output.as_ref());
+            let start = std::time::Instant::now();
+            let (result, unit) = match opts.animate {
+                Some(delay) => (animate(&inputs, output, delay, opts), "frame(s)"),
+                None => (combine_pdf(&inputs, output, opts), "page(s)")
+            };
+            return Ok(vec![FileOutcome {
+                input: format!("{} {unit}", inputs.len()).into(),
+                input_bytes: inputs.iter().map(file_size).sum(),
+                duration: start.elapsed(),
+                result
+            }])
+        }
+        if let Some(preset) = &opts.preset {
+            let mut outcomes = vec![];
+            for job in jobs {
+                // Without an output the assets go next to the source
+                let dir = job.output.unwrap_or_else(|| job.input.parent().unwrap_or(Path::new("")).to_path_buf());
+                if opts.cancel.load(Ordering::Relaxed) { break }
+                let start = std::time::Instant::now();
+                let conversions = match preset_process(preset, &job.input, &dir, opts) {
+                    Ok(conversions) => conversions,
+                    Err(e) => {
+                        outcomes.push(FileOutcome { input_bytes: file_size(&job.input), input: job.input, duration: start.elapsed(), result: Err(e) });
+                        if opts.keep_going { continue } else { break }
+                    }
+                };
+                for conversion in conversions {
+                    outcomes.push(FileOutcome { input: job.input.clone(), input_bytes: file_size(&job.input), duration: start.elapsed(), result: Ok(conversion) });
+                }
+            }
+            return Ok(outcomes)
+        }
+
+        let (plan, renamed) = plan_outputs(&jobs, opts)?;
+        if opts.check_space && !opts.dry_run && opts.archive.is_none() { check_space(&jobs, &plan, opts)? }
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(opts.jobs).build()
+            .map_err(|e| ImcoError::ThreadPool(e.to_string()))?;
+        // Once a file failed (without keep_going) the ones not started yet are skipped
+        let abort = AtomicBool::new(false);
+        let total = jobs.len() as u64;
+        let progress = if opts.progress && jobs.len() > 1 {
+            ProgressBar::new(jobs.len() as u64).with_style(
+                ProgressStyle::with_template("{bar:40} {pos}/{len} ({per_sec}) {wide_msg}").unwrap())
+        } else { ProgressBar::hidden() };
+        // Indices stay those of the jobs given, for the name template
+        let (links, jobs) = recreated_links(jobs, opts);
+        let finished = |job: &ConversionJob| opts.session.as_ref().is_some_and(|session| session.is_done(&job.input));
+        let (links, jobs): (IndexedJobs, IndexedJobs) = (links.into_iter().filter(|(_, job)| !finished(job)).collect(), jobs.into_iter().filter(|(_, job)| !finished(job)).collect());
+        progress.inc(total - links.len() as u64 - jobs.len() as u64);
+        if let Some(cache) = &opts.decode_cache { cache.expect(&jobs) }
+        let results: Vec<Option<FileOutcome>> = pool.install(|| jobs.par_iter().map(|(index, job)| {
+            if abort.load(Ordering::Relaxed) || opts.cancel.load(Ordering::Relaxed) { return None }
+            progress.set_message(job.input.display().to_string());
+            let start = std::time::Instant::now();
+            let (input, output, number, renamed, shared) = (job.input.clone(), job.output.clone(), index + 1, renamed.get(index).cloned(), self.opts.clone());
+            let result = with_timeout(opts.timeout, &job.input, move || individual_process(input, output, number, renamed.as_deref(), &shared));
+            if let Some(session) = &opts.session { session.record(&job.input, &result) }
+            if result.is_err() && !opts.keep_going { abort.store(true, Ordering::Relaxed) }
+            progress.inc(1);
+            Some(FileOutcome { input: job.input.clone(), input_bytes: file_size(&job.input), duration: start.elapsed(), result })
+        }).collect());
+        progress.finish_and_clear();
+        if let Some(cache) = &opts.decode_cache { cache.clear() }
+
+        let mut outcomes = vec![];
+        // Outputs in further formats get outcomes of their own, right after the first one
+        let results = results.into_iter().flatten().flat_map(|mut outcome| {
+            let variants = outcome.result.as_mut().map(|c| std::mem::take(&mut c.variants)).unwrap_or_default();
+            let rest: Vec<_> = variants.into_iter().map(|variant| FileOutcome { input: outcome.input.clone(), input_bytes: outcome.input_bytes, duration: outcome.duration, result: Ok(variant) }).collect();
+            std::iter::once(outcome).chain(rest)
+        });
+        for outcome in results {
+            let failed = outcome.result.is_err();
+            outcomes.push(outcome);
+            if failed && !opts.keep_going { return Ok(outcomes) }
+        }
+        // Links point at the output in the first format
+        let converted: HashMap<PathBuf, PathBuf> = outcomes.iter().rev()
+            .filter_map(|o| Some((std::fs::canonicalize(&o.input).ok()?, o.result.as_ref().ok()?.output.clone())))
+            .collect();
+        for (index, job) in links {
+            let start = std::time::Instant::now();
+            let result = recreate_link(&job, index + 1, &converted, opts);
+            if let Some(session) = &opts.session { session.record(&job.input, &result) }
+            let failed = result.is_err();
+            outcomes.push(FileOutcome { input: job.input, input_bytes: None, duration: start.elapsed(), result });
+            if failed && !opts.keep_going { break }
+        }
+        Ok(outcomes)
+    }
+
+    /// Files linked instead of written and the bytes that saved, with [`Options::dedupe`]
+    pub fn deduplicated(&self) -> Option<(u64, u64)> {
+        self.opts.dedupe.as_ref().map(|dedupe| (dedupe.linked.load(Ordering::Relaxed), dedupe.saved.load(Ordering::Relaxed)))
+    }
+
+    /// Completes the [`Options::archive`], if any
+    pub fn finish(self) -> ImcoResult<()> {
+        // Abandoned conversions may still hold the options, which is why a timeout excludes an archive
+        let opts = match Arc::try_unwrap(self.opts) {
+            Ok(opts) => opts,
+            Err(opts) => return opts.checksums.as_ref().map_or(Ok(()), |checksums| checksums.write_manifest()),
+        };
+        if let Some(archive) = opts.archive {
+            let path = archive.path.clone();
+            archive.finish()?;
+            if let Some(checksums) = &opts.checksums { checksums.record(Path::new(&path))? }
+        }
+        match &opts.checksums {
+            Some(checksums) => checksums.write_manifest(),
+            None => Ok(())
+        }
+    }
+}
+
+// Columns of the printable ASCII characters, the lowest bit being the top row
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5f, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7f, 0x14, 0x7f, 0x14],
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1c, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1c, 0x00], [0x14, 0x08, 0x3e, 0x08, 0x14], [0x08, 0x08, 0x3e, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], [0x00, 0x42, 0x7f, 0x40, 0x00], [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4b, 0x31],
+    [0x18, 0x14, 0x12, 0x7f, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39], [0x3c, 0x4a, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1e], [0x00, 0x36, 0x36, 0x00, 0x00], [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14], [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3e], [0x7e, 0x11, 0x11, 0x11, 0x7e], [0x7f, 0x49, 0x49, 0x49, 0x36], [0x3e, 0x41, 0x41, 0x41, 0x22],
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], [0x7f, 0x49, 0x49, 0x49, 0x41], [0x7f, 0x09, 0x09, 0x09, 0x01], [0x3e, 0x41, 0x49, 0x49, 0x7a],
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], [0x00, 0x41, 0x7f, 0x41, 0x00], [0x20, 0x40, 0x41, 0x3f, 0x01], [0x7f, 0x08, 0x14, 0x22, 0x41],
+    [0x7f, 0x40, 0x40, 0x40, 0x40], [0x7f, 0x02, 0x0c, 0x02, 0x7f], [0x7f, 0x04, 0x08, 0x10, 0x7f], [0x3e, 0x41, 0x41, 0x41, 0x3e],
+    [0x7f, 0x09, 0x09, 0x09, 0x06], [0x3e, 0x41, 0x51, 0x21, 0x5e], [0x7f, 0x09, 0x19, 0x29, 0x46], [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7f, 0x01, 0x01], [0x3f, 0x40, 0x40, 0x40, 0x3f], [0x1f, 0x20, 0x40, 0x20, 0x1f], [0x3f, 0x40, 0x38, 0x40, 0x3f],
+    [0x63, 0x14, 0x08, 0x14, 0x63], [0x07, 0x08, 0x70, 0x08, 0x07], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7f, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7f, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78], [0x7f, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20],
+    [0x38, 0x44, 0x44, 0x48, 0x7f], [0x38, 0x54, 0x54, 0x54, 0x18], [0x08, 0x7e, 0x09, 0x01, 0x02], [0x0c, 0x52, 0x52, 0x52, 0x3e],
+    [0x7f, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7d, 0x40, 0x00], [0x20, 0x40, 0x44, 0x3d, 0x00], [0x7f, 0x10, 0x28, 0x44, 0x00],
+    [0x00, 0x41, 0x7f, 0x40, 0x00], [0x7c, 0x04, 0x18, 0x04, 0x78], [0x7c, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0x7c, 0x14, 0x14, 0x14, 0x08], [0x08, 0x14, 0x14, 0x18, 0x7c], [0x7c, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3f, 0x44, 0x40, 0x20], [0x3c, 0x40, 0x40, 0x20, 0x7c], [0x1c, 0x20, 0x40, 0x20, 0x1c], [0x3c, 0x40, 0x30, 0x40, 0x3c],
+    [0x44, 0x28, 0x10, 0x28, 0x44], [0x0c, 0x50, 0x50, 0x50, 0x3c], [0x44, 0x64, 0x54, 0x4c, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x7f, 0x00, 0x00], [0x00, 0x41, 0x36, 0x08, 0x00], [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+const GLYPH_WIDTH: u32 = 6;
+const GLYPH_HEIGHT: u32 = 8;
+
+// Draws ASCII text in the 5x7 font, characters outside of it as '?'
+fn draw_text(canvas: &mut image::RgbaImage, text: &str, x: u32, y: u32, scale: u32, color: image::Rgba<u8>) {
+    for (i, c) in text.chars().enumerate() {
+        let glyph = FONT[if (' '..='~').contains(&c) { c as usize - 32 } else { '?' as usize - 32 }];
+        for (column, bits) in glyph.iter().enumerate() {
+            for row in 0..7 {
+                if bits >> row & 1 == 0 { continue }
+                let (left, top) = (x + (i as u32 * GLYPH_WIDTH + column as u32) * scale, y + row * scale);
+                for (px, py) in (0..scale).flat_map(|dx| (0..scale).map(move |dy| (left + dx, top + dy))) {
+                    if px < canvas.width() && py < canvas.height() { canvas.put_pixel(px, py, color) }
+                }
+            }
+        }
+    }
+}
+
+/// Layout of a contact sheet, see [`montage`]
+pub struct Montage {
+    /// Images per row, enough for a roughly square sheet if None
+    pub columns: Option<u32>,
+    /// Box every image is shrunk into
+    pub cell: (u32, u32),
+    /// Space around the cells
+    pub gap: u32,
+    /// Print the file name under every image
+    pub labels: bool,
+    pub background: image::Rgb<u8>,
+}
+
+/// Puts the images, in order, onto one grid and writes it to output (or stdout for [`STDIO`]),
+/// returning the bytes written. Inputs that can't be decoded are left out and returned with their error
+pub fn montage(paths: &[PathBuf], output: &Path, layout: &Montage, filter: FilterType) -> ImcoResult<(u64, Vec<(PathBuf, ImcoError)>)> {
+    register_decoding_hooks();
+    let fmt = mk_format_fp(output)?;
+    let (cell_width, cell_height) = layout.cell;
+    let decoded: Vec<_> = paths.par_iter().map(|path| {
+        let (mut image, meta, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+        if let Some(orientation) = meta.orientation {
+            image.apply_orientation(orientation);
+        }
+        if image.width() > cell_width || image.height() > cell_height {
+            image = image.resize(cell_width, cell_height, filter);
+        }
+        Ok(image.to_rgba8())
+    }).collect();
+    let mut tiles = vec![];
+    let mut failed = vec![];
+    for (path, result) in paths.iter().zip(decoded) {
+        match result {
+            Ok(tile) => tiles.push((path, tile)),
+            Err(e) => failed.push((path.clone(), e)),
+        }
+    }
+    if tiles.is_empty() { return Err(ImcoError::EmptyMontage) }
+
+    let count = tiles.len() as u32;
+    let columns = layout.columns.unwrap_or_else(|| (count as f64).sqrt().ceil() as u32).clamp(1, count);
+    let rows = count.div_ceil(columns);
+    // Labels grow with the cells, one glyph pixel per 160 pixels of width
+    let scale = (cell_width / 160).max(1);
+    let label_height = if layout.labels { (GLYPH_HEIGHT + 4) * scale } else { 0 };
+    let row_height = cell_height + label_height;
+    let [r, g, b] = layout.background.0;
+    let mut sheet = image::RgbaImage::from_pixel(
+        columns * cell_width + (columns + 1) * layout.gap,
+        rows * row_height + (rows + 1) * layout.gap,
+        image::Rgba([r, g, b, 255])
+    );
+    let dark = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000 < 128;
+    let ink = if dark { image::Rgba([255, 255, 255, 255]) } else { image::Rgba([0, 0, 0, 255]) };
+    for (i, (path, tile)) in tiles.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        let (x, y) = (layout.gap + column * (cell_width + layout.gap), layout.gap + row * (row_height + layout.gap));
+        image::imageops::overlay(&mut sheet, tile, (x + (cell_width - tile.width()) / 2) as i64, (y + (cell_height - tile.height()) / 2) as i64);
+        if layout.labels {
+            let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+            let fits = (cell_width / (GLYPH_WIDTH * scale)) as usize;
+            let label: String = if name.chars().count() > fits {
+                name.chars().take(fits.saturating_sub(2)).chain("..".chars()).collect()
+            } else { name.to_string() };
+            let width = label.chars().count() as u32 * GLYPH_WIDTH * scale;
+            draw_text(&mut sheet, &label, x + cell_width.saturating_sub(width) / 2, y + cell_height + 2 * scale, scale, ink);
+        }
+    }
+
+    let sheet = image::DynamicImage::ImageRgb8(image::DynamicImage::ImageRgba8(sheet).to_rgb8());
+    let opts = Options::default();
+    let mut data = Cursor::new(Vec::new());
+    image_err_convert(encode(&sheet, &mut data, fmt, &Metadata::default(), &opts), output)?;
+    write_encoded(data.get_ref(), output.into(), paths, &opts)?;
+    Ok((data.get_ref().len() as u64, failed))
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Direction {
+    Horizontal,
+    Vertical,
+}
+
+/// Where images smaller than the tallest (or widest, when vertical) one sit across the strip
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Align {
+    Start,
+    Center,
+    End,
+}
+
+/// Layout of a strip, see [`concat`]
+pub struct Concat {
+    pub direction: Direction,
+    pub align: Align,
+    /// Pixels between neighbouring images
+    pub gap: u32,
+    /// Color of the gaps and the space beside smaller images, transparent if None
+    pub background: Option<image::Rgb<u8>>,
+}
+
+pub struct Strip {
+    pub width: u32,
+    pub height: u32,
+    /// Size of the written strip
+    pub output_bytes: u64,
+}
+
+/// Joins the images, in order, edge to edge into one strip and writes it to output (or stdout for [`STDIO`]),
+/// also returning the inputs that couldn't be decoded
+pub fn concat(paths: &[PathBuf], output: &Path, layout: &Concat) -> ImcoResult<(Strip, Vec<(PathBuf, ImcoError)>)> {
+    register_decoding_hooks();
+    let fmt = mk_format_fp(output)?;
+    let decoded: Vec<_> = paths.par_iter().map(|path| {
+        let (mut image, meta, _) = decode_input(path, None, &Rasterize::default(), &DecodeLimits::default())?;
+        if let Some(orientation) = meta.orientation {
+            image.apply_orientation(orientation);
+        }
+        Ok(image.to_rgba8())
+    }).collect();
+    let mut parts = vec![];
+    let mut failed = vec![];
+    for (path, result) in paths.iter().zip(decoded) {
+        match result {
+            Ok(part) => parts.push(part),
+            Err(e) => failed.push((path.clone(), e)),
+        }
+    }
+    if parts.is_empty() { return Err(ImcoError::EmptyConcat) }
+
+    let horizontal = layout.direction == Direction::Horizontal;
+    // Length along the strip and breadth across it
+    let gaps = (parts.len() as u32 - 1) * layout.gap;
+    let length = parts.iter().map(|p| if horizontal { p.width() } else { p.height() }).sum::<u32>() + gaps;
+    let breadth = parts.iter().map(|p| if horizontal { p.height() } else { p.width() }).max().unwrap();
+    let (width, height) = if horizontal { (length, breadth) } else { (breadth, length) };
+    let fill = layout.background.map_or(image::Rgba([0, 0, 0, 0]), |image::Rgb([r, g, b])| image::Rgba([r, g, b, 255]));
+    let mut strip = image::RgbaImage::from_pixel(width, height, fill);
+    let mut along = 0;
+    for part in &parts {
+        let (part_length, part_breadth) = if horizontal { (part.width(), part.height()) } else { (part.height(), part.width()) };
+        let across = match layout.align {
+            Align::Start => 0,
+            Align::Center => (breadth - part_breadth) / 2,
+            Align::End => breadth - part_breadth,
+        };
+        let (x, y) = if horizontal { (along, across) } else { (across, along) };
+        image::imageops::overlay(&mut strip, part, x as i64, y as i64);
+        along += part_length + layout.gap;
+    }
+
+    let opts = Options { background: layout.background, ..Options::default() };
+    let (strip, _) = flatten_for(image::DynamicImage::ImageRgba8(strip), fmt, &opts);
+    let mut data = Cursor::new(Vec::new());
+    image_err_convert(encode(&strip, &mut data, fmt, &Metadata::default(), &opts), output)?;
+    write_encoded(data.get_ref(), output.into(), paths, &opts)?;
+    Ok((Strip { width, height, output_bytes: data.get_ref().len() as u64 }, failed))
+}
+
+/// Where one input ended up in a [`SpriteSheet`]
+pub struct Sprite {
+    /// File name of the input, or its whole path if several inputs share the name
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct SpriteSheet {
+    pub width: u32,
+    pub height: u32,
+    pub sprites: Vec<Sprite>,
+    /// Size of the written texture
+    pub output_bytes: u64,
+}
+
+impl SpriteSheet {
+    /// The atlas in the JSON hash format of TexturePacker, which most game engines read
+    pub fn atlas(&self, image: &str) -> serde_json::Value {
+        let frames: serde_json::Map<String, serde_json::Value> = self.sprites.iter().map(|sprite| (sprite.name.clone(), serde_json::json!({
+            "frame": { "x": sprite.x, "y": sprite.y, "w": sprite.width, "h": sprite.height },
+            "rotated": false,
+            "trimmed": false,
+            "spriteSourceSize": { "x": 0, "y": 0, "w": sprite.width, "h": sprite.height },
+            "sourceSize": { "w": sprite.width, "h": sprite.height },
+        }))).collect();
+        serde_json::json!({
+            "frames": frames,
+            "meta": { "app": "imco", "image": image, "format": "RGBA8888", "size": { "w": self.width, "h": self.height }, "scale": "1" },
+        })
+    }
+}
+
+/// Packs the images onto shelves of one texture written to output, leaving padding pixels around every
+/// sprite, and writes the [`SpriteSheet::atlas`] to atlas if given. Inputs that can't be decoded are
+/// left out and returned with their error
+pub fn spritesheet(paths: &[PathBuf], output: &Path, atlas: Option<&Path>, padding: u32) -> ImcoResult<(SpriteSheet, Vec<(PathBuf, ImcoError)>)> {
+    register_decoding_hooks();
+    let fmt = mk_format_fp(output)?;
+    let decoded: Vec<_> = paths.par_iter()
+        .map(|path| decode_input(path, None, &Rasterize::default(), &DecodeLimits::default()).map(|(image, _, _)| image.to_rgba8()))
+        .collect();
+    let mut images = vec![];
+    let mut failed = vec![];
+    for (path, result) in paths.iter().zip(decoded) {
+        match result {
+            Ok(image) => images.push((path, image)),
+            Err(e) => failed.push((path.clone(), e)),
+        }
+    }
+    if images.is_empty() { return Err(ImcoError::EmptySpriteSheet) }
+
+    let file_name = |path: &Path| path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned();
+    let mut names: HashMap<String, usize> = HashMap::new();
+    for (path, _) in &images { *names.entry(file_name(path)).or_default() += 1 }
+    // Tallest first, so every shelf wastes little height
+    images.sort_by_key(|(_, image)| std::cmp::Reverse(image.height()));
+    let area: u64 = images.iter().map(|(_, image)| (image.width() + padding) as u64 * (image.height() + padding) as u64).sum();
+    let widest = images.iter().map(|(_, image)| image.width()).max().unwrap();
+    let width = ((area as f64).sqrt().ceil() as u32).max(widest) + 2 * padding;
+    let (mut x, mut y, mut shelf) = (padding, padding, 0);
+    let mut sprites = vec![];
+    for (path, image) in &images {
+        if x + image.width() + padding > width {
+            (x, y) = (padding, y + shelf + padding);
+            shelf = 0;
+        }
+        let name = if names[&file_name(path)] > 1 { path.to_string_lossy().into_owned() } else { file_name(path) };
+        sprites.push(Sprite { name, x, y, width: image.width(), height: image.height() });
+        x += image.width() + padding;
+        shelf = shelf.max(image.height());
+    }
+    let height = y + shelf + padding;
+    let mut texture = image::RgbaImage::new(width, height);
+    for ((_, image), sprite) in images.iter().zip(&sprites) {
+        image::imageops::replace(&mut texture, image, sprite.x as i64, sprite.y as i64);
+    }
+
+    let opts = Options::default();
+    let mut data = Cursor::new(Vec::new());
+    image_err_convert(encode(&image::DynamicImage::ImageRgba8(texture), &mut data, fmt, &Metadata::default(), &opts), output)?;
+    write_encoded(data.get_ref(), output.into(), paths, &opts)?;
+    let sheet = SpriteSheet { width, height, sprites, output_bytes: data.get_ref().len() as u64 };
+    if let Some(atlas) = atlas {
+        let image = output.file_name().unwrap_or(output.as_os_str()).to_string_lossy();
+        let json = serde_json::to_vec_pretty(&sheet.atlas(&image)).unwrap();
+        write_encoded(&json, atlas.into(), paths, &opts)?;
+    }
+    Ok((sheet, failed))
+}
+
+/// Files matching the glob patterns, directories only if keep_dirs
+pub fn expand_patterns_to_files<S: AsRef<OsStr>>(patterns: impl IntoIterator<Item = S>, keep_dirs: bool) -> ImcoResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        // Patterns can only be written in UTF-8, other names are taken as they are
+        let Some(pattern) = pattern.as_ref().to_str() else {
+            files.push(PathBuf::from(pattern.as_ref()));
+            continue
+        };
+        glob::Pattern::new(pattern).map_err(|e| ImcoError::BatchPattern(e.to_string(), pattern.to_string()))?;
+        // Patterns like 'dir/*' also match subdirectories, which can't be converted
+        files.extend(glob_paths(pattern)?.into_iter().filter(|path| keep_dirs || !path.is_dir()));
+    }
+    Ok(files)
+}
+
+// The paths matching a valid pattern, in the order glob finds them. Unlike glob, names that aren't UTF-8 are matched
+// too, written with replacement characters that wildcards match
+fn glob_paths(pattern: &str) -> ImcoResult<Vec<PathBuf>> {
+    let read_dir = |dir: &Path| -> ImcoResult<Vec<PathBuf>> {
+        let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let unreadable = |e: std::io::Error| ImcoError::BatchReadEntry(format!("attempting to read '{}' resulted in an error: {e}", listed.display()));
+        let mut entries = std::fs::read_dir(listed).map_err(unreadable)?
+            .map(|entry| entry.map(|entry| dir.join(entry.file_name())).map_err(unreadable))
+            .collect::<ImcoResult<Vec<_>>>()?;
+        entries.sort();
+        Ok(entries)
+    };
+    let mut found = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let name = component.as_os_str().to_str().unwrap_or_default();
+        found = if name == "**" {
+            // The directories themselves and all below them
+            let mut below = vec![];
+            let mut pending = found;
+            pending.reverse();
+            while let Some(dir) = pending.pop() {
+                let mut subdirs: Vec<PathBuf> = read_dir(&dir)?.into_iter().filter(|path| path.is_dir()).collect();
+                subdirs.reverse();
+                pending.extend(subdirs);
+                below.push(dir);
+            }
+            below
+        } else if name.contains(['*', '?', '[']) {
+            let compiled = glob::Pattern::new(name).map_err(|e| ImcoError::BatchPattern(e.to_string(), pattern.to_string()))?;
+            let mut matched = vec![];
+            for dir in found.iter().filter(|dir| dir.as_os_str().is_empty() || dir.is_dir()) {
+                matched.extend(read_dir(dir)?.into_iter().filter(|path| path.file_name().is_some_and(|name| compiled.matches(&name.to_string_lossy()))));
+            }
+            matched
+        } else {
+            found.into_iter().map(|dir| dir.join(component)).filter(|path| path.exists()).collect()
+        };
+    }
+    Ok(found.into_iter().filter(|path| !path.as_os_str().is_empty()).collect())
+}
+
+/// Entry of a jobs file, converted like a command line of its own
+pub struct Job {
+    /// Comma separated like on the command line
+    pub input: String,
+    pub output: Option<String>,
+    pub format: Option<String>,
+    /// By long option name, true for flags
+    pub options: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A JSON array of jobs like `{"input": "a.png", "output": "a.webp", "format": "webp", "options": {"resize": "800x"}}`
+/// where only the input is required, '-' reads it from stdin
+pub fn read_jobs(path: &str) -> ImcoResult<Vec<Job>> {
+    let mut raw = vec![];
+    if path == STDIO {
+        io_error_convert(std::io::stdin().read_to_end(&mut raw), path, true)?;
+    } else {
+        raw = io_error_convert(std::fs::read(path), path, true)?;
+    }
+    let invalid = |hint: String| ImcoError::JobsFile(path.to_string(), hint);
+    let jobs: serde_json::Value = serde_json::from_slice(&raw).map_err(|e| invalid(e.to_string()))?;
+    let Some(jobs) = jobs.as_array() else { return Err(invalid("expected an array of jobs".to_string())) };
+    jobs.iter().enumerate().map(|(index, job)| parse_job(job).map_err(|hint| invalid(format!("job {}: {hint}", index + 1)))).collect()
+}
+
+/// One job of a jobs file, see [`read_jobs`]
+pub fn parse_job(job: &serde_json::Value) -> Result<Job, String> {
+    let Some(job) = job.as_object() else { return Err("expected an object".to_string()) };
+    if let Some(key) = job.keys().find(|key| !["input", "output", "format", "options"].contains(&key.as_str())) {
+        return Err(format!("unknown key '{key}'"))
+    }
+    let text = |key: &str| match job.get(key) {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(format!("'{key}' must be a string")),
+    };
+    let input = match job.get("input") {
+        Some(serde_json::Value::Array(inputs)) if !inputs.is_empty() => inputs.iter()
+            .map(|input| input.as_str().ok_or("'input' must be a string or an array of strings"))
+            .collect::<Result<Vec<_>, _>>()?.join(","),
+        Some(serde_json::Value::Array(_)) | None => return Err("'input' is missing".to_string()),
+        _ => text("input")?.ok_or("'input' is missing")?,
+    };
+    let options = match job.get("options") {
+        None | Some(serde_json::Value::Null) => serde_json::Map::new(),
+        Some(serde_json::Value::Object(options)) => options.clone(),
+        Some(_) => return Err("'options' must be an object".to_string()),
+    };
+    Ok(Job { input, output: text("output")?, format: text("format")?, options })
+}
+
+/// Paths listed in the file (or stdin for [`STDIO`]), one per line or separated by NUL bytes if nul_delimited,
+/// e.g. from `find -print0`. Empty entries are skipped
+pub fn read_input_list(path: &Path, nul_delimited: bool) -> ImcoResult<Vec<PathBuf>> {
+    let mut raw = vec![];
+    if path.as_os_str() == STDIO {
+        io_error_convert(std::io::stdin().read_to_end(&mut raw), path, true)?;
+    } else {
+        raw = io_error_convert(std::fs::read(path), path, true)?;
+    }
+    let delimiter = if nul_delimited { b'\0' } else { b'\n' };
+    raw.split(|b| *b == delimiter)
+        .map(|entry| if nul_delimited { entry } else { entry.strip_suffix(b"\r").unwrap_or(entry) })
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| list_entry(entry, path))
+        .collect()
+}
+
+// Names are bytes on unix, so entries are taken as they are there and have to be UTF-8 elsewhere
+#[cfg(unix)]
+fn list_entry(entry: &[u8], _list: &Path) -> ImcoResult<PathBuf> {
+    Ok(<OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(entry).into())
+}
+
+#[cfg(not(unix))]
+fn list_entry(entry: &[u8], list: &Path) -> ImcoResult<PathBuf> {
+    std::str::from_utf8(entry).map(PathBuf::from).map_err(|_| ImcoError::FailedFileRead("Not valid UTF-8".to_string(), list.display().to_string()))
+}
+
+// Collects the files below dir together with their directory relative to root,
+// only taking files with an image extension unless any_file
+// Visited holds the directories entered so far, so links back up don't loop forever
+fn walk_directory(root: &Path, dir: &Path, any_file: bool, symlinks: Symlinks, visited: &mut HashSet<PathBuf>, files: &mut Vec<(PathBuf, PathBuf)>) -> ImcoResult<()> {
+    if !visited.insert(io_error_convert(std::fs::canonicalize(dir), dir, true)?) { return Ok(()) }
+    let mut paths: Vec<_> = io_error_convert(std::fs::read_dir(dir), dir, true)?
+        .map(|entry| io_error_convert(entry, dir, true).map(|e| e.path()))
+        .collect::<ImcoResult<_>>()?;
+    paths.sort();
+    for path in paths {
+        if path.is_symlink() && symlinks == Symlinks::Skip { continue }
+        if path.is_dir() {
+            walk_directory(root, &path, any_file, symlinks, visited, files)?;
+        } else if any_file || is_image_path(&path) {
+            files.push((path.clone(), dir.strip_prefix(root).unwrap().to_path_buf()))
+        }
+    }
+    Ok(())
+}
+
+/// Whether the extension names an image imco can read
+pub fn is_image_path(path: &Path) -> bool {
+    mk_format_fp(path).is_ok_and(|fmt| fmt != Format::Raw) || is_svg_path(path) || is_heif_path(path) || is_raw_path(path)
+}
+
+const ARCHIVE_EXTENSIONS: [&str; 4] = [".zip", ".tar", ".tar.gz", ".tgz"];
+
+/// Whether the path names a zip or (gzipped) tar archive
+pub fn is_archive_path(path: &Path) -> bool {
+    let path = path.to_string_lossy().to_lowercase();
+    ARCHIVE_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+}
+
+/// The archive path without its extension, where the images inside it are converted to by default
+pub fn archive_stem(path: &Path) -> PathBuf {
+    let lower = path.to_string_lossy().to_lowercase();
+    let mut stem = path.to_path_buf();
+    if let Some(ext) = ARCHIVE_EXTENSIONS.iter().rev().find(|ext| lower.ends_with(*ext)) {
+        // One extension per dot, like .tar.gz
+        for _ in 0..ext.matches('.').count() { stem.set_extension(""); }
+    }
+    stem
+}
+
+/// Images extracted from zip and tar archives or downloaded, kept in a temporary directory that is removed on drop
+pub struct TemporaryInputs {
+    dir: std::path::PathBuf,
+    count: usize,
+}
+
+impl Default for TemporaryInputs {
+    fn default() -> Self {
+        TemporaryInputs { dir: std::env::temp_dir().join(format!("imco-{}", std::process::id())), count: 0 }
+    }
+}
+
+impl TemporaryInputs {
+    /// Extracts the images inside the archive (or all files if any_file), paired with their
+    /// directory inside the archive
+    pub fn extract(&mut self, path: &Path, any_file: bool) -> ImcoResult<Vec<(PathBuf, PathBuf)>> {
+        // Named after the archive, so reported inputs show where they came from
+        let root = self.dir.join(self.count.to_string()).join(path.file_name().unwrap_or_default());
+        self.count += 1;
+        let archive_err = |e: &dyn std::error::Error| ImcoError::ArchiveRead(e.to_string(), path.display().to_string());
+        let file = io_error_convert(File::open(path), path, true)?;
+        let mut files = vec![];
+        let mut unpack = |name: &std::path::Path, entry: &mut dyn Read| -> ImcoResult<()> {
+            // Only plain components, so entries can't escape the directory
+            let name: PathBuf = name.components()
+                .filter(|c| matches!(c, std::path::Component::Normal(_)))
+                .collect();
+            if name.as_os_str().is_empty() || !(any_file || is_image_path(&name)) { return Ok(()) }
+            let target = root.join(&name);
+            io_error_convert(std::fs::create_dir_all(target.parent().unwrap()), &target, false)?;
+            let mut out = io_error_convert(File::create(&target), &target, false)?;
+            io_error_convert(std::io::copy(entry, &mut out), path, true)?;
+            files.push((target, name.parent().unwrap_or(Path::new("")).to_path_buf()));
+            Ok(())
+        };
+        if path.to_string_lossy().to_lowercase().ends_with(".zip") {
+            let mut zip = zip::ZipArchive::new(file).map_err(|e| archive_err(&e))?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).map_err(|e| archive_err(&e))?;
+                if entry.is_dir() { continue }
+                let Some(name) = entry.enclosed_name() else { continue };
+                unpack(&name, &mut entry)?;
+            }
+        } else {
+            let reader: Box<dyn Read> = if path.to_string_lossy().to_lowercase().ends_with(".tar") { Box::new(file) } else { Box::new(flate2::read::GzDecoder::new(file)) };
+            let mut tar = tar::Archive::new(reader);
+            for entry in tar.entries().map_err(|e| archive_err(&e))? {
+                let mut entry = entry.map_err(|e| archive_err(&e))?;
+                if !entry.header().entry_type().is_file() { continue }
+                let name = entry.path().map_err(|e| archive_err(&e))?.into_owned();
+                unpack(&name, &mut entry)?;
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+impl TemporaryInputs {
+    /// Downloads the image at the url, reading at most max_size bytes and giving up after timeout.
+    /// The file is named like the last segment of the url, with an extension after the served type if it has none
+    pub fn download(&mut self, url: &str, max_size: u64, timeout: std::time::Duration) -> ImcoResult<PathBuf> {
+        let dir = self.dir.join(self.count.to_string());
+        self.count += 1;
+        let (data, content_type) = fetch(url, max_size, timeout)?;
+        let name = url.split(['?', '#']).next().unwrap_or_default()
+            .rsplit('/').next().filter(|name| !name.is_empty() && !name.contains(':'))
+            .unwrap_or("download");
+        let served = content_type.as_deref().and_then(|t| t.strip_prefix("image/"))
+            .map(|t| t.split(['+', ';']).next().unwrap().trim())
+            .and_then(|t| if t == "svg" { Some("svg") } else { mk_format(t).ok().map(Format::extension) });
+        let name = match served {
+            Some(ext) if !is_image_path(Path::new(name)) => format!("{name}.{ext}"),
+            _ => name.to_string()
+        };
+        let target = dir.join(name);
+        io_error_convert(std::fs::create_dir_all(&dir), &target, false)?;
+        io_error_convert(std::fs::write(&target, data), &target, false)?;
+        Ok(target)
+    }
+}
+
+/// Whether the input is an http(s) url to download
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("https://") || path.starts_with("http://"))
+}
+
+// The body and content type of the url
+#[cfg(feature = "remote")]
+fn fetch(url: &str, max_size: u64, timeout: std::time::Duration) -> ImcoResult<(Vec<u8>, Option<String>)> {
+    let download_err = |e: ureq::Error| ImcoError::Download(url.to_string(), match e {
+        ureq::Error::BodyExceedsLimit(limit) => format!("it is larger than {limit} bytes"),
+        e => e.to_string()
+    });
+    let agent: ureq::Agent = ureq::Agent::config_builder().timeout_global(Some(timeout)).build().into();
+    let mut response = agent.get(url).call().map_err(download_err)?;
+    let content_type = response.headers().get("content-type").and_then(|t| t.to_str().ok()).map(str::to_string);
+    let data = response.body_mut().with_config().limit(max_size).read_to_vec().map_err(download_err)?;
+    Ok((data, content_type))
+}
+
+#[cfg(not(feature = "remote"))]
+fn fetch(url: &str, _max_size: u64, _timeout: std::time::Duration) -> ImcoResult<(Vec<u8>, Option<String>)> {
+    Err(ImcoError::Download(url.to_string(), "imco was built without the remote feature".to_string()))
+}
+
+impl Drop for TemporaryInputs {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Replaces directories among the inputs by the images below them, each paired with its directory relative
+/// to the input. Files that aren't images are only taken if any_file
+pub fn expand_directories(inputs: Vec<PathBuf>, any_file: bool, symlinks: Symlinks) -> ImcoResult<Vec<(PathBuf, PathBuf)>> {
+    let mut files = vec![];
+    let mut visited = HashSet::new();
+    for input in inputs {
+        if input.is_dir() {
+            walk_directory(&input, &input, any_file, symlinks, &mut visited, &mut files)?;
+        } else {
+            files.push((input, PathBuf::new()))
+        }
+    }
+    Ok(files)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_aliases() {
+        for name in ["jpeg", "jpg", "jfif"] { assert_eq!(mk_format(name).unwrap(), Format::Image(ImageFormat::Jpeg)) }
+        for name in ["tif", "tiff"] { assert_eq!(mk_format(name).unwrap(), Format::Image(ImageFormat::Tiff)) }
+        for name in ["ppm", "pgm", "pbm", "pam"] { assert_eq!(mk_format(name).unwrap(), Format::Image(ImageFormat::Pnm)) }
+        assert_eq!(mk_format("apng").unwrap(), Format::Image(ImageFormat::Png));
+        assert_eq!(mk_format("jpegxl").unwrap(), Format::Jxl);
+    }
+
+    #[test]
+    fn format_mixed_case() {
+        assert_eq!(mk_format("JPEG").unwrap(), Format::Image(ImageFormat::Jpeg));
+        assert_eq!(mk_format("Jpg").unwrap(), Format::Image(ImageFormat::Jpeg));
+        assert_eq!(mk_format("TiFf").unwrap(), Format::Image(ImageFormat::Tiff));
+        assert_eq!(mk_format("WebP").unwrap(), Format::Image(ImageFormat::WebP));
+        assert_eq!(mk_format_fp("photo.PNG").unwrap(), Format::Image(ImageFormat::Png));
+    }
+
+    #[test]
+    fn format_unknown() {
+        assert!(matches!(mk_format("jpgg"), Err(ImcoError::InvalidFormat(f)) if f == "jpgg"));
+        assert!(matches!(mk_format_fp("noext"), Err(ImcoError::InvalidFormat(_))));
+    }
+
+    #[cfg(feature = "textures")]
+    #[test]
+    fn bc7_dds_round_trip() {
+        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(6, 5, |x, y| image::Rgba([x as u8 * 40, y as u8 * 50, 128, 255])));
+        let opts = Options { texture_compression: Some(TextureCompression::Bc7), ..Options::default() };
+        let mut dds = vec![];
+        encode_texture(&image, &mut dds, Container::Dds, &opts).unwrap();
+        assert!(dds.starts_with(b"DDS "));
+        assert_eq!(&dds[84..88], b"DX10");
+        assert_eq!(u32::from_le_bytes(dds[128..132].try_into().unwrap()), 98);
+        let (decoded, _, format) = decode_data(dds, Path::new("a.dds"), None, &Rasterize::default(), &DecodeLimits::default()).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (6, 5));
+        assert_eq!(format, Some(Format::Image(ImageFormat::Dds)));
+    }
+}
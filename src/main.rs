@@ -1,305 +1,3804 @@
-use std::fmt;
-use std::fs::File;
-use std::io::{BufReader, ErrorKind};
+use std::io::IsTerminal;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use clap::{Arg, ArgMatches, ColorChoice, ValueHint};
 use clap::parser::ValuesRef;
-use image::{ImageError, ImageFormat, ImageReader};
-use image::error::{UnsupportedError, UnsupportedErrorKind};
-use glob::glob;
-use image::imageops::FilterType;
-
-pub const NAME: &str = env!("CARGO_PKG_NAME");
-pub const VERSION: &str = env!("CARGO_PKG_VERSION");
-pub const DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
-
-#[derive(Debug)]
-enum ImcoError {
-    // IO Errors; Reason, Path
-    FailedFileRead(String, String),
-    FailedFileWrite(String, String),
-    InvalidBatching,
-    // Format
-    InvalidFormat(String),
-    NoDestFormat,
-    // file path, [hint]
-    Decoding(String, String),
-    Encoding(String, String),
-    Unsupported(String, String),
-    InternalConversionError(String),
-    ResourceLimitReached(String),
-    // Error, Pattern
-    BatchPattern(String, String),
-    BatchReadEntry(String),
-    // "Number"
-    NotANumber
-}
-
-impl fmt::Display for ImcoError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ImcoError::FailedFileRead(cause, path) => {write!(f, "Failed reading '{path}' => {cause}")}
-            ImcoError::FailedFileWrite(cause, path) => {write!(f, "Failed writing '{path}' => {cause}")}
-            ImcoError::InvalidFormat(fmt) => {write!(f, "Unknown format {fmt}, use --help for a list")}
-            ImcoError::InvalidBatching => {write!(f, "Batching is only allowed when specifying an output format (using --output-format)")}
-            ImcoError::NoDestFormat => {write!(f, "No output format provided (use --output-format)")}
-            ImcoError::Decoding(path, hint) => {write!(f, "Error during decoding of '{path}' => {hint}")}
-            ImcoError::Encoding(path, hint) => {write!(f, "Error during encoding of '{path}' => {hint}")}
-            ImcoError::Unsupported(path, hint) => {write!(f, "{hint} during conversion of '{path}'")}
-            ImcoError::InternalConversionError(path) => {write!(f, "Internal error during conversion of '{path}'")}
-            ImcoError::ResourceLimitReached(path) => {write!(f, "Exceeded resource limitation during conversion of '{path}'")},
-            ImcoError::BatchPattern(err, pat) => { write!(f, "Failed to collect files using glob, '{pat}' => {err}") }
-            ImcoError::BatchReadEntry(err) => { write!(f, "Failed to read directory entry using glob => {err}") },
-            ImcoError::NotANumber => write!(f, "Resolution contains a string which is not an unsigned number"),
-        }
-    }
-}
-
-type ImcoResult<T> = Result<T, ImcoError>;
-type ImReader = ImageReader<BufReader<File>>;
-
-fn io_error_convert<T>(res: Result<T, std::io::Error>, file_path: &str, is_read: bool) -> Result<T, ImcoError> {
-    res.map_err(|x| {
-        let reason = match x.kind() {
-            ErrorKind::NotFound => {"Not found"}
-            ErrorKind::PermissionDenied => {"Permission denied"}
-            ErrorKind::AlreadyExists => {"Already exists"}
-            ErrorKind::NotADirectory => {"Is not a directory"}
-            ErrorKind::IsADirectory => {"Is a directory"}
-            ErrorKind::StorageFull => {"Storage is full"}
-            ErrorKind::FileTooLarge => {"File is too large"}
-            _ => {"Unknown (unhandled)"}
-        }.to_string();
-        if is_read {
-            ImcoError::FailedFileRead(reason, file_path.to_string())
+use notify::{EventKind, RecursiveMode, Watcher};
+use notify::event::ModifyKind;
+use imco::*;
+#[cfg(feature = "plugins")]
+use imco::plugin::load_plugin;
+
+#[cfg(feature = "tui")]
+mod tui;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+fn use_color(choice: &str) -> bool {
+    match choice {
+        "always" => true,
+        "never" => false,
+        // auto: NO_COLOR (when set and non-empty) wins, otherwise color only on a terminal
+        _ => std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stdout().is_terminal()
+    }
+}
+
+fn paint(s: String, code: &str, color: bool) -> String {
+    if color { format!("{code}{s}{RESET}") } else { s }
+}
+
+// --escape-names, set once the arguments are parsed
+static ESCAPE_NAMES: AtomicBool = AtomicBool::new(false);
+
+fn escape_char(c: char) -> String {
+    match c {
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '\r' => "\\r".to_string(),
+        c if (c as u32) < 0x80 => format!("\\x{:02x}", c as u32),
+        c => format!("\\u{:04x}", c as u32),
+    }
+}
+
+// A name as text reports show it. With --escape-names, names holding anything but letters, digits and ._-/+,:@%=
+// are quoted the way a POSIX shell reads them back, like ls does, control characters and bytes that aren't UTF-8
+// written as $'\n' and $'\xff'. Without it, those bytes are shown as replacement characters
+fn shown(name: &(impl AsRef<OsStr> + ?Sized)) -> std::borrow::Cow<'_, str> {
+    let name = name.as_ref();
+    let plain = name.to_str().is_some_and(|name| !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || "._-/+,:@%=".contains(c)));
+    if !ESCAPE_NAMES.load(Ordering::Relaxed) || plain { return name.to_string_lossy() }
+    let mut quoted = String::new();
+    let mut open = false;
+    for chunk in name.as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c.is_control() {
+                if open { quoted.push('\'') }
+                open = false;
+                quoted.push_str(&format!("$'{}'", escape_char(c)));
+            } else {
+                if !open { quoted.push('\'') }
+                open = true;
+                if c == '\'' { quoted.push_str("'\\''") } else { quoted.push(c) }
+            }
+        }
+        for byte in chunk.invalid() {
+            if open { quoted.push('\'') }
+            open = false;
+            quoted.push_str(&format!("$'\\x{byte:02x}'"));
+        }
+    }
+    if open { quoted.push('\'') }
+    if quoted.is_empty() { quoted.push_str("''") }
+    quoted.into()
+}
+
+// A message with --escape-names, its control characters escaped so the names within can't garble the terminal or log
+fn shown_message(message: impl std::fmt::Display) -> String {
+    let message = message.to_string();
+    if !ESCAPE_NAMES.load(Ordering::Relaxed) { return message }
+    message.chars().map(|c| if c.is_control() { escape_char(c) } else { c.to_string() }).collect()
+}
+
+// How results are printed, every line of the command line goes through it
+#[derive(Clone, Copy)]
+struct Report {
+    // JSON lines instead of text
+    json: bool,
+    color: bool,
+    // 0 for --quiet, 1 by default and one more for every -V
+    verbosity: u8,
+}
+
+impl Report {
+    // Results on stdout, JSON lines are kept with --quiet
+    fn out(&self, line: impl std::fmt::Display) {
+        if self.json || self.verbosity > 0 { println!("{line}") }
+    }
+
+    // Progress on stderr, keeping stdout clean for results and image data
+    fn status(&self, line: impl std::fmt::Display) {
+        if self.verbosity > 0 { eprintln!("{line}") }
+    }
+
+    // Details on stderr, shown once -V is given more than level times
+    fn verbose(&self, level: u8, line: impl std::fmt::Display) {
+        if self.verbosity > level { eprintln!("{line}") }
+    }
+
+    // Errors go to stderr, unless they are part of the JSON lines on stdout
+    fn error(&self, e: &ImcoError, input: Option<&Path>) {
+        log_event("error", input, e.to_string(), serde_json::json!({}));
+        match input {
+            _ if !self.json => eprintln!("{}", paint(shown_message(e), RED, self.color)),
+            Some(input) => println!("{}", serde_json::json!({ "input": input.to_string_lossy(), "error": e.to_string() })),
+            None => println!("{}", serde_json::json!({ "error": e.to_string() })),
+        }
+    }
+}
+
+// The --log-file, recording events regardless of the verbosity
+struct Log {
+    file: std::sync::Mutex<std::fs::File>,
+    json: bool,
+}
+
+static LOG: std::sync::OnceLock<Log> = std::sync::OnceLock::new();
+
+fn open_log(path: &str, json: bool) -> ImcoResult<()> {
+    let file = std::fs::File::options().create(true).append(true).open(path).map_err(|e| ImcoError::FailedFileWrite(e.to_string(), path.to_string()))?;
+    let _ = LOG.set(Log { file: std::sync::Mutex::new(file), json });
+    Ok(())
+}
+
+// One line per event, details are only kept by the json format
+fn log_event(event: &str, input: Option<&Path>, message: impl std::fmt::Display, details: serde_json::Value) {
+    use std::io::Write;
+    let Some(log) = LOG.get() else { return };
+    let time = utc_timestamp();
+    let line = if log.json {
+        let mut entry = serde_json::json!({ "time": time, "event": event, "input": input.map(Path::to_string_lossy), "message": message.to_string() });
+        if let (Some(entry), serde_json::Value::Object(details)) = (entry.as_object_mut(), details) { entry.extend(details) }
+        entry.to_string()
+    } else {
+        match input {
+            Some(input) => format!("{time} {:<9} {}: {}", event.to_uppercase(), shown(input), shown_message(message)),
+            None => format!("{time} {:<9} {}", event.to_uppercase(), shown_message(message)),
+        }
+    };
+    // Logging must not fail the conversion
+    let _ = writeln!(log.file.lock().unwrap(), "{line}");
+}
+
+// How long files have to stay unchanged before --watch converts them
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(500);
+
+fn list_formats() {
+    for (_, names) in FORMAT_NAMES {
+        println!("{}", names.join(" / "))
+    }
+}
+
+fn formats_command(report: Report) {
+    let formats = format_capabilities();
+    let width = formats.iter().map(|format| format.names.join(" / ").len()).max().unwrap_or(0);
+    for format in formats {
+        if report.json {
+            report.out(format.json());
+            continue
+        }
+        let mut abilities: Vec<String> = [(format.read, "read"), (format.write, "write"), (format.alpha, "alpha"), (format.lossy, "lossy"), (format.pages, "pages")]
+            .into_iter().filter(|(has, _)| *has).map(|(_, ability)| ability.to_string()).collect();
+        match (format.read_animation, format.write_animation) {
+            (true, true) => abilities.push("animation".to_string()),
+            (true, false) => abilities.push("reads animation".to_string()),
+            _ => {}
+        }
+        if !format.depths.is_empty() {
+            abilities.push(format!("{} bit", format.depths.iter().map(u8::to_string).collect::<Vec<_>>().join("/")));
+        }
+        let name = format.names.join(" / ");
+        let extensions = format.extensions.iter().map(|extension| format!(".{extension}")).collect::<Vec<_>>().join(" ");
+        if !format.read && !format.write {
+            report.out(paint(format!("{name:<width$} not built in ({extensions})"), YELLOW, report.color));
         } else {
-            ImcoError::FailedFileWrite(reason, file_path.to_string())
+            report.out(format!("{name:<width$} {} ({extensions})", abilities.join(", ")));
         }
-    })
+    }
+}
+
+// Accepts any format name, mk_format reports unknown ones later. Only offers the known names to shell
+// completions, the help lists them with --list-formats
+#[derive(Clone)]
+struct FormatNameParser;
+
+impl clap::builder::TypedValueParser for FormatNameParser {
+    type Value = String;
+
+    fn parse_ref(&self, cmd: &clap::Command, arg: Option<&Arg>, value: &std::ffi::OsStr) -> Result<String, clap::Error> {
+        clap::builder::StringValueParser::new().parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = clap::builder::PossibleValue> + '_>> {
+        Some(Box::new(FORMAT_NAMES.iter().flat_map(|(_, names)| names.iter()).map(clap::builder::PossibleValue::new)))
+    }
 }
 
-fn imread(path: &str) -> ImcoResult<ImReader> {
-    io_error_convert::<ImReader>(ImageReader::open(path), path, true)
+// Exit codes, clap exits with EXIT_USAGE on invalid arguments too
+const EXIT_FAILED: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_CONVERSION: i32 = 4;
+// What shells report for programs ended by Ctrl-C
+const EXIT_INTERRUPTED: i32 = 130;
+
+// Set by the first Ctrl-C, shared by the options of every conversion
+fn interruption() -> Arc<AtomicBool> {
+    static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+    INTERRUPTED.get_or_init(Arc::default).clone()
 }
 
-fn mk_format(f: &String) -> ImcoResult<ImageFormat> {
-    ImageFormat::from_extension(f).ok_or(ImcoError::InvalidFormat(f.to_owned()))
+// The first Ctrl-C lets the files being converted finish, a second one discards them and exits
+fn catch_interrupts(report: Report) {
+    // Only the first handler of the process is installed, which is the same anyway
+    let _ = ctrlc::set_handler(move || {
+        if interruption().swap(true, Ordering::Relaxed) {
+            discard_partial_outputs();
+            std::process::exit(EXIT_INTERRUPTED)
+        }
+        report.status("Finishing the files being converted, press Ctrl-C again to discard them");
+    });
 }
 
-fn mk_format_fp(f: &String) -> ImcoResult<ImageFormat> {
-    ImageFormat::from_extension(std::path::Path::new(f).extension().ok_or(ImcoError::InvalidFormat(f.to_owned()))?).ok_or(ImcoError::InvalidFormat(f.to_owned()))
+fn exit_code(e: &ImcoError) -> i32 {
+    match e {
+        ImcoError::FailedFileRead(..) | ImcoError::FailedFileWrite(..) | ImcoError::InputIsDirectory(_) | ImcoError::BatchReadEntry(_)
+        | ImcoError::BackupExists(_) | ImcoError::OutputExists(_) | ImcoError::Archive(..) | ImcoError::ArchiveRead(..) | ImcoError::Download(..) | ImcoError::Watch(_) | ImcoError::Clipboard(_) | ImcoError::Serve(..) | ImcoError::Tui(_) | ImcoError::NoSpace(..) | ImcoError::Session(..) => EXIT_IO,
+        ImcoError::InvalidBatching | ImcoError::InvalidFormat(_) | ImcoError::NoDestFormat | ImcoError::BatchPattern(..)
+        | ImcoError::InvalidResolution(_) | ImcoError::InvalidCrop(_) | ImcoError::InvalidRawInput(_) | ImcoError::InvalidAspect(_) | ImcoError::InvalidPages(_) | ImcoError::InvalidColor(_) | ImcoError::InvalidUnsharp(_) | ImcoError::InvalidDenoise(_) | ImcoError::InvalidTint(_) | ImcoError::InvalidVignette(_) | ImcoError::InvalidBorder(_) | ImcoError::InvalidSize(_) | ImcoError::InvalidScale(_) | ImcoError::InvalidSetting(..) | ImcoError::InvalidExifEdit(..) | ImcoError::ExtractToStdout | ImcoError::FormatsToStdout | ImcoError::SeveralFormats(_) | ImcoError::NoCombinedOutput | ImcoError::EmptyMontage | ImcoError::EmptyConcat | ImcoError::EmptyStack | ImcoError::EmptySpriteSheet | ImcoError::NoChannels | ImcoError::TileName(_) | ImcoError::MissingTile(..)
+        | ImcoError::Config(..) | ImcoError::JobsFile(..) | ImcoError::InvalidLut(..) | ImcoError::InvalidFont(_) | ImcoError::UnknownPreset(_) | ImcoError::InvalidOperation(..) | ImcoError::Plugin(..) | ImcoError::ConfirmFromStdin | ImcoError::DuplicateOutput(..) => EXIT_USAGE,
+        ImcoError::Declined => EXIT_FAILED,
+        _ => EXIT_CONVERSION
+    }
 }
 
-fn mk_unsupported_str(u: UnsupportedError) -> String {
-    match u.kind() {
-        UnsupportedErrorKind::Color(c) => {
-            format!("Unsupported color ({:?})", c)
+// Mismatching extensions are only warned about when the content decided the input format
+fn print_outcome(outcome: &FileOutcome, detected: bool, report: Report) {
+    let res = match &outcome.result {
+        Ok(res) => res,
+        Err(e) => {
+            log_event("error", Some(&outcome.input), e, serde_json::json!({}));
+            if report.json { report.out(json_report(outcome)) }
+            return
         }
-        UnsupportedErrorKind::Format(f) => {
-            format!("Unsupported or not allowed image format ({})", f)
+    };
+    match res.skipped {
+        true => log_event("skipped", Some(&outcome.input), format!("{} exists", shown(&res.output)), serde_json::json!({ "output": res.output.to_string_lossy() })),
+        false => log_event("converted", Some(&outcome.input), format!("-> {} ({}, {} bytes)", shown(&res.output), res.output_format.extension(), res.output_bytes.map_or("?".to_string(), |b| b.to_string())),
+            serde_json::json!({ "output": res.output.to_string_lossy(), "output_format": res.output_format.extension(), "input_bytes": outcome.input_bytes, "output_bytes": res.output_bytes, "duration_ms": outcome.duration.as_micros() as f64 / 1000.0 })),
+    }
+    let line = if report.json {
+        json_report(outcome).to_string()
+    } else if res.skipped {
+        format!("{} -> {} (exists, skipped)", shown(&outcome.input), shown(&res.output))
+    } else if let Some(target) = &res.symlink {
+        paint(format!("{} -> {} (link to {})", shown(&outcome.input), shown(&res.output), shown(target)), GREEN, report.color)
+    } else if let Some(org_fmt) = res.input_format {
+        paint(format!("{} ({}) -> {} ({}{})", shown(&outcome.input), org_fmt.extension(), shown(&res.output), res.output_format.extension(), high_depth(res)), GREEN, report.color)
+    } else {
+        paint(format!("{} -> {} ({}{})", shown(&outcome.input), shown(&res.output), res.output_format.extension(), high_depth(res)), GREEN, report.color)
+    };
+    // Keep stdout clean when it carries image data
+    if res.output.as_os_str() == STDIO { report.status(line) } else { report.out(line) }
+    if let Some(found) = extension_mismatch(&outcome.input, res.input_format).filter(|_| detected) {
+        let warning = format!("{} holds a {} image, not what its extension says", shown(&outcome.input), found.extension());
+        log_event("warning", Some(&outcome.input), &warning, serde_json::json!({}));
+        if !report.json { report.status(paint(format!("  warning: {warning}"), YELLOW, report.color)) }
+    }
+    for downgrade in &res.downgrades {
+        let warning = format!("{} output: {downgrade}", res.output_format.extension());
+        log_event("warning", Some(&outcome.input), &warning, serde_json::json!({ "downgrade": downgrade.name() }));
+        if !report.json { report.status(paint(format!("  warning: {warning}"), YELLOW, report.color)) }
+    }
+    if res.ignored_pages > 0 {
+        let warning = format!("only the first page was converted, {} more were left out (see --pages)", res.ignored_pages);
+        log_event("warning", Some(&outcome.input), &warning, serde_json::json!({}));
+        if !report.json { report.status(paint(format!("  warning: {warning}"), YELLOW, report.color)) }
+    }
+    if !report.json {
+        if let Some(hash) = &res.placeholders.blurhash { report.out(format!("  blurhash: {hash}")) }
+        if let Some(hash) = &res.placeholders.thumbhash { report.out(format!("  thumbhash: {hash}")) }
+    }
+    let size = |bytes: Option<u64>| bytes.map_or("?".to_string(), |b| b.to_string());
+    report.verbose(1, format!("  {} ({} bytes) -> {} ({} bytes{}) in {:.1} ms",
+        res.input_format.map_or("?", |f| f.extension()), size(outcome.input_bytes),
+        res.output_format.extension(), size(res.output_bytes), res.depth.map_or(String::new(), |bits| format!(", {}", depth_name(bits))), outcome.duration.as_secs_f64() * 1000.0));
+}
+
+// The depth for the conversion line, which leaves out the usual 8 bits
+fn high_depth(res: &Conversion) -> String {
+    res.depth.filter(|bits| *bits > 8).map_or(String::new(), |bits| format!(", {}", depth_name(bits)))
+}
+
+// Prints the srcset attribute of every converted input, or one JSON object of all of them. Bypasses the
+// report, being what was asked for even with --quiet
+fn print_srcset(outcomes: &[FileOutcome], style: &str) {
+    let converted: Vec<(&PathBuf, &Vec<(PathBuf, u32)>)> = outcomes.iter()
+        .filter_map(|outcome| outcome.result.as_ref().ok().map(|res| (&outcome.input, &res.srcset)))
+        .collect();
+    if style == "json" {
+        let manifest: serde_json::Map<String, serde_json::Value> = converted.iter()
+            .map(|(input, srcset)| (input.to_string_lossy().into_owned(), srcset.iter().map(|(src, width)| serde_json::json!({ "src": src.to_string_lossy(), "width": width })).collect()))
+            .collect();
+        println!("{}", serde_json::Value::Object(manifest));
+        return
+    }
+    for (input, srcset) in &converted {
+        let candidates: Vec<String> = srcset.iter().map(|(src, width)| format!("{} {width}w", src.display())).collect();
+        let attribute = format!("srcset=\"{}\"", candidates.join(", "));
+        if converted.len() > 1 { println!("{}: {attribute}", shown(input)) } else { println!("{attribute}") }
+    }
+}
+
+// The transforms converting applies, in order and written like --ops
+fn describe_operations(opts: &Options) -> Vec<String> {
+    let mut described = vec![];
+    match opts.tonemap {
+        Some(Tonemap::Reinhard) => described.push("tonemap:reinhard".to_string()),
+        Some(Tonemap::Aces) => described.push("tonemap:aces".to_string()),
+        None => {}
+    }
+    if opts.exposure != 0.0 { described.push(format!("exposure:{}", opts.exposure)) }
+    let mut ops: Vec<Operation> = opts.ops.clone();
+    if opts.rotate != 0 { ops.push(Operation::Rotate(opts.rotate)) }
+    if let Some(rotation) = opts.rotate_degrees { ops.push(Operation::RotateDegrees(rotation)) }
+    if opts.flip_horizontal { ops.push(Operation::FlipHorizontal) }
+    if opts.flip_vertical { ops.push(Operation::FlipVertical) }
+    if let Some(key) = opts.chroma_key { ops.push(Operation::ChromaKey(key)) }
+    if let Some(trim) = opts.trim { ops.push(Operation::Trim(trim)) }
+    if let Some(crop) = opts.crop { ops.push(Operation::Crop(crop)) }
+    if let Some(aspect) = opts.aspect { ops.push(Operation::Aspect(aspect)) }
+    if let Some(denoise) = opts.denoise { ops.push(Operation::Denoise(denoise)) }
+    if let Some(resize) = opts.resize { ops.push(Operation::Resize(resize)) }
+    if let Some(factor) = opts.scale { ops.push(Operation::Scale(factor)) }
+    if let Some(crop) = opts.crop_to { ops.push(Operation::CropTo(crop)) }
+    if let Some(size) = opts.thumbnail { ops.push(Operation::Thumbnail(size)) }
+    described.extend(ops.iter().map(|op| op.to_string()));
+    if let Some(min) = opts.min_dimension { described.push(format!("min-dimension:{min}")) }
+    if let Some(max) = opts.max_dimension { described.push(format!("max-dimension:{max}")) }
+    if let Some(megapixels) = opts.max_megapixels { described.push(format!("max-megapixels:{megapixels}")) }
+    if let Some(sigma) = opts.blur { described.push(Operation::Blur(sigma).to_string()) }
+    if let Some(mask) = opts.unsharp { described.push(Operation::Unsharp(mask).to_string()) }
+    if let Some(levels) = opts.auto_levels { described.push(Operation::AutoLevels(levels).to_string()) }
+    if opts.brightness != 0 { described.push(Operation::Brightness(opts.brightness).to_string()) }
+    if opts.contrast != 0.0 { described.push(Operation::Contrast(opts.contrast).to_string()) }
+    if opts.gamma != 1.0 { described.push(Operation::Gamma(opts.gamma).to_string()) }
+    if opts.lut.is_some() { described.push("lut".to_string()) }
+    if opts.invert { described.push(Operation::Invert.to_string()) }
+    if opts.sepia { described.push(Operation::Sepia.to_string()) }
+    if let Some(tint) = opts.tint { described.push(Operation::Tint(tint).to_string()) }
+    if let Some(vignette) = opts.vignette { described.push(Operation::Vignette(vignette).to_string()) }
+    if let Some(luma) = opts.threshold { described.push(Operation::Threshold(luma).to_string()) }
+    if let Some(levels) = opts.posterize { described.push(Operation::Posterize(levels).to_string()) }
+    if opts.alpha_mask.is_some() { described.push("apply-alpha".to_string()) }
+    if let Some(pad) = opts.pad { described.push(Operation::Pad(pad).to_string()) }
+    if let Some(corners) = opts.corners { described.push(Operation::Corners(corners).to_string()) }
+    if let Some(border) = opts.border { described.push(Operation::Border(border).to_string()) }
+    if opts.watermark.is_some() { described.push("watermark".to_string()) }
+    if opts.caption.is_some() { described.push("caption".to_string()) }
+    if opts.extract_alpha { described.push("extract-alpha".to_string()) }
+    if let Some(color) = opts.colorspace { described.push(format!("colorspace:{color:?}")) }
+    if let Some(depth) = opts.depth { described.push(format!("depth:{depth}")) }
+    if let Some(quantize) = opts.quantize { described.push(format!("colors:{}", quantize.colors)) }
+    described
+}
+
+// Prints the outcomes in order, up to the first failure without keep_going, which is returned instead
+fn print_outcomes(outcomes: Vec<FileOutcome>, converter: &Converter, report: Report) -> ImcoResult<Vec<FileOutcome>> {
+    let operations = describe_operations(converter.options()).join("|");
+    let mut printed = vec![];
+    for outcome in outcomes {
+        if outcome.result.is_err() && !converter.options().keep_going { return Err(outcome.result.err().unwrap()) }
+        print_outcome(&outcome, converter.options().i_fmt.is_none(), report);
+        if outcome.result.as_ref().is_ok_and(|res| !res.skipped) && !operations.is_empty() {
+            report.verbose(1, format!("  operations: {operations}"));
         }
-        UnsupportedErrorKind::GenericFeature(gf) => {
-            gf
-        },
-        _ => "Other".to_string(),
+        printed.push(outcome);
     }
+    if let Some((linked, saved)) = converter.deduplicated().filter(|_| !report.json) {
+        report.out(format!("Deduplicated {linked} file(s), saved {saved} bytes"));
+    }
+    Ok(printed)
 }
 
-fn image_err_convert<T>(res: Result<T, ImageError>, img_path: String) -> Result<T, ImcoError> {
-    res.map_err(|e| {
-        match e {
-            ImageError::Decoding(de) => { ImcoError::Decoding(img_path, de.to_string()) }
-            ImageError::Encoding(ee) => { ImcoError::Encoding(img_path, ee.to_string()) }
-            ImageError::Parameter(_) => { ImcoError::InternalConversionError(img_path) }
-            ImageError::Limits(_) => { ImcoError::ResourceLimitReached(img_path) }
-            ImageError::Unsupported(u) => {ImcoError::Unsupported(img_path, mk_unsupported_str(u))}
-            ImageError::IoError(e) => { io_error_convert::<String>(Err(e), &*img_path, false).unwrap_err() }
+// Prints what identify finds for every path, returning whether all of them could be read
+fn info(paths: ValuesRef<PathBuf>, report: Report) -> bool {
+    let mut all_read = true;
+    for path in paths {
+        let info = match identify(path) {
+            Ok(info) => info,
+            Err(e) => {
+                all_read = false;
+                report.error(&e, Some(path));
+                continue
+            }
+        };
+        // Formats imco can't write are named after the extension
+        let format = info.format.map(|f| f.extension().to_string()).unwrap_or_else(|| {
+            path.extension().and_then(|e| e.to_str()).unwrap_or("unknown").to_lowercase()
+        });
+        if report.json {
+            report.out(serde_json::json!({
+                "input": path.to_string_lossy(),
+                "format": format,
+                "width": info.width,
+                "height": info.height,
+                "color_type": format!("{:?}", info.color),
+                "bit_depth": info.bit_depth(),
+                "frames": info.frames,
+                "file_bytes": info.file_size,
+                "exif": info.exif,
+                "icc": info.icc,
+            }));
+        } else {
+            let yes_no = |present: bool| if present { "yes" } else { "no" };
+            report.out(format!("{path}: {format}, {}x{}, {:?} ({} bit), {} frame(s), {} bytes, exif: {}, icc: {}",
+                info.width, info.height, info.color, info.bit_depth(), info.frames,
+                info.file_size.map(|s| s.to_string()).unwrap_or("?".to_string()), yes_no(info.exif), yes_no(info.icc), path = shown(path)));
         }
-    })
+    }
+    all_read
 }
 
-fn mk_filename(p: &String, fmt: ImageFormat) -> String {
-    std::path::Path::new(&p).file_stem()
-        .and_then(|t| {Some(format!("{}.{}", t.to_str().unwrap(), fmt.extensions_str()[0]))})
-        .or(Some(p.to_string() + fmt.extensions_str()[0]))
-        .unwrap()
+// Prints the figures and a histogram line per channel of every file, returning whether all of them could be read
+fn stats_command(paths: &[&PathBuf], histogram: Option<&PathBuf>, report: Report) -> bool {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let mut all_read = true;
+    for path in paths {
+        let result = stats(path).and_then(|stats| {
+            // Several histograms are told apart by the input's stem
+            if let Some(template) = histogram {
+                let stem = path.file_stem().unwrap_or_default();
+                let output = if has_placeholder(template, "{stem}") { fill_placeholder(template.as_os_str(), "{stem}", stem).into() }
+                    else if paths.len() > 1 {
+                        let mut name = template.file_stem().unwrap_or_default().to_os_string();
+                        name.push("_");
+                        name.push(stem);
+                        if let Some(ext) = template.extension() {
+                            name.push(".");
+                            name.push(ext);
+                        }
+                        template.with_file_name(name)
+                    } else { template.to_path_buf() };
+                write_histogram(&stats, &output)?;
+            }
+            Ok(stats)
+        });
+        let stats = match result {
+            Ok(stats) => stats,
+            Err(e) => {
+                all_read = false;
+                report.error(&e, Some(path));
+                continue
+            }
+        };
+        if report.json {
+            report.out(serde_json::json!({
+                "input": path.to_string_lossy(),
+                "width": stats.width,
+                "height": stats.height,
+                "mean_luma": stats.mean_luma,
+                "median_luma": stats.median_luma,
+                "shadows_clipped": stats.shadows_clipped,
+                "highlights_clipped": stats.highlights_clipped,
+                "histograms": stats.histograms.iter().map(|(name, bins)| (name.to_string(), serde_json::json!(bins.to_vec()))).collect::<serde_json::Map<_, _>>(),
+            }));
+            continue
+        }
+        report.out(format!("{path}: {}x{}, mean luma {:.1}, median luma {}, clipped shadows {:.2}%, highlights {:.2}%",
+            stats.width, stats.height, stats.mean_luma, stats.median_luma, stats.shadows_clipped, stats.highlights_clipped, path = shown(path)));
+        // 32 buckets of 8 values each, scaled to the fullest bucket of the channel
+        for (name, bins) in &stats.histograms {
+            let buckets: Vec<u64> = bins.chunks(8).map(|chunk| chunk.iter().sum()).collect();
+            let highest = buckets.iter().copied().max().unwrap_or(0).max(1);
+            let bars: String = buckets.iter().map(|count| BARS[(*count * 7).div_ceil(highest) as usize]).collect();
+            report.out(format!("  {name} {bars}"));
+        }
+    }
+    all_read
 }
 
-fn join_path(p: &String, fmt: ImageFormat, stem: &String) -> String {
-    std::path::Path::new(stem).join(mk_filename(p, fmt)).to_str().unwrap().to_string()
+// Reports groups of visually identical images and applies the action to them, returning whether all inputs could be read
+fn dedupe(patterns: ValuesRef<PathBuf>, kind: HashKind, max_distance: u32, action: DuplicateAction, report: Report) -> ImcoResult<bool> {
+    let paths: Vec<PathBuf> = expand_patterns_to_files(patterns, false)?.into_iter().filter(|p| is_image_path(p)).collect();
+    let (groups, failed) = find_duplicates(&paths, kind, max_distance);
+    for (path, e) in &failed {
+        report.error(e, Some(path));
+    }
+    for group in &groups {
+        apply_duplicate_action(group, action)?;
+        if report.json {
+            report.out(serde_json::json!({ "keep": group[0].to_string_lossy(), "duplicates": group[1..].iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>() }));
+            continue
+        }
+        let verb = match action {
+            DuplicateAction::Report => "duplicate",
+            DuplicateAction::Delete => "deleted",
+            DuplicateAction::Hardlink => "linked",
+        };
+        report.out(paint(shown(&group[0]).into_owned(), GREEN, report.color));
+        for duplicate in &group[1..] {
+            report.out(format!("  {} ({verb})", shown(duplicate)));
+        }
+    }
+    if !report.json {
+        report.out(format!("{} group(s), {} duplicate(s) among {} image(s)", groups.len(), groups.iter().map(|g| g.len() - 1).sum::<usize>(), paths.len()));
+    }
+    Ok(failed.is_empty())
 }
 
-fn individual_process(path: String, output: Option<String>, i_fmt: Option<ImageFormat>, o_fmt: Option<ImageFormat>, batch: bool, resolution: Option<(u32, u32)>) -> ImcoResult<(String, Option<ImageFormat>, ImageFormat)> {
-    if output.is_none() && o_fmt.is_none() { return Err(ImcoError::NoDestFormat) }
-    
-    let mut raw_image = imread(&*path)?;
-    let org_fmt = if i_fmt.is_some() {
-        raw_image.set_format(i_fmt.unwrap());
-        i_fmt
-    } else { raw_image.format() };
-    let mut image = image_err_convert(raw_image.decode(), path.clone())?;
-    if resolution.is_some() {
-        let resolution = resolution.unwrap();
-        image = image.resize_exact(resolution.0, resolution.1, FilterType::Nearest);
+// Prints how close the images are, returning whether they are at least as close as the threshold
+fn compare_command(a: &Path, b: &Path, metric: &str, threshold: Option<f64>, report: Report) -> ImcoResult<bool> {
+    let comparison = compare(a, b)?;
+    let value = if metric == "psnr" { comparison.psnr } else { comparison.ssim };
+    let pass = threshold.is_none_or(|threshold| value >= threshold);
+    if report.json {
+        // JSON has no infinity
+        report.out(serde_json::json!({
+            "psnr": comparison.psnr.is_finite().then_some(comparison.psnr),
+            "ssim": comparison.ssim,
+            "max_difference": comparison.max_difference,
+            "pass": pass,
+        }));
+    } else {
+        let line = format!("PSNR: {:.2} dB, SSIM: {:.4}, max difference: {}", comparison.psnr, comparison.ssim, comparison.max_difference);
+        report.out(if pass { line } else { paint(format!("{line} ({metric} below {})", threshold.unwrap()), RED, report.color) });
     }
-    
-    Ok(if o_fmt.is_some() {
-        let fmt = o_fmt.unwrap();
-        let output = if batch { join_path(&path, fmt, &output.unwrap()) } else { if output.is_some() { output.unwrap() } else { mk_filename(&path, fmt) } };
-        image_err_convert(image.save_with_format(&output, fmt), path)?;
-        (output, org_fmt, fmt)
+    Ok(pass)
+}
+
+fn diff_image_command(matches: &ArgMatches, report: Report) -> ImcoResult<()> {
+    let images: Vec<&PathBuf> = matches.get_many::<PathBuf>("input").unwrap().collect();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let mode = match matches.get_one::<String>("mode").unwrap().as_str() {
+        "blink" => DiffMode::Blink,
+        "heatmap" => DiffMode::Heatmap,
+        _ => DiffMode::SideBySide,
+    };
+    let delay = image::Delay::from_numer_denom_ms(*matches.get_one::<u32>("delay").unwrap(), 1);
+    let (comparison, bytes) = diff_image(images[0], images[1], output, mode, delay)?;
+    report.out(paint(format!("{} vs {} -> {output} ({bytes} bytes), PSNR: {:.2} dB, SSIM: {:.4}, max difference: {}",
+        shown(images[0]), shown(images[1]), comparison.psnr, comparison.ssim, comparison.max_difference, output = shown(output)), GREEN, report.color));
+    Ok(())
+}
+
+// The graphics a terminal understands, guessed from the environment as asking it takes a round trip
+fn detect_graphics() -> Graphics {
+    let var = |name: &str| std::env::var(name).unwrap_or_default();
+    let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+    if std::env::var_os("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || program == "ghostty" {
+        Graphics::Kitty
+    } else if matches!(program.as_str(), "iTerm.app" | "WezTerm") {
+        Graphics::Iterm
+    } else if term.contains("sixel") || term.starts_with("foot") || term == "mlterm" {
+        Graphics::Sixel
     } else {
-        if batch { return Err(ImcoError::InvalidBatching) }
-        let output = output.unwrap();
-        let aif = mk_format_fp(&output)?;
-        image_err_convert(image.save(&output), path)?;
-        (output, org_fmt, aif)
-    })
+        Graphics::Blocks
+    }
+}
+
+// Columns and rows a preview may take, from the shell's $COLUMNS and $LINES if exported
+fn terminal_size() -> (u32, u32) {
+    let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u32>().ok()).filter(|n| *n > 2);
+    (var("COLUMNS").unwrap_or(80), var("LINES").map_or(22, |lines| lines - 2))
 }
 
-fn process(couples: Vec<(&String, Option<&&String>)>, i_fmt_s: Option<&String>, o_fmt_s: Option<&String>,
-           batch: bool, resolution: Option<(u32, u32)>) -> ImcoResult<()> {
-    let i_fmt = if i_fmt_s.is_some() { Some(mk_format(i_fmt_s.unwrap())?) } else {None};
-    let o_fmt = if o_fmt_s.is_some() { Some(mk_format(o_fmt_s.unwrap())?) } else {None};
+// Prints each image under its path, returning whether all of them could be shown
+fn preview_files<S: AsRef<Path>>(paths: &[S], graphics: Graphics, (columns, rows): (u32, u32), report: Report) -> bool {
+    let mut all_shown = true;
+    for path in paths {
+        let path = path.as_ref();
+        match terminal_preview(path, graphics, columns, rows) {
+            Ok(preview) => {
+                report.out(shown(path));
+                // A closed pipe only ends the preview
+                if report.verbosity > 0 { let _ = std::io::Write::write_all(&mut std::io::stdout(), preview.as_bytes()); }
+            }
+            Err(e) => {
+                all_shown = false;
+                report.error(&e, Some(path));
+            }
+        }
+    }
+    all_shown
+}
 
-    for couple in couples {
-        let res = individual_process(couple.0.to_string(), couple.1.and_then(|t| { Some(t.to_string()) }), i_fmt, o_fmt, batch, resolution)?;
-        if res.1.is_some() {
-            println!("{} ({}) -> {} ({})", couple.0, res.1.unwrap().extensions_str()[0], res.0, res.2.extensions_str()[0])
-        } else {
-            println!("{} -> {} ({})", couple.0, res.0, res.2.extensions_str()[0])
+// Decodes every input, printing the broken ones and a summary. Returns whether all of them are intact
+fn verify_command(matches: &ArgMatches, report: Report) -> ImcoResult<bool> {
+    let inputs = matches.get_many::<PathBuf>("input").unwrap();
+    let paths = if matches.get_flag("batch") { expand_patterns_to_files(inputs, false)? } else { inputs.cloned().collect() };
+    let mut corrupt = 0;
+    for path in &paths {
+        let result = verify_image(path);
+        if report.json {
+            report.out(serde_json::json!({
+                "input": path.to_string_lossy(),
+                "ok": result.is_ok(),
+                "error": result.as_ref().err().map(|e| e.to_string()),
+            }));
+        } else if result.is_ok() {
+            report.verbose(1, format!("{}: ok", shown(path)));
+        }
+        if let Err(e) = result {
+            corrupt += 1;
+            if !report.json { report.error(&e, Some(path)) }
         }
     }
-    
+    if !report.json {
+        let summary = format!("{} file(s) checked, {corrupt} corrupt", paths.len());
+        report.out(paint(summary, if corrupt == 0 { GREEN } else { RED }, report.color));
+    }
+    Ok(corrupt == 0)
+}
+
+// Checks the files listed in every manifest, printing the changed and missing ones and a summary. Returns whether all of them match
+fn verify_checksums_command(manifests: ValuesRef<PathBuf>, report: Report) -> ImcoResult<bool> {
+    let (mut checked, mut failed) = (0, 0);
+    for manifest in manifests {
+        for check in verify_checksums(manifest)? {
+            checked += 1;
+            if report.json {
+                report.out(serde_json::json!({
+                    "path": check.path,
+                    "ok": check.result.is_ok(),
+                    "error": check.result.as_ref().err().map(|e| e.to_string()),
+                }));
+            } else if check.result.is_ok() {
+                report.verbose(1, format!("{}: ok", shown(&check.path)));
+            }
+            if let Err(e) = check.result {
+                failed += 1;
+                if !report.json { report.error(&e, None) }
+            }
+        }
+    }
+    if !report.json {
+        let summary = format!("{checked} file(s) checked, {failed} failed");
+        report.out(paint(summary, if failed == 0 { GREEN } else { RED }, report.color));
+    }
+    Ok(failed == 0)
+}
+
+// Optimizes every file and reports the savings, returning whether all of them could be optimized
+fn optimize_command(paths: ValuesRef<PathBuf>, dry_run: bool, report: Report) -> bool {
+    let (mut total_saved, mut optimized, mut all_done) = (0, 0, true);
+    for path in paths {
+        match optimize(path, dry_run) {
+            Ok(result) if report.json => report.out(serde_json::json!({
+                "input": path.to_string_lossy(),
+                "original_bytes": result.original_bytes,
+                "optimized_bytes": result.optimized_bytes,
+            })),
+            Ok(result) if result.saved() == 0 => report.out(format!("{path}: {} bytes, already optimal", result.original_bytes, path = shown(path))),
+            Ok(result) => {
+                total_saved += result.saved();
+                optimized += 1;
+                let percent = result.saved() as f64 * 100.0 / result.original_bytes as f64;
+                report.out(paint(format!("{path}: {} -> {} bytes (saved {}, {percent:.1}%)", result.original_bytes, result.optimized_bytes, result.saved(), path = shown(path)), GREEN, report.color));
+            }
+            Err(e) => {
+                all_done = false;
+                report.error(&e, Some(path));
+            }
+        }
+    }
+    if !report.json { report.out(format!("Saved {total_saved} bytes in total, {optimized} file(s) optimized")) }
+    all_done
+}
+
+fn json_report(outcome: &FileOutcome) -> serde_json::Value {
+    let mut report = serde_json::json!({
+        "input": outcome.input.to_string_lossy(),
+        "input_bytes": outcome.input_bytes,
+        "duration_ms": outcome.duration.as_micros() as f64 / 1000.0,
+    });
+    match &outcome.result {
+        Ok(res) => {
+            report["input_format"] = res.input_format.map(|f| f.extension()).into();
+            report["output"] = res.output.to_string_lossy().into();
+            report["output_format"] = res.output_format.extension().into();
+            report["output_bytes"] = res.output_bytes.into();
+            report["skipped"] = res.skipped.into();
+            report["flattened"] = res.flattened.into();
+            if res.ignored_pages > 0 { report["ignored_pages"] = res.ignored_pages.into() }
+            if !res.downgrades.is_empty() { report["downgrades"] = res.downgrades.iter().map(|d| d.name()).collect::<Vec<_>>().into() }
+            if let Some(depth) = res.depth { report["depth"] = depth.into() }
+            if let Some(target) = &res.symlink { report["symlink"] = target.to_string_lossy().into() }
+            if !res.srcset.is_empty() {
+                report["srcset"] = res.srcset.iter().map(|(src, width)| serde_json::json!({ "src": src.to_string_lossy(), "width": width })).collect();
+            }
+            if let Some(hash) = &res.placeholders.blurhash { report["blurhash"] = hash.clone().into() }
+            if let Some(hash) = &res.placeholders.thumbhash { report["thumbhash"] = hash.clone().into() }
+            report["timings"] = timings_json(&res.timings);
+        }
+        Err(e) => report["error"] = e.to_string().into()
+    }
+    report
+}
+
+fn timings_json(timings: &Timings) -> serde_json::Value {
+    let ms = |duration: std::time::Duration| duration.as_micros() as f64 / 1000.0;
+    serde_json::json!({
+        "decode_ms": ms(timings.decode),
+        "transform_ms": ms(timings.transform),
+        "encode_ms": ms(timings.encode),
+    })
+}
+
+// Totals and averages of the converted files' stages, and the files that took the longest
+fn print_timings(outcomes: &[FileOutcome], report: Report) {
+    const SLOWEST: usize = 5;
+    let mut converted: Vec<_> = outcomes.iter()
+        .filter_map(|o| o.result.as_ref().ok().filter(|res| !res.skipped).map(|res| (o, &res.timings)))
+        .collect();
+    let mut total = Timings::default();
+    for (_, timings) in &converted {
+        total.decode += timings.decode;
+        total.transform += timings.transform;
+        total.encode += timings.encode;
+    }
+    let count = converted.len().max(1) as u32;
+    let average = Timings { decode: total.decode / count, transform: total.transform / count, encode: total.encode / count };
+    let wall: std::time::Duration = converted.iter().map(|(o, _)| o.duration).sum();
+    converted.sort_by_key(|(outcome, _)| std::cmp::Reverse(outcome.duration));
+    let slowest = &converted[..converted.len().min(SLOWEST)];
+    if report.json {
+        report.out(serde_json::json!({
+            "timings": {
+                "files": converted.len(),
+                "total_ms": wall.as_micros() as f64 / 1000.0,
+                "total": timings_json(&total),
+                "average": timings_json(&average),
+                "slowest": slowest.iter().map(|(o, _)| serde_json::json!({
+                    "input": o.input.to_string_lossy(),
+                    "duration_ms": o.duration.as_micros() as f64 / 1000.0,
+                })).collect::<Vec<_>>(),
+            }
+        }));
+        return
+    }
+    let ms = |duration: std::time::Duration| format!("{:.1} ms", duration.as_secs_f64() * 1000.0);
+    let stages = |timings: &Timings| format!("decode {}, transform {}, encode {}", ms(timings.decode), ms(timings.transform), ms(timings.encode));
+    report.out(format!("Timings of {} file(s), {} in total", converted.len(), ms(wall)));
+    report.out(format!("  total:   {}", stages(&total)));
+    report.out(format!("  average: {}", stages(&average)));
+    for (outcome, timings) in slowest {
+        report.out(format!("  slow: {} {} ({})", shown(&outcome.input), ms(outcome.duration), stages(timings)));
+    }
+}
+
+// Counts of the run's outcomes and the bytes saved over the converted files whose sizes are known,
+// an input with several outputs counting once. Outputs of a dry run are only planned, and sizes are left out when no output was written
+fn print_summary(outcomes: &[FileOutcome], elapsed: std::time::Duration, dry_run: bool, report: Report) {
+    let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+    let skipped = outcomes.iter().filter(|o| o.result.as_ref().is_ok_and(|res| res.skipped)).count();
+    let converted = outcomes.len() - failed - skipped;
+    let sizes: Vec<_> = outcomes.iter()
+        .filter_map(|o| o.result.as_ref().ok().filter(|res| !res.skipped).and_then(|res| Some((&o.input, o.input_bytes?, res.output_bytes?))))
+        .collect();
+    let mut counted = HashSet::new();
+    let input_bytes: u64 = sizes.iter().filter(|(input, ..)| counted.insert(*input)).map(|(_, i, _)| i).sum();
+    let output_bytes: u64 = sizes.iter().map(|(.., o)| o).sum();
+    let saved = if input_bytes == 0 { 0.0 } else { (1.0 - output_bytes as f64 / input_bytes as f64) * 100.0 };
+    let done = if dry_run { "planned" } else { "converted" };
+    if report.json {
+        let known = |value: serde_json::Value| if sizes.is_empty() { serde_json::Value::Null } else { value };
+        report.out(serde_json::json!({
+            "summary": {
+                "files": outcomes.len(),
+                done: converted,
+                "skipped": skipped,
+                "failed": failed,
+                "input_bytes": known(input_bytes.into()),
+                "output_bytes": known(output_bytes.into()),
+                "saved_percent": known(saved.into()),
+                "elapsed_ms": elapsed.as_micros() as f64 / 1000.0,
+            }
+        }));
+        return
+    }
+    report.out(format!("{} file(s) in {:.2} s: {converted} {done}, {skipped} skipped, {failed} failed", outcomes.len(), elapsed.as_secs_f64()));
+    if !sizes.is_empty() {
+        report.out(format!("  {input_bytes} -> {output_bytes} bytes ({})", if saved >= 0.0 { format!("saved {saved:.1}%") } else { format!("grew {:.1}%", -saved) }));
+    }
+}
+
+fn montage_command(matches: &ArgMatches, report: Report) -> ImcoResult<()> {
+    let cell = matches.get_one::<String>("cell").unwrap();
+    let layout = Montage {
+        columns: matches.get_one::<u32>("columns").copied(),
+        cell: match parse_resolution(cell)? {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Err(ImcoError::InvalidResolution(cell.to_string()))
+        },
+        gap: *matches.get_one::<u32>("gap").unwrap(),
+        labels: matches.get_one::<String>("label").unwrap() == "filename",
+        background: parse_color(matches.get_one::<String>("background").unwrap())?,
+    };
+    let mut paths = expand_patterns_to_files(matches.get_many::<PathBuf>("input").unwrap(), false)?;
+    paths.retain(|p| is_image_path(p));
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let (bytes, failed) = montage(&paths, output, &layout, image::imageops::FilterType::Lanczos3)?;
+    for (path, e) in &failed {
+        report.error(e, Some(path));
+    }
+    report.out(paint(format!("{} image(s) -> {output} ({bytes} bytes)", paths.len() - failed.len(), output = shown(output)), GREEN, report.color));
+    if !failed.is_empty() { std::process::exit(EXIT_FAILED) }
+    Ok(())
+}
+
+fn concat_command(matches: &ArgMatches, report: Report) -> ImcoResult<()> {
+    let layout = Concat {
+        direction: match matches.get_one::<String>("direction").unwrap().as_str() {
+            "vertical" => Direction::Vertical,
+            _ => Direction::Horizontal,
+        },
+        align: match matches.get_one::<String>("align").unwrap().as_str() {
+            "start" => Align::Start,
+            "end" => Align::End,
+            _ => Align::Center,
+        },
+        gap: *matches.get_one::<u32>("gap").unwrap(),
+        background: matches.get_one::<String>("background").map(|c| parse_color(c)).transpose()?,
+    };
+    let mut paths = expand_patterns_to_files(matches.get_many::<PathBuf>("input").unwrap(), false)?;
+    paths.retain(|p| is_image_path(p));
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let (strip, failed) = concat(&paths, output, &layout)?;
+    for (path, e) in &failed {
+        report.error(e, Some(path));
+    }
+    report.out(paint(format!("{} image(s) -> {output} ({}x{}, {} bytes)", paths.len() - failed.len(), strip.width, strip.height, strip.output_bytes, output = shown(output)), GREEN, report.color));
+    if !failed.is_empty() { std::process::exit(EXIT_FAILED) }
+    Ok(())
+}
+
+fn stack_command(matches: &ArgMatches, report: Report) -> ImcoResult<()> {
+    let method = if matches.get_one::<String>("method").unwrap() == "median" { StackMethod::Median } else { StackMethod::Mean };
+    let mut paths = expand_patterns_to_files(matches.get_many::<PathBuf>("input").unwrap(), false)?;
+    paths.retain(|p| is_image_path(p));
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let (stacked, failed) = stack(&paths, output, method)?;
+    for (path, e) in &failed {
+        report.error(e, Some(path));
+    }
+    report.out(paint(format!("{} image(s) -> {output} ({}x{}, {} bytes)", stacked.images, stacked.width, stacked.height, stacked.output_bytes, output = shown(output)), GREEN, report.color));
+    if !failed.is_empty() { std::process::exit(EXIT_FAILED) }
+    Ok(())
+}
+
+fn spritesheet_command(matches: &ArgMatches, report: Report) -> ImcoResult<()> {
+    let mut paths = expand_patterns_to_files(matches.get_many::<PathBuf>("input").unwrap(), false)?;
+    paths.retain(|p| is_image_path(p));
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let atlas = matches.get_one::<PathBuf>("atlas");
+    let (sheet, failed) = spritesheet(&paths, output, atlas.map(PathBuf::as_path), *matches.get_one::<u32>("padding").unwrap())?;
+    for (path, e) in &failed {
+        report.error(e, Some(path));
+    }
+    report.out(paint(format!("{} sprite(s) -> {output} ({}x{}, {} bytes)", sheet.sprites.len(), sheet.width, sheet.height, sheet.output_bytes, output = shown(output)), GREEN, report.color));
+    for sprite in &sheet.sprites {
+        report.verbose(1, format!("  {} at {},{} ({}x{})", sprite.name, sprite.x, sprite.y, sprite.width, sprite.height));
+    }
+    if !failed.is_empty() { std::process::exit(EXIT_FAILED) }
     Ok(())
 }
 
-fn expand_patterns_to_files(patterns: ValuesRef<String>) -> ImcoResult<Vec<String>> {
-    let mut files = Vec::new();
+// The directory above the first wildcard of a pattern
+fn watch_root(pattern: &str) -> std::path::PathBuf {
+    let mut root = std::path::PathBuf::new();
+    for component in std::path::Path::new(pattern).components() {
+        if component.as_os_str().to_str().is_some_and(|c| c.contains(['*', '?', '['])) { break }
+        root.push(component);
+    }
+    // Without wildcards the pattern is a file
+    if root == std::path::Path::new(pattern) { root.pop(); }
+    if root.as_os_str().is_empty() { ".".into() } else { root }
+}
+
+fn compile_patterns<'a>(patterns: impl IntoIterator<Item = &'a String>) -> ImcoResult<Vec<glob::Pattern>> {
+    patterns.into_iter()
+        .map(|pattern| glob::Pattern::new(pattern).map_err(|e| ImcoError::BatchPattern(e.to_string(), pattern.to_string())))
+        .collect()
+}
+
+// Converts files matching the patterns but none of the excludes as they are created or modified,
+// does not return unless watching fails
+fn watch(patterns: ValuesRef<PathBuf>, excludes: &[glob::Pattern], output: Option<PathBuf>, converter: &Converter, report: Report) -> ImcoResult<()> {
+    let watch_err = |e: &dyn std::error::Error| ImcoError::Watch(e.to_string());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|e| watch_err(&e))?;
+    let mut compiled = vec![];
     for pattern in patterns {
-        match glob(&pattern) {
-            Ok(paths) => {
-                for entry in paths {
-                    match entry {
-                        Ok(path) => files.push(path.to_str().unwrap().to_string()),
-                        Err(e) => return Err(ImcoError::BatchReadEntry(e.to_string())),
+        // Patterns can only be written in UTF-8
+        let Some(pattern) = pattern.to_str() else { return Err(ImcoError::BatchPattern("Not valid UTF-8".to_string(), pattern.display().to_string())) };
+        let compiled_pattern = glob::Pattern::new(pattern).map_err(|e| ImcoError::BatchPattern(e.to_string(), pattern.to_string()))?;
+        compiled.push((compiled_pattern, std::path::Path::new(pattern).is_absolute()));
+        let mode = if pattern.contains("**") { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        watcher.watch(&watch_root(pattern), mode).map_err(|e| watch_err(&e))?;
+    }
+    let cwd = std::env::current_dir().and_then(std::fs::canonicalize).unwrap_or_default();
+    if !report.json { report.status("Watching for changes, press Ctrl+C to stop") }
+
+    loop {
+        let mut changed = std::collections::BTreeSet::new();
+        let mut next = receiver.recv().map_err(|e| watch_err(&e));
+        loop {
+            let event = next?.map_err(|e| watch_err(&e))?;
+            if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) && !matches!(event.kind, EventKind::Modify(ModifyKind::Metadata(_))) {
+                for path in event.paths {
+                    // Event paths are absolute, relative patterns are matched against the path below the working directory.
+                    // Names that aren't UTF-8 are matched with replacement characters
+                    let relative = path.strip_prefix(&cwd).unwrap_or(&path);
+                    if let Some((_, absolute)) = compiled.iter().find(|(p, absolute)| p.matches(&if *absolute { &path } else { relative }.to_string_lossy())) {
+                        let matched = if *absolute { &path } else { relative };
+                        if !excludes.iter().any(|exclude| exclude.matches(&matched.to_string_lossy())) {
+                            changed.insert(matched.to_path_buf());
+                        }
                     }
                 }
             }
-            Err(e) => return Err(ImcoError::BatchPattern(e.to_string(), pattern.to_string())),
+            next = match receiver.recv_timeout(WATCH_DEBOUNCE) {
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => break,
+                other => other.map_err(|e| watch_err(&e)),
+            };
+        }
+        // Files can be gone again by now
+        let jobs: Vec<_> = changed.into_iter()
+            .filter(|path| path.is_file())
+            .map(|path| ConversionJob::new(path, output.clone()))
+            .collect();
+        if jobs.is_empty() { continue }
+        if let Err(e) = converter.run(jobs).and_then(|outcomes| print_outcomes(outcomes, converter, report)) {
+            report.error(&e, None);
         }
     }
-    Ok(files)
 }
 
-fn collect_ok<T, E>(results: Vec<Result<T, E>>) -> Result<Vec<T>, E> {
-    let mut ok_values = Vec::new();
+// Answers POST requests with their body, of at most max_upload bytes, converted, see convert_upload for the query
+fn serve(addr: &str, max_upload: u64, report: Report) -> ImcoResult<()> {
+    let server = tiny_http::Server::http(addr).map_err(|e| ImcoError::Serve(e.to_string(), addr.to_string()))?;
+    report.status(format!("Serving on http://{addr}, press Ctrl+C to stop"));
+    for mut request in server.incoming_requests() {
+        rayon::spawn(move || {
+            report.verbose(1, format!("{} {}", request.method(), request.url()));
+            let response = match convert_upload(&mut request, max_upload) {
+                Ok((data, fmt)) => tiny_http::Response::from_data(data)
+                    .with_header(tiny_http::Header::from_bytes("Content-Type", fmt.mime_type()).unwrap()),
+                Err((status, message)) => tiny_http::Response::from_string(message).with_status_code(status)
+            };
+            let _ = request.respond(response);
+        });
+    }
+    Ok(())
+}
 
-    for result in results {
-        match result {
-            Ok(value) => ok_values.push(value),
-            Err(err) => return Err(err),
+// Query parameters: format (required), quality, resize (like --resize) and fit.
+// Errors are a status code and the message sent back
+fn convert_upload(request: &mut tiny_http::Request, max_upload: u64) -> Result<(Vec<u8>, Format), (u16, String)> {
+    use std::io::Read;
+    if request.method() != &tiny_http::Method::Post { return Err((405, "Upload the image with a POST request".to_string())) }
+    let too_large = || (413, format!("The upload is larger than {max_upload} bytes"));
+    if request.body_length().is_some_and(|length| length as u64 > max_upload) { return Err(too_large()) }
+    let url = request.url().to_string();
+    let query: HashMap<&str, &str> = url.split_once('?').map(|(_, q)| q).unwrap_or_default()
+        .split('&').filter_map(|pair| pair.split_once('=')).collect();
+    let bad_request = |e: ImcoError| (400, e.to_string());
+    let fmt = mk_format(query.get("format").ok_or((400, "Missing the format parameter".to_string()))?).map_err(bad_request)?;
+    let quality = match query.get("quality") {
+        Some(q) => Some(q.parse::<u8>().ok().filter(|q| (1..=100).contains(q)).ok_or((400, format!("Invalid quality '{q}', expected 1-100")))?),
+        None => None
+    };
+    let resize = match query.get("resize") {
+        Some(res) => {
+            let (width, height) = parse_resolution(res).map_err(bad_request)?;
+            let fit = match query.get("fit").copied() {
+                Some("contain") => Fit::Contain,
+                Some("cover") => Fit::Cover,
+                _ => Fit::Exact
+            };
+            Some(Resize { width, height, fit })
         }
+        None => None
+    };
+    let converter = Converter::new(Options { o_fmt: Some(fmt), quality, resize, ..Options::default() });
+
+    // The length can be left out, so the body is cut one byte after the limit as well
+    let mut data = vec![];
+    request.as_reader().take(max_upload + 1).read_to_end(&mut data).map_err(|e| (400, e.to_string()))?;
+    if data.len() as u64 > max_upload { return Err(too_large()) }
+    let output = converter.convert_bytes(data).map_err(|e| (422, e.to_string()))?;
+    Ok((output, fmt))
+}
+
+// $XDG_RUNTIME_DIR/imco.sock, or one per user in the temporary directory
+fn default_socket() -> String {
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => std::path::Path::new(&dir).join("imco.sock").to_string_lossy().to_string(),
+        None => std::env::temp_dir().join(format!("imco-{}.sock", std::env::var("USER").unwrap_or_default())).to_string_lossy().to_string()
     }
+}
+
+// Converts the jobs sent as JSON lines over a Unix socket, like those of a --jobs-file, answering every
+// line with one of its results. Does not return unless listening fails
+#[cfg(unix)]
+fn daemon(socket: &str, report: Report) -> ImcoResult<()> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    let fail = |e: std::io::Error| ImcoError::Serve(e.to_string(), socket.to_string());
+    if let Ok(existing) = std::fs::symlink_metadata(socket) {
+        // Someone else's socket in a shared directory is neither used nor removed
+        if existing.uid() != unsafe { libc::getuid() } { return Err(ImcoError::Serve("the socket belongs to another user".to_string(), socket.to_string())) }
+        if UnixStream::connect(socket).is_ok() { return Err(ImcoError::Serve("another daemon is listening".to_string(), socket.to_string())) }
+        // Left behind by a daemon that was killed
+        std::fs::remove_file(socket).map_err(fail)?;
+    }
+    let listener = UnixListener::bind(socket).map_err(fail)?;
+    // Jobs read and write files as this user, so only this user may send them
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600)).map_err(fail)?;
+    report.status(format!("Listening on {socket}, press Ctrl+C to stop"));
+    for stream in listener.incoming() {
+        let stream = stream.map_err(fail)?;
+        std::thread::spawn(move || {
+            let mut answers = &stream;
+            for line in BufReader::new(&stream).lines() {
+                let Ok(line) = line else { break };
+                if line.trim().is_empty() { continue }
+                report.verbose(1, &line);
+                let answer = match daemon_job(&line) {
+                    Ok(outcomes) => serde_json::json!({ "results": outcomes.iter().map(json_report).collect::<Vec<_>>() }),
+                    Err(e) => serde_json::json!({ "error": e.to_string() })
+                };
+                if writeln!(answers, "{answer}").is_err() { break }
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn daemon(socket: &str, _report: Report) -> ImcoResult<()> {
+    Err(ImcoError::Serve("the daemon needs Unix sockets".to_string(), socket.to_string()))
+}
+
+// Options jobs sent to the daemon can't have, as they load code, write files besides the outputs or replace the inputs
+const DAEMON_REFUSED: [&str; 7] = ["plugin", "log-file", "resume", "session", "serve", "daemon", "in-place"];
 
-    Ok(ok_values)
+// Converts one job sent to the daemon without printing anything, up to the first failure without --keep-going
+fn daemon_job(line: &str) -> ImcoResult<Vec<FileOutcome>> {
+    let job = serde_json::from_str(line).map_err(|e| e.to_string()).and_then(|job| parse_job(&job)).map_err(ImcoError::InvalidJob)?;
+    if let Some(option) = job.options.keys().find(|key| DAEMON_REFUSED.contains(&key.as_str())) {
+        return Err(ImcoError::InvalidJob(format!("the daemon doesn't take --{option}")))
+    }
+    let args = [NAME.into(), "--no-progress".into()];
+    let matches = job_matches(&args, &job, &cli())?.map_err(ImcoError::InvalidJob)?;
+    // These never finish or wait on the terminal
+    if let Some(option) = ["watch", "to-clipboard", "preview"].into_iter().find(|id| matches.get_flag(id)) {
+        return Err(ImcoError::InvalidJob(format!("the daemon can't {option}")))
+    }
+    parse_and_execute(matches, Report { json: false, color: false, verbosity: 0 }, None)
 }
 
-fn parse_and_execute(matches: ArgMatches) -> Result<(), ImcoError> {
-    let raw_resolution = matches.get_many::<String>("resolution");
-    let resolution = if raw_resolution.is_some() {
-        let x = collect_ok(raw_resolution.unwrap().map(|x1| { x1.parse::<u32>() }).collect()).map_err(|_| {ImcoError::NotANumber})?;
-        Some((x[0], x[1]))
+// The session (of a batch) is only written with several jobs
+fn parse_and_execute(matches: ArgMatches, report: Report, session: Option<Session>) -> ImcoResult<Vec<FileOutcome>> {
+    // Their operations have to be known before parsing --ops
+    for path in matches.get_many::<String>("plugin").into_iter().flatten() {
+        let operations = load_plugin(path)?;
+        report.verbose(1, format!("Loaded {path}: {}", operations.join(", ")));
+    }
+    let resize = if let Some(raw_resolution) = matches.get_one::<String>("resize") {
+        let (width, height) = parse_resolution(raw_resolution)?;
+        let fit = match matches.get_one::<String>("fit").unwrap().as_str() {
+            "contain" => Fit::Contain,
+            "cover" => Fit::Cover,
+            _ => Fit::Exact
+        };
+        Some(Resize { width, height, fit })
     } else {None};
     
+    let recursive = matches.get_flag("recursive");
     let batch = matches.get_flag("batch");
+    let watching = matches.get_flag("watch");
+    let input_list = matches.get_one::<PathBuf>("input-list");
+    let from_clipboard = matches.get_flag("from-clipboard");
 
-    let mut couples = vec![];
+    let mut jobs = vec![];
+    let symlinks = if matches.get_flag("no-follow-symlinks") { Symlinks::Skip }
+        else if matches.get_flag("recreate-symlinks") { Symlinks::Recreate }
+        else { Symlinks::Follow };
 
-    let input_files: Vec<String> = if batch {
-        expand_patterns_to_files(matches.get_many::<String>("input").unwrap())?
+    let input_files: Vec<PathBuf> = if let Some(list) = input_list {
+        read_input_list(list, matches.get_flag("null"))?
+    } else if from_clipboard {
+        vec![CLIPBOARD.into()]
+    } else if batch && !watching {
+        expand_patterns_to_files(matches.get_many::<PathBuf>("input").unwrap(), recursive)?
+    } else {
+        matches.get_many::<PathBuf>("input").unwrap().cloned().collect()
+    };
+    // Urls are downloaded first, without an output they are converted into the current directory
+    let mut temporary = TemporaryInputs::default();
+    let mut default_outputs = HashMap::new();
+    let from_url = !watching && input_files.iter().any(|file| is_url(file));
+    let input_files = if from_url {
+        let max_size = parse_size(matches.get_one::<String>("max-download").unwrap())?;
+        let timeout = std::time::Duration::from_secs(*matches.get_one::<u64>("download-timeout").unwrap());
+        input_files.into_iter().map(|file| {
+            if !is_url(&file) { return Ok(file) }
+            report.verbose(1, format!("Downloading {}", file.display()));
+            // is_url only takes UTF-8, nothing is lost
+            let downloaded = temporary.download(&file.to_string_lossy(), max_size, timeout)?;
+            default_outputs.insert(downloaded.clone(), PathBuf::from("."));
+            Ok(downloaded)
+        }).collect::<ImcoResult<Vec<_>>>()?
+    } else { input_files };
+    // Files found in directories keep their relative directory below the output
+    let mut input_files = if recursive {
+        expand_directories(input_files, matches.contains_id("input-format") || matches.contains_id("raw-input"), symlinks)?
     } else {
-        matches.get_many::<String>("input").unwrap().map(|x| {x.to_string()}).collect()
+        input_files.into_iter().map(|f| (f, PathBuf::new())).collect()
     };
+    // Images inside archives are converted into a directory mirroring the archive, named after it without an output
+    let from_archive = !watching && input_files.iter().any(|(file, _)| is_archive_path(file));
+    if from_archive {
+        let default_dir = |file: &Path, downloaded: bool| match file.file_name() {
+            _ if matches.contains_id("archive") => PathBuf::new(),
+            Some(name) if downloaded => archive_stem(Path::new(name)),
+            _ => archive_stem(file)
+        };
+        let mut expanded = vec![];
+        for (file, relative) in input_files {
+            if !is_archive_path(&file) {
+                expanded.push((file, relative));
+                continue
+            }
+            let dir = default_dir(&file, default_outputs.contains_key(&file));
+            for (entry, inner) in temporary.extract(&file, matches.contains_id("input-format") || matches.contains_id("raw-input"))? {
+                default_outputs.insert(entry.clone(), dir.clone());
+                expanded.push((entry, relative.join(inner)))
+            }
+        }
+        input_files = expanded;
+    }
+    let excludes = compile_patterns(matches.get_many::<String>("exclude").into_iter().flatten())?;
+    input_files.retain(|(file, _)| !excludes.iter().any(|exclude| exclude.matches(&file.to_string_lossy())));
+    // Links matched by the patterns themselves, those below directories are left out while walking them
+    if symlinks == Symlinks::Skip && batch { input_files.retain(|(file, _)| !file.is_symlink()) }
+    let filter = InputFilter {
+        min_width: matches.get_one::<u32>("min-width").copied(),
+        max_width: matches.get_one::<u32>("max-width").copied(),
+        min_height: matches.get_one::<u32>("min-height").copied(),
+        max_height: matches.get_one::<u32>("max-height").copied(),
+        min_bytes: matches.get_one::<String>("min-bytes").map(|size| parse_size(size)).transpose()?,
+        max_bytes: matches.get_one::<String>("max-bytes").map(|size| parse_size(size)).transpose()?,
+        formats: matches.get_many::<String>("only-format").into_iter().flatten().map(|fmt| mk_format(fmt)).collect::<ImcoResult<_>>()?,
+    };
+    if !filter.is_empty() {
+        input_files.retain(|(file, _)| {
+            let accepted = filter.accepts(file);
+            if !accepted { report.verbose(2, format!("Filtered out {}", shown(file))) }
+            accepted
+        });
+    }
+    let sample = match (matches.get_one::<usize>("sample"), matches.get_one::<f64>("sample-percent"), matches.get_one::<u64>("sample-every")) {
+        (Some(count), _, _) => Some(Sample::Count(*count)),
+        (_, Some(percent), _) => Some(Sample::Percent(*percent)),
+        (_, _, Some(step)) => Some(Sample::Every(*step as usize)),
+        _ => None
+    };
+    if let Some(session) = session.as_ref().filter(|session| session.resumed()) {
+        // Outputs of the interrupted run may match its patterns now, the inputs it picked (or sampled) are kept.
+        // Downloaded and extracted files get new paths every run
+        input_files.retain(|(file, _)| default_outputs.contains_key(file) || session.started_with(file));
+    } else if let Some(sample) = sample {
+        // Without a seed every run picks others, the seed being shown to pick the same ones again
+        let seed = matches.get_one::<u64>("seed").copied()
+            .unwrap_or_else(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64));
+        let total = input_files.len();
+        let picked = sample.pick(total, seed);
+        input_files = input_files.into_iter().enumerate().filter(|(i, _)| picked.binary_search(i).is_ok()).map(|(_, file)| file).collect();
+        report.status(match sample {
+            Sample::Every(_) => format!("Sampled {} of {total} inputs", picked.len()),
+            _ => format!("Sampled {} of {total} inputs (--seed {seed})", picked.len()),
+        });
+    }
 
-    let output_files: Vec<&String> = matches
-        .get_many::<String>("output")
-        .map(|values| values.collect())
-        .unwrap_or_default();
+    // The inputs come from the list or the clipboard, so the only positional argument is the output
+    let clipboard = PathBuf::from(CLIPBOARD);
+    let output_files: Vec<&PathBuf> = if matches.get_flag("to-clipboard") {
+        vec![&clipboard]
+    } else if let Some(dir) = matches.get_one::<PathBuf>("output-dir") {
+        vec![dir]
+    } else {
+        matches
+            .get_many::<PathBuf>(if input_list.is_some() || from_clipboard { "input" } else { "output" })
+            .map(|values| values.collect())
+            .unwrap_or_default()
+    };
 
-    for (i, input_file) in input_files.iter().enumerate() {
+    for (i, (input_file, relative)) in input_files.into_iter().enumerate() {
         let partner = if output_files.is_empty() {
-            None
+            default_outputs.get(&input_file)
         } else if i >= output_files.len() {
-            Some(output_files.last().unwrap())
+            output_files.last().copied()
         } else {
-            Some(&output_files[i])
+            Some(output_files[i])
         };
+        let partner = partner.map(|p| if relative.as_os_str().is_empty() { p.clone() } else { p.join(relative) });
 
-        couples.push((input_file, partner))
+        jobs.push(ConversionJob::new(input_file, partner))
     }
 
     let i_fmt = matches.get_one::<String>("input-format");
-    let o_fmt = matches.get_one::<String>("output-format");
+    let mut o_fmts = matches.get_many::<String>("output-format").into_iter().flatten();
+    let o_fmt = o_fmts.next();
+    let formats = o_fmts.map(|fmt| mk_format(fmt)).collect::<ImcoResult<Vec<_>>>()?;
+    if !formats.is_empty() {
+        // These write their own set of files per input
+        let single = ["tile", "slice", "split-channels", "extract-frames", "pages", "srcset", "animate", "preset", "to-clipboard"];
+        if let Some(option) = single.into_iter().find(|id| matches.value_source(id).is_some_and(|source| source != clap::parser::ValueSource::DefaultValue)) {
+            return Err(ImcoError::SeveralFormats(option.to_string()))
+        }
+    }
 
-    process(couples, i_fmt, o_fmt, batch, resolution)
-}
+    let raw_input = matches.get_one::<String>("raw-input").map(|raw| parse_raw_input(raw)).transpose()?;
+    let dry_run = matches.get_flag("dry-run");
+    let opts = Options {
+        rasterize: Rasterize {
+            dpi: *matches.get_one::<f32>("dpi").unwrap(),
+            size: match matches.get_one::<String>("rasterize-size") {
+                Some(size) => Some(parse_resolution(size)?),
+                None => None
+            },
+        },
+        i_fmt: if let Some(i_fmt) = i_fmt { Some(mk_format(i_fmt)?) } else if raw_input.is_some() { Some(Format::Raw) } else {None},
+        raw_input,
+        raw_layout: RawLayout::from_name(matches.get_one::<String>("raw-layout").unwrap()).unwrap(),
+        raw_header: matches.get_flag("raw-header"),
+        o_fmt: if let Some(o_fmt) = o_fmt { Some(mk_format(o_fmt)?) } else {None},
+        formats,
+        batch: batch || recursive || watching || input_list.is_some() || from_archive || (from_url && output_files.is_empty()) || matches.contains_id("output-dir"),
+        create_dirs: true,
+        name_template: {
+            let template = matches.get_one::<String>("name-template").cloned()
+                .unwrap_or_else(|| format!("{{stem}}{}.{{ext}}", matches.get_one::<String>("suffix").map(String::as_str).unwrap_or_default()));
+            if matches.get_flag("organize-by-date") { format!("{{exif.year}}/{{exif.month}}/{template}") } else { template }
+        },
+        ops: match matches.get_one::<String>("ops") {
+            Some(ops) => parse_ops(ops)?,
+            None => vec![]
+        },
+        rotate: matches.get_one::<String>("rotate").map(|r| r.parse().unwrap()).unwrap_or(0),
+        rotate_degrees: matches.get_one::<f32>("rotate-deg").map(|degrees| Rotation { degrees: *degrees, crop: matches.get_flag("rotate-crop") }),
+        flip_horizontal: matches.get_many::<String>("flip").is_some_and(|mut f| f.any(|f| f == "h")),
+        flip_vertical: matches.get_many::<String>("flip").is_some_and(|mut f| f.any(|f| f == "v")),
+        crop: if let Some(crop) = matches.get_one::<String>("crop") { Some(parse_crop(crop)?) }
+            else if let Some(size) = matches.get_one::<String>("crop-center") {
+                match parse_resolution(size) {
+                    Ok((Some(width), Some(height))) => Some(Crop::Center(width, height)),
+                    _ => return Err(ImcoError::InvalidResolution(size.to_string()))
+                }
+            } else { None },
+        resize,
+        chroma_key: match matches.get_one::<String>("chroma-key") {
+            Some(color) => Some(ChromaKey { color: parse_color(color)?, tolerance: *matches.get_one::<f32>("key-tolerance").unwrap() }),
+            None => None
+        },
+        trim: matches.get_one::<f32>("trim").map(|fuzz| Trim { fuzz: *fuzz }),
+        aspect: match matches.get_one::<String>("aspect") {
+            Some(aspect) => Some(parse_aspect(aspect, parse_gravity(matches.get_one::<String>("gravity").unwrap()).unwrap())?),
+            None => None
+        },
+        crop_to: match matches.get_one::<String>("crop-to") {
+            Some(size) => match parse_resolution(size)? {
+                (Some(width), Some(height)) => Some(CropTo { width, height, gravity: parse_gravity(matches.get_one::<String>("gravity").unwrap()).unwrap() }),
+                _ => return Err(ImcoError::InvalidResolution(size.to_string()))
+            },
+            None => None
+        },
+        thumbnail: matches.get_one::<u32>("thumbnail").copied(),
+        filter: mk_filter(matches.get_one::<String>("filter").unwrap()),
+        linear_resize: matches.get_flag("linear-resize"),
+        resize_backend: match matches.get_one::<String>("resize-backend").unwrap().as_str() {
+            "image" => ResizeBackend::Image,
+            _ => ResizeBackend::Fast
+        },
+        // Opened once for all jobs of a --jobs-file
+        gpu: if matches.get_flag("gpu") {
+            static GPU: std::sync::OnceLock<Option<std::sync::Arc<Gpu>>> = std::sync::OnceLock::new();
+            GPU.get_or_init(|| match Gpu::open() {
+                Ok(gpu) => Some(std::sync::Arc::new(gpu)),
+                Err(e) => {
+                    let warning = format!("{e}, transforming on the CPU");
+                    log_event("warning", None, &warning, serde_json::json!({}));
+                    if !report.json { report.status(paint(format!("warning: {warning}"), YELLOW, report.color)) }
+                    None
+                }
+            }).clone()
+        } else { None },
+        quality: matches.get_one::<u8>("quality").copied(),
+        target_size: matches.get_one::<String>("target-size").map(|size| parse_size(size)).transpose()?,
+        encoder: {
+            let mut encoder = EncoderSettings::default();
+            for setting in matches.get_many::<String>("set").into_iter().flatten() {
+                encoder.set(setting)?;
+            }
+            encoder.jpeg.progressive |= matches.get_flag("progressive");
+            encoder.png.interlace |= matches.get_flag("interlace");
+            encoder
+        },
+        blur: matches.get_one::<f32>("blur").copied(),
+        denoise: matches.get_one::<String>("denoise").map(|denoise| parse_denoise(denoise)).transpose()?,
+        unsharp: match matches.get_one::<String>("unsharp") {
+            Some(mask) => Some(parse_unsharp(mask)?),
+            None => matches.get_flag("sharpen").then(Unsharp::light)
+        },
+        auto_levels: match (matches.get_one::<f32>("auto-levels"), matches.get_one::<f32>("auto-contrast")) {
+            (Some(clip), _) => Some(AutoLevels { per_channel: true, clip: *clip }),
+            (_, Some(clip)) => Some(AutoLevels { per_channel: false, clip: *clip }),
+            _ => None
+        },
+        brightness: *matches.get_one::<i32>("brightness").unwrap(),
+        contrast: *matches.get_one::<f32>("contrast").unwrap(),
+        gamma: *matches.get_one::<f32>("gamma").unwrap(),
+        lut: match matches.get_one::<String>("lut") {
+            Some(path) => Some(Lut::load(path)?),
+            None => None
+        },
+        invert: matches.get_flag("invert"),
+        sepia: matches.get_flag("sepia"),
+        tint: match matches.get_one::<String>("tint") {
+            Some(tint) => Some(parse_tint(tint)?),
+            None => None
+        },
+        vignette: matches.get_one::<String>("vignette").map(|vignette| parse_vignette(vignette)).transpose()?,
+        threshold: matches.get_one::<u8>("threshold").copied(),
+        posterize: matches.get_one::<u8>("posterize").copied(),
+        pad: match matches.get_one::<String>("pad") {
+            Some(size) => match parse_resolution(size)? {
+                (Some(width), Some(height)) => Some(Pad { width, height, color: parse_color(matches.get_one::<String>("pad-color").unwrap())? }),
+                _ => return Err(ImcoError::InvalidResolution(size.to_string()))
+            },
+            None => None
+        },
+        border: match matches.get_one::<String>("border") {
+            Some(border) => Some(parse_border(border)?),
+            None => None
+        },
+        corners: match (matches.get_one::<u32>("round-corners"), matches.get_flag("circle-crop")) {
+            (Some(radius), _) => Some(Corners::Round(*radius)),
+            (_, true) => Some(Corners::Circle),
+            _ => None
+        },
+        alpha_mask: match matches.get_one::<String>("apply-alpha") {
+            Some(path) => Some(AlphaMask::load(path)?),
+            None => None
+        },
+        extract_alpha: matches.get_flag("extract-alpha"),
+        watermark: match matches.get_one::<String>("watermark") {
+            Some(path) => Some(Watermark::load(path,
+                matches.get_one::<String>("watermark-pos").unwrap(),
+                *matches.get_one::<f32>("watermark-opacity").unwrap())?),
+            None => None
+        },
+        caption: match matches.get_one::<String>("caption") {
+            Some(text) => Some(Caption::load(text,
+                matches.get_one::<String>("font").map(String::as_str),
+                *matches.get_one::<f32>("font-size").unwrap(),
+                matches.get_one::<String>("caption-pos").unwrap(),
+                parse_color(matches.get_one::<String>("caption-color").unwrap())?)?),
+            None => None
+        },
+        preset: matches.get_one::<String>("preset").map(|preset| match preset.as_str() {
+            "ios-appicon" => Preset::IosAppIcon,
+            "android-mipmap" => Preset::AndroidMipmap,
+            _ => Preset::Favicon
+        }),
+        ico_sizes: {
+            let mut sizes: Vec<u32> = matches.get_many::<u32>("ico-sizes").map(|s| s.copied().collect()).unwrap_or_default();
+            sizes.sort_unstable();
+            sizes.dedup();
+            sizes
+        },
+        mipmaps: matches.get_flag("mipmaps"),
+        texture_compression: matches.get_one::<String>("texture-compression").map(|c| match c.as_str() {
+            "bc1" => TextureCompression::Bc1,
+            "bc3" => TextureCompression::Bc3,
+            _ => TextureCompression::Bc7
+        }),
+        colorspace: matches.get_one::<String>("colorspace").map(|c| match c.as_str() {
+            "gray" => image::ColorType::L8,
+            "gray-alpha" => image::ColorType::La8,
+            "rgb" => image::ColorType::Rgb8,
+            _ => image::ColorType::Rgba8
+        }),
+        quantize: matches.get_one::<u16>("colors").map(|colors| Quantize {
+            colors: *colors,
+            dither: match matches.get_one::<String>("dither").unwrap().as_str() {
+                "ordered" => Dither::Ordered,
+                "none" => Dither::None,
+                _ => Dither::FloydSteinberg
+            },
+        }),
+        depth: matches.get_one::<String>("depth").map(|d| d.parse().unwrap()),
+        preserve_depth: matches.get_flag("preserve-depth"),
+        lossless_only: matches.get_flag("lossless-only") && !matches.get_flag("allow-lossy"),
+        strict: matches.get_flag("strict"),
+        check_space: !matches.get_flag("no-space-check"),
+        space_ratio: matches.get_one::<f64>("space-ratio").copied(),
+        tonemap: matches.get_one::<String>("tonemap").map(|t| match t.as_str() {
+            "aces" => Tonemap::Aces,
+            _ => Tonemap::Reinhard
+        }),
+        exposure: *matches.get_one::<f32>("exposure").unwrap(),
+        background: match matches.get_one::<String>("background") {
+            Some(color) => Some(parse_color(color)?),
+            None => None
+        },
+        first_frame_only: matches.get_flag("first-frame-only"),
+        extract_frames: matches.get_flag("extract-frames"),
+        pages: matches.get_one::<String>("pages").map(|pages| parse_pages(pages)).transpose()?,
+        split_channels: matches.get_flag("split-channels"),
+        srcset: {
+            let mut widths: Vec<u32> = matches.get_many::<u32>("srcset").map(|w| w.copied().collect()).unwrap_or_default();
+            widths.sort_unstable();
+            widths.dedup();
+            widths
+        },
+        blurhash: matches.get_flag("blurhash"),
+        thumbhash: matches.get_flag("thumbhash"),
+        hash_sidecar: matches.get_flag("hash-sidecar"),
+        tile: match matches.get_one::<String>("tile") {
+            Some(size) => match parse_resolution(size)? {
+                (Some(width), Some(height)) => Some((width, height)),
+                _ => return Err(ImcoError::InvalidResolution(size.to_string()))
+            },
+            None => None
+        },
+        slice: matches.get_one::<(u32, u32)>("slice").copied(),
+        pdf_per_file: matches.get_flag("pdf-per-file"),
+        animate: matches.get_flag("animate").then(|| match matches.get_one::<u32>("fps") {
+            Some(fps) => image::Delay::from_numer_denom_ms(1000, *fps),
+            None => image::Delay::from_numer_denom_ms(*matches.get_one::<u32>("delay").unwrap(), 1)
+        }),
+        frame_delay: match (matches.get_one::<u32>("frame-delay"), matches.get_one::<u32>("fps")) {
+            _ if matches.get_flag("animate") => None,
+            (Some(ms), _) => Some(image::Delay::from_numer_denom_ms(*ms, 1)),
+            (None, Some(fps)) => Some(image::Delay::from_numer_denom_ms(1000, *fps)),
+            (None, None) => None
+        },
+        loops: *matches.get_one::<u16>("loop").unwrap(),
+        scale: matches.get_one::<String>("scale").map(|scale| parse_scale(scale)).transpose()?,
+        min_dimension: matches.get_one::<u32>("min-dimension").copied(),
+        max_dimension: matches.get_one::<u32>("max-dimension").copied(),
+        max_megapixels: matches.get_one::<f64>("max-megapixels").copied(),
+        dry_run,
+        data_uri: matches.get_flag("data-uri"),
+        low_memory: matches.get_flag("low-memory"),
+        retries: *matches.get_one::<u32>("retries").unwrap(),
+        retry_delay: std::time::Duration::from_millis(*matches.get_one::<u64>("retry-delay").unwrap()),
+        progress: !matches.get_flag("no-progress") && !dry_run && std::io::stdout().is_terminal(),
+        symlinks,
+        conflict: if matches.get_flag("no-overwrite") { Conflict::Error }
+            else if matches.get_flag("skip-existing") { Conflict::Skip }
+            else if matches.get_flag("rename-on-conflict") { Conflict::Rename }
+            else { Conflict::Overwrite },
+        uniquify: matches.get_flag("uniquify"),
+        newer_only: matches.get_flag("newer-only"),
+        metadata: if matches.get_flag("keep-metadata") { MetadataPolicy::Keep }
+            else if matches.get_flag("strip-metadata") { MetadataPolicy::Strip }
+            else { MetadataPolicy::SameFormat },
+        strip_icc: matches.get_flag("strip-icc"),
+        convert_to_srgb: matches.get_flag("convert-to-srgb"),
+        // Removing first lets --set-exif replace a field that is removed from all others
+        exif_edits: matches.get_many::<String>("remove-exif").into_iter().flatten().map(|key| Ok(ExifEdit::Remove(key.clone())))
+            .chain(matches.get_many::<String>("set-exif").into_iter().flatten().map(|edit| parse_exif_set(edit)))
+            .collect::<ImcoResult<_>>()?,
+        deterministic: matches.get_flag("deterministic"),
+        preserve_times: matches.get_flag("preserve-times"),
+        preserve_mode: matches.get_flag("preserve-mode"),
+        auto_orient: !matches.get_flag("no-auto-orient"),
+        backup: matches.get_one::<String>("backup").cloned(),
+        strict_backup: matches.get_flag("strict-backup"),
+        backup_dir: matches.get_one::<String>("backup-dir").map(std::path::PathBuf::from),
+        in_place: matches.get_flag("in-place"),
+        strict_extensions: matches.get_flag("strict-extensions"),
+        keep_going: matches.get_flag("keep-going"),
+        jobs: *matches.get_one::<usize>("jobs").unwrap(),
+        timeout: matches.get_one::<u64>("timeout").map(|secs| std::time::Duration::from_secs(*secs)),
+        cancel: interruption(),
+        verify: matches.get_flag("verify"),
+        verify_full: matches.get_flag("verify-full"),
+        archive: match matches.get_one::<String>("archive") {
+            Some(path) if !dry_run && matches.get_flag("deterministic") => Some(Archive::create(path)?.sorted()),
+            Some(path) if !dry_run => Some(Archive::create(path)?),
+            _ => None
+        },
+        limits: if matches.get_flag("no-limits") { DecodeLimits::unlimited() } else {
+            DecodeLimits {
+                max_pixels: matches.get_one::<u64>("max-pixels").copied(),
+                max_memory: matches.get_one::<u64>("max-memory").map(|mib| mib * 1024 * 1024).or(DecodeLimits::default().max_memory),
+            }
+        },
+        dedupe: if matches.get_flag("dedupe") && !dry_run { Some(Dedupe::new(matches.get_flag("dedupe-symlink"))) } else { None },
+        decode_cache: match *matches.get_one::<u64>("cache-max-mb").unwrap() {
+            0 => None,
+            mib => Some(DecodeCache::new(mib * 1024 * 1024)),
+        },
+        session: session.filter(|session| session.resumed() || (jobs.len() > 1 && !dry_run && !watching)),
+        checksums: matches.get_one::<String>("checksum").filter(|_| !dry_run).map(|algorithm| {
+            let algorithm = if algorithm == "sha512" { ChecksumAlgorithm::Sha512 } else { ChecksumAlgorithm::Sha256 };
+            Checksums::new(algorithm, matches.get_one::<String>("checksum-manifest").cloned())
+        }),
+    };
 
-fn main() {
-    let matches = clap::Command::new(NAME)
-        .about(DESCRIPTION)
-        .version(VERSION)
-        .color(ColorChoice::Never)
-        .disable_version_flag(true)
-        .after_help("Accepted file formats:\n avif, jpg / jpeg / jfif, png / apng,\n gif, webp, tif / tiff, tga, dds,\n bmp, ico, hdr, exr, pbm / pam / ppm / pgm,\n ff, qoi, pcx")
+    let converter = Converter::new(opts);
+    if matches.get_flag("confirm") {
+        if input_list.is_some_and(|list| list.as_os_str() == STDIO) || jobs.iter().any(|job| job.input.as_os_str() == STDIO) { return Err(ImcoError::ConfirmFromStdin) }
+        jobs = confirm_plan(jobs, &converter, report)?;
+    }
+    if watching {
+        watch(matches.get_many::<PathBuf>("input").unwrap(), &excludes, output_files.first().map(|o| o.to_path_buf()), &converter, report)?;
+        return Ok(vec![])
+    }
+    report.verbose(2, format!("{} input(s), {} at a time", jobs.len(), converter.options().jobs));
+    for job in &jobs {
+        report.verbose(2, format!("  {} -> {}", shown(&job.input), job.output.as_deref().map_or("(derived from the input)".into(), shown)));
+    }
+    if let Some(session) = &converter.options().session {
+        session.begin(&jobs)?;
+        report.verbose(1, format!("Recording the progress in {}", session.path()));
+    }
+    catch_interrupts(report);
+    let outcomes = converter.run(jobs);
+    // Conversions abandoned after their timeout end with imco, possibly halfway through writing
+    if converter.options().timeout.is_some() { discard_partial_outputs() }
+    let outcomes = match (outcomes, &converter.options().session) {
+        // Nothing was converted, so there is nothing to resume unless an earlier run converted some
+        (Err(e), Some(session)) if !session.resumed() => {
+            session.remove()?;
+            return Err(e)
+        }
+        (outcomes, _) => outcomes?
+    };
+    let unfinished = interruption().load(Ordering::Relaxed) || outcomes.iter().any(|o| o.result.is_err());
+    // Without keep_going this is the first failure, after which the session is resumed as well
+    let printed = print_outcomes(outcomes, &converter, report);
+    if let Some(session) = &converter.options().session {
+        if unfinished {
+            let id = std::path::Path::new(session.path()).file_stem().map_or(session.path().into(), |stem| stem.to_string_lossy());
+            report.status(paint(format!("Continue where this run stopped with: imco --resume {id}"), YELLOW, report.color));
+        } else {
+            session.remove()?;
+        }
+    }
+    let outcomes = printed?;
+    if let Some(style) = matches.get_one::<String>("srcset-print") { print_srcset(&outcomes, style) }
+    converter.finish()?;
+    Ok(outcomes)
+}
+
+// Where the sessions of batches are kept until they finish
+fn sessions_dir() -> Option<std::path::PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME").map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".local").join("state")));
+    state_home.map(|dir| dir.join("imco").join("sessions"))
+}
+
+// A session named after the time and process, for a run with these arguments. Runs with arguments that aren't UTF-8
+// go without one, the session file couldn't give them back
+fn new_session(args: &[OsString]) -> Option<Session> {
+    let args = args.iter().map(|arg| arg.to_str().map(str::to_string)).collect::<Option<Vec<_>>>()?;
+    let cwd = std::env::current_dir().ok()?.to_str()?.to_string();
+    let stamp: String = utc_timestamp().chars().take(19).filter(char::is_ascii_digit).collect();
+    let path = sessions_dir()?.join(format!("{}-{}-{}.jsonl", &stamp[..8], &stamp[8..], std::process::id()));
+    Some(Session::new(path.to_str()?.to_string(), args, cwd))
+}
+
+// The session file of a name given to --resume: a path, the name of one in the sessions directory or "last"
+fn session_path(name: &str) -> ImcoResult<String> {
+    if std::path::Path::new(name).is_file() { return Ok(name.to_string()) }
+    let unknown = |reason: &str| ImcoError::Session(name.to_string(), reason.to_string());
+    let dir = sessions_dir().ok_or_else(|| unknown("there is no state directory (XDG_STATE_HOME or HOME)"))?;
+    if name == "last" {
+        let newest = std::fs::read_dir(&dir).into_iter().flatten().flatten()
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .max_by_key(|(modified, _)| *modified);
+        return newest.map(|(_, path)| path.to_string_lossy().into_owned()).ok_or_else(|| unknown("no batch was left unfinished"))
+    }
+    let path = dir.join(format!("{name}.jsonl"));
+    if path.is_file() { Ok(path.to_string_lossy().into_owned()) } else { Err(unknown("there is no such session")) }
+}
+
+// Continues a session in the directory and with the arguments it was started with, leaving out the inputs it finished
+fn resume_session(name: &str, report: Report) -> ImcoResult<Vec<FileOutcome>> {
+    let session = Session::resume(&session_path(name)?)?;
+    let failed = |reason: String| ImcoError::Session(session.path().to_string(), reason);
+    std::env::set_current_dir(session.cwd()).map_err(|e| failed(format!("can't enter {}: {e}", session.cwd())))?;
+    let matches = cli().try_get_matches_from(imply_subcommand_flag(session.args().iter().map(OsString::from).collect())).map_err(|e| failed(e.kind().to_string()))?;
+    let matches = match matches.subcommand() {
+        Some((_, convert_matches)) => convert_matches.clone(),
+        None => matches
+    };
+    report.status(match session.inputs() {
+        Some(inputs) => format!("Resuming {}, {} of {inputs} input(s) were converted", session.path(), session.done()),
+        None => format!("Resuming {}, {} input(s) were converted", session.path(), session.done())
+    });
+    parse_and_execute(matches, report, Some(session))
+}
+
+// Preset files, presets of the project's imco.toml replace those of the same name in the user's
+fn config_paths() -> Vec<std::path::PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME").map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config")));
+    config_home.map(|dir| dir.join("imco").join("imco.toml")).into_iter()
+        .chain([std::path::PathBuf::from("imco.toml")])
+        .collect()
+}
+
+// The arguments a preset of the [presets] table stands for, its keys being long option names
+fn load_preset(name: &str, command: &clap::Command) -> ImcoResult<Vec<String>> {
+    let mut found = None;
+    for path in config_paths() {
+        let Ok(text) = std::fs::read_to_string(&path) else { continue };
+        let path = path.to_string_lossy().to_string();
+        let config: toml::Table = text.parse().map_err(|e: toml::de::Error| ImcoError::Config(path.clone(), e.message().to_string()))?;
+        if let Some(preset) = config.get("presets").and_then(|presets| presets.get(name)) {
+            found = Some((path, preset.clone()));
+        }
+    }
+    let Some((path, preset)) = found else { return Err(ImcoError::UnknownPreset(name.to_string())) };
+    let Some(preset) = preset.as_table() else { return Err(ImcoError::Config(path, format!("preset '{name}' is not a table"))) };
+    let plain = |value: &toml::Value| match value {
+        toml::Value::String(s) => s.clone(),
+        value => value.to_string()
+    };
+    let mut args = vec![];
+    for (key, value) in preset {
+        let long = if key == "format" { "output-format" } else { key.as_str() };
+        if long == "preset" || !command.get_arguments().any(|arg| arg.get_long() == Some(long)) {
+            return Err(ImcoError::Config(path, format!("unknown option '{key}' in preset '{name}'")))
+        }
+        match value {
+            toml::Value::Boolean(true) => args.push(format!("--{long}")),
+            toml::Value::Boolean(false) => {}
+            toml::Value::Array(items) => args.push(format!("--{long}={}", items.iter().map(plain).collect::<Vec<_>>().join(","))),
+            value => args.push(format!("--{long}={}", plain(value))),
+        }
+    }
+    Ok(args)
+}
+
+// The command line of a job: the options imco was started with (without the --jobs-file), then those of
+// the job overriding them, then its input and output
+fn job_args(args: &[OsString], job: &Job, command: &clap::Command) -> Result<Vec<OsString>, String> {
+    let mut job_args = vec![];
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--jobs-file" { rest.next(); } else if !arg.to_str().is_some_and(|arg| arg.starts_with("--jobs-file=")) { job_args.push(arg.clone()) }
+    }
+    let plain = |value: &serde_json::Value| match value {
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string()
+    };
+    for (key, value) in &job.options {
+        let long = if key == "format" { "output-format" } else { key.as_str() };
+        if long == "jobs-file" || !command.get_arguments().any(|arg| arg.get_long() == Some(long)) {
+            return Err(format!("unknown option '{key}'"))
+        }
+        match value {
+            serde_json::Value::Bool(true) => job_args.push(format!("--{long}").into()),
+            serde_json::Value::Bool(false) | serde_json::Value::Null => {}
+            serde_json::Value::Array(items) => job_args.push(format!("--{long}={}", items.iter().map(plain).collect::<Vec<_>>().join(",")).into()),
+            serde_json::Value::Object(_) => return Err(format!("option '{key}' can't be an object")),
+            value => job_args.push(format!("--{long}={}", plain(value)).into()),
+        }
+    }
+    job_args.extend(job.format.iter().map(|format| format!("--output-format={format}").into()));
+    job_args.push("--".into());
+    job_args.push(job.input.clone().into());
+    job_args.extend(job.output.clone().map(OsString::from));
+    Ok(job_args)
+}
+
+// The options of converting a job, the hint of what's wrong with them otherwise
+fn job_matches(args: &[OsString], job: &Job, command: &clap::Command) -> ImcoResult<Result<ArgMatches, String>> {
+    let job_args = match job_args(args, job, command) {
+        Ok(job_args) => imply_subcommand_flag(expand_preset(job_args, command)?),
+        Err(hint) => return Ok(Err(hint))
+    };
+    Ok(match command.clone().try_get_matches_from(job_args) {
+        Ok(matches) => Ok(match matches.subcommand() {
+            Some((_, convert_matches)) => convert_matches.clone(),
+            None => matches
+        }),
+        // Just the message, without the usage clap adds
+        Err(e) => Err(e.to_string().split("\n\n").next().unwrap_or_default().trim_start_matches("error: ").split_whitespace().collect::<Vec<_>>().join(" "))
+    })
+}
+
+// Batches of this many files are confirmed with --confirm even when nothing is overwritten
+const CONFIRM_BATCH: usize = 50;
+
+// Shows where every input goes and, when outputs would be overwritten or the batch is large, asks whether
+// to go on, for all of them at once or file by file. Returns the agreed jobs
+fn confirm_plan(jobs: Vec<ConversionJob>, converter: &Converter, report: Report) -> ImcoResult<Vec<ConversionJob>> {
+    let opts = converter.options();
+    // Inputs sharing an output fail here already, before anything is asked
+    let plan = converter.plan(&jobs)?;
+    let overwrites = matches!(opts.conflict, Conflict::Overwrite) && !opts.newer_only && opts.archive.is_none();
+    let risks: Vec<Option<String>> = plan.iter().map(|planned| match planned {
+        Ok((output, _)) if overwrites && output != STDIO && std::path::Path::new(output).exists() => Some("overwrites".to_string()),
+        _ => None
+    }).collect();
+    for ((job, planned), risk) in jobs.iter().zip(&plan).zip(&risks) {
+        let target = match planned {
+            Ok((output, fmt)) => format!("{} ({})", shown(output), fmt.extension()),
+            Err(e) => paint(shown_message(e), RED, report.color),
+        };
+        let risk = risk.as_ref().map(|risk| paint(format!("  [{risk}]"), YELLOW, report.color)).unwrap_or_default();
+        eprintln!("{} -> {target}{risk}", shown(&job.input));
+    }
+    let risky = risks.iter().flatten().count();
+    if risky == 0 && jobs.len() < CONFIRM_BATCH { return Ok(jobs) }
+    let question = match risky {
+        0 => format!("Convert all {} files? [y]es, [n]o, [e]ach file: ", jobs.len()),
+        _ => format!("{risky} of {} output(s) overwrite a file, convert? [y]es, [n]o, [e]ach file: ", jobs.len()),
+    };
+    match ask(&question, "yne") {
+        'y' => return Ok(jobs),
+        'n' => return Err(ImcoError::Declined),
+        _ => {}
+    }
+    // File by file only the risky ones are asked about, or every one of a large batch without any
+    let mut agreed = vec![];
+    let mut all = false;
+    for ((job, planned), risk) in jobs.into_iter().zip(plan).zip(risks) {
+        if all || (risky > 0 && risk.is_none()) {
+            agreed.push(job);
+            continue
+        }
+        let target = planned.map(|(output, _)| output).unwrap_or_default();
+        let risk = risk.map(|risk| format!(" ({risk})")).unwrap_or_default();
+        match ask(&format!("{} -> {}{risk}? [y]es, [n]o, [a]ll remaining, [q]uit: ", shown(&job.input), shown(&target)), "ynaq") {
+            'y' => agreed.push(job),
+            'a' => {
+                all = true;
+                agreed.push(job)
+            }
+            'q' => break,
+            _ => {}
+        }
+    }
+    if agreed.is_empty() { return Err(ImcoError::Declined) }
+    Ok(agreed)
+}
+
+// Asks on stderr until one of the answers is typed, the end of stdin declining
+fn ask(question: &str, answers: &str) -> char {
+    loop {
+        eprint!("{question}");
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            eprintln!();
+            return if answers.contains('q') { 'q' } else { 'n' }
+        }
+        let mut chars = line.trim().chars().map(|c| c.to_ascii_lowercase());
+        if let (Some(answer), None) = (chars.next(), chars.next()) {
+            if answers.contains(answer) { return answer }
+        }
+    }
+}
+
+// Converts every job of a --jobs-file in turn, after checking all of their command lines
+fn run_jobs(path: &str, args: &[OsString], report: Report) -> ImcoResult<Vec<FileOutcome>> {
+    let command = cli();
+    let mut jobs = vec![];
+    for (index, job) in read_jobs(path)?.iter().enumerate() {
+        jobs.push(job_matches(args, job, &command)?.map_err(|hint| ImcoError::JobsFile(path.to_string(), format!("job {}: {hint}", index + 1)))?);
+    }
+    let mut outcomes = vec![];
+    for matches in jobs {
+        outcomes.extend(parse_and_execute(matches, report, None)?);
+    }
+    Ok(outcomes)
+}
+
+#[cfg(not(feature = "plugins"))]
+fn load_plugin(path: &str) -> ImcoResult<Vec<String>> {
+    Err(ImcoError::Plugin(path.to_string(), "imco was built without the plugins feature".to_string()))
+}
+
+// Presets generating assets, left to clap instead of the [presets] table
+const BUILTIN_PRESETS: [&str; 3] = ["favicon", "ios-appicon", "android-mipmap"];
+
+// Subcommands taking the options of converting
+const CONVERT_COMMANDS: [&str; 4] = ["convert", "resize", "frames", "watch"];
+
+// Replaces `--preset NAME` by the options of that preset, placed first (after a conversion subcommand)
+// so the command line overrides them. The BUILTIN_PRESETS are left to clap
+fn expand_preset(args: Vec<OsString>, command: &clap::Command) -> ImcoResult<Vec<OsString>> {
+    let Some(position) = args.iter().position(|arg| arg == "--preset" || arg.to_str().is_some_and(|arg| arg.starts_with("--preset="))) else { return Ok(args) };
+    // Names that aren't UTF-8 are left to clap to refuse
+    let (name, taken) = match args[position].to_str().and_then(|arg| arg.strip_prefix("--preset=")) {
+        Some(name) => (name, 1),
+        None => match args.get(position + 1).and_then(|name| name.to_str()) {
+            Some(name) => (name, 2),
+            None => return Ok(args)
+        }
+    };
+    if BUILTIN_PRESETS.contains(&name) { return Ok(args) }
+    let preset = load_preset(name, command)?;
+    let start = if args.get(1).and_then(|arg| arg.to_str()).is_some_and(|arg| CONVERT_COMMANDS.contains(&arg)) { 2 } else { 1 };
+    Ok(args[..start].iter().cloned()
+        .chain(preset.into_iter().map(OsString::from))
+        .chain(args[start..position].iter().cloned())
+        .chain(args[position + taken..].iter().cloned())
+        .collect())
+}
+
+// The options of converting, shared by the root command (converting by default) and the conversion subcommands
+fn with_convert_args(command: clap::Command) -> clap::Command {
+    command
+        .after_help("Accepted file formats:\n avif, jpg / jpeg / jfif, jxl, png / apng, pdf (output only),\n gif, webp, tif / tiff, tga, dds,\n bmp, ico, hdr, exr, pbm / pam / ppm / pgm,\n ff, qoi, pcx, svg (input only),\n heic / heif (input only, with the heif feature),\n camera raw like cr2 / nef / arw / dng (input only, with the raw feature)")
         .arg(Arg::new("input")
             .index(1)
-            .help("Input files (seperated by ',')")
-            .required(true)
+            .help("Input files (seperated by ','), or the output with --input-list or --from-clipboard")
+            .required_unless_present_any(["input-list", "from-clipboard", "jobs-file", "resume"])
             .value_delimiter(',')
             .value_hint(ValueHint::AnyPath)
+            .value_parser(clap::value_parser!(PathBuf))
             .value_name("FILE")
             .action(clap::ArgAction::Set))
         .arg(Arg::new("output")
             .index(2)
-            .help("Output files (seperated by ',')")
+            .help("Output files (seperated by ','), each written in the format of its extension or one named like out.bin:png, else in --output-format")
             .value_delimiter(',')
             .value_hint(ValueHint::AnyPath)
+            .value_parser(clap::value_parser!(PathBuf))
             .value_name("FILE")
             .action(clap::ArgAction::Set))
+        .arg(Arg::new("output-dir")
+            .help("Write every output into this directory (created if missing) instead of pairing outputs with the inputs, in --output-format or else the input's format")
+            .long("output-dir")
+            .value_hint(ValueHint::DirPath)
+            .value_parser(clap::value_parser!(PathBuf))
+            .value_name("DIR")
+            .conflicts_with_all(["output", "to-clipboard", "in-place"])
+            .action(clap::ArgAction::Set))
         .arg(Arg::new("input-format")
             .help("Input files formats (see below)")
             .short('f')
             .long("input-format")
             .value_name("FORMAT")
+            .value_parser(FormatNameParser)
+            .hide_possible_values(true)
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("raw-input")
+            .help("Read inputs as bare pixels of this size and layout (rgba8, bgra8 or gray8) like 640x480:rgba8, skipping a header of --raw-header")
+            .long("raw-input")
+            .value_name("WxH:LAYOUT")
+            .conflicts_with("input-format")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("raw-layout")
+            .help("The pixels of raw outputs (-o raw), in rows from top to bottom without padding")
+            .long("raw-layout")
+            .value_parser(["rgba8", "bgra8", "gray8"])
+            .default_value("rgba8")
             .action(clap::ArgAction::Set))
+        .arg(Arg::new("raw-header")
+            .help("Start raw outputs with a line of JSON giving their width, height, stride (bytes per row) and layout")
+            .long("raw-header")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("strict-extensions")
+            .help("Fail on inputs whose extension names another format than their content instead of warning")
+            .long("strict-extensions")
+            .conflicts_with_all(["input-format", "raw-input"])
+            .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("output-format")
-            .help("Output files formats (see below)")
+            .help("Output files formats (see below), several like webp,avif,jpg write every output in each of them from one decode")
             .short('o')
             .long("output-format")
             .value_name("FORMAT")
+            .value_delimiter(',')
+            .value_parser(FormatNameParser)
+            .hide_possible_values(true)
             .action(clap::ArgAction::Set))
         .arg(Arg::new("batch")
             .help("Enables batch processing (using patterns to specify multiple files at once)")
             .short('b')
             .long("batch")
             .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("recursive")
+            .help("Convert all images inside input directories, recreating their structure below the output directory")
+            .short('R')
+            .long("recursive")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("follow-symlinks")
+            .help("Convert what symbolic links point to, entering linked directories only once (the default)")
+            .long("follow-symlinks")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("no-follow-symlinks")
+            .help("Leave out symbolic links matched by --batch patterns or found with --recursive")
+            .long("no-follow-symlinks")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("recreate-symlinks")
+            .help("Link the outputs of symbolic links to other inputs to those inputs' outputs instead of converting them twice")
+            .long("recreate-symlinks")
+            .action(clap::ArgAction::SetTrue))
+        .group(clap::ArgGroup::new("symlinks")
+            .args(["follow-symlinks", "no-follow-symlinks", "recreate-symlinks"]))
+        .arg(Arg::new("exclude")
+            .help("Skip inputs matching this pattern, e.g. '**/node_modules/**' or '*_thumb.png', may be given multiple times")
+            .long("exclude")
+            .value_name("PATTERN")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("only-format")
+            .help("Only convert inputs whose content is in one of these formats (seperated by ','), e.g. png,tiff")
+            .long("only-format")
+            .value_name("FORMATS")
+            .value_delimiter(',')
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("min-width")
+            .help("Only convert inputs at least this many pixels wide")
+            .long("min-width")
+            .value_name("PIXELS")
+            .value_parser(clap::value_parser!(u32))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("max-width")
+            .help("Only convert inputs at most this many pixels wide")
+            .long("max-width")
+            .value_name("PIXELS")
+            .value_parser(clap::value_parser!(u32))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("min-height")
+            .help("Only convert inputs at least this many pixels high")
+            .long("min-height")
+            .value_name("PIXELS")
+            .value_parser(clap::value_parser!(u32))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("max-height")
+            .help("Only convert inputs at most this many pixels high")
+            .long("max-height")
+            .value_name("PIXELS")
+            .value_parser(clap::value_parser!(u32))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("min-bytes")
+            .help("Only convert inputs of at least this file size, e.g. 200KB or 1.5MiB")
+            .long("min-bytes")
+            .value_name("SIZE")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("max-bytes")
+            .help("Only convert inputs of at most this file size, e.g. 200KB or 1.5MiB")
+            .long("max-bytes")
+            .value_name("SIZE")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("sample")
+            .help("Only convert this many of the matched inputs, picked at random, e.g. to try settings before a long run")
+            .long("sample")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("sample-percent")
+            .help("Only convert this percentage of the matched inputs, picked at random")
+            .long("sample-percent")
+            .value_name("PERCENT")
+            .conflicts_with("sample")
+            .value_parser(|v: &str| v.strip_suffix('%').unwrap_or(v).parse::<f64>().ok().filter(|f| *f > 0.0 && *f <= 100.0).ok_or("must be a percentage above 0 and up to 100"))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("sample-every")
+            .help("Only convert every Nth of the matched inputs, starting with the first")
+            .long("sample-every")
+            .value_name("N")
+            .conflicts_with_all(["sample", "sample-percent"])
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("seed")
+            .help("Pick the same random --sample or --sample-percent inputs on every run, otherwise the seed of a run is shown")
+            .long("seed")
+            .value_name("SEED")
+            .value_parser(clap::value_parser!(u64))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("space-ratio")
+            .help("Expect this many output bytes per input byte when checking for free space before a batch, e.g. 0.3 for photos into avif (by default generous guesses)")
+            .long("space-ratio")
+            .value_name("RATIO")
+            .value_parser(|v: &str| v.parse::<f64>().ok().filter(|ratio| *ratio >= 0.0 && ratio.is_finite()).ok_or("must be a positive number"))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("no-space-check")
+            .help("Start converting even when the outputs likely don't fit onto their volume")
+            .long("no-space-check")
+            .conflicts_with("space-ratio")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("input-list")
+            .help("Read the input paths from this file ('-' for stdin), one per line, converting them like --batch")
+            .long("input-list")
+            .value_hint(ValueHint::FilePath)
+            .value_parser(clap::value_parser!(PathBuf))
+            .value_name("FILE")
+            .conflicts_with_all(["output", "batch", "watch"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("jobs-file")
+            .help("Convert the jobs of this JSON file ('-' for stdin) in turn, an array of objects like {\"input\": \"a.png\", \"output\": \"a.webp\", \"format\": \"webp\", \"options\": {\"resize\": \"800x\", \"quality\": 80}} whose options (by long name) override those of the command line")
+            .long("jobs-file")
+            .value_hint(ValueHint::FilePath)
+            .value_name("FILE")
+            .conflicts_with_all(["input", "output", "input-list", "from-clipboard", "watch"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("resume")
+            .help("Continue an interrupted batch where it stopped, with its arguments: the name imco printed, a session file or 'last'. Inputs it converted are left out")
+            .long("resume")
+            .value_name("SESSION")
+            .conflicts_with_all(["input", "output", "input-list", "from-clipboard", "jobs-file", "watch"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("no-session")
+            .help("Don't record the progress of batches, which can't be resumed then")
+            .long("no-session")
+            .conflicts_with("resume")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("from-clipboard")
+            .help("Convert the image on the clipboard, the only positional argument is then the output")
+            .long("from-clipboard")
+            .conflicts_with_all(["output", "input-list", "batch", "recursive", "watch"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("to-clipboard")
+            .help("Put the converted image onto the clipboard instead of writing it (waits on Linux until something else is copied)")
+            .long("to-clipboard")
+            .conflicts_with_all(["output", "data-uri", "archive", "preview", "tile", "slice", "extract-frames", "animate"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("max-download")
+            .help("Largest url input that is downloaded, like 20MB")
+            .long("max-download")
+            .value_name("SIZE")
+            .default_value("100MB")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("download-timeout")
+            .help("Seconds until downloading a url input is given up")
+            .long("download-timeout")
+            .value_name("SECONDS")
+            .default_value("30")
+            .value_parser(clap::value_parser!(u64))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("null")
+            .help("Paths in the --input-list are separated by NUL bytes, as printed by find -print0")
+            .short('0')
+            .long("null")
+            .requires("input-list")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("watch")
+            .help("Keep running and convert files matching the input patterns whenever they are created or modified")
+            .long("watch")
+            .requires("output-format")
+            .conflicts_with_all(["recursive", "animate", "preset", "dry-run"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("dpi")
+            .help("Resolution svg inputs are rasterized at")
+            .long("dpi")
+            .value_name("DPI")
+            .value_parser(|v: &str| v.parse::<f32>().ok().filter(|d| *d > 0.0).ok_or("must be a positive number"))
+            .default_value("96")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("rasterize-size")
+            .help("Rasterize svg inputs at this size instead (WxH, Wx or xH)")
+            .long("rasterize-size")
+            .value_name("SIZE")
+            .conflicts_with("dpi")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("ops")
+            .help("Operations applied in this order before all other transforms, separated by '|' (autorotate, rotate:DEGREES[:crop], flip:h|v, crop:X,Y,WxH, crop:center:WxH, resize:WxH[:contain|cover], scale:PERCENT, thumbnail:SIZE, auto-levels[:CLIP], auto-contrast[:CLIP], brightness:N, contrast:N, gamma:N, grayscale, invert, sepia, tint:COLOR[:STRENGTH], vignette:STRENGTH[,RADIUS], threshold:LUMA, posterize:LEVELS, blur:SIGMA, denoise:STRENGTH[:median|bilateral], sharpen, unsharp:AMOUNT,RADIUS[,THRESHOLD], pad:WxH[:COLOR], border:WIDTH[:COLOR], round-corners:RADIUS, circle-crop, crop-to:WxH[:GRAVITY], aspect:W:H[:GRAVITY], trim[:FUZZ%], chroma-key:COLOR[:TOLERANCE%] and those of --plugin)")
+            .long("ops")
+            .value_name("OPS")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("plugin")
+            .help("Load the operations of a plugin, a dynamic library exporting the functions of include/imco_plugin.h, for --ops")
+            .long("plugin")
+            .value_name("LIBRARY")
+            .value_hint(ValueHint::FilePath)
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("rotate")
+            .help("Rotate clockwise by the given degrees")
+            .long("rotate")
+            .value_name("DEGREES")
+            .value_parser(["90", "180", "270"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("rotate-deg")
+            .help("Rotate clockwise by any angle (applied after --rotate), e.g. to deskew scans, filling the corners with --background or transparency")
+            .long("rotate-deg")
+            .value_name("DEGREES")
+            .allow_negative_numbers(true)
+            .value_parser(|v: &str| v.parse::<f32>().ok().filter(|d| d.is_finite()).ok_or("must be a number"))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("rotate-crop")
+            .help("Keep the size of the image with --rotate-deg instead of growing the canvas to fit it")
+            .long("rotate-crop")
+            .requires("rotate-deg")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("flip")
+            .help("Flip horizontally (h) or vertically (v), may be given twice")
+            .long("flip")
+            .value_parser(["h", "v"])
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("crop")
+            .help("Crop to the WxH area starting at X,Y (before resizing)")
+            .long("crop")
+            .value_name("X,Y,WxH")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("crop-center")
+            .help("Crop to a WxH area around the center (before resizing)")
+            .long("crop-center")
+            .value_name("WxH")
+            .conflicts_with("crop")
+            .action(clap::ArgAction::Set))
         .arg(Arg::new("resize")
-            .help("Set output resolution a.k.a resize")
+            .help("Set output resolution a.k.a resize (WxH, or Wx / xH to keep the aspect ratio)")
             .short('r')
             .long("resize")
             .alias("res")
-            .num_args(2)
-            .value_names(["width", "height"])
+            .value_name("WxH")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("crop-to")
+            .help("Scale to cover WxH and crop the overflow, placing the window after --gravity (after resizing)")
+            .long("crop-to")
+            .value_name("WxH")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("chroma-key")
+            .help("Make the background of this color transparent, e.g. '#00ff00' for a green screen or '#fff' for a white sweep (before --trim, outputs need alpha)")
+            .long("chroma-key")
+            .value_name("COLOR")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("key-tolerance")
+            .help("How many percent off in every channel colors still count as the --chroma-key background, the edges fading in over as much again")
+            .long("key-tolerance")
+            .value_name("PERCENT")
+            .requires("chroma-key")
+            .default_value("10")
+            .value_parser(|v: &str| v.strip_suffix('%').unwrap_or(v).parse::<f32>().ok().filter(|f| (0.0..=100.0).contains(f)).ok_or("must be a percentage up to 100"))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("trim")
+            .help("Crop away the uniform border (the color of the top left corner) or transparent margins, counting colors up to FUZZ percent off as border, e.g. for scans and screenshots (before --crop)")
+            .long("trim")
+            .value_name("FUZZ")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("0")
+            .value_parser(|v: &str| v.strip_suffix('%').unwrap_or(v).parse::<f32>().ok().filter(|f| (0.0..=100.0).contains(f)).ok_or("must be a percentage up to 100"))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("aspect")
+            .help("Crop to the largest window with this aspect ratio, like 16:9, 4:3, 1:1 or 9:16, placing it after --gravity (before resizing)")
+            .long("aspect")
+            .value_name("W:H")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("gravity")
+            .help("Where --crop-to and --aspect keep the image: a side, the center, the most detailed part (entropy) or the most eye-catching part (attention)")
+            .long("gravity")
+            .value_parser(["center", "north", "south", "east", "west", "entropy", "attention"])
+            .default_value("center")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("thumbnail")
+            .help("Fast, aspect preserving thumbnail whose longest side is SIZE pixels")
+            .long("thumbnail")
+            .value_name("SIZE")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .conflicts_with("resize")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("suffix")
+            .help("Append this to the name of generated outputs, e.g. _thumb")
+            .long("suffix")
+            .value_name("SUFFIX")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("name-template")
+            .help("Name generated outputs after this template, e.g. {stem}_{width}x{height}.{ext}. Placeholders: {stem}, {ext}, {format}, {width}, {height}, {index}, {date}, and {exif.year}, {exif.month}, {exif.day}, {exif.date} from the date a photo was taken")
+            .long("name-template")
+            .value_name("TEMPLATE")
+            .conflicts_with("suffix")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("organize-by-date")
+            .help("Sort generated outputs into YEAR/MONTH folders after the date in their exif (unknown/unknown without one)")
+            .long("organize-by-date")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("fit")
+            .help("How --resize treats the aspect ratio when both sides are given")
+            .long("fit")
+            .value_parser(["exact", "contain", "cover"])
+            .default_value("exact")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("filter")
+            .help("Resampling filter used when resizing")
+            .long("filter")
+            .value_parser(["nearest", "triangle", "catmull-rom", "gaussian", "lanczos3"])
+            .default_value("lanczos3")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("resize-backend")
+            .help("Implementation used by --resize, fast is SIMD accelerated, image is the slower fallback")
+            .long("resize-backend")
+            .value_parser(["image", "fast"])
+            .default_value("fast")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("linear-resize")
+            .help("Resize in linear light instead of on the sRGB values, avoiding dark halos and lost highlights, recommended for photos")
+            .long("linear-resize")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("gpu")
+            .help("Resize, apply --lut, --sepia and gray --colorspace on the GPU (needs the gpu feature), falls back to the CPU without one")
+            .long("gpu")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("keep-going")
+            .help("Continue with the remaining files when a conversion fails")
+            .short('k')
+            .long("keep-going")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("dry-run")
+            .help("Print the planned conversions without reading or writing any image")
+            .long("dry-run")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("data-uri")
+            .help("Write outputs as data:image/...;base64 URIs for inlining into CSS or HTML, printed to stdout without an output")
+            .long("data-uri")
+            .conflicts_with_all(["tile", "slice", "extract-frames", "animate", "archive", "pdf-per-file"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("preview")
+            .help("Show every output in the terminal after converting (see the preview subcommand)")
+            .long("preview")
+            .conflicts_with_all(["json", "data-uri", "archive", "dry-run", "tile", "slice", "extract-frames"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("json")
+            .help("Print one JSON object per file instead of text")
+            .long("json")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("timings")
+            .help("Time decoding, transforming and encoding of every file and print a summary at the end")
+            .long("timings")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("summary")
+            .help("Print how many files were converted, skipped and failed and how much smaller they got, also for single files and with --json (done after every batch otherwise)")
+            .long("summary")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("log-file")
+            .help("Append every conversion, skip, warning and error with a timestamp to this file, regardless of --quiet and --verbose")
+            .long("log-file")
+            .value_hint(ValueHint::FilePath)
+            .value_name("FILE")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("log-format")
+            .help("Write the --log-file as text lines or JSON lines")
+            .long("log-format")
+            .value_name("FORMAT")
+            .value_parser(["text", "json"])
+            .default_value("text")
+            .requires("log-file")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("no-progress")
+            .help("Never show a progress bar (it is only shown on terminals anyway)")
+            .long("no-progress")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("jobs")
+            .help("Number of files to convert in parallel (0 for one per CPU core)")
+            .short('j')
+            .long("jobs")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("1")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("cache-max-mb")
+            .help("Memory for keeping decoded inputs that several outputs are made from, so they are decoded once (0 decodes them for every output)")
+            .long("cache-max-mb")
+            .value_name("MIB")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("512")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("timeout")
+            .help("Give up on files taking longer than this to convert, leaving their output unwritten")
+            .long("timeout")
+            .value_name("SECS")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .conflicts_with("archive")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("low-memory")
+            .help("Convert PNG and TIFF inputs into PNG or TIFF a strip at a time when only per-pixel adjustments are used, others are decoded whole")
+            .long("low-memory")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("retries")
+            .help("Try reading inputs and writing outputs this many more times when that fails transiently (timeouts, busy or stale files of network shares)")
+            .long("retries")
+            .value_name("N")
+            .default_value("0")
+            .value_parser(clap::value_parser!(u32))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("retry-delay")
+            .help("Milliseconds to wait before each of the --retries")
+            .long("retry-delay")
+            .value_name("MS")
+            .default_value("500")
+            .value_parser(clap::value_parser!(u64))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("verify")
+            .help("Read every output back after writing to make sure it is valid")
+            .long("verify")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("verify-full")
+            .help("Like --verify, but fully decodes the output")
+            .long("verify-full")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("no-overwrite")
+            .help("Fail instead of overwriting existing outputs")
+            .short('n')
+            .long("no-overwrite")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("skip-existing")
+            .help("Leave existing outputs alone and skip their inputs")
+            .long("skip-existing")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("rename-on-conflict")
+            .help("Write to <name>-1, <name>-2, ... when the output already exists")
+            .long("rename-on-conflict")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("uniquify")
+            .help("Write inputs that would share an output to <name>-1, <name>-2, ... instead of failing")
+            .long("uniquify")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("newer-only")
+            .help("Skip inputs whose output is newer than them, like make")
+            .long("newer-only")
+            .action(clap::ArgAction::SetTrue))
+        .group(clap::ArgGroup::new("conflict")
+            .args(["no-overwrite", "skip-existing", "rename-on-conflict"]))
+        .arg(Arg::new("backup")
+            .help("Move existing outputs to <output><SUFFIX> before overwriting")
+            .long("backup")
+            .value_name("SUFFIX")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value(".bak")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("strict-backup")
+            .help("Fail instead of numbering backups when the backup already exists")
+            .long("strict-backup")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("backup-dir")
+            .help("Move backups into this directory instead of next to the output (implies --backup)")
+            .long("backup-dir")
+            .value_hint(ValueHint::DirPath)
+            .value_name("DIR")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("in-place")
+            .help("Write every input back onto itself in its own format, keeping the original as <input>.bak (see --backup)")
+            .long("in-place")
+            .conflicts_with_all(["output", "output-format", "from-clipboard", "to-clipboard", "data-uri", "archive", "animate", "extract-frames", "tile", "slice", "split-channels", "srcset"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("confirm")
+            .help("Show where every input goes first and ask on stdin before overwriting files, writing one output for several inputs or converting a large batch")
+            .long("confirm")
+            .conflicts_with("watch")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("dedupe")
+            .help("Link outputs with identical pixel data to the first one written instead of encoding again")
+            .long("dedupe")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("dedupe-symlink")
+            .help("Use symlinks instead of hardlinks for --dedupe")
+            .long("dedupe-symlink")
+            .requires("dedupe")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("checksum")
+            .help("Write a checksum of every output into a sidecar like out.png.sha256, check them with imco verify-checksums or sha256sum -c")
+            .long("checksum")
+            .value_name("ALGORITHM")
+            .value_parser(["sha256", "sha512"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("checksum-manifest")
+            .help("Collect the --checksum of every output into this one file instead of sidecars, with paths relative to it")
+            .long("checksum-manifest")
+            .value_hint(ValueHint::FilePath)
+            .value_name("FILE")
+            .requires("checksum")
+            .conflicts_with("watch")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("archive")
+            .help("Write all outputs into a single zip archive instead of separate files")
+            .long("archive")
+            .visible_alias("output-archive")
+            .value_name("ZIP")
+            .value_hint(ValueHint::FilePath)
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("quality")
+            .help("Output quality for lossy formats (JPEG, AVIF)")
+            .long("quality")
+            .value_name("1-100")
+            .value_parser(clap::value_parser!(u8).range(1..=100))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("set")
+            .help(format!("Advanced encoder setting, may be repeated: {}", EncoderSettings::KEYS.iter().map(|(key, values)| format!("{key} ({values})")).collect::<Vec<_>>().join(", ")))
+            .long("set")
+            .value_name("FORMAT.KEY=VALUE")
+            .value_delimiter(',')
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("progressive")
+            .help("Write progressive JPEGs, which are usually smaller and load coarse to fine on the web (same as --set jpeg.progressive=true)")
+            .long("progressive")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("interlace")
+            .help("Write Adam7 interlaced PNGs (same as --set png.interlace=true)")
+            .long("interlace")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("target-size")
+            .help("Largest output size for lossy formats (JPEG, AVIF), the highest quality that fits is searched for, e.g. 200KB or 1.5MiB")
+            .long("target-size")
+            .value_name("SIZE")
+            .conflicts_with("quality")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("denoise")
+            .help("Reduce noise before resizing, e.g. of high-ISO photos before lossy compression: a median filter of this radius or a bilateral filter (the default) smoothing grain but not edges")
+            .long("denoise")
+            .value_name("STRENGTH[:median|bilateral]")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("blur")
+            .help("Gaussian blur with this sigma (after resizing)")
+            .long("blur")
+            .value_name("SIGMA")
+            .value_parser(clap::value_parser!(f32))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("sharpen")
+            .help("Light unsharp mask for downscaled images, the same as --unsharp 0.5,1,2")
+            .long("sharpen")
+            .conflicts_with("unsharp")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("unsharp")
+            .help("Unsharp mask (after resizing and blurring), THRESHOLD being the smallest difference (out of 255) that is sharpened")
+            .long("unsharp")
+            .value_name("AMOUNT,RADIUS[,THRESHOLD]")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("auto-levels")
+            .help("Stretch every color channel so the darkest and brightest CLIP percent of it become black and white, e.g. for flat scans")
+            .long("auto-levels")
+            .value_name("CLIP")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("0.5")
+            .value_parser(|v: &str| v.parse::<f32>().ok().filter(|c| (0.0..50.0).contains(c)).ok_or("must be a percentage below 50"))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("auto-contrast")
+            .help("Like --auto-levels, but stretching all channels alike by the luma, keeping the hues")
+            .long("auto-contrast")
+            .value_name("CLIP")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("0.5")
+            .value_parser(|v: &str| v.parse::<f32>().ok().filter(|c| (0.0..50.0).contains(c)).ok_or("must be a percentage below 50"))
+            .conflicts_with("auto-levels")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("brightness")
+            .help("Add to every color channel (negative values darken)")
+            .long("brightness")
+            .value_name("AMOUNT")
+            .allow_negative_numbers(true)
+            .value_parser(clap::value_parser!(i32).range(-255..=255))
+            .default_value("0")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("contrast")
+            .help("Adjust the contrast (negative values reduce it), applied after --brightness")
+            .long("contrast")
+            .value_name("AMOUNT")
+            .allow_negative_numbers(true)
+            .value_parser(clap::value_parser!(f32))
+            .default_value("0")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("gamma")
+            .help("Gamma correction, values above 1 brighten (applied after --contrast)")
+            .long("gamma")
+            .value_name("GAMMA")
+            .value_parser(|v: &str| v.parse::<f32>().ok().filter(|g| *g > 0.0).ok_or("must be a positive number"))
+            .default_value("1")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("lut")
+            .help("Color grade with a 3D lookup table, a .cube file or a HALD image like a 512x512 png (applied after --gamma)")
+            .long("lut")
+            .value_name("FILE")
+            .value_hint(ValueHint::FilePath)
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("invert")
+            .help("Invert the colors, keeping the alpha (applied after --lut)")
+            .long("invert")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("sepia")
+            .help("Give the image a sepia tone (applied after --invert)")
+            .long("sepia")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("tint")
+            .help("Colorize the image in this color, by a strength from 0 to 1 (0.5 by default), like '#ff8800:0.3' (applied after --sepia)")
+            .long("tint")
+            .value_name("COLOR[:STRENGTH]")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("vignette")
+            .help("Darken the corners by a strength from 0 to 1, starting at RADIUS (0.5 by default) of the way from the center to the corners, like '0.4,0.6' (applied after --tint)")
+            .long("vignette")
+            .value_name("STRENGTH[,RADIUS]")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("threshold")
+            .help("Turn the image black and white, pixels with at least this luma (0-255) becoming white, like for OCR (applied after --vignette)")
+            .long("threshold")
+            .value_name("LUMA")
+            .value_parser(clap::value_parser!(u8))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("posterize")
+            .help("Reduce every color channel to this many levels (applied after --threshold)")
+            .long("posterize")
+            .value_name("LEVELS")
+            .value_parser(clap::value_parser!(u8).range(2..))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("pad")
+            .help("Center the image on a WxH canvas (letterbox), shrinking it first if it doesn't fit")
+            .long("pad")
+            .value_name("WxH")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("pad-color")
+            .help("Color of the --pad canvas")
+            .long("pad-color")
+            .value_name("COLOR")
+            .default_value("#000000")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("border")
+            .help("Frame the image with a border WIDTH pixels wide (after --round-corners or --circle-crop, following their shape), black if no color is given")
+            .long("border")
+            .value_name("WIDTH[:COLOR]")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("round-corners")
+            .help("Make the corners transparent, rounded with this radius (after --pad), for cards")
+            .long("round-corners")
+            .value_name("RADIUS")
+            .value_parser(clap::value_parser!(u32))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("circle-crop")
+            .help("Crop the centered square and make everything outside its circle transparent, for avatars")
+            .long("circle-crop")
+            .conflicts_with("round-corners")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("apply-alpha")
+            .help("Set the alpha of every output from the luma of this mask (white opaque), stretched to the output's size")
+            .long("apply-alpha")
+            .value_name("MASK")
+            .value_hint(ValueHint::FilePath)
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("extract-alpha")
+            .help("Write the alpha channel of outputs as a grayscale mask instead, white where there is no alpha")
+            .long("extract-alpha")
+            .conflicts_with("split-channels")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("watermark")
+            .help("Composite this image onto every output")
+            .long("watermark")
+            .value_name("IMAGE")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("watermark-pos")
+            .help("Where to place the watermark")
+            .long("watermark-pos")
+            .value_name("POSITION")
+            .value_parser(["top-left", "top", "top-right", "left", "center", "right", "bottom-left", "bottom", "bottom-right"])
+            .default_value("bottom-right")
+            .requires("watermark")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("watermark-opacity")
+            .help("Opacity of the watermark between 0 and 1")
+            .long("watermark-opacity")
+            .value_name("OPACITY")
+            .value_parser(|v: &str| v.parse::<f32>().ok().filter(|o| (0.0..=1.0).contains(o)).ok_or("must be between 0 and 1"))
+            .default_value("1")
+            .requires("watermark")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("caption")
+            .help("Stamp this text onto every output (after --watermark), with the placeholders {filename}, {stem}, {width}, {height} and {date}")
+            .long("caption")
+            .value_name("TEXT")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("font")
+            .help("TrueType or OpenType font of the caption [default: a built-in ASCII pixel font]")
+            .long("font")
+            .value_name("FILE")
+            .value_hint(ValueHint::FilePath)
+            .requires("caption")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("font-size")
+            .help("Height of the caption text in pixels")
+            .long("font-size")
+            .value_name("PIXELS")
+            .value_parser(|v: &str| v.parse::<f32>().ok().filter(|s| *s > 0.0 && s.is_finite()).ok_or("must be a positive number"))
+            .default_value("24")
+            .requires("caption")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("caption-pos")
+            .help("Where to place the caption")
+            .long("caption-pos")
+            .value_name("POSITION")
+            .value_parser(["top-left", "top", "top-right", "left", "center", "right", "bottom-left", "bottom", "bottom-right"])
+            .default_value("bottom")
+            .requires("caption")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("caption-color")
+            .help("Color of the caption text, which has a dark shadow")
+            .long("caption-color")
+            .value_name("COLOR")
+            .default_value("#ffffff")
+            .requires("caption")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("first-frame-only")
+            .help("Only convert the first frame of animations (GIF and PNG outputs keep them otherwise)")
+            .long("first-frame-only")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("extract-frames")
+            .help("Write every frame of an animation or page of a TIFF to its own file, numbered at a placeholder like out_%03d.png")
+            .long("extract-frames")
+            .conflicts_with("first-frame-only")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("pages")
+            .help("Pages of a multi-page TIFF (or frames of an animation) to convert: all, N or FIRST-LAST. Several are numbered like with --extract-frames")
+            .long("pages")
+            .value_name("PAGES")
+            .conflicts_with_all(["first-frame-only", "tile", "slice", "split-channels", "srcset", "animate"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("tile")
+            .help("Split outputs into WxH tiles named after their row and column, at {row} and {col} or behind the file stem (see the stitch subcommand)")
+            .long("tile")
+            .value_name("WxH")
+            .conflicts_with_all(["extract-frames", "animate"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("slice")
+            .help("Cut outputs into ROWS rows and COLUMNS columns of equal cells numbered row by row like --extract-frames, e.g. to take a sprite sheet or album page apart")
+            .long("slice")
+            .value_name("ROWSxCOLUMNS")
+            .value_parser(|v: &str| v.split_once('x')
+                .and_then(|(rows, columns)| Some((rows.parse::<u32>().ok()?, columns.parse::<u32>().ok()?)))
+                .filter(|(rows, columns)| *rows > 0 && *columns > 0)
+                .ok_or("must be ROWSxCOLUMNS like 2x3"))
+            .conflicts_with_all(["tile", "extract-frames", "animate"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("split-channels")
+            .help("Write every channel of outputs as a grayscale file named with {channel} or behind the file stem, like photo_r.png (see the merge-channels subcommand)")
+            .long("split-channels")
+            .conflicts_with_all(["tile", "slice", "extract-frames", "animate", "data-uri", "to-clipboard", "preview"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("srcset")
+            .help("Write outputs at each of these widths for responsive images, named with {width} or behind the file stem like photo-480.webp (never enlarging)")
+            .long("srcset")
+            .value_name("WIDTHS")
+            .value_delimiter(',')
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .conflicts_with_all(["tile", "slice", "split-channels", "extract-frames", "animate", "data-uri", "to-clipboard", "preview"])
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("srcset-print")
+            .help("Print the srcset attribute of every input as HTML, or all of them as a JSON manifest, also with --quiet")
+            .long("srcset-print")
+            .value_name("STYLE")
+            .value_parser(["html", "json"])
+            .requires("srcset")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("blurhash")
+            .help("Compute the BlurHash placeholder of every output, printed and put into the --json report")
+            .long("blurhash")
+            .conflicts_with_all(["tile", "slice", "split-channels", "extract-frames", "animate"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("thumbhash")
+            .help("Compute the base64 ThumbHash placeholder of every output, printed and put into the --json report")
+            .long("thumbhash")
+            .conflicts_with_all(["tile", "slice", "split-channels", "extract-frames", "animate"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("hash-sidecar")
+            .help("Also write the placeholder hashes next to every output, into <output>.json")
+            .long("hash-sidecar")
+            .requires("placeholder-hash")
+            .action(clap::ArgAction::SetTrue))
+        .group(clap::ArgGroup::new("placeholder-hash").args(["blurhash", "thumbhash"]).multiple(true))
+        .arg(Arg::new("animate")
+            .help("Assemble all inputs, in order, into one animated gif or png")
+            .long("animate")
+            .conflicts_with_all(["extract-frames", "first-frame-only", "archive"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("fps")
+            .help("Frames per second of the animation built by --animate, or of converted animations (replacing their timing)")
+            .long("fps")
+            .value_name("FPS")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .conflicts_with_all(["delay", "frame-delay"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("frame-delay")
+            .help("Replace the delay of every frame of converted animations, in milliseconds")
+            .long("frame-delay")
+            .value_name("MS")
+            .value_parser(clap::value_parser!(u32))
+            .conflicts_with("animate")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("delay")
+            .help("Delay of every frame built by --animate in milliseconds")
+            .long("delay")
+            .value_name("MS")
+            .value_parser(clap::value_parser!(u32))
+            .default_value("100")
+            .requires("animate")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("loop")
+            .help("How often animations play, 0 for forever")
+            .long("loop")
+            .value_name("COUNT")
+            .value_parser(clap::value_parser!(u16))
+            .default_value("0")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("ico-sizes")
+            .help("Embed these square sizes into ico outputs (seperated by ','), e.g. 16,32,48,256")
+            .long("ico-sizes")
+            .value_name("SIZES")
+            .value_delimiter(',')
+            .value_parser(clap::value_parser!(u32).range(1..=256))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("mipmaps")
+            .help("Write the full mip chain, down to 1x1, into dds and ktx2 outputs")
+            .long("mipmaps")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("texture-compression")
+            .help("Block compress dds and ktx2 outputs: bc1 (RGB), bc3 (RGBA) or bc7 (higher quality RGBA), they hold plain RGBA otherwise")
+            .long("texture-compression")
+            .value_parser(["bc1", "bc3", "bc7"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("preset")
+            .help("Generate a set of assets from every input into the output directory (favicon: favicon.ico, apple-touch-icon.png and android-chrome pngs; ios-appicon: AppIcon.appiconset with Contents.json for Xcode; android-mipmap: mipmap-*dpi launcher icons and the Play Store icon), or apply a preset of options from the [presets] table of ./imco.toml or ~/.config/imco/imco.toml")
+            .long("preset")
+            .value_name("PRESET")
+            .value_parser(BUILTIN_PRESETS)
+            .conflicts_with_all(["animate", "extract-frames", "batch", "recursive"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("pdf-per-file")
+            .help("Write one pdf per input instead of combining all inputs into the pages of one")
+            .long("pdf-per-file")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("colorspace")
+            .help("Convert the output to 8-bit gray, gray with alpha, RGB or RGBA")
+            .long("colorspace")
+            .value_parser(["gray", "gray-alpha", "rgb", "rgba"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("depth")
+            .help("Convert the output to 8 or 16 bits per channel, keeping its channels")
+            .long("depth")
+            .value_parser(["8", "16"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("preserve-depth")
+            .help("Fail instead of writing 16 bit and float inputs into formats that only hold 8 bits per channel (jpg, webp, avif, gif, ...)")
+            .long("preserve-depth")
+            .conflicts_with_all(["depth", "colors"])
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("lossless-only")
+            .help("Refuse conversions that would lose information: lossy formats, fewer bits per channel, lost transparency or a palette. Errors name the lossy step")
+            .long("lossless-only")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("strict")
+            .help("Fail instead of warning when an output silently loses something of its input: transparency, bits per channel, animation frames, a color profile or the pixel density")
+            .long("strict")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("allow-lossy")
+            .help("Convert lossily even with --lossless-only, e.g. when a preset sets it")
+            .long("allow-lossy")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("tonemap")
+            .help("Compress the range of HDR (exr, hdr) inputs with this curve and write them as sRGB instead of clipping")
+            .long("tonemap")
+            .value_parser(["reinhard", "aces"])
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("exposure")
+            .help("Brighten HDR inputs by this many stops before tone mapping, negative to darken")
+            .long("exposure")
+            .value_name("STOPS")
+            .allow_negative_numbers(true)
+            .value_parser(clap::value_parser!(f32))
+            .default_value("0")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("background")
+            .help("Color transparent pixels are put onto for formats without alpha (jpeg, pdf), e.g. '#000000' [default: white]")
+            .long("background")
+            .value_name("COLOR")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("colors")
+            .help("Reduce the output to a palette of at most N colors, writing png outputs with the palette")
+            .long("colors")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u16).range(2..=256))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("dither")
+            .help("How --colors spreads the difference to the original colors")
+            .long("dither")
+            .value_parser(["floyd-steinberg", "ordered", "none"])
+            .default_value("floyd-steinberg")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("keep-metadata")
+            .help("Copy EXIF metadata into outputs that support it (default when the format stays the same)")
+            .long("keep-metadata")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("strip-metadata")
+            .help("Never copy EXIF metadata into outputs")
+            .long("strip-metadata")
+            .conflicts_with("keep-metadata")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("strip-icc")
+            .help("Don't embed the input's ICC color profile into outputs")
+            .long("strip-icc")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("set-exif")
+            .help("Write an exif text field into outputs, one of Artist, Copyright, ImageDescription, Make, Model, Software, DateTime, DateTimeOriginal and DateTimeDigitized (dates as YYYY:MM:DD HH:MM:SS), may be given multiple times")
+            .long("set-exif")
+            .value_name("KEY=VALUE")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("remove-exif")
+            .help("Remove exif fields from outputs by their name, like GPSLatitude, may be given multiple times")
+            .long("remove-exif")
+            .value_name("KEY")
+            .value_delimiter(',')
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("deterministic")
+            .help("Make converting the same input always give the same bytes, blanking exif dates and software names and sorting --archive entries")
+            .long("deterministic")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("convert-to-srgb")
+            .help("Convert the colors from the input's ICC profile into sRGB instead of embedding the profile")
+            .long("convert-to-srgb")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("preserve-times")
+            .help("Give outputs the modification time of their input")
+            .long("preserve-times")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("preserve-mode")
+            .help("Give outputs the permissions of their input (only the read-only flag outside of Unix)")
+            .long("preserve-mode")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("no-auto-orient")
+            .help("Don't rotate images according to their EXIF orientation")
+            .long("no-auto-orient")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("scale")
+            .help("Resize by a percentage like 50% or a factor like 0.5 (applied after --resize)")
+            .long("scale")
+            .value_name("PERCENT")
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("max-megapixels")
+            .help("Downscale images with more than N million pixels, keeping the aspect ratio, like upload limits of photo services (applied after --max-dimension)")
+            .long("max-megapixels")
+            .value_name("N")
+            .value_parser(|v: &str| v.parse::<f64>().ok().filter(|n| n.is_finite() && *n > 0.0).ok_or("must be a number of megapixels above 0"))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("max-dimension")
+            .help("Downscale images whose longest side exceeds N (applied after --resize)")
+            .long("max-dimension")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("min-dimension")
+            .help("Upscale images whose longest side is below N (applied after --resize)")
+            .long("min-dimension")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("max-pixels")
+            .help("Refuse to decode inputs with more pixels (width times height), or to grow images beyond them with borders, padding or resizing")
+            .long("max-pixels")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("max-memory")
+            .help("Memory a decoder may allocate per input, and an image grown by operations may take, in MiB [default: 512]")
+            .long("max-memory")
+            .value_name("MIB")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .action(clap::ArgAction::Set))
+        .arg(Arg::new("no-limits")
+            .help("Decode inputs of any size, e.g. huge panoramas")
+            .long("no-limits")
+            .conflicts_with_all(["max-pixels", "max-memory"])
+            .action(clap::ArgAction::SetTrue))
+}
+
+// frames and watch stand for converting with --extract-frames / --watch. The flag is passed on
+// instead of defaulted, as clap only checks conflicts and requirements of given flags
+fn imply_subcommand_flag(mut args: Vec<OsString>) -> Vec<OsString> {
+    let flag = match args.get(1).and_then(|arg| arg.to_str()) {
+        Some("frames") => "--extract-frames",
+        Some("watch") => "--watch",
+        _ => return args
+    };
+    args.insert(2, flag.into());
+    args
+}
+
+fn cli() -> clap::Command {
+    with_convert_args(clap::Command::new(NAME))
+        .about(DESCRIPTION)
+        .version(VERSION)
+        .color(ColorChoice::Never)
+        .disable_version_flag(true)
+        .arg(Arg::new("color")
+            .help("Colorize output (honors NO_COLOR when set to auto)")
+            .long("color")
+            .value_name("WHEN")
+            .value_parser(["auto", "always", "never"])
+            .default_value("auto")
+            .global(true)
             .action(clap::ArgAction::Set))
+        .arg(Arg::new("escape-names")
+            .help("Quote file names in text reports when they hold spaces, quotes or control characters, the way a shell reads them back")
+            .long("escape-names")
+            .global(true)
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("quiet")
+            .help("Only print errors (and JSON lines)")
+            .short('q')
+            .long("quiet")
+            .global(true)
+            .conflicts_with("verbose")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("verbose")
+            .help("Print timing, formats and applied operations of every file, twice to also list the jobs")
+            .short('V')
+            .long("verbose")
+            .global(true)
+            .action(clap::ArgAction::Count))
+        .arg(Arg::new("list-formats")
+            .help("Print accepted format names and exit (see the formats subcommand for what each supports)")
+            .long("list-formats")
+            .exclusive(true)
+            .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("version")
             .short('v')
             .long("version")
             .help("Print version")
             .action(clap::ArgAction::Version))
-        .get_matches();
-    
-    let res = parse_and_execute(matches);
-    if res.is_err() {
-        println!("{}", res.unwrap_err())
+        .subcommand(with_convert_args(clap::Command::new("convert"))
+            .about("Convert images, the same as leaving out the subcommand"))
+        .subcommand(with_convert_args(clap::Command::new("resize"))
+            .about("Convert images to a new size")
+            .mut_arg("resize", |arg| arg.required_unless_present_any(["thumbnail", "crop-to", "scale", "max-dimension", "min-dimension", "max-megapixels", "ops"])))
+        .subcommand(with_convert_args(clap::Command::new("frames"))
+            .about("Write every frame or page of the inputs to its own numbered file")
+            .mut_arg("extract-frames", |arg| arg.hide(true)))
+        .subcommand(with_convert_args(clap::Command::new("watch"))
+            .about("Keep converting files matching the input patterns whenever they are created or modified")
+            .mut_arg("watch", |arg| arg.hide(true)))
+        .subcommand(clap::Command::new("formats")
+            .about("Print the formats this build of imco reads and writes, with their alpha, animation and bit depth support")
+            .arg(Arg::new("json")
+                .help("Print one JSON object per format")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(clap::Command::new("info")
+            .about("Print format, dimensions, color type, bit depth, frame count, size and metadata of images without converting them")
+            .arg(Arg::new("input")
+                .help("Input files")
+                .required(true)
+                .num_args(1..)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("json")
+                .help("Print one JSON object per file")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(clap::Command::new("stats")
+            .about("Print channel histograms, mean and median luma and the share of clipped pixels of images")
+            .arg(Arg::new("input")
+                .help("Input files")
+                .required(true)
+                .num_args(1..)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("histogram-image")
+                .help("Also draw the histogram into this image, named with {stem} or behind the file stem for several inputs")
+                .long("histogram-image")
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("json")
+                .help("Print one JSON object per file, with all 256 bins of every channel")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(clap::Command::new("palette")
+            .about("Print the dominant colors of an image as hex values, e.g. for placeholder backgrounds")
+            .arg(Arg::new("input")
+                .help("Input file")
+                .short('i')
+                .long("input")
+                .required(true)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("colors")
+                .help("How many colors to extract at most")
+                .long("colors")
+                .value_parser(clap::value_parser!(u16).range(1..=64))
+                .default_value("5")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("swatch")
+                .help("Also write the colors as a row of squares into this image")
+                .long("swatch")
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("json")
+                .help("Print the colors and their shares as JSON")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(clap::Command::new("dedupe")
+            .about("Find visually identical images across formats and resolutions using perceptual hashes")
+            .arg(Arg::new("input")
+                .help("Input patterns, e.g. 'photos/**/*'")
+                .required(true)
+                .num_args(1..)
+                .value_hint(ValueHint::AnyPath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("PATTERN")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("hash")
+                .help("Perceptual hash to compare")
+                .long("hash")
+                .value_parser(["average", "difference", "perceptual"])
+                .default_value("perceptual")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("distance")
+                .help("How many of the 64 hash bits may differ between duplicates")
+                .long("distance")
+                .value_parser(clap::value_parser!(u32).range(0..=64))
+                .default_value("4")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("action")
+                .help("What to do with the duplicates of the largest image of a group")
+                .long("action")
+                .value_parser(["report", "delete", "hardlink"])
+                .default_value("report")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("json")
+                .help("Print one JSON object per group")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(clap::Command::new("compare")
+            .about("Report PSNR, SSIM and the largest channel difference of two images of the same size")
+            .arg(Arg::new("input")
+                .help("The two images")
+                .required(true)
+                .num_args(2)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("threshold")
+                .help("Exit with 1 when the metric is below this, e.g. 0.95 for SSIM or 35 for PSNR")
+                .long("threshold")
+                .value_parser(clap::value_parser!(f64))
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("metric")
+                .help("Metric --threshold applies to")
+                .long("metric")
+                .value_parser(["ssim", "psnr"])
+                .default_value("ssim")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("json")
+                .help("Print the result as JSON")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(clap::Command::new("diff-image")
+            .about("Draw two images of the same size next to each other, as a blinking animation or as a heatmap of their differences")
+            .arg(Arg::new("input")
+                .help("The two images, e.g. the original and its compressed version")
+                .required(true)
+                .num_args(2)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("output")
+                .help("The comparison, its format taken from the extension (gif or png for blink)")
+                .short('o')
+                .long("output")
+                .required(true)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("mode")
+                .help("Side by side with their names, an animation switching between them, or black where they agree up to white for the largest difference")
+                .long("mode")
+                .value_parser(["side-by-side", "blink", "heatmap"])
+                .default_value("side-by-side")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("delay")
+                .help("Milliseconds each image of blink is shown")
+                .long("delay")
+                .value_name("MS")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .default_value("500")
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("montage")
+            .about("Put images onto one contact sheet, optionally labeled with their file names")
+            .arg(Arg::new("input")
+                .help("Input patterns, e.g. 'shoot/*.jpg'")
+                .short('i')
+                .long("input")
+                .required(true)
+                .num_args(1..)
+                .value_hint(ValueHint::AnyPath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("PATTERN")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("output")
+                .help("The contact sheet, its format taken from the extension")
+                .short('o')
+                .long("output")
+                .required(true)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("columns")
+                .help("Images per row [default: enough for a square sheet]")
+                .long("columns")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("cell")
+                .help("Box every image is shrunk into")
+                .long("cell")
+                .value_name("WxH")
+                .default_value("256x256")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("gap")
+                .help("Pixels between the cells")
+                .long("gap")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("8")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("label")
+                .help("Caption under every image")
+                .long("label")
+                .value_parser(["none", "filename"])
+                .default_value("none")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("background")
+                .help("Color of the sheet")
+                .long("background")
+                .value_name("COLOR")
+                .default_value("#ffffff")
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("concat")
+            .about("Join images edge to edge into one strip, e.g. for comparisons and banners")
+            .arg(Arg::new("input")
+                .help("Input patterns, joined in order, e.g. 'a.png,b.png,c.png'")
+                .short('i')
+                .long("input")
+                .required(true)
+                .num_args(1..)
+                .value_delimiter(',')
+                .value_hint(ValueHint::AnyPath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("PATTERN")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("output")
+                .help("The strip, its format taken from the extension")
+                .short('o')
+                .long("output")
+                .required(true)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("direction")
+                .help("Whether the images go side by side or on top of each other")
+                .long("direction")
+                .value_parser(["horizontal", "vertical"])
+                .default_value("horizontal")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("align")
+                .help("Where smaller images sit across the strip (top/left for start)")
+                .long("align")
+                .value_parser(["start", "center", "end"])
+                .default_value("center")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("gap")
+                .help("Pixels between the images")
+                .long("gap")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("0")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("background")
+                .help("Color of the gaps and the space beside smaller images [default: transparent, white for formats without alpha]")
+                .long("background")
+                .value_name("COLOR")
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("stack")
+            .about("Average exposures of the same size pixel by pixel into one image to reduce noise, without aligning them")
+            .arg(Arg::new("input")
+                .help("Input patterns, e.g. 'burst/*.dng'")
+                .short('i')
+                .long("input")
+                .required(true)
+                .num_args(1..)
+                .value_delimiter(',')
+                .value_hint(ValueHint::AnyPath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("PATTERN")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("output")
+                .help("The stacked image, its format taken from the extension (16 bit where it holds that)")
+                .short('o')
+                .long("output")
+                .required(true)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("method")
+                .help("The average of every pixel, or its middle value, which leaves out satellites, planes and hot pixels but keeps all inputs in memory")
+                .long("method")
+                .value_parser(["mean", "median"])
+                .default_value("mean")
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("spritesheet")
+            .about("Pack images into one texture and write a JSON atlas of where every sprite is")
+            .arg(Arg::new("input")
+                .help("Input patterns, e.g. 'sprites/*.png'")
+                .short('i')
+                .long("input")
+                .required(true)
+                .num_args(1..)
+                .value_hint(ValueHint::AnyPath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("PATTERN")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("output")
+                .help("The texture, its format taken from the extension")
+                .short('o')
+                .long("output")
+                .required(true)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("atlas")
+                .help("Write the sprite coordinates to this file (TexturePacker JSON hash format)")
+                .long("atlas")
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("padding")
+                .help("Transparent pixels around every sprite, keeping neighbours from bleeding in when filtering")
+                .long("padding")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("1")
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("stitch")
+            .about("Reassemble tiles written with --tile (named like map_ROW_COLUMN.png) into one image")
+            .arg(Arg::new("input")
+                .help("Tile patterns, e.g. 'tiles/map_*.png'")
+                .short('i')
+                .long("input")
+                .required(true)
+                .num_args(1..)
+                .value_hint(ValueHint::AnyPath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("PATTERN")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("output")
+                .help("The stitched image, its format taken from the extension")
+                .short('o')
+                .long("output")
+                .required(true)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("merge-channels")
+            .about("Combine grayscale images into the channels of one image, like packing roughness and metalness maps")
+            .arg(Arg::new("red")
+                .help("Image for the red channel")
+                .short('r')
+                .long("red")
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("green")
+                .help("Image for the green channel")
+                .short('g')
+                .long("green")
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("blue")
+                .help("Image for the blue channel")
+                .short('b')
+                .long("blue")
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("luma")
+                .help("Image for the luma of a grayscale output")
+                .short('l')
+                .long("luma")
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .conflicts_with_all(["red", "green", "blue"])
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("alpha")
+                .help("Image for the alpha channel, the output is opaque without it")
+                .short('a')
+                .long("alpha")
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set))
+            .group(clap::ArgGroup::new("channels").args(["red", "green", "blue", "luma", "alpha"]).multiple(true).required(true))
+            .arg(Arg::new("output")
+                .help("The merged image, its format taken from the extension")
+                .short('o')
+                .long("output")
+                .required(true)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("preview")
+            .about("Show images in the terminal, with kitty, iTerm2 or sixel graphics or colored block characters")
+            .arg(Arg::new("input")
+                .help("Images to show")
+                .required(true)
+                .num_args(1..)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("protocol")
+                .help("How to draw the images, auto guesses from the terminal")
+                .long("protocol")
+                .value_parser(["auto", "kitty", "iterm", "sixel", "blocks"])
+                .default_value("auto")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("width")
+                .help("Columns the images may take [default: $COLUMNS or 80]")
+                .long("width")
+                .value_name("COLUMNS")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("height")
+                .help("Rows the images may take [default: $LINES - 2 or 22]")
+                .long("height")
+                .value_name("ROWS")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("verify")
+            .about("Fully decode images to find corrupt or truncated files, without writing anything")
+            .arg(Arg::new("input")
+                .help("Input files, or patterns like '**/*.jpg' with --batch")
+                .short('i')
+                .long("input")
+                .required(true)
+                .num_args(1..)
+                .value_hint(ValueHint::AnyPath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("PATTERN")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("batch")
+                .help("Treat the inputs as glob patterns")
+                .short('b')
+                .long("batch")
+                .action(clap::ArgAction::SetTrue))
+            .arg(Arg::new("json")
+                .help("Print one JSON object per file")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(clap::Command::new("verify-checksums")
+            .about("Check the files listed in checksum manifests or sidecars written by --checksum")
+            .arg(Arg::new("manifest")
+                .help("Manifests or sidecars, listed paths are relative to them")
+                .required(true)
+                .num_args(1..)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("MANIFEST")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("json")
+                .help("Print one JSON object per listed file")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(clap::Command::new("optimize")
+            .about("Shrink png and jpeg files in place without changing how they look, keeping them when that doesn't help")
+            .arg(Arg::new("input")
+                .help("Input files")
+                .required(true)
+                .num_args(1..)
+                .value_hint(ValueHint::FilePath)
+                .value_parser(clap::value_parser!(PathBuf))
+                .value_name("FILE")
+                .action(clap::ArgAction::Append))
+            .arg(Arg::new("dry-run")
+                .help("Only report what would be saved")
+                .long("dry-run")
+                .action(clap::ArgAction::SetTrue))
+            .arg(Arg::new("json")
+                .help("Print one JSON object per file")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(clap::Command::new("serve")
+            .about("Convert images uploaded over HTTP, e.g. curl --data-binary @in.jpg 'localhost:8080/?format=webp&resize=800x'")
+            .arg(Arg::new("port")
+                .help("Port to listen on")
+                .short('p')
+                .long("port")
+                .value_parser(clap::value_parser!(u16))
+                .default_value("8080")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("host")
+                .help("Address to listen on, 0.0.0.0 for all interfaces")
+                .long("host")
+                .default_value("127.0.0.1")
+                .action(clap::ArgAction::Set))
+            .arg(Arg::new("max-upload")
+                .help("Largest upload that is converted, like 20MB, larger ones are answered with 413")
+                .long("max-upload")
+                .value_name("SIZE")
+                .default_value("100MB")
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("daemon")
+            .about("Stay running and convert jobs sent as JSON lines over a Unix socket, like the entries of --jobs-file (with absolute paths), answering each with a line of its results")
+            .arg(Arg::new("socket")
+                .help("Socket to listen on [default: $XDG_RUNTIME_DIR/imco.sock, else imco-$USER.sock in the temporary directory]")
+                .long("socket")
+                .value_hint(ValueHint::FilePath)
+                .value_name("PATH")
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("completions")
+            .about("Print the completion script for a shell, e.g. imco completions bash > /etc/bash_completion.d/imco")
+            .arg(Arg::new("shell")
+                .help("Shell to complete in")
+                .index(1)
+                .required(true)
+                .value_parser(clap::value_parser!(clap_complete::Shell))
+                .action(clap::ArgAction::Set)))
+        .subcommand(clap::Command::new("tui")
+            .about("Pick files and options interactively, with estimated output sizes and live progress")
+            .arg(Arg::new("dir")
+                .help("Directory to start browsing in")
+                .index(1)
+                .default_value(".")
+                .value_hint(ValueHint::DirPath)
+                .value_name("DIR")
+                .action(clap::ArgAction::Set)))
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .args_override_self(true)
+}
+
+fn main() {
+    let command = cli();
+    let args = match expand_preset(std::env::args_os().collect(), &command) {
+        Ok(args) => args,
+        Err(e) => {
+            Report { json: false, color: use_color("auto"), verbosity: 1 }.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+    };
+    let matches = command.get_matches_from(imply_subcommand_flag(args.clone()));
+    // Global options are found with the subcommand
+    let globals = matches.subcommand().map_or(&matches, |(_, sub_matches)| sub_matches);
+    let color = use_color(globals.get_one::<String>("color").unwrap());
+    let verbosity = if globals.get_flag("quiet") { 0 } else { 1 + globals.get_count("verbose") };
+    ESCAPE_NAMES.store(globals.get_flag("escape-names"), Ordering::Relaxed);
+
+    if matches.get_flag("list-formats") {
+        list_formats();
+        return
+    }
+    if let Some(formats_matches) = matches.subcommand_matches("formats") {
+        formats_command(Report { json: formats_matches.get_flag("json"), color, verbosity });
+        return
+    }
+    if let Some(info_matches) = matches.subcommand_matches("info") {
+        let report = Report { json: info_matches.get_flag("json"), color, verbosity };
+        if !info(info_matches.get_many::<PathBuf>("input").unwrap(), report) { std::process::exit(EXIT_FAILED) }
+        return
+    }
+    if let Some(stats_matches) = matches.subcommand_matches("stats") {
+        let report = Report { json: stats_matches.get_flag("json"), color, verbosity };
+        let paths: Vec<&PathBuf> = stats_matches.get_many::<PathBuf>("input").unwrap().collect();
+        if !stats_command(&paths, stats_matches.get_one::<PathBuf>("histogram-image"), report) { std::process::exit(EXIT_FAILED) }
+        return
+    }
+    if let Some(palette_matches) = matches.subcommand_matches("palette") {
+        let report = Report { json: palette_matches.get_flag("json"), color, verbosity };
+        let input = palette_matches.get_one::<PathBuf>("input").unwrap();
+        let colors = *palette_matches.get_one::<u16>("colors").unwrap() as usize;
+        let result = palette(input, colors).and_then(|swatches| {
+            if let Some(swatch) = palette_matches.get_one::<PathBuf>("swatch") { write_swatches(&swatches, 64, swatch)? }
+            Ok(swatches)
+        });
+        match result {
+            Ok(swatches) => {
+                let hex = |c: &image::Rgb<u8>| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]);
+                if report.json {
+                    report.out(serde_json::json!({
+                        "input": input.to_string_lossy(),
+                        "colors": swatches.iter().map(|s| serde_json::json!({ "hex": hex(&s.color), "share": s.share })).collect::<Vec<_>>(),
+                    }));
+                } else {
+                    swatches.iter().for_each(|s| report.out(format!("{} {:5.1}%", hex(&s.color), s.share * 100.0)));
+                }
+            }
+            Err(e) => {
+                report.error(&e, Some(input));
+                std::process::exit(exit_code(&e))
+            }
+        }
+        return
+    }
+    if let Some(dedupe_matches) = matches.subcommand_matches("dedupe") {
+        let report = Report { json: dedupe_matches.get_flag("json"), color, verbosity };
+        let kind = match dedupe_matches.get_one::<String>("hash").unwrap().as_str() {
+            "average" => HashKind::Average,
+            "difference" => HashKind::Difference,
+            _ => HashKind::Perceptual
+        };
+        let action = match dedupe_matches.get_one::<String>("action").unwrap().as_str() {
+            "delete" => DuplicateAction::Delete,
+            "hardlink" => DuplicateAction::Hardlink,
+            _ => DuplicateAction::Report
+        };
+        match dedupe(dedupe_matches.get_many::<PathBuf>("input").unwrap(), kind, *dedupe_matches.get_one::<u32>("distance").unwrap(), action, report) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(EXIT_FAILED),
+            Err(e) => {
+                report.error(&e, None);
+                std::process::exit(exit_code(&e))
+            }
+        }
+        return
+    }
+    if let Some(compare_matches) = matches.subcommand_matches("compare") {
+        let images: Vec<&PathBuf> = compare_matches.get_many::<PathBuf>("input").unwrap().collect();
+        let metric = compare_matches.get_one::<String>("metric").unwrap();
+        let report = Report { json: compare_matches.get_flag("json"), color, verbosity };
+        // EXIT_FAILED for images too different
+        match compare_command(images[0], images[1], metric, compare_matches.get_one::<f64>("threshold").copied(), report) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(EXIT_FAILED),
+            Err(e) => {
+                report.error(&e, None);
+                std::process::exit(exit_code(&e))
+            }
+        }
+        return
+    }
+    if let Some(diff_matches) = matches.subcommand_matches("diff-image") {
+        let report = Report { json: false, color, verbosity };
+        if let Err(e) = diff_image_command(diff_matches, report) {
+            report.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+        return
+    }
+    if let Some(montage_matches) = matches.subcommand_matches("montage") {
+        let report = Report { json: false, color, verbosity };
+        if let Err(e) = montage_command(montage_matches, report) {
+            report.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+        return
+    }
+    if let Some(concat_matches) = matches.subcommand_matches("concat") {
+        let report = Report { json: false, color, verbosity };
+        if let Err(e) = concat_command(concat_matches, report) {
+            report.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+        return
+    }
+    if let Some(stack_matches) = matches.subcommand_matches("stack") {
+        let report = Report { json: false, color, verbosity };
+        if let Err(e) = stack_command(stack_matches, report) {
+            report.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+        return
+    }
+    if let Some(sprite_matches) = matches.subcommand_matches("spritesheet") {
+        let report = Report { json: false, color, verbosity };
+        if let Err(e) = spritesheet_command(sprite_matches, report) {
+            report.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+        return
+    }
+    if let Some(stitch_matches) = matches.subcommand_matches("stitch") {
+        let report = Report { json: false, color, verbosity };
+        let output = stitch_matches.get_one::<PathBuf>("output").unwrap();
+        let stitched = expand_patterns_to_files(stitch_matches.get_many::<PathBuf>("input").unwrap(), false)
+            .and_then(|paths| stitch(&paths, output).map(|res| (paths.len(), res)));
+        match stitched {
+            Ok((tiles, (width, height, bytes))) => report.out(paint(format!("{tiles} tile(s) -> {output} ({width}x{height}, {bytes} bytes)", output = shown(output)), GREEN, report.color)),
+            Err(e) => {
+                report.error(&e, None);
+                std::process::exit(exit_code(&e))
+            }
+        }
+        return
+    }
+    if let Some(merge_matches) = matches.subcommand_matches("merge-channels") {
+        let report = Report { json: false, color, verbosity };
+        let output = merge_matches.get_one::<PathBuf>("output").unwrap();
+        let source = |name: &str| merge_matches.get_one::<PathBuf>(name).cloned();
+        let sources = ChannelSources { red: source("red"), green: source("green"), blue: source("blue"), luma: source("luma"), alpha: source("alpha") };
+        match merge_channels(&sources, output) {
+            Ok((width, height, bytes)) => {
+                let channels = [&sources.red, &sources.green, &sources.blue, &sources.luma, &sources.alpha].iter().filter(|source| source.is_some()).count();
+                report.out(paint(format!("{channels} channel(s) -> {output} ({width}x{height}, {bytes} bytes)", output = shown(output)), GREEN, report.color))
+            }
+            Err(e) => {
+                report.error(&e, None);
+                std::process::exit(exit_code(&e))
+            }
+        }
+        return
+    }
+    if let Some(preview_matches) = matches.subcommand_matches("preview") {
+        let report = Report { json: false, color, verbosity };
+        let graphics = match preview_matches.get_one::<String>("protocol").unwrap().as_str() {
+            "kitty" => Graphics::Kitty,
+            "iterm" => Graphics::Iterm,
+            "sixel" => Graphics::Sixel,
+            "blocks" => Graphics::Blocks,
+            _ => detect_graphics()
+        };
+        let (columns, rows) = terminal_size();
+        let size = (preview_matches.get_one::<u32>("width").copied().unwrap_or(columns), preview_matches.get_one::<u32>("height").copied().unwrap_or(rows));
+        let paths: Vec<_> = preview_matches.get_many::<PathBuf>("input").unwrap().collect();
+        if !preview_files(&paths, graphics, size, report) { std::process::exit(EXIT_FAILED) }
+        return
+    }
+    if let Some(verify_matches) = matches.subcommand_matches("verify") {
+        let report = Report { json: verify_matches.get_flag("json"), color, verbosity };
+        match verify_command(verify_matches, report) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(EXIT_FAILED),
+            Err(e) => {
+                report.error(&e, None);
+                std::process::exit(exit_code(&e))
+            }
+        }
+        return
+    }
+    if let Some(checksum_matches) = matches.subcommand_matches("verify-checksums") {
+        let report = Report { json: checksum_matches.get_flag("json"), color, verbosity };
+        match verify_checksums_command(checksum_matches.get_many::<PathBuf>("manifest").unwrap(), report) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(EXIT_FAILED),
+            Err(e) => {
+                report.error(&e, None);
+                std::process::exit(exit_code(&e))
+            }
+        }
+        return
+    }
+    if let Some(optimize_matches) = matches.subcommand_matches("optimize") {
+        let report = Report { json: optimize_matches.get_flag("json"), color, verbosity };
+        if !optimize_command(optimize_matches.get_many::<PathBuf>("input").unwrap(), optimize_matches.get_flag("dry-run"), report) { std::process::exit(EXIT_FAILED) }
+        return
+    }
+    if let Some(serve_matches) = matches.subcommand_matches("serve") {
+        let addr = format!("{}:{}", serve_matches.get_one::<String>("host").unwrap(), serve_matches.get_one::<u16>("port").unwrap());
+        let report = Report { json: false, color, verbosity };
+        if let Err(e) = parse_size(serve_matches.get_one::<String>("max-upload").unwrap()).and_then(|max_upload| serve(&addr, max_upload, report)) {
+            report.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+        return
+    }
+    if let Some(daemon_matches) = matches.subcommand_matches("daemon") {
+        let socket = daemon_matches.get_one::<String>("socket").cloned().unwrap_or_else(default_socket);
+        let report = Report { json: false, color, verbosity };
+        if let Err(e) = daemon(&socket, report) {
+            report.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+        return
+    }
+    if let Some(completions_matches) = matches.subcommand_matches("completions") {
+        let shell = *completions_matches.get_one::<clap_complete::Shell>("shell").unwrap();
+        clap_complete::generate(shell, &mut cli(), NAME, &mut std::io::stdout());
+        return
+    }
+    if let Some(tui_matches) = matches.subcommand_matches("tui") {
+        let report = Report { json: false, color, verbosity };
+        let dir = tui_matches.get_one::<String>("dir").unwrap();
+        #[cfg(feature = "tui")]
+        let result = tui::run(dir);
+        #[cfg(not(feature = "tui"))]
+        let result: ImcoResult<()> = Err(ImcoError::Tui(format!("imco was built without the tui feature, can't browse '{dir}'")));
+        if let Err(e) = result {
+            report.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+        return
+    }
+    // Only the conversion subcommands are left, taking the same options as converting without one
+    let matches = match matches.subcommand() {
+        Some((_, convert_matches)) => convert_matches.clone(),
+        None => matches
+    };
+    let json = matches.get_flag("json");
+    let timings = matches.get_flag("timings");
+    let summary = matches.get_flag("summary");
+    let dry_run = matches.get_flag("dry-run");
+    let started = std::time::Instant::now();
+    let preview = matches.get_flag("preview");
+    let report = Report { json, color, verbosity };
+    if let Some(path) = matches.get_one::<String>("log-file") {
+        if let Err(e) = open_log(path, matches.get_one::<String>("log-format").unwrap() == "json") {
+            report.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+        log_event("started", None, std::env::args_os().map(|arg| arg.to_string_lossy().into_owned()).collect::<Vec<_>>().join(" "), serde_json::json!({}));
+    }
+    let result = match (matches.get_one::<String>("jobs-file"), matches.get_one::<String>("resume")) {
+        (Some(path), _) => run_jobs(path, &args, report),
+        (None, Some(name)) => resume_session(name, report),
+        (None, None) => {
+            let session = if matches.get_flag("no-session") { None } else { new_session(&args) };
+            parse_and_execute(matches, report, session)
+        }
+    };
+    match result {
+        Ok(outcomes) => {
+            let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+            let skipped = outcomes.iter().filter(|o| o.result.as_ref().is_ok_and(|res| res.skipped)).count();
+            log_event("finished", None, format!("{} file(s) in {:.2} s: {} converted, {skipped} skipped, {failed} failed", outcomes.len(), started.elapsed().as_secs_f64(), outcomes.len() - failed - skipped),
+                serde_json::json!({ "files": outcomes.len(), "skipped": skipped, "failed": failed }));
+            if timings { print_timings(&outcomes, report) }
+            let interrupted = interruption().load(Ordering::Relaxed);
+            // A single file's line says everything already
+            if summary || interrupted || (!json && outcomes.len() > 1) { print_summary(&outcomes, started.elapsed(), dry_run, report) }
+            if preview {
+                let outputs: Vec<_> = outcomes.iter()
+                    .filter_map(|o| o.result.as_ref().ok())
+                    .filter(|res| !res.skipped && res.output.as_os_str() != STDIO && res.output_format != Format::Pdf)
+                    .map(|res| res.output.as_path())
+                    .collect();
+                preview_files(&outputs, detect_graphics(), terminal_size(), report);
+            }
+            let failed: Vec<_> = outcomes.iter()
+                .filter_map(|o| o.result.as_ref().err().map(|e| (&o.input, e)))
+                .collect();
+            if interrupted {
+                eprintln!("{}", paint("Interrupted, the files that weren't started yet were left out".to_string(), YELLOW, color));
+            }
+            let exit = if interrupted { EXIT_INTERRUPTED } else { EXIT_FAILED };
+            if !failed.is_empty() && json {
+                std::process::exit(exit)
+            }
+            if !failed.is_empty() {
+                eprintln!("{}", paint(format!("{} of {} file(s) failed, {} converted:", failed.len(), outcomes.len(), outcomes.len() - failed.len()), RED, color));
+                for (input, e) in failed {
+                    eprintln!("  {}: {}", shown(input), paint(shown_message(e), RED, color))
+                }
+                std::process::exit(exit)
+            }
+            if interrupted { std::process::exit(EXIT_INTERRUPTED) }
+        }
+        Err(e) => {
+            report.error(&e, None);
+            std::process::exit(exit_code(&e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daemon_refuses_plugins() {
+        let job = r#"{"input": "a.png", "output": "a.webp", "options": {"plugin": "/tmp/evil.so"}}"#;
+        assert!(matches!(daemon_job(job), Err(ImcoError::InvalidJob(hint)) if hint.contains("--plugin")));
     }
 }
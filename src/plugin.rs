@@ -0,0 +1,102 @@
+//! Operations loaded from dynamic libraries, which export the functions declared in `include/imco_plugin.h`.
+//!
+//! Images are handed to plugins as 8 bit RGBA and converted back into their color type afterwards
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::sync::Arc;
+use crate::{convert_color, register_operation, CustomOperation, ImcoError, ImcoResult};
+
+/// The version of the interface plugins are built against, see `IMCO_PLUGIN_VERSION`
+pub const PLUGIN_VERSION: u32 = 1;
+
+// Room for the messages of failed operations, including the NUL
+const ERROR_LEN: usize = 512;
+
+#[repr(C)]
+struct PluginImage {
+    width: u32,
+    height: u32,
+    pixels: *mut u8,
+}
+
+type Version = unsafe extern "C" fn() -> u32;
+type Operations = unsafe extern "C" fn() -> *const c_char;
+type Apply = unsafe extern "C" fn(*const c_char, *const c_char, *mut PluginImage, *mut c_char, usize) -> c_int;
+type Free = unsafe extern "C" fn(*mut u8);
+
+struct PluginOperation {
+    name: CString,
+    apply: Apply,
+    free: Option<Free>,
+    // Keeps the functions loaded
+    _library: Arc<libloading::Library>,
+}
+
+impl CustomOperation for PluginOperation {
+    fn apply(&self, image: image::DynamicImage, args: &str) -> Result<image::DynamicImage, String> {
+        let color = image.color();
+        let mut rgba = image.into_rgba8();
+        let (width, height) = rgba.dimensions();
+        let args = CString::new(args).map_err(|_| "the arguments hold a NUL byte".to_string())?;
+        let mut plugin_image = PluginImage { width, height, pixels: rgba.as_mut_ptr() };
+        let mut error = [0 as c_char; ERROR_LEN];
+        // SAFETY: the plugin was loaded with the signatures of imco_plugin.h, the pixels hold width * height * 4 bytes
+        let status = unsafe { (self.apply)(self.name.as_ptr(), args.as_ptr(), &mut plugin_image, error.as_mut_ptr(), ERROR_LEN) };
+        if status != 0 {
+            error[ERROR_LEN - 1] = 0;
+            // SAFETY: the buffer is NUL-terminated
+            let message = unsafe { CStr::from_ptr(error.as_ptr()) }.to_string_lossy().into_owned();
+            return Err(if message.is_empty() { format!("the plugin returned {status}") } else { message })
+        }
+        if plugin_image.pixels == rgba.as_mut_ptr() {
+            if (plugin_image.width, plugin_image.height) != (width, height) {
+                return Err("the plugin changed the size without handing out pixels of its own".to_string())
+            }
+            return Ok(convert_color(rgba.into(), color))
+        }
+        // A buffer of the plugin's, copied before it is released
+        let Some(free) = self.free else { return Err("the plugin handed out pixels without exporting imco_plugin_free".to_string()) };
+        let len = plugin_image.width as usize * plugin_image.height as usize * 4;
+        // SAFETY: the plugin promises width * height * 4 bytes at the pointer, released by its imco_plugin_free only
+        let pixels = unsafe {
+            let pixels = std::slice::from_raw_parts(plugin_image.pixels, len).to_vec();
+            free(plugin_image.pixels);
+            pixels
+        };
+        let rgba = image::RgbaImage::from_raw(plugin_image.width, plugin_image.height, pixels).expect("the buffer holds every pixel");
+        Ok(convert_color(rgba.into(), color))
+    }
+}
+
+fn symbol<T: Copy>(library: &libloading::Library, name: &str, path: &str) -> ImcoResult<T> {
+    // SAFETY: the symbols are declared with these types in imco_plugin.h
+    unsafe { library.get::<T>(name.as_bytes()) }
+        .map(|symbol| *symbol)
+        .map_err(|_| ImcoError::Plugin(path.to_string(), format!("it doesn't export {name}")))
+}
+
+/// Loads the dynamic library at `path` and registers its operations with [`register_operation`], returning their names
+pub fn load_plugin(path: &str) -> ImcoResult<Vec<String>> {
+    let failed = |reason: String| ImcoError::Plugin(path.to_string(), reason);
+    // SAFETY: loading runs the library's initializers, plugins are trusted like the imco binary itself
+    let library = unsafe { libloading::Library::new(path) }.map_err(|e| failed(e.to_string()))?;
+    // SAFETY: the functions take no arguments
+    let version = unsafe { symbol::<Version>(&library, "imco_plugin_version", path)?() };
+    if version != PLUGIN_VERSION {
+        return Err(failed(format!("it was built for version {version} of the plugin interface, imco uses {PLUGIN_VERSION}")))
+    }
+    let operations = symbol::<Operations>(&library, "imco_plugin_operations", path)?;
+    // SAFETY: imco_plugin_operations returns a static NUL-terminated string
+    let names = unsafe { CStr::from_ptr(operations()) }.to_str()
+        .map_err(|_| failed("the names of its operations aren't valid UTF-8".to_string()))?
+        .split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string).collect::<Vec<_>>();
+    if names.is_empty() { return Err(failed("it has no operations".to_string())) }
+    let apply = symbol::<Apply>(&library, "imco_plugin_apply", path)?;
+    let free = symbol::<Free>(&library, "imco_plugin_free", path).ok();
+    let library = Arc::new(library);
+    for name in &names {
+        let c_name = CString::new(name.as_str()).expect("the names came from a C string");
+        register_operation(name, PluginOperation { name: c_name, apply, free, _library: library.clone() });
+    }
+    Ok(names)
+}
@@ -0,0 +1,424 @@
+// The interactive terminal interface of imco tui: a file browser, the common conversion options, the
+// estimated size of the highlighted file and the live progress of converting the selection
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use rayon::prelude::*;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use imco::*;
+
+const FORMATS: [&str; 8] = ["webp", "avif", "jpg", "png", "jxl", "gif", "tiff", "qoi"];
+const FITS: [&str; 3] = ["contain", "cover", "exact"];
+const FIELDS: [Field; 5] = [Field::Format, Field::Quality, Field::Resize, Field::Fit, Field::Output];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pane { Files, Settings }
+
+#[derive(Clone, Copy, PartialEq)]
+enum Field { Format, Quality, Resize, Fit, Output }
+
+// What the options pane edits, turned into Options for every estimate and the batch
+#[derive(Clone, PartialEq)]
+struct Settings {
+    format: usize,
+    quality: u8,
+    resize: String,
+    fit: usize,
+    // Empty for next to the inputs
+    output: String,
+}
+
+impl Settings {
+    fn options(&self) -> ImcoResult<Options> {
+        let resize = match self.resize.trim() {
+            "" => None,
+            resolution => {
+                let (width, height) = parse_resolution(resolution)?;
+                let fit = match FITS[self.fit] {
+                    "cover" => Fit::Cover,
+                    "exact" => Fit::Exact,
+                    _ => Fit::Contain
+                };
+                Some(Resize { width, height, fit })
+            }
+        };
+        Ok(Options {
+            o_fmt: Some(mk_format(FORMATS[self.format])?),
+            quality: Some(self.quality),
+            resize,
+            batch: !self.output.is_empty(),
+            create_dirs: true,
+            keep_going: true,
+            ..Options::default()
+        })
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    name: String,
+    dir: bool,
+}
+
+// Parent first, then the directories and the images, each by name
+fn list_dir(dir: &Path) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = std::fs::read_dir(dir).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() || is_image_path(path))
+        .map(|path| Entry { name: path.file_name().unwrap_or_default().to_string_lossy().into_owned(), dir: path.is_dir(), path })
+        .collect();
+    entries.sort_by(|a, b| b.dir.cmp(&a.dir).then_with(|| a.name.cmp(&b.name)));
+    if let Some(parent) = dir.parent() {
+        entries.insert(0, Entry { path: parent.to_path_buf(), name: "..".to_string(), dir: true });
+    }
+    entries
+}
+
+// Input and output bytes, or why the highlighted file can't be converted like this
+type Estimate = Result<(u64, u64), String>;
+
+enum Message {
+    Estimated(PathBuf, Settings, Estimate),
+    Converted(FileOutcome),
+    Finished,
+}
+
+struct Batch {
+    total: usize,
+    outcomes: Vec<FileOutcome>,
+    finished: bool,
+}
+
+struct App {
+    dir: PathBuf,
+    entries: Vec<Entry>,
+    cursor: ListState,
+    selected: BTreeSet<PathBuf>,
+    pane: Pane,
+    field: usize,
+    settings: Settings,
+    estimate: Option<(PathBuf, Settings, Estimate)>,
+    // The estimate being worked on, only the latest one is asked for
+    estimating: Option<(PathBuf, Settings)>,
+    batch: Option<Batch>,
+    message: Option<String>,
+    quit: bool,
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+}
+
+impl App {
+    fn new(dir: PathBuf) -> Self {
+        let (sender, receiver) = channel();
+        let mut app = App {
+            entries: list_dir(&dir),
+            dir,
+            cursor: ListState::default().with_selected(Some(0)),
+            selected: BTreeSet::new(),
+            pane: Pane::Files,
+            field: 0,
+            settings: Settings { format: 0, quality: 80, resize: String::new(), fit: 0, output: String::new() },
+            estimate: None,
+            estimating: None,
+            batch: None,
+            message: None,
+            quit: false,
+            sender,
+            receiver,
+        };
+        // Start on the first file rather than the parent
+        app.cursor.select(Some(app.entries.iter().position(|e| e.name != "..").unwrap_or(0)));
+        app
+    }
+
+    fn highlighted(&self) -> Option<&Entry> {
+        self.cursor.selected().and_then(|i| self.entries.get(i))
+    }
+
+    fn open(&mut self, dir: PathBuf) {
+        let previous = std::mem::replace(&mut self.dir, dir);
+        self.entries = list_dir(&self.dir);
+        // Coming back up keeps the directory just left highlighted
+        let position = self.entries.iter().position(|e| e.path == previous && e.name != "..");
+        self.cursor.select(Some(position.unwrap_or(0)));
+    }
+
+    fn toggle(&mut self, path: PathBuf) {
+        if !self.selected.remove(&path) { self.selected.insert(path); }
+    }
+
+    fn handle(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return
+        }
+        if let Some(batch) = &self.batch {
+            // Any key leaves a finished batch, a running one can't be interrupted
+            if batch.finished {
+                self.batch = None;
+                self.selected.clear();
+                self.entries = list_dir(&self.dir);
+            }
+            return
+        }
+        self.message = None;
+        match key.code {
+            KeyCode::Esc => self.quit = true,
+            KeyCode::Tab | KeyCode::BackTab => self.pane = if self.pane == Pane::Files { Pane::Settings } else { Pane::Files },
+            KeyCode::F(5) => self.start(),
+            _ if self.pane == Pane::Files => self.handle_files(key.code),
+            _ => self.handle_settings(key.code),
+        }
+    }
+
+    fn handle_files(&mut self, code: KeyCode) {
+        let last = self.entries.len().saturating_sub(1);
+        let cursor = self.cursor.selected().unwrap_or(0);
+        match code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Up | KeyCode::Char('k') => self.cursor.select(Some(cursor.saturating_sub(1))),
+            KeyCode::Down | KeyCode::Char('j') => self.cursor.select(Some((cursor + 1).min(last))),
+            KeyCode::PageUp => self.cursor.select(Some(cursor.saturating_sub(10))),
+            KeyCode::PageDown => self.cursor.select(Some((cursor + 10).min(last))),
+            KeyCode::Home => self.cursor.select(Some(0)),
+            KeyCode::End => self.cursor.select(Some(last)),
+            KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => {
+                if let Some(parent) = self.dir.parent().map(Path::to_path_buf) { self.open(parent) }
+            }
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') | KeyCode::Char(' ') => match self.highlighted() {
+                Some(entry) if entry.dir && code != KeyCode::Char(' ') => self.open(entry.path.clone()),
+                Some(entry) if !entry.dir => {
+                    self.toggle(entry.path.clone());
+                    self.cursor.select(Some((cursor + 1).min(last)));
+                }
+                _ => {}
+            },
+            KeyCode::Char('a') => {
+                let images: Vec<PathBuf> = self.entries.iter().filter(|e| !e.dir).map(|e| e.path.clone()).collect();
+                // Selects all of them unless they already are
+                if images.iter().all(|image| self.selected.contains(image)) {
+                    images.iter().for_each(|image| { self.selected.remove(image); });
+                } else {
+                    self.selected.extend(images);
+                }
+            }
+            KeyCode::Char('r') => self.start(),
+            _ => {}
+        }
+    }
+
+    fn handle_settings(&mut self, code: KeyCode) {
+        let field = FIELDS[self.field];
+        let step = |value: usize, count: usize, forward: bool| if forward { (value + 1) % count } else { (value + count - 1) % count };
+        match code {
+            KeyCode::Up => self.field = self.field.saturating_sub(1),
+            KeyCode::Down | KeyCode::Enter => self.field = (self.field + 1).min(FIELDS.len() - 1),
+            KeyCode::Left | KeyCode::Right => {
+                let forward = code == KeyCode::Right;
+                match field {
+                    Field::Format => self.settings.format = step(self.settings.format, FORMATS.len(), forward),
+                    Field::Fit => self.settings.fit = step(self.settings.fit, FITS.len(), forward),
+                    Field::Quality if forward => self.settings.quality = (self.settings.quality + 5).min(100),
+                    Field::Quality => self.settings.quality = self.settings.quality.saturating_sub(5).max(1),
+                    _ => {}
+                }
+            }
+            KeyCode::Char(c) => match field {
+                Field::Resize => self.settings.resize.push(c),
+                Field::Output => self.settings.output.push(c),
+                Field::Quality if c == '+' => self.settings.quality = (self.settings.quality + 1).min(100),
+                Field::Quality if c == '-' => self.settings.quality = self.settings.quality.saturating_sub(1).max(1),
+                _ => {}
+            },
+            KeyCode::Backspace => match field {
+                Field::Resize => { self.settings.resize.pop(); }
+                Field::Output => { self.settings.output.pop(); }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // Asks for the estimate of the highlighted file with the current settings, unless it is known or on its way
+    fn estimate(&mut self) {
+        let Some(path) = self.highlighted().filter(|e| !e.dir).map(|e| e.path.clone()) else { return };
+        let wanted = (path, self.settings.clone());
+        let known = self.estimate.as_ref().is_some_and(|(p, s, _)| (p, s) == (&wanted.0, &wanted.1));
+        if known || self.estimating.as_ref() == Some(&wanted) { return }
+        self.estimating = Some(wanted.clone());
+        let sender = self.sender.clone();
+        let options = self.settings.options();
+        rayon::spawn(move || {
+            let (path, settings) = wanted;
+            let estimate = options.and_then(|opts| {
+                let data = std::fs::read(&path).map_err(|e| ImcoError::FailedFileRead(e.to_string(), path.display().to_string()))?;
+                let input_bytes = data.len() as u64;
+                let output = Converter::new(opts).convert_bytes(data)?;
+                Ok((input_bytes, output.len() as u64))
+            }).map_err(|e| e.to_string());
+            let _ = sender.send(Message::Estimated(path, settings, estimate));
+        });
+    }
+
+    // Converts the selection, or the highlighted file without one
+    fn start(&mut self) {
+        let inputs: Vec<PathBuf> = if self.selected.is_empty() {
+            self.highlighted().filter(|e| !e.dir).map(|e| e.path.clone()).into_iter().collect()
+        } else {
+            self.selected.iter().cloned().collect()
+        };
+        if inputs.is_empty() {
+            self.message = Some("Select images with space first".to_string());
+            return
+        }
+        let opts = match self.settings.options() {
+            Ok(opts) => opts,
+            Err(e) => {
+                self.message = Some(e.to_string());
+                return
+            }
+        };
+        // Relative to the directory being browsed
+        let output = Some(self.settings.output.as_str()).filter(|o| !o.is_empty()).map(|o| self.dir.join(o));
+        let jobs: Vec<ConversionJob> = inputs.iter().map(|input| ConversionJob::new(input, output.clone())).collect();
+        self.batch = Some(Batch { total: jobs.len(), outcomes: vec![], finished: false });
+        let sender = self.sender.clone();
+        rayon::spawn(move || {
+            let converter = Converter::new(opts);
+            jobs.par_iter().for_each_with(sender.clone(), |sender, job| {
+                let start = std::time::Instant::now();
+                let result = converter.convert(job);
+                let input_bytes = std::fs::metadata(&job.input).ok().map(|m| m.len());
+                let _ = sender.send(Message::Converted(FileOutcome { input: job.input.clone(), input_bytes, duration: start.elapsed(), result }));
+            });
+            let _ = converter.finish();
+            let _ = sender.send(Message::Finished);
+        });
+    }
+
+    fn receive(&mut self) {
+        while let Ok(message) = self.receiver.try_recv() {
+            match message {
+                Message::Estimated(path, settings, estimate) => {
+                    if self.estimating.as_ref().is_some_and(|(p, s)| (p, s) == (&path, &settings)) { self.estimating = None }
+                    self.estimate = Some((path, settings, estimate));
+                }
+                Message::Converted(outcome) => if let Some(batch) = &mut self.batch { batch.outcomes.push(outcome) },
+                Message::Finished => if let Some(batch) = &mut self.batch { batch.finished = true },
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, estimate, help] = Layout::vertical([Constraint::Min(0), Constraint::Length(3), Constraint::Length(1)]).areas(frame.area());
+        let focused = |pane: Pane| if self.pane == pane && self.batch.is_none() { Style::new().fg(Color::Cyan) } else { Style::new() };
+        if let Some(batch) = &self.batch {
+            draw_batch(frame, main, batch, &self.dir);
+        } else {
+            let [files, settings] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(main);
+            let items: Vec<ListItem> = self.entries.iter().map(|entry| {
+                let mark = if self.selected.contains(&entry.path) { "[x] " } else if entry.dir { "    " } else { "[ ] " };
+                let name = if entry.dir { format!("{}/", entry.name) } else { entry.name.clone() };
+                let style = if entry.dir { Style::new().fg(Color::Blue) } else { Style::new() };
+                ListItem::new(Line::from(vec![Span::raw(mark), Span::styled(name, style)]))
+            }).collect();
+            let title = format!(" {} ({} selected) ", self.dir.display(), self.selected.len());
+            let list = List::new(items)
+                .block(Block::bordered().title(title).border_style(focused(Pane::Files)))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+            frame.render_stateful_widget(list, files, &mut self.cursor);
+
+            let s = &self.settings;
+            let lines: Vec<Line> = FIELDS.iter().enumerate().map(|(i, field)| {
+                let (label, value) = match field {
+                    Field::Format => ("Format ", format!("< {} >", FORMATS[s.format])),
+                    Field::Quality => ("Quality", format!("< {} >", s.quality)),
+                    Field::Resize => ("Resize ", if s.resize.is_empty() { "(original size)".to_string() } else { s.resize.clone() }),
+                    Field::Fit => ("Fit    ", format!("< {} >", FITS[s.fit])),
+                    Field::Output => ("Output ", if s.output.is_empty() { "(next to the inputs)".to_string() } else { s.output.clone() }),
+                };
+                let style = if i == self.field && self.pane == Pane::Settings { Style::new().add_modifier(Modifier::REVERSED) } else { Style::new() };
+                Line::from(vec![Span::raw(format!("{label}  ")), Span::styled(value, style)])
+            }).collect();
+            frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Options ").border_style(focused(Pane::Settings))), settings);
+        }
+
+        let estimate_text = match (self.highlighted().filter(|e| !e.dir), &self.estimate) {
+            (None, _) => "Highlight an image to estimate its output size".to_string(),
+            (Some(entry), Some((path, settings, estimate))) if *path == entry.path && *settings == self.settings => match estimate {
+                Ok((input, output)) => {
+                    let saved = (1.0 - *output as f64 / (*input).max(1) as f64) * 100.0;
+                    let change = if saved >= 0.0 { format!("saved {saved:.1}%") } else { format!("grew {:.1}%", -saved) };
+                    format!("{}: {input} -> ~{output} bytes as {} ({change})", entry.name, FORMATS[self.settings.format])
+                }
+                Err(e) => format!("{}: {e}", entry.name),
+            },
+            (Some(entry), _) => format!("Estimating {}...", entry.name),
+        };
+        frame.render_widget(Paragraph::new(estimate_text).block(Block::bordered().title(" Estimate ")), estimate);
+
+        let help_text = match (&self.message, &self.batch) {
+            (Some(message), _) => Span::styled(message.clone(), Style::new().fg(Color::Red)),
+            (None, Some(batch)) if batch.finished => Span::raw("Press any key to go back"),
+            (None, Some(_)) => Span::raw("Converting..."),
+            (None, None) if self.pane == Pane::Files => Span::raw("space select  a all  enter open  backspace up  tab options  r convert  q quit"),
+            (None, None) => Span::raw("up/down field  left/right change  type to edit  tab files  F5 convert  esc quit"),
+        };
+        frame.render_widget(Paragraph::new(help_text), help);
+    }
+}
+
+fn draw_batch(frame: &mut Frame, area: ratatui::layout::Rect, batch: &Batch, dir: &Path) {
+    let relative = |path: &Path| path.strip_prefix(dir).unwrap_or(path).display().to_string();
+    let [gauge, log] = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(area);
+    let done = batch.outcomes.len();
+    frame.render_widget(Gauge::default()
+        .block(Block::bordered().title(" Converting "))
+        .gauge_style(Style::new().fg(Color::Green))
+        .ratio(done as f64 / batch.total.max(1) as f64)
+        .label(format!("{done}/{}", batch.total)), gauge);
+    let failed = batch.outcomes.iter().filter(|o| o.result.is_err()).count();
+    let (input_bytes, output_bytes) = batch.outcomes.iter()
+        .filter_map(|o| o.result.as_ref().ok().and_then(|res| Some((o.input_bytes?, res.output_bytes?))))
+        .fold((0, 0), |(input, output), (i, o)| (input + i, output + o));
+    let mut lines: Vec<Line> = batch.outcomes.iter().map(|outcome| match &outcome.result {
+        Ok(res) => Line::styled(format!("{} -> {} ({} bytes)", relative(&outcome.input), relative(&res.output), res.output_bytes.unwrap_or_default()), Style::new().fg(Color::Green)),
+        Err(e) => Line::styled(format!("{}: {e}", relative(&outcome.input)), Style::new().fg(Color::Red)),
+    }).collect();
+    if batch.finished {
+        lines.push(Line::raw(format!("{} converted, {failed} failed, {input_bytes} -> {output_bytes} bytes", done - failed)));
+    }
+    // The latest lines stay in view
+    let skip = lines.len().saturating_sub(log.height.saturating_sub(2) as usize);
+    frame.render_widget(Paragraph::new(lines.split_off(skip)).block(Block::bordered().title(" Files ")), log);
+}
+
+/// Runs the interface in dir until it is quit
+pub fn run(dir: &str) -> ImcoResult<()> {
+    let dir = std::fs::canonicalize(dir).map_err(|e| ImcoError::FailedFileRead(e.to_string(), dir.to_string()))?;
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, App::new(dir));
+    ratatui::restore();
+    result.map_err(|e| ImcoError::Tui(e.to_string()))
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, mut app: App) -> std::io::Result<()> {
+    while !app.quit {
+        app.receive();
+        if app.batch.is_none() { app.estimate() }
+        terminal.draw(|frame| app.draw(frame))?;
+        // Wakes up regularly for estimates and progress coming in
+        if event::poll(std::time::Duration::from_millis(100))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press { app.handle(key) }
+            }
+        }
+    }
+    Ok(())
+}